@@ -0,0 +1,14 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // The Protobuf types and the `EventFeed` gRPC service of `proto/event.proto` (see `entities::proto`). `protoc` is
+    // vendored, so that building with the `grpc` feature does not require installing it
+    #[cfg(feature = "grpc")]
+    {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().expect("no vendored protoc for this platform"));
+        tonic_build::configure()
+            .build_transport(false)
+            .compile(&["proto/event.proto"], &["proto"])
+            .expect("could not compile proto/event.proto");
+    }
+}
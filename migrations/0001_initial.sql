@@ -0,0 +1,75 @@
+/*
+ * This is the PostgreSQL schema that Infobserve uses to store processed events. Later changes to it are made by the
+ * migrations that follow this one. Like every migration, it is applied once and never edited afterwards
+ */
+
+CREATE TABLE IF NOT EXISTS events (
+  id SERIAL PRIMARY KEY,
+  source TEXT, -- The service in which the event was found
+  url TEXT, -- The url of the paste
+  size BIGINT, -- The size of the paste (in bytes)
+  raw_content TEXT, -- The raw text of the event
+  filename TEXT, -- The name of the file in which the event was found
+  creator TEXT, -- The name of the user that created the post that contained the event
+  created_at TIMESTAMPTZ, -- The time and date the event was created
+  discovered_at TIMESTAMPTZ -- The time and date the event was discovered
+);
+ALTER TABLE events ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ; -- Set when the event is soft-deleted
+ALTER TABLE events ADD COLUMN IF NOT EXISTS metadata JSONB; -- Source-specific fields that don't fit the columns above
+ALTER TABLE events ADD COLUMN IF NOT EXISTS categories TEXT [] NOT NULL DEFAULT '{}'; -- The categories of the matched tags
+CREATE INDEX IF NOT EXISTS events_categories_idx ON events USING GIN (categories);
+-- Tell configuration files (few lines) apart from data dumps (many lines). NULL for events stored before they were added
+ALTER TABLE events ADD COLUMN IF NOT EXISTS word_count BIGINT;
+ALTER TABLE events ADD COLUMN IF NOT EXISTS line_count BIGINT;
+-- The first 500 characters of raw_content, so that dashboards don't have to fetch the full content
+ALTER TABLE events ADD COLUMN IF NOT EXISTS content_preview VARCHAR(500);
+-- The IPs, domains and URLs found in raw_content (`{"ips": [...], "domains": [...], "urls": [...]}`), if extracted
+ALTER TABLE events ADD COLUMN IF NOT EXISTS indicators_extracted JSONB;
+CREATE INDEX IF NOT EXISTS events_indicators_extracted_idx ON events USING GIN (indicators_extracted jsonb_path_ops);
+-- Backs full-text searches on the content. Building it on a large existing table may take a long time and blocks
+-- writes to `events` meanwhile: create it by hand (e.g. CONCURRENTLY) during a maintenance window before upgrading
+CREATE INDEX IF NOT EXISTS events_raw_content_fts_idx ON events USING GIN (to_tsvector('english', raw_content));
+CREATE TABLE IF NOT EXISTS rule_matches (
+  id SERIAL PRIMARY KEY,
+  event_id INTEGER REFERENCES events(id), -- A reference to the event in which the rule matched
+  rule_matched TEXT, -- The name of the yara rule that matched
+  tags_matched TEXT [] -- The tags of the rule that matched
+);
+CREATE TABLE IF NOT EXISTS ascii_matches (
+  id SERIAL PRIMARY KEY,
+  match_id INTEGER REFERENCES rule_matches(id),
+  matched_string TEXT -- The matched ASCII string
+);
+-- What threat intelligence providers know about the IPs and domains in matched_string, if looked up
+ALTER TABLE ascii_matches ADD COLUMN IF NOT EXISTS threat_intel JSONB;
+-- Where matched_string was found in the raw_content of the event (in bytes)
+ALTER TABLE ascii_matches ADD COLUMN IF NOT EXISTS byte_offset BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE ascii_matches ADD COLUMN IF NOT EXISTS byte_length BIGINT NOT NULL DEFAULT 0;
+-- The matched bytes, if they are not valid UTF-8. matched_string holds a lossy conversion of them
+ALTER TABLE ascii_matches ADD COLUMN IF NOT EXISTS raw_bytes BYTEA;
+CREATE TABLE IF NOT EXISTS index_cache (
+  id SERIAL PRIMARY KEY,
+  source TEXT,
+  source_id TEXT, -- The Reason is each kind of source could have different definition of a unique id format.
+  cached_time TIMESTAMP NOT NULL DEFAULT NOW()
+);
+-- Older schemas allowed duplicate entries, which would prevent the unique index from being created
+DELETE FROM index_cache a USING index_cache b
+  WHERE a.id < b.id AND a.source = b.source AND a.source_id = b.source_id;
+CREATE UNIQUE INDEX IF NOT EXISTS index_cache_source_idx ON index_cache (source, source_id);
+
+CREATE OR REPLACE FUNCTION expire_cached_rows() RETURNS trigger
+  LANGUAGE plpgsql
+  AS $$
+BEGIN
+  DELETE FROM index_cache WHERE cached_time < NOW() - INTERVAL '2 hours';
+  RETURN NULL;
+END;
+$$;
+
+
+DROP TRIGGER IF EXISTS trigger_expire_cached_rows
+  ON PUBLIC.INDEX_CACHE;
+CREATE TRIGGER trigger_expire_cached_rows
+  AFTER INSERT ON index_cache
+  EXECUTE PROCEDURE expire_cached_rows();
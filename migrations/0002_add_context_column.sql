@@ -0,0 +1,2 @@
+-- matched_string along with the text around it in the raw_content of the event, if extracted
+ALTER TABLE ascii_matches ADD COLUMN IF NOT EXISTS context TEXT;
@@ -0,0 +1,2 @@
+-- The meta fields of the matched rule (e.g. author, description), as a JSON object of strings
+ALTER TABLE rule_matches ADD COLUMN IF NOT EXISTS meta JSONB;
@@ -1,15 +1,101 @@
 extern crate clap;
 
 use clap::{App, Arg};
+use log::LevelFilter;
+
+use crate::logger::LogFormat;
 
 pub struct Cli {
     config_path: String,
+    log_level: LevelFilter,
+    log_format: LogFormat,
+    input_file: Option<String>,
+    dry_run: bool,
+    delete_events_file: Option<String>,
+    dump_schema: bool,
+    benchmark_rules: bool,
+    benchmark_content: Option<String>,
+    benchmark_iterations: u32,
+    search: Option<String>,
+    search_limit: i64,
+    compare_stats_file: Option<String>,
+    validate_rules: bool,
+    validate_rules_dir: Option<String>,
+    dedup_matches_event_id: Option<i32>,
 }
 
 impl Cli {
     pub fn config_path(&self) -> &str {
         &self.config_path
     }
+
+    /// The most verbose level logged. Default: `LevelFilter::Info`
+    pub fn log_level(&self) -> LevelFilter {
+        self.log_level
+    }
+
+    /// How each log line is written. Default: `LogFormat::Text`
+    pub fn log_format(&self) -> LogFormat {
+        self.log_format
+    }
+
+    /// A file of newline-delimited JSON events, read instead of popping events from redis (or Kafka)
+    pub fn input_file(&self) -> Option<&str> {
+        self.input_file.as_deref()
+    }
+
+    /// Whether the processed events are printed instead of being stored, leaving the database alone
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub fn delete_events_file(&self) -> Option<&str> {
+        self.delete_events_file.as_deref()
+    }
+
+    pub fn dump_schema(&self) -> bool {
+        self.dump_schema
+    }
+
+    pub fn benchmark_rules(&self) -> bool {
+        self.benchmark_rules
+    }
+
+    pub fn benchmark_content(&self) -> &str {
+        self.benchmark_content.as_deref().unwrap_or_default()
+    }
+
+    pub fn benchmark_iterations(&self) -> u32 {
+        self.benchmark_iterations
+    }
+
+    pub fn search(&self) -> Option<&str> {
+        self.search.as_deref()
+    }
+
+    pub fn search_limit(&self) -> i64 {
+        self.search_limit
+    }
+
+    pub fn compare_stats_file(&self) -> Option<&str> {
+        self.compare_stats_file.as_deref()
+    }
+
+    /// Whether to compile the rules of the configuration (`yara_rule_dir`, along with the include and exclude
+    /// patterns) and exit
+    pub fn validate_rules(&self) -> bool {
+        self.validate_rules
+    }
+
+    /// The directory passed to the `validate-rules` subcommand, if it was invoked
+    pub fn validate_rules_dir(&self) -> Option<&str> {
+        self.validate_rules_dir.as_deref()
+    }
+
+    /// The event passed to the `dedup-matches` subcommand, if it was invoked
+    pub fn dedup_matches_event_id(&self) -> Option<i32> {
+        self.dedup_matches_event_id
+    }
 }
 
 impl Cli {
@@ -22,7 +108,118 @@ impl Cli {
                     .short('c')
                     .long("config")
                     .value_name("CONFIG")
-                    .default_value("config.yaml"),
+                    .default_value("config.yaml")
+                    .help("Path to the configuration file. Pass `-` to read the configuration from stdin"),
+            )
+            .arg(
+                Arg::new("log-level")
+                    .short('l')
+                    .long("log-level")
+                    .value_name("LEVEL")
+                    .value_parser(parse_log_level)
+                    .default_value("info")
+                    .help("The most verbose messages logged: error (errors only), warn (errors and warnings), info (progress as well), debug (details of each event) or trace (everything)"),
+            )
+            .arg(
+                Arg::new("log-format")
+                    .long("log-format")
+                    .value_name("FORMAT")
+                    .value_parser(parse_log_format)
+                    .default_value("text")
+                    .help("How each log line is written: text (human-readable) or json (an object with the level, message, target and timestamp of the line, for log aggregators)"),
+            )
+            .arg(
+                Arg::new("input-file")
+                    .short('f')
+                    .long("input-file")
+                    .value_name("PATH")
+                    .help("Processes the events in PATH (one JSON event per line) instead of popping them from redis or Kafka, and exits once they are all stored"),
+            )
+            .arg(
+                Arg::new("dry-run")
+                    .short('n')
+                    .long("dry-run")
+                    .conflicts_with_all(&["delete-events-file", "search"])
+                    .help("Processes the events without connecting to the database, printing a summary of each matching event instead of storing it"),
+            )
+            .arg(
+                Arg::new("delete-events-file")
+                    .long("delete-events-file")
+                    .value_name("PATH")
+                    .help("Deletes the events whose IDs are listed (one per line) in PATH, along with their matches, and exits"),
+            )
+            .arg(
+                Arg::new("dump-schema")
+                    .long("dump-schema")
+                    .help("Prints the SQL that creates the database schema and exits"),
+            )
+            .arg(
+                Arg::new("benchmark-rules")
+                    .long("benchmark-rules")
+                    .requires("content")
+                    .help("Measures how long the yara rules take to scan --content and exits"),
+            )
+            .arg(
+                Arg::new("content")
+                    .long("content")
+                    .value_name("STRING")
+                    .help("The content to scan when benchmarking the rules"),
+            )
+            .arg(
+                Arg::new("iterations")
+                    .long("iterations")
+                    .value_name("N")
+                    .value_parser(clap::value_parser!(u32))
+                    .default_value("100")
+                    .help("How many times to scan --content when benchmarking the rules"),
+            )
+            .arg(
+                Arg::new("search")
+                    .long("search")
+                    .value_name("QUERY")
+                    .help("Prints the stored events whose content matches QUERY (full-text search), along with their matches, and exits"),
+            )
+            .arg(
+                Arg::new("limit")
+                    .long("limit")
+                    .value_name("N")
+                    .value_parser(clap::value_parser!(i64).range(1..))
+                    .default_value("20")
+                    .help("The maximum number of events printed by --search"),
+            )
+            .arg(
+                Arg::new("compare-stats-file")
+                    .long("compare-stats-file")
+                    .value_name("PATH")
+                    .help("Prints how the processing stats of the last run in PATH (see `monitoring.stats_file`) differ from the run before it, and exits"),
+            )
+            .arg(
+                Arg::new("validate-rules")
+                    .long("validate-rules")
+                    .help("Compiles the yara rules of the configuration (yara_rule_dir, yara_include_patterns and yara_exclude_patterns), prints how many were loaded and exits"),
+            )
+            .subcommand(
+                App::new("validate-rules")
+                    .about("Compiles the yara rules under --rules-dir, prints the name of every rule and exits")
+                    .arg(
+                        Arg::new("rules-dir")
+                            .long("rules-dir")
+                            .value_name("DIR")
+                            .required(true)
+                            .help("The directory under which the .yar files will be found"),
+                    ),
+            )
+            .subcommand(
+                App::new("dedup-matches")
+                    .about("Deletes all but the earliest rule match of each rule that matched --event-id more than once, and exits")
+                    .arg(
+                        Arg::new("event-id")
+                            .long("event-id")
+                            .value_name("ID")
+                            .value_parser(clap::value_parser!(i32))
+                            .required(true)
+                            .help("The event whose rule matches will be deduplicated"),
+                    ),
             )
             .get_matches();
 
@@ -33,6 +230,83 @@ impl Cli {
                 .value_of("config")
                 .unwrap()
                 .to_string(),
+            log_level: *a.get_one::<LevelFilter>("log-level").unwrap(),
+            log_format: *a.get_one::<LogFormat>("log-format").unwrap(),
+            input_file: a.value_of("input-file").map(String::from),
+            dry_run: a.is_present("dry-run"),
+            delete_events_file: a.value_of("delete-events-file").map(String::from),
+            dump_schema: a.is_present("dump-schema"),
+            benchmark_rules: a.is_present("benchmark-rules"),
+            benchmark_content: a.value_of("content").map(String::from),
+            benchmark_iterations: *a.get_one::<u32>("iterations").unwrap(),
+            search: a.value_of("search").map(String::from),
+            search_limit: *a.get_one::<i64>("limit").unwrap(),
+            compare_stats_file: a.value_of("compare-stats-file").map(String::from),
+            validate_rules: a.is_present("validate-rules"),
+            validate_rules_dir: a
+                .subcommand_matches("validate-rules")
+                .and_then(|m| m.value_of("rules-dir"))
+                .map(String::from),
+            dedup_matches_event_id: a
+                .subcommand_matches("dedup-matches")
+                .and_then(|m| m.get_one::<i32>("event-id"))
+                .copied(),
         }
     }
 }
+
+/// Parses the value of `--log-level`
+fn parse_log_level(level: &str) -> Result<LevelFilter, String> {
+    match level.to_ascii_lowercase().as_str() {
+        "error" => Ok(LevelFilter::Error),
+        "warn" => Ok(LevelFilter::Warn),
+        "info" => Ok(LevelFilter::Info),
+        "debug" => Ok(LevelFilter::Debug),
+        "trace" => Ok(LevelFilter::Trace),
+        _ => Err("expected one of error, warn, info, debug or trace".to_owned())
+    }
+}
+
+/// Parses the value of `--log-format`
+fn parse_log_format(format: &str) -> Result<LogFormat, String> {
+    match format.to_ascii_lowercase().as_str() {
+        "text" => Ok(LogFormat::Text),
+        "json" => Ok(LogFormat::Json),
+        _ => Err("expected text or json".to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_levels_are_parsed() {
+        let levels = [
+            ("error", LevelFilter::Error),
+            ("warn", LevelFilter::Warn),
+            ("info", LevelFilter::Info),
+            ("debug", LevelFilter::Debug),
+            ("trace", LevelFilter::Trace),
+            ("TRACE", LevelFilter::Trace)
+        ];
+
+        for (level, expected) in levels {
+            assert_eq!(parse_log_level(level), Ok(expected));
+        }
+    }
+
+    #[test]
+    fn unknown_log_levels_are_rejected() {
+        for level in ["", "off", "verbose", "warning"] {
+            assert!(parse_log_level(level).is_err());
+        }
+    }
+
+    #[test]
+    fn log_formats_are_parsed() {
+        assert_eq!(parse_log_format("text"), Ok(LogFormat::Text));
+        assert_eq!(parse_log_format("JSON"), Ok(LogFormat::Json));
+        assert!(parse_log_format("yaml").is_err());
+    }
+}
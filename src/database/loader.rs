@@ -3,19 +3,156 @@
 //! and inserts them into the DB
 extern crate r2d2;
 
-use std::{fs, error, thread, sync};
-use log::{info, error};
+use std::{fmt, thread, sync};
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::time::{Duration, Instant};
+use std::io::{BufRead, BufReader, BufWriter, LineWriter, Write};
+use log::{debug, info, warn, error};
 
-use crossbeam_channel::Receiver;
-use anyhow::Result;
+use chrono::{DateTime, Local};
+use crossbeam_channel::{Receiver, Sender};
+use anyhow::{anyhow, Context, Result};
+use r2d2_postgres::postgres::{IsolationLevel, Row, Transaction};
+use r2d2_postgres::postgres::types::ToSql;
+#[cfg(feature = "threat-intel")]
+use crate::enrichment::ThreatIntel;
+#[cfg(feature = "threat-intel")]
+use crate::indicators::extract_indicators;
+use serde::{Deserialize, Serialize};
 
-use crate::entities::{RuleMatch, ProcessedEvent, AsciiMatch};
-use crate::database::{DbConnection, Insert};
+use crate::entities::{Event, RuleMatch, ProcessedEvent, AsciiMatch, IndexCache};
+use crate::database::{migrations, retry_transaction, Client, DbConnection, RetryingDbConnection, Insert};
+use crate::errors::DbLoaderError;
+use crate::config::LoaderCfg;
+use crate::utils::pluralize;
+
+/// Adds the optional `UNIQUE(event_id, rule_matched)` constraint to `rule_matches`, unless it is already there
+/// (see `DbLoader::add_unique_rule_matches_constraint`)
+const UNIQUE_RULE_MATCHES_SQL: &str = "
+DO $$
+BEGIN
+  IF NOT EXISTS (SELECT 1 FROM pg_constraint WHERE conname = 'rule_matches_event_id_rule_matched_key') THEN
+    ALTER TABLE rule_matches
+      ADD CONSTRAINT rule_matches_event_id_rule_matched_key UNIQUE (event_id, rule_matched);
+  END IF;
+END;
+$$;
+";
+
+/// How many times a batch is stored again after its transaction was aborted to break a deadlock (see
+/// `database::retry_transaction`)
+const DEADLOCK_MAX_RETRIES: u32 = 3;
+
+/// How many of a persisted event's rules are listed in the logs
+const ALERT_SUMMARY_MAX_MATCHES: usize = 5;
+
+/// The tables included in snapshots, in the order their rows are exported and imported (referenced tables first)
+const SNAPSHOT_TABLES: [&str; 3] = ["events", "rule_matches", "ascii_matches"];
+
+/// A line of a snapshot file (see `DbLoader::export_snapshot`)
+#[derive(Serialize, Deserialize)]
+struct SnapshotEntry {
+    table: String,
+    row: serde_json::Value
+}
+
+/// Returned by each loader thread when it is joined. Counts the events the thread stored, and measures the time
+/// it spent storing them
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LoaderStats {
+    num_persisted: u32,
+    num_failed: u32,
+    num_retried: u32,
+    overall_persist_time: Duration
+}
+
+impl LoaderStats {
+    /// The number of events that were stored
+    #[allow(dead_code)]
+    pub fn num_persisted(&self) -> u32 {
+        self.num_persisted
+    }
+
+    /// The number of events that could not be stored, whether they were dead-lettered or discarded
+    #[allow(dead_code)]
+    pub fn num_failed(&self) -> u32 {
+        self.num_failed
+    }
+
+    /// The number of events stored again one by one, after the batch they were in failed
+    #[allow(dead_code)]
+    pub fn num_retried(&self) -> u32 {
+        self.num_retried
+    }
+
+    /// The time spent storing events, retries included
+    #[allow(dead_code)]
+    pub fn overall_persist_time(&self) -> Duration {
+        self.overall_persist_time
+    }
+
+    /// The time spent on each event, whether it was stored or not. Zero if there were no events
+    pub fn avg_persist_time(&self) -> Duration {
+        match self.num_persisted + self.num_failed {
+            0 => Duration::from_secs(0),
+            num_events => self.overall_persist_time / num_events
+        }
+    }
+
+    /// Combines these stats with those of another loader thread
+    pub fn merge(mut self, other: LoaderStats) -> LoaderStats {
+        self.num_persisted += other.num_persisted;
+        self.num_failed += other.num_failed;
+        self.num_retried += other.num_retried;
+        self.overall_persist_time += other.overall_persist_time;
+        self
+    }
+}
+
+impl fmt::Display for LoaderStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Persisted events: {}, failed events: {}, retried events: {}, overall time spent persisting: {}ms, \
+             average time spent on each event: {}us",
+            self.num_persisted, self.num_failed, self.num_retried, self.overall_persist_time.as_millis(),
+            self.avg_persist_time().as_micros()
+        )
+    }
+}
+
+/// Returned by the dry run sink (see `start_dry_run_sink`) when it is joined
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SinkStats {
+    num_events: u32,
+    num_matches: u32
+}
+
+impl SinkStats {
+    /// The number of processed (i.e. matching) events received
+    #[allow(dead_code)]
+    pub fn num_events(&self) -> u32 {
+        self.num_events
+    }
+
+    /// The number of rule matches of these events
+    #[allow(dead_code)]
+    pub fn num_matches(&self) -> u32 {
+        self.num_matches
+    }
+}
+
+impl fmt::Display for SinkStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Matching events: {}, rule matches: {}", self.num_events, self.num_matches)
+    }
+}
 
 /// Given the consuming end of a crossbeam channel, continuously consumes
 /// ProcessedEvent objects and stores them in the db.
 /// This work happens in N threads
-/// Returns a vector of the spawned thread handles
+/// Returns a vector of the spawned thread handles, each joining with the stats of its thread
 ///
 /// # Example
 /// 
@@ -27,7 +164,7 @@ use crate::database::{DbConnection, Insert};
 /// let loader = DbLoader::with_connection(conn);
 /// let (receiver, sender) = crossbeam_channel::unbounded();
 ///
-/// let handles = start_loaders(&receiver, loader, 4);
+/// let handles = start_loaders(&receiver, loader, 4, &LoaderCfg::default());
 /// 
 /// assert_eq!(handles.len(), 4);
 /// // let pevent = ProcessedEvent(...)
@@ -36,33 +173,43 @@ use crate::database::{DbConnection, Insert};
 /// drop(sender);
 /// 
 /// for handle in handles {
-///     handle.join.unwrap();
+///     println!("{}", handle.join().unwrap().unwrap());
 /// }
 /// ```
 pub fn start_loaders(
     load_recvr: &Receiver<ProcessedEvent>,
     db_loader: DbLoader,
-    num_loaders: i32
-) -> Vec<thread::JoinHandle<()>> {
+    num_loaders: i32,
+    loader_cfg: &LoaderCfg
+) -> Vec<thread::JoinHandle<Result<LoaderStats>>> {
     if num_loaders == 0 {
         let msg = "Refusing to continue with 0 loaders -- Process would hang";
         error!("{}", msg);
         panic!("{}", msg);
     }
 
-    let mut l_handles: Vec<thread::JoinHandle<()>> = Vec::with_capacity(num_loaders as usize);
+    let mut l_handles: Vec<thread::JoinHandle<Result<LoaderStats>>> = Vec::with_capacity(num_loaders as usize);
     let db_loader_arc = sync::Arc::new(db_loader);
 
-    info!("Spawning {} DB loaders", num_loaders);
+    info!("Spawning {}", pluralize(num_loaders as usize, "DB loader"));
     for _ in 0..num_loaders {
         let rx = crossbeam_channel::Receiver::clone(load_recvr);
         let db_loader = sync::Arc::clone(&db_loader_arc);
+        let (batch_size, batch_timeout) = (loader_cfg.batch_size(), loader_cfg.batch_timeout());
 
         l_handles.push(
             thread::spawn(move || {
-                for proc_event in rx {
-                    db_loader.persist_processed_event(proc_event);
+                let mut stats = LoaderStats::default();
+                loop {
+                    let batch = next_batch(&rx, batch_size, batch_timeout);
+                    if batch.is_empty() {
+                        break;
+                    }
+
+                    stats = stats.merge(db_loader.load_batch(batch));
                 }
+
+                Ok(stats)
             })
         );
     }
@@ -70,106 +217,2140 @@ pub fn start_loaders(
     l_handles
 }
 
+/// Appends the events received from `dead_letter_rx` to the file at `path`, one JSON event per line, until all
+/// senders are gone. The lines are in the format the feeders read (see `Event::to_json_str`), so the events can
+/// be replayed with `--input-file` once the database is back
+///
+/// # Errors
+///
+/// When the file cannot be opened for appending
+pub fn start_dead_letter_logger(
+    dead_letter_rx: Receiver<ProcessedEvent>,
+    path: &str
+) -> Result<thread::JoinHandle<()>> {
+    let mut file = LineWriter::new(OpenOptions::new().create(true).append(true).open(path)?);
+    let path = path.to_owned();
+
+    info!("Writing the events that can't be stored to {}", path);
+    Ok(thread::spawn(move || {
+        let mut written = 0;
+        for ProcessedEvent(event, _) in dead_letter_rx.iter() {
+            let line = match event.to_json_str() {
+                Ok(line) => line,
+                Err(e) => {
+                    error!("Could not serialize dead letter event {}: {}", event.url(), e);
+                    continue;
+                }
+            };
+            match writeln!(file, "{}", line) {
+                Ok(()) => written += 1,
+                Err(e) => error!("Could not write event {} to {}: {}", event.url(), path, e)
+            }
+        }
+
+        if written > 0 {
+            warn!("Wrote {} to {}", pluralize(written, "event"), path);
+        }
+    }))
+}
+
+/// Stands in for the loaders in a dry run: prints a summary of each event received from `load_recvr` to stdout
+/// (see `ProcessedEvent::to_alert_summary`) instead of storing it, until all senders are gone
+///
+/// # Returns
+/// The handle of the spawned thread, joining with the number of events and matches it received
+pub fn start_dry_run_sink(load_recvr: &Receiver<ProcessedEvent>) -> thread::JoinHandle<SinkStats> {
+    let rx = crossbeam_channel::Receiver::clone(load_recvr);
+
+    info!("Dry run: printing the processed events instead of storing them");
+    thread::spawn(move || {
+        let mut stats = SinkStats::default();
+        for proc_event in rx.iter() {
+            println!("{}", proc_event.to_alert_summary(ALERT_SUMMARY_MAX_MATCHES));
+            stats.num_events += 1;
+            stats.num_matches += proc_event.1.len() as u32;
+        }
+
+        stats
+    })
+}
+
+/// Blocks until an event arrives, then keeps receiving until `batch_size` events have arrived or `timeout`
+/// has passed since the first one
+///
+/// # Returns
+/// The received events. Empty only when all senders are gone and the channel is drained
+fn next_batch(rx: &Receiver<ProcessedEvent>, batch_size: usize, timeout: Duration) -> Vec<ProcessedEvent> {
+    let first = match rx.recv() {
+        Ok(proc_event) => proc_event,
+        Err(_) => return Vec::new()
+    };
+
+    let deadline = Instant::now() + timeout;
+    let mut batch = Vec::with_capacity(batch_size);
+    batch.push(first);
+
+    while batch.len() < batch_size {
+        match rx.recv_deadline(deadline) {
+            Ok(proc_event) => batch.push(proc_event),
+            Err(_) => break
+        }
+    }
+
+    batch
+}
+
+/// The fields the `DbLoader` read methods can order events by (see `QueryOptions::order_by`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum EventOrderField {
+    /// Most recently discovered first
+    DiscoveredAt,
+    /// Largest first
+    Size,
+    /// Alphabetically
+    Source
+}
+
+impl EventOrderField {
+    /// The column to order by. Never derived from user input, so it is safe to interpolate into queries
+    pub fn to_sql_column(self) -> &'static str {
+        match self {
+            EventOrderField::DiscoveredAt => "discovered_at",
+            EventOrderField::Size => "size",
+            EventOrderField::Source => "source"
+        }
+    }
+
+    fn sql_direction(self) -> &'static str {
+        match self {
+            EventOrderField::DiscoveredAt | EventOrderField::Size => "DESC",
+            EventOrderField::Source => "ASC"
+        }
+    }
+}
+
+/// Which events the `DbLoader` read methods return, and in which order. The default hides soft-deleted events
+/// (see `DbLoader::bulk_soft_delete_events`), returns all of them and keeps each method's own order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[allow(dead_code)]
+pub struct QueryOptions {
+    /// Whether soft-deleted events (and their matches) are returned too
+    pub include_deleted: bool,
+    /// The page (starting from 0) and page size, if the results are paginated
+    pub page: Option<(u32, u32)>,
+    /// Overrides the method's order, e.g. relevance for `DbLoader::search_events`
+    pub order_by: Option<EventOrderField>
+}
+
+#[allow(dead_code)]
+impl QueryOptions {
+    /// Includes soft-deleted events
+    pub fn with_deleted(mut self) -> Self {
+        self.include_deleted = true;
+        self
+    }
+
+    /// Returns only the `page`th (starting from 0) page of `page_size` results
+    pub fn paginated(mut self, page: u32, page_size: u32) -> Self {
+        self.page = Some((page, page_size));
+        self
+    }
+
+    pub fn ordered_by(mut self, order_by: EventOrderField) -> Self {
+        self.order_by = Some(order_by);
+        self
+    }
+
+    /// An extra `WHERE` condition on the `events` table, which hides soft-deleted events unless they are included
+    fn deleted_condition(&self) -> &'static str {
+        if self.include_deleted {
+            ""
+        } else {
+            "AND events.deleted_at IS NULL"
+        }
+    }
+
+    /// The `ORDER BY` expressions, `default` unless `order_by` is set. Ties are broken by the newest event
+    fn order_clause(&self, default: &str) -> String {
+        match self.order_by {
+            Some(field) => format!("events.{} {}, events.id DESC", field.to_sql_column(), field.sql_direction()),
+            None => default.to_owned()
+        }
+    }
+
+    /// The `LIMIT`/`OFFSET` clause of the page, if any. Only built from integers, so it is safe to interpolate
+    fn limit_clause(&self) -> String {
+        match self.page {
+            Some((page, page_size)) => format!("LIMIT {} OFFSET {}", page_size, u64::from(page) * u64::from(page_size)),
+            None => String::new()
+        }
+    }
+}
+
+/// How much of each event `DbLoader::get_events_by_rule` loads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum LoadMode {
+    /// Events come with their `raw_content`
+    Full,
+    /// Events come with their `content_preview` only. The content can be fetched later with `Event::full_content`
+    Lightweight
+}
+
+impl LoadMode {
+    /// The `events` columns to select. Never derived from user input, so it is safe to interpolate into queries
+    fn to_sql_columns(self) -> &'static str {
+        match self {
+            LoadMode::Full => "events.*",
+            LoadMode::Lightweight => "events.id, events.source, events.url, events.size, events.filename, \
+                                      events.creator, events.created_at, events.discovered_at, events.metadata, \
+                                      events.categories, events.content_preview, events.indicators_extracted"
+        }
+    }
+}
+
 pub struct DbLoader {
-    conn: DbConnection
+    conn: RetryingDbConnection,
+    dead_letter_sx: Option<Sender<ProcessedEvent>>,
+    strip_secrets: bool,
+    max_match_length: usize,
+    store_binary_matches: bool,
+    #[cfg(feature = "threat-intel")]
+    threat_intel: Option<ThreatIntel>
 }
 
 impl DbLoader {
     pub fn with_connection(conn: DbConnection) -> Self {
-        Self { conn }
+        Self {
+            conn: RetryingDbConnection::new(conn),
+            dead_letter_sx: None,
+            strip_secrets: false,
+            max_match_length: 0,
+            store_binary_matches: false,
+            #[cfg(feature = "threat-intel")]
+            threat_intel: None
+        }
     }
 
-    /// Reads and loads the infobserve schema from the "infobserve-schema.sql"
-    /// file
-    pub fn create_schema(&self) -> Result<(), Box<dyn error::Error>> {
+    /// Asks for a database connection up to `max_retries` more times, `retry_delay` apart, before giving up on
+    /// the events at hand (see `RetryingDbConnection::with_retries`)
+    pub fn with_retries(mut self, max_retries: u32, retry_delay: Duration) -> Self {
+        self.conn = self.conn.with_retries(max_retries, retry_delay);
+        self
+    }
+
+    /// Sends the events given up on for lack of a database connection to `dead_letter_sx`, instead of discarding
+    /// them (see `start_dead_letter_logger`)
+    pub fn with_dead_letter(mut self, dead_letter_sx: Sender<ProcessedEvent>) -> Self {
+        self.dead_letter_sx = Some(dead_letter_sx);
+        self
+    }
+
+    /// Looks up the indicators of matched strings with `threat_intel` before persisting them
+    #[cfg(feature = "threat-intel")]
+    pub fn with_threat_intel(mut self, threat_intel: ThreatIntel) -> Self {
+        self.threat_intel = Some(threat_intel);
+        self
+    }
+
+    /// Redacts credentials from the content of events before persisting them (see `Event::strip_secrets`)
+    pub fn with_secret_stripping(mut self, enabled: bool) -> Self {
+        self.strip_secrets = enabled;
+        self
+    }
+
+    /// Truncates matched strings longer than `max_length` characters before persisting them
+    /// (see `AsciiMatch::truncate`). `0` means unlimited
+    pub fn with_max_match_length(mut self, max_length: usize) -> Self {
+        self.max_match_length = max_length;
+        self
+    }
+
+    /// Persists the matches that are not valid UTF-8 (see `FlatMatch::raw_data`) as well, instead of discarding them
+    pub fn with_binary_matches(mut self, enabled: bool) -> Self {
+        self.store_binary_matches = enabled;
+        self
+    }
+
+    /// Brings the infobserve schema up to date, applying the migrations the database is missing (see
+    /// `database::migrations`)
+    ///
+    /// # Returns
+    /// The versions of the migrations that were applied
+    pub fn run_migrations(&self) -> Result<Vec<i32>> {
+        let migrations = migrations::load()?;
         let mut client = self.conn.get()?;
 
-        info!("Creating initial infobserve schema");
-        let contents = match fs::read_to_string("infobserve-schema.sql") {
-            Ok(c) => c,
-            Err(e) => {
-                error!("Failed to load infobserve schema file: {}", e);
-                return Err(e.into());
-            }
-        };
+        migrations::run(&mut client, &migrations)
+    }
 
-        if let Err(e) = client.simple_query(&contents) {
-            error!("Failed to create infobserve schema: {}", e);
-            return Err(Box::new(e));
-        }
+    /// The SQL that creates the infobserve schema: every migration embedded in the binary, in order
+    pub fn schema_sql() -> String {
+        migrations::schema_sql()
+    }
 
-        Ok(())
+    /// The tables (and their columns) that the loader expects to find in the database.
+    /// Mirrors the migrations (see `database::migrations`)
+    pub fn expected_schema() -> HashMap<&'static str, Vec<&'static str>> {
+        let mut schema = HashMap::new();
+
+        schema.insert(
+            "events",
+            vec![
+                "id", "source", "url", "size", "raw_content", "filename", "creator", "created_at", "discovered_at",
+                "deleted_at", "metadata", "categories", "word_count", "line_count", "content_preview",
+                "indicators_extracted"
+            ]
+        );
+        schema.insert("rule_matches", vec!["id", "event_id", "rule_matched", "tags_matched", "meta"]);
+        schema.insert("ascii_matches", vec![
+            "id", "match_id", "matched_string", "threat_intel", "byte_offset", "byte_length", "raw_bytes", "context"
+        ]);
+        schema.insert("index_cache", vec!["id", "source", "source_id", "cached_time"]);
+
+        schema
     }
 
-    pub fn persist_processed_event(&self, proc_event: ProcessedEvent) {
-        // TODO: All these should be in a transaction
-        // I should pick up here and check how transactions in
-        // postgres-rs work (https://docs.rs/postgres/0.15.2/postgres/transaction/struct.Transaction.html)
-        info!("Persisting {:?}", proc_event);
+    /// Compares the database's schema against `DbLoader::expected_schema`
+    ///
+    /// # Returns
+    /// The missing columns, as `table.column` strings (sorted). An empty vector means the schema is healthy
+    pub fn schema_health_check(&self) -> Result<Vec<String>> {
+        let stmt = "
+        SELECT table_name::TEXT, column_name::TEXT
+        FROM information_schema.columns
+        WHERE table_schema = CURRENT_SCHEMA()
+        ";
+
+        let mut client = self.conn.get()?;
+        let present: HashSet<(String, String)> = client.query(stmt, &[])?
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+
+        let mut missing: Vec<String> = Self::expected_schema()
+            .into_iter()
+            .flat_map(|(table, columns)| columns.into_iter().map(move |column| (table, column)))
+            .filter(|(table, column)| !present.contains(&(table.to_string(), column.to_string())))
+            .map(|(table, column)| format!("{}.{}", table, column))
+            .collect();
+        missing.sort();
+
+        Ok(missing)
+    }
 
-        let mut client = match self.conn.get() {
-            Ok(c) => c,
+    /// Persists a single processed event, along with its matches (see `DbLoader::persist_events`). If no database
+    /// connection can be had, the event is sent to the dead letter channel instead
+    #[allow(dead_code)]
+    pub fn persist_processed_event(&self, mut proc_event: ProcessedEvent) -> bool {
+        match self.persist_events(std::slice::from_mut(&mut proc_event)) {
+            Ok(persisted) => persisted,
             Err(e) => {
-                error!("Failed to get connection: {}", e);
-                return;
+                self.dead_letter(vec![proc_event], &e);
+                false
             }
-        };
+        }
+    }
+
+    /// Persists `batch` (see `DbLoader::persist_events`). When several events of the batch share a url, only the
+    /// most recently discovered one is persisted. If the batch fails, its events are persisted one by one, so that
+    /// a single bad event does not cost the rest. The events that could not be persisted because no database
+    /// connection could be had are sent to the dead letter channel
+    ///
+    /// # Returns
+    /// The stats of storing `batch`, to be merged into those of the loader thread. Events that were already in
+    /// the index cache (and so were not stored again) count as persisted
+    pub fn load_batch(&self, batch: Vec<ProcessedEvent>) -> LoaderStats {
+        let mut stats = LoaderStats::default();
+        let start = Instant::now();
+        let mut batch = Self::dedup_batch(batch);
+        let num_events = batch.len() as u32;
 
-        let mut trans = match client.transaction() {
-            Ok(t) => t,
+        match self.persist_events(&mut batch) {
+            Ok(true) => stats.num_persisted += num_events,
+            Ok(false) if batch.len() > 1 => {
+                warn!("Failed to persist a batch of {}, retrying them one by one", batch.len());
+                stats.num_retried += num_events;
+                for mut proc_event in batch {
+                    match self.persist_events(std::slice::from_mut(&mut proc_event)) {
+                        Ok(true) => stats.num_persisted += 1,
+                        Ok(false) => stats.num_failed += 1,
+                        Err(e) => {
+                            stats.num_failed += 1;
+                            self.dead_letter(vec![proc_event], &e);
+                        }
+                    }
+                }
+            },
+            Ok(false) => stats.num_failed += num_events,
             Err(e) => {
-                error!("Could not initiate transaction to db: {}", e);
-                return;
+                stats.num_failed += num_events;
+                self.dead_letter(batch, &e);
             }
-        };
+        }
 
-        let ProcessedEvent(mut event, matches) = proc_event;
+        stats.overall_persist_time = start.elapsed();
+        stats
+    }
 
-        if let Err(e) = event.insert(&mut trans) {
-            error!("Failed to insert event: {}", e);
-            return;
+    /// Persists all events of `batch`, along with their matches, in a single transaction. The rule matches
+    /// (and matched strings) of the whole batch are inserted with multi-row statements. Events whose
+    /// `(source, url)` is already in the index cache are skipped, and the persisted ones are added to it in the
+    /// same transaction
+    ///
+    /// # Returns
+    /// Whether the transaction was committed. If it wasn't, none of the events were stored
+    ///
+    /// # Errors
+    ///
+    /// `errors::DbLoaderError::ConnectionExhausted` - When no connection could be taken from the pool
+    pub fn persist_events(&self, batch: &mut [ProcessedEvent]) -> Result<bool> {
+        if batch.is_empty() {
+            return Ok(true);
         }
 
-        let event_id = match event.id() {
-            Some(id) => id,
+        let mut client = self.conn.get()?;
+
+        Ok(self.persist_events_with(&mut client, batch))
+    }
+
+    /// Sends `events` to the dead letter channel, or discards them if there is none
+    fn dead_letter(&self, events: Vec<ProcessedEvent>, reason: &anyhow::Error) {
+        let sx = match &self.dead_letter_sx {
+            Some(sx) => sx,
             None => {
-                error!("Inserted event has empty ID? {:?}", event);
+                error!("Discarding {}: {}", pluralize(events.len(), "processed event"), reason);
                 return;
             }
         };
 
-        for flat_match in matches {
-            let mut rule_match = RuleMatch::new(
-                // TODO: unwrap ID before usage and handle errors
-                event_id, flat_match.rule_name().to_owned(),
-                flat_match.tags().into()
-            );
+        error!("Sending {} to the dead letter file: {}", pluralize(events.len(), "processed event"), reason);
+        for proc_event in events {
+            if let Err(e) = sx.send(proc_event) {
+                error!("Could not send event {} to the dead letter file: {}", (e.0).0.url(), e);
+            }
+        }
+    }
 
-            if let Err(e) = rule_match.insert(&mut trans) {
-                error!("Failed to insert rule match: {}", e);
-                return;
+    fn persist_events_with(&self, client: &mut Client, batch: &mut [ProcessedEvent]) -> bool {
+        let batch = Self::skip_seen(client, batch);
+        if batch.is_empty() {
+            return true;
+        }
+
+        for proc_event in batch.iter() {
+            info!("Persisting {}", proc_event.to_alert_summary(ALERT_SUMMARY_MAX_MATCHES));
+        }
+
+        // The providers are slow compared to the database, so they are asked before the transaction starts
+        #[cfg(feature = "threat-intel")]
+        let threat_intel: Vec<_> = batch.iter().map(|proc_event| self.look_up_threat_intel(&proc_event.1)).collect();
+
+        if self.strip_secrets {
+            for ProcessedEvent(event, _) in batch.iter_mut() {
+                event.strip_secrets();
             }
+        }
 
-            let match_id = match rule_match.id() {
-                Some(id) => id,
-                None => {
-                    error!("Inserted rule match has empty ID? {:?}", rule_match);
-                    return;
-                }
-            };
+        let persisted = retry_transaction(client, DEADLOCK_MAX_RETRIES, |trans| {
+            #[cfg(feature = "threat-intel")]
+            self.insert_events(trans, batch, &threat_intel)?;
+            #[cfg(not(feature = "threat-intel"))]
+            self.insert_events(trans, batch)?;
+
+            let entries: Vec<(&str, &str)> = batch.iter()
+                .map(|proc_event| (proc_event.0.source(), proc_event.0.url()))
+                .collect();
+            IndexCache::mark_seen_batch(trans, &entries).context("Failed to update the index cache")
+        });
+        if let Err(e) = persisted {
+            error!("Failed to persist {}: {:#}", pluralize(batch.len(), "event"), e);
+            return false;
+        }
+
+        true
+    }
+
+    /// Inserts the events of `batch`, their rule matches and their matched strings (see `DbLoader::persist_events`)
+    fn insert_events(
+        &self,
+        trans: &mut Transaction,
+        batch: &mut [ProcessedEvent],
+        #[cfg(feature = "threat-intel")] threat_intel: &[HashMap<String, serde_json::Value>]
+    ) -> Result<()> {
+        let mut rule_matches: Vec<RuleMatch> = Vec::new();
+        for ProcessedEvent(event, matches) in batch.iter_mut() {
+            event.insert(trans).context("Failed to insert event")?;
+            let event_id = event.id().ok_or_else(|| anyhow!("Inserted event has empty ID? {:?}", event))?;
+
+            rule_matches.extend(matches.iter().map(|flat_match| {
+                RuleMatch::new(event_id, flat_match.rule_name().to_owned(), flat_match.tags().into())
+                    .with_meta(flat_match.meta())
+            }));
+        }
+
+        // `Vec::insert` shadows `Insert::insert`
+        Insert::insert(&mut rule_matches, trans).context("Failed to insert rule matches")?;
+
+        // The rule matches were built in the same order as the events' flat matches
+        let flat_matches = batch.iter().enumerate()
+            .flat_map(|(i, proc_event)| proc_event.1.iter().map(move |flat_match| (i, flat_match)));
+
+        let mut ascii_matches: Vec<AsciiMatch> = Vec::new();
+        #[allow(unused_variables)]
+        for (rule_match, (event_idx, flat_match)) in rule_matches.iter().zip(flat_matches) {
+            let match_id = rule_match.id().ok_or_else(|| anyhow!("Inserted rule match has empty ID? {:?}", rule_match))?;
+            let positions = flat_match.offsets().iter().zip(flat_match.lengths());
+            let mut rule_ascii_matches: Vec<AsciiMatch> = flat_match.data().iter()
+                .zip(positions)
+                .enumerate()
+                .map(|(i, (data, (&offset, &length)))| {
+                    let context = flat_match.contexts().get(i).cloned().flatten();
+                    #[allow(unused_mut)]
+                    let mut ascii_match = AsciiMatch::new(match_id, data.to_owned())
+                        .with_position(offset, length)
+                        .with_context(context);
+                    #[cfg(feature = "threat-intel")]
+                    if let Some(results) = threat_intel[event_idx].get(data) {
+                        ascii_match.set_threat_intel(results.clone());
+                    }
+                    ascii_match
+                })
+                .collect();
 
-            for data in flat_match.data() {
-                let mut ascii_match = AsciiMatch::new(match_id, data.to_owned());
+            if self.store_binary_matches {
+                let raw_positions = flat_match.raw_offsets().iter().zip(flat_match.raw_lengths());
+                rule_ascii_matches.extend(flat_match.raw_data().iter()
+                    .zip(raw_positions)
+                    .map(|(bytes, (&offset, &length))| {
+                        AsciiMatch::from_bytes(match_id, bytes.to_owned()).with_position(offset, length)
+                    }));
+            } else if !flat_match.raw_data().is_empty() {
+                debug!(
+                    "Discarding {} of rule {} that are not valid UTF-8 (see `processing.store_binary_matches`)",
+                    pluralize(flat_match.raw_data().len(), "match"), flat_match.rule_name()
+                );
+            }
+
+            let mut rule_ascii_matches = AsciiMatch::dedup_within_rule_match(rule_ascii_matches);
+            for ascii_match in &mut rule_ascii_matches {
+                ascii_match.truncate(self.max_match_length);
+            }
+            ascii_matches.append(&mut rule_ascii_matches);
+        }
+
+        Insert::insert(&mut ascii_matches, trans).context("Failed to insert ascii matches")?;
+
+        Ok(())
+    }
 
-                if let Err(e) = ascii_match.insert(&mut trans) {
-                    error!("Failed to insert ascii match: {}", e);
-                    return;
+    /// Looks up the IP addresses and domains in each of the matched strings of `matches` (see `enrichment`)
+    ///
+    /// # Returns
+    /// The results of the lookups (as a JSON array) by matched string. Strings without any results are left out
+    #[cfg(feature = "threat-intel")]
+    fn look_up_threat_intel(&self, matches: &[crate::entities::FlatMatch]) -> HashMap<String, serde_json::Value> {
+        let threat_intel = match &self.threat_intel {
+            Some(t) => t,
+            None => return HashMap::new()
+        };
+
+        matches.iter()
+            .flat_map(|m| m.data())
+            .filter_map(|data| {
+                let indicators = extract_indicators(data);
+                let values: Vec<String> = indicators.ips.into_iter().chain(indicators.domains).collect();
+                if values.is_empty() {
+                    return None;
+                }
+
+                let results = threat_intel.lookup(&values);
+                if results.is_empty() {
+                    None
+                } else {
+                    Some((data.to_owned(), serde_json::json!(results)))
                 }
+            })
+            .collect()
+    }
+
+    /// Moves the events of `batch` whose `(source, url)` is already in the index cache to its end, keeping the
+    /// order of the rest. When the cache can't be queried, no event is considered seen
+    ///
+    /// # Returns
+    /// The events of `batch` that were not seen yet
+    fn skip_seen<'a>(client: &mut Client, batch: &'a mut [ProcessedEvent]) -> &'a mut [ProcessedEvent] {
+        let entries: Vec<(&str, &str)> = batch.iter()
+            .map(|proc_event| (proc_event.0.source(), proc_event.0.url()))
+            .collect();
+        let seen = IndexCache::check_seen_batch(client, &entries).unwrap_or_else(|e| {
+            error!("Failed to query the index cache: {}", e);
+            HashSet::new()
+        });
+
+        let is_seen = |proc_event: &ProcessedEvent| {
+            seen.contains(&(proc_event.0.source().to_owned(), proc_event.0.url().to_owned()))
+        };
+        // A stable sort, so the unseen events keep their order
+        batch.sort_by_cached_key(|proc_event| is_seen(proc_event));
+
+        let (unseen, seen_events) = batch.split_at_mut(batch.iter().take_while(|e| !is_seen(e)).count());
+        for proc_event in seen_events.iter() {
+            debug!("Skipping already seen event {}", proc_event.0.url());
+        }
+
+        unseen
+    }
+
+    /// Drops the events that share a url with another one of the batch, keeping the one with the newest
+    /// `discovered_at`. The order of the remaining events is preserved
+    fn dedup_batch(batch: Vec<ProcessedEvent>) -> Vec<ProcessedEvent> {
+        let mut unique: Vec<ProcessedEvent> = Vec::with_capacity(batch.len());
+
+        for proc_event in batch {
+            match unique.iter().position(|kept| kept.0.is_url_duplicate_of(&proc_event.0)) {
+                Some(i) => {
+                    if proc_event.0.discovered_at() > unique[i].0.discovered_at() {
+                        unique[i] = proc_event;
+                    }
+                },
+                None => unique.push(proc_event)
+            }
+        }
+
+        unique
+    }
+
+    /// Deletes the given events, along with their rule and ascii matches, in a single transaction
+    ///
+    /// # Returns
+    /// The number of deleted events. IDs that do not exist are ignored
+    pub fn bulk_delete_events(&self, event_ids: &[i32]) -> Result<u64> {
+        let mut client = self.conn.get()?;
+        let mut trans = client.transaction()?;
+
+        trans.execute(
+            "DELETE FROM ascii_matches WHERE match_id IN
+                (SELECT id FROM rule_matches WHERE event_id = ANY($1::INT[]))",
+            &[&event_ids]
+        )?;
+        trans.execute("DELETE FROM rule_matches WHERE event_id = ANY($1::INT[])", &[&event_ids])?;
+        let deleted = trans.execute("DELETE FROM events WHERE id = ANY($1::INT[])", &[&event_ids])?;
+
+        trans.commit()?;
+
+        Ok(deleted)
+    }
+
+    /// Marks the given events as deleted (by setting their `deleted_at` column) without removing them
+    ///
+    /// # Returns
+    /// The number of events that were marked. IDs that do not exist or are already deleted are ignored
+    #[allow(dead_code)]
+    pub fn bulk_soft_delete_events(&self, event_ids: &[i32]) -> Result<u64> {
+        let stmt = "UPDATE events SET deleted_at = NOW() WHERE id = ANY($1::INT[]) AND deleted_at IS NULL";
+
+        let mut client = self.conn.get()?;
+
+        Ok(client.execute(stmt, &[&event_ids])?)
+    }
+
+    /// Deletes all but the earliest `rule_matches` row of each rule that matched the given event more than once
+    /// (e.g. because the event was processed again), along with their ascii matches, in a single transaction
+    ///
+    /// # Returns
+    /// The number of deleted rule matches
+    pub fn dedup_rule_matches_for_event(&self, event_id: i32) -> Result<u64> {
+        let mut client = self.conn.get()?;
+        let mut trans = client.transaction()?;
+
+        let deleted = Self::delete_duplicate_rule_matches(&mut trans, "WHERE event_id = $1", &[&event_id])?;
+        trans.commit()?;
+
+        Ok(deleted)
+    }
+
+    /// Deduplicates the rule matches of every event (see `DbLoader::dedup_rule_matches_for_event`) and adds a
+    /// `UNIQUE(event_id, rule_matched)` constraint to `rule_matches`, so that duplicates are refused from then on.
+    /// Does nothing but the deduplication if the constraint already exists
+    ///
+    /// # Returns
+    /// The number of deleted rule matches
+    pub fn add_unique_rule_matches_constraint(&self) -> Result<u64> {
+        let mut client = self.conn.get()?;
+        let mut trans = client.transaction()?;
+
+        let deleted = Self::migrate_unique_rule_matches(&mut trans)?;
+        trans.commit()?;
+
+        Ok(deleted)
+    }
+
+    fn migrate_unique_rule_matches(trans: &mut Transaction) -> Result<u64> {
+        let deleted = Self::delete_duplicate_rule_matches(trans, "", &[])?;
+        trans.batch_execute(UNIQUE_RULE_MATCHES_SQL)?;
+
+        Ok(deleted)
+    }
+
+    /// Deletes the `rule_matches` rows that repeat the event and rule of an earlier row, along with their
+    /// `ascii_matches`. `condition` (e.g. `WHERE event_id = $1`) narrows down the rule matches considered
+    fn delete_duplicate_rule_matches(
+        trans: &mut Transaction,
+        condition: &str,
+        params: &[&(dyn ToSql + Sync)]
+    ) -> Result<u64> {
+        let ranked = format!(
+            "WITH ranked AS (
+                SELECT id, ROW_NUMBER() OVER (PARTITION BY event_id, rule_matched ORDER BY id) AS rn
+                FROM rule_matches {}
+            )",
+            condition
+        );
+
+        trans.execute(
+            format!("{} DELETE FROM ascii_matches WHERE match_id IN (SELECT id FROM ranked WHERE rn > 1)", ranked).as_str(),
+            params
+        )?;
+        let deleted = trans.execute(
+            format!("{} DELETE FROM rule_matches WHERE id IN (SELECT id FROM ranked WHERE rn > 1)", ranked).as_str(),
+            params
+        )?;
+
+        Ok(deleted)
+    }
+
+    /// Writes every row of the `events`, `rule_matches` and `ascii_matches` tables to `path`, as JSON lines of the
+    /// form `{"table": "events", "row": {...}}`. The rows are read in a single read-only, repeatable read transaction,
+    /// so the snapshot is consistent even while events are being stored
+    #[allow(dead_code)]
+    pub fn export_snapshot(&self, path: &str) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        let mut client = self.conn.get()?;
+        let mut trans = client.build_transaction()
+            .isolation_level(IsolationLevel::RepeatableRead)
+            .read_only(true)
+            .start()?;
+
+        for table in SNAPSHOT_TABLES.iter() {
+            let stmt = format!("SELECT row_to_json(t) FROM {} t ORDER BY id", table);
+            for row in trans.query(stmt.as_str(), &[])? {
+                let entry = SnapshotEntry { table: table.to_string(), row: row.get(0) };
+                serde_json::to_writer(&mut writer, &entry)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        trans.commit()?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Inserts the rows of a snapshot written by `DbLoader::export_snapshot`, keeping their original IDs. The rows
+    /// are inserted in a single transaction, so nothing is imported if any of them fails (e.g. because its ID is
+    /// taken). The ID sequences are then advanced past the imported rows
+    ///
+    /// # Returns
+    /// The number of imported events, rule matches and ascii matches
+    ///
+    /// # Errors
+    ///
+    /// `errors::DbLoaderError::InvalidSnapshot` - When a line is not a snapshot entry, or refers to an unknown table
+    #[allow(dead_code)]
+    pub fn import_snapshot(&self, path: &str) -> Result<(u64, u64, u64)> {
+        let reader = BufReader::new(File::open(path)?);
+
+        let mut client = self.conn.get()?;
+        let mut trans = client.transaction()?;
+
+        let mut stmts = Vec::with_capacity(SNAPSHOT_TABLES.len());
+        for table in SNAPSHOT_TABLES.iter() {
+            stmts.push(trans.prepare(&format!(
+                "INSERT INTO {0} OVERRIDING SYSTEM VALUE SELECT * FROM json_populate_record(NULL::{0}, $1)",
+                table
+            ))?);
+        }
+
+        let mut counts = [0_u64; 3];
+        for (idx, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
             }
+            let invalid = |reason: String| DbLoaderError::InvalidSnapshot { line: idx + 1, reason };
+
+            let entry: SnapshotEntry = serde_json::from_str(&line).map_err(|e| invalid(e.to_string()))?;
+            let table_idx = SNAPSHOT_TABLES.iter()
+                .position(|t| *t == entry.table)
+                .ok_or_else(|| invalid(format!("unknown table '{}'", entry.table)))?;
+
+            counts[table_idx] += trans.execute(&stmts[table_idx], &[&entry.row])?;
         }
 
-        if let Err(e) = trans.commit() {
-            error!("Unable to commit transaction: {}", e);
+        for table in SNAPSHOT_TABLES.iter() {
+            let stmt = format!(
+                "SELECT setval(pg_get_serial_sequence('{0}', 'id'), (SELECT COALESCE(MAX(id), 1) FROM {0}))",
+                table
+            );
+            trans.execute(stmt.as_str(), &[])?;
         }
+        trans.commit()?;
+
+        info!(
+            "Imported {} ({} rule and {} ascii matches) from {}",
+            pluralize(counts[0] as usize, "event"), counts[1], counts[2], path
+        );
+
+        Ok((counts[0], counts[1], counts[2]))
+    }
+
+    /// Returns all events whose `key` metadata field equals `value`
+    #[allow(dead_code)]
+    pub fn get_events_by_metadata_key_value(&self, key: &str, value: &str, options: &QueryOptions) -> Result<Vec<Event>> {
+        let filter = serde_json::json!({ key: value });
+
+        self.query_events("*", "metadata @> $1", &[&filter], options)
+    }
+
+    /// Returns all events one of whose matched tags belongs to `category` (see `processing.tag_category_map`)
+    #[allow(dead_code)]
+    pub fn get_events_by_category(&self, category: &str, options: &QueryOptions) -> Result<Vec<Event>> {
+        self.query_events("*", "categories @> ARRAY[$1]", &[&category], options)
+    }
+
+    /// Returns all events that matched the rule `rule_name`. `LoadMode::Lightweight` leaves out their content,
+    /// which is much cheaper for listings of large events
+    #[allow(dead_code)]
+    pub fn get_events_by_rule(&self, rule_name: &str, mode: LoadMode, options: &QueryOptions) -> Result<Vec<Event>> {
+        let condition =
+            "EXISTS (SELECT 1 FROM rule_matches WHERE rule_matches.event_id = events.id AND rule_matched = $1)";
+
+        self.query_events(mode.to_sql_columns(), condition, &[&rule_name], options)
+    }
+
+    /// Returns all events whose extracted indicators (see `processing.extract_indicators`) include the IP address
+    /// or domain name `ip_or_domain`
+    #[allow(dead_code)]
+    pub fn get_events_by_indicator(&self, ip_or_domain: &str, options: &QueryOptions) -> Result<Vec<Event>> {
+        let ips = serde_json::json!({ "ips": [ip_or_domain] });
+        let domains = serde_json::json!({ "domains": [ip_or_domain.to_lowercase()] });
+
+        self.query_events("*", "indicators_extracted @> $1 OR indicators_extracted @> $2", &[&ips, &domains], options)
+    }
+
+    /// Returns all events with at least `min` and at most `max` lines (see `Event::line_count`)
+    #[allow(dead_code)]
+    pub fn get_events_by_line_count_range(&self, min: usize, max: usize, options: &QueryOptions) -> Result<Vec<Event>> {
+        self.query_events("*", "line_count BETWEEN $1 AND $2", &[&(min as i64), &(max as i64)], options)
+    }
+
+    /// Returns the events whose content matches `query` (English full-text search, see `plainto_tsquery`), most
+    /// relevant first unless `options` orders them otherwise. Paginate `options` to limit the number of results
+    pub fn search_events(&self, query: &str, options: &QueryOptions) -> Result<Vec<Event>> {
+        let stmt = format!(
+            "SELECT * FROM events
+            WHERE to_tsvector('english', raw_content) @@ plainto_tsquery('english', $1) {}
+            ORDER BY {} {}",
+            options.deleted_condition(),
+            options.order_clause(
+                "ts_rank(to_tsvector('english', raw_content), plainto_tsquery('english', $1)) DESC, events.id"
+            ),
+            options.limit_clause()
+        );
+
+        let mut client = self.conn.get()?;
+        let rows = client.query(stmt.as_str(), &[&query])?;
+
+        Ok(rows.into_iter().map(Event::from_row).collect())
+    }
+
+    /// Same as `DbLoader::search_events`, but each event comes along with the strings its rules matched
+    pub fn search_matches(&self, query: &str, options: &QueryOptions) -> Result<Vec<(Event, Vec<AsciiMatch>)>> {
+        let events = self.search_events(query, options)?;
+        let event_ids: Vec<i32> = events.iter().filter_map(Event::id).collect();
+
+        let stmt = "
+        SELECT ascii_matches.*, rule_matches.event_id
+        FROM ascii_matches JOIN rule_matches ON ascii_matches.match_id = rule_matches.id
+        WHERE rule_matches.event_id = ANY($1)
+        ORDER BY ascii_matches.id
+        ";
+
+        let mut client = self.conn.get()?;
+        let mut matches: HashMap<i32, Vec<AsciiMatch>> = HashMap::new();
+        for row in client.query(stmt, &[&event_ids])? {
+            matches.entry(row.get("event_id")).or_default().push(AsciiMatch::from_row(&row));
+        }
+
+        Ok(
+            events.into_iter()
+                .map(|event| {
+                    let event_matches = event.id().and_then(|id| matches.remove(&id)).unwrap_or_default();
+                    (event, event_matches)
+                })
+                .collect()
+        )
+    }
+
+    /// Returns the page of events selected by `options` (all of them if it is not paginated), most recently
+    /// discovered first unless it orders them otherwise, along with the overall number of events (soft-deleted
+    /// ones only count if they are included). Both are read in the same snapshot, so they are consistent
+    #[allow(dead_code)]
+    pub fn get_events_paginated(&self, options: &QueryOptions) -> Result<(Vec<Event>, u64)> {
+        let stmt = format!(
+            "SELECT * FROM events WHERE TRUE {} ORDER BY {} {}",
+            options.deleted_condition(),
+            options.order_clause("events.discovered_at DESC, events.id DESC"),
+            options.limit_clause()
+        );
+        let count_stmt = format!("SELECT COUNT(*) FROM events WHERE TRUE {}", options.deleted_condition());
+
+        self.paginate(&stmt, &count_stmt, Event::from_row)
+    }
+
+    /// Returns the page of rule matches selected by `options`, most recent first unless it orders them (by their
+    /// events' fields) otherwise, along with the overall number of rule matches (see `DbLoader::get_events_paginated`)
+    #[allow(dead_code)]
+    pub fn get_matches_paginated(&self, options: &QueryOptions) -> Result<(Vec<RuleMatch>, u64)> {
+        let from = "FROM rule_matches JOIN events ON events.id = rule_matches.event_id";
+        let stmt = format!(
+            "SELECT rule_matches.* {} WHERE TRUE {} ORDER BY {} {}",
+            from,
+            options.deleted_condition(),
+            options.order_clause("rule_matches.id DESC"),
+            options.limit_clause()
+        );
+        let count_stmt = format!("SELECT COUNT(*) {} WHERE TRUE {}", from, options.deleted_condition());
+
+        self.paginate(&stmt, &count_stmt, |row| RuleMatch::from_row(&row))
+    }
+
+    /// Runs `SELECT {columns} FROM events WHERE {condition}` with the filtering, ordering (by ID by default) and
+    /// pagination of `options`
+    fn query_events(
+        &self,
+        columns: &str,
+        condition: &str,
+        params: &[&(dyn ToSql + Sync)],
+        options: &QueryOptions
+    ) -> Result<Vec<Event>> {
+        let stmt = format!(
+            "SELECT {} FROM events WHERE ({}) {} ORDER BY {} {}",
+            columns, condition, options.deleted_condition(), options.order_clause("events.id"), options.limit_clause()
+        );
+
+        let mut client = self.conn.get()?;
+        let rows = client.query(stmt.as_str(), params)?;
+
+        Ok(rows.into_iter().map(Event::from_row).collect())
+    }
+
+    /// Runs a query and its count query in a single read-only, repeatable read transaction
+    fn paginate<T, F>(&self, stmt: &str, count_stmt: &str, from_row: F) -> Result<(Vec<T>, u64)>
+        where F: Fn(Row) -> T
+    {
+        let mut client = self.conn.get()?;
+        let mut trans = client.build_transaction()
+            .isolation_level(IsolationLevel::RepeatableRead)
+            .read_only(true)
+            .start()?;
+
+        let rows = trans.query(stmt, &[])?;
+        let total: i64 = trans.query_one(count_stmt, &[])?.get(0);
+        trans.commit()?;
+
+        Ok((rows.into_iter().map(from_row).collect(), total as u64))
+    }
+
+    /// Counts how many times `rule_name` matched, grouped in buckets of `bucket_size_hours` hours
+    /// according to the `discovered_at` time of the matching events. Of `options`, only `include_deleted` applies
+    ///
+    /// # Arguments
+    ///
+    /// * `rule_name` - The full name of the rule (`namespace::identifier`)
+    /// * `bucket_size_hours` - The width of each bucket. Buckets are aligned to the unix epoch
+    /// * `options` - Whether the matches of soft-deleted events are counted
+    ///
+    /// # Returns
+    /// A vector of `(bucket_start, count)` tuples, ordered chronologically. Empty buckets are omitted
+    #[allow(dead_code)]
+    pub fn get_match_timeline(
+        &self,
+        rule_name: &str,
+        bucket_size_hours: u32,
+        options: &QueryOptions
+    ) -> Result<Vec<(DateTime<Local>, u64)>> {
+        if bucket_size_hours == 0 {
+            return Err(DbLoaderError::ZeroBucketSize.into());
+        }
+
+        let stmt = format!(
+            "SELECT
+                TO_TIMESTAMP(FLOOR(EXTRACT(EPOCH FROM events.discovered_at)::FLOAT8 / $2::FLOAT8) * $2::FLOAT8) AS bucket,
+                COUNT(*) AS num_matches
+            FROM rule_matches
+            JOIN events ON events.id = rule_matches.event_id
+            WHERE rule_matches.rule_matched = $1 {}
+            GROUP BY bucket
+            ORDER BY bucket",
+            options.deleted_condition()
+        );
+        let bucket_secs = f64::from(bucket_size_hours) * 3600.0;
+
+        let mut client = self.conn.get()?;
+        let rows = client.query(stmt.as_str(), &[&rule_name, &bucket_secs])?;
+
+        Ok(rows.iter()
+            .map(|row| (row.get("bucket"), row.get::<&str, i64>("num_matches") as u64))
+            .collect())
+    }
+
+    /// Returns the `limit` sources whose events have matched the most rules, along with the number of matches
+    /// each has produced, in descending order. Of `options`, only `include_deleted` applies
+    #[allow(dead_code)]
+    pub fn get_top_sources_by_match_count(&self, limit: i64, options: &QueryOptions) -> Result<Vec<(String, u64)>> {
+        let stmt = format!(
+            "SELECT events.source, COUNT(*) AS num_matches
+            FROM rule_matches
+            JOIN events ON events.id = rule_matches.event_id
+            WHERE TRUE {}
+            GROUP BY events.source
+            ORDER BY num_matches DESC, events.source
+            LIMIT $1",
+            options.deleted_condition()
+        );
+
+        let mut client = self.conn.get()?;
+        let rows = client.query(stmt.as_str(), &[&limit])?;
+
+        Ok(rows.iter()
+            .map(|row| (row.get("source"), row.get::<&str, i64>("num_matches") as u64))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! The tests in this module need a running postgres instance (see `docker-compose.yml`) and are
+    //! ignored by default. Run them with `cargo test -- --ignored`
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use crate::database::Client;
+    use crate::entities::FlatMatch;
+
+    fn loader() -> DbLoader {
+        let conn = DbConnection::connect("postgres", "infobserve", "infobserve", "localhost", 5432).unwrap();
+        let loader = DbLoader::with_connection(conn);
+        loader.run_migrations().unwrap();
+
+        loader
+    }
+
+    /// A name that no other test (or previous run of the same test) will use
+    fn unique(prefix: &str) -> String {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+        format!("{}-{}", prefix, nanos)
+    }
+
+    fn datetime(rfc3339: &str) -> DateTime<Local> {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().with_timezone(&Local)
+    }
+
+    fn insert_event(loader: &DbLoader) -> i32 {
+        let mut client = loader.conn.get().unwrap();
+        let mut trans = client.transaction().unwrap();
+
+        let mut event = Event::new(
+            "https://pastebin.com/foo", 3, "pastebin", "foo", "foo.txt", "bar", Local::now(), Local::now()
+        );
+        event.insert(&mut trans).unwrap();
+        let mut rule_match = RuleMatch::new(event.id().unwrap(), "default::MyPass".to_owned(), vec![]);
+        rule_match.insert(&mut trans).unwrap();
+        AsciiMatch::new(rule_match.id().unwrap(), "foo".to_owned()).insert(&mut trans).unwrap();
+
+        trans.commit().unwrap();
+
+        event.id().unwrap()
+    }
+
+    /// The lines of the snapshot at `path` that hold the rows of `event_id`, its rule matches and their ascii matches
+    fn snapshot_lines_of(path: &str, event_id: i32) -> Vec<String> {
+        let mut match_ids = HashSet::new();
+        std::fs::read_to_string(path).unwrap()
+            .lines()
+            .filter(|line| {
+                let entry: SnapshotEntry = serde_json::from_str(line).unwrap();
+                let (id, parent) = match entry.table.as_str() {
+                    "events" => (entry.row["id"].as_i64(), entry.row["id"].as_i64()),
+                    "rule_matches" => (entry.row["id"].as_i64(), entry.row["event_id"].as_i64()),
+                    _ => (None, entry.row["match_id"].as_i64())
+                };
+                match entry.table.as_str() {
+                    "rule_matches" if parent == Some(event_id as i64) => match_ids.insert(id.unwrap()),
+                    "ascii_matches" => parent.is_some_and(|p| match_ids.contains(&p)),
+                    _ => parent == Some(event_id as i64)
+                }
+            })
+            .map(String::from)
+            .collect()
+    }
+
+    fn snapshot_path(test: &str) -> String {
+        std::env::temp_dir().join(format!("{}.jsonl", unique(test))).to_string_lossy().into_owned()
+    }
+
+    fn count_existing(loader: &DbLoader, event_ids: &[i32], extra_condition: &str) -> i64 {
+        let mut client = loader.conn.get().unwrap();
+        let stmt = format!("SELECT COUNT(*) FROM events WHERE id = ANY($1::INT[]) {}", extra_condition);
+
+        client.query_one(stmt.as_str(), &[&event_ids]).unwrap().get(0)
+    }
+
+    fn insert_match(loader: &DbLoader, source: &str, rule_name: &str, discovered_at: DateTime<Local>) {
+        let mut client = loader.conn.get().unwrap();
+        let mut trans = client.transaction().unwrap();
+
+        let mut event = Event::new(
+            "https://pastebin.com/foo", 3, source, "foo", "foo.txt", "bar", discovered_at, discovered_at
+        );
+        event.insert(&mut trans).unwrap();
+        RuleMatch::new(event.id().unwrap(), rule_name.to_owned(), vec![]).insert(&mut trans).unwrap();
+
+        trans.commit().unwrap();
+    }
+
+    fn proc_event(url: &str, discovered_at: &str) -> ProcessedEvent {
+        let discovered_at = datetime(discovered_at);
+
+        ProcessedEvent(Event::new(url, 3, "pastebin", "foo", "foo.txt", "bar", discovered_at, discovered_at), vec![])
+    }
+
+    #[test]
+    fn batches_stop_at_the_batch_size() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        for i in 0..5 {
+            tx.send(proc_event(&format!("https://pastebin.com/{}", i), "2021-01-01T10:00:00+00:00")).unwrap();
+        }
+
+        assert_eq!(next_batch(&rx, 3, Duration::from_secs(60)).len(), 3);
+        assert_eq!(next_batch(&rx, 3, Duration::from_millis(10)).len(), 2);
+    }
+
+    #[test]
+    fn batches_are_empty_once_the_senders_are_gone() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx.send(proc_event("https://pastebin.com/1", "2021-01-01T10:00:00+00:00")).unwrap();
+        drop(tx);
+
+        assert_eq!(next_batch(&rx, 100, Duration::from_secs(60)).len(), 1);
+        assert!(next_batch(&rx, 100, Duration::from_secs(60)).is_empty());
+    }
+
+    #[test]
+    fn batch_dedup_keeps_the_newest_event_per_url() {
+        let batch = vec![
+            proc_event("https://pastebin.com/1", "2021-01-01T10:00:00+00:00"),
+            proc_event("https://pastebin.com/2", "2021-01-01T10:00:00+00:00"),
+            proc_event("https://pastebin.com/1", "2021-01-01T12:00:00+00:00"),
+            proc_event("https://pastebin.com/1", "2021-01-01T11:00:00+00:00")
+        ];
+
+        let unique = DbLoader::dedup_batch(batch);
+        let kept: Vec<(&str, DateTime<Local>)> = unique.iter().map(|e| (e.0.url(), *e.0.discovered_at())).collect();
+
+        assert_eq!(kept, vec![
+            ("https://pastebin.com/1", datetime("2021-01-01T12:00:00+00:00")),
+            ("https://pastebin.com/2", datetime("2021-01-01T10:00:00+00:00"))
+        ]);
+    }
+
+    /// A loader whose pool can't connect, since nothing listens on port 1
+    fn unreachable_loader() -> DbLoader {
+        DbLoader::with_connection(DbConnection::lazy(1, 1, Duration::from_millis(20)))
+            .with_retries(2, Duration::from_millis(10))
+    }
+
+    #[test]
+    fn batches_are_dead_lettered_without_a_connection() {
+        let (dead_letter_sx, dead_letter_rx) = crossbeam_channel::unbounded();
+        let loader = unreachable_loader().with_dead_letter(dead_letter_sx);
+
+        let stats = loader.load_batch(vec![
+            proc_event("https://pastebin.com/1", "2021-01-01T10:00:00+00:00"),
+            proc_event("https://pastebin.com/2", "2021-01-01T10:00:00+00:00")
+        ]);
+        assert_eq!((stats.num_persisted(), stats.num_failed(), stats.num_retried()), (0, 2, 0));
+        assert!(!loader.persist_processed_event(proc_event("https://pastebin.com/3", "2021-01-01T10:00:00+00:00")));
+
+        let urls: Vec<String> = dead_letter_rx.try_iter().map(|e| e.0.url().to_owned()).collect();
+        assert_eq!(urls, ["https://pastebin.com/1", "https://pastebin.com/2", "https://pastebin.com/3"]);
+    }
+
+    #[test]
+    fn batches_are_discarded_without_a_connection_or_dead_letter_channel() {
+        let loader = unreachable_loader();
+
+        let mut batch = [proc_event("https://pastebin.com/1", "2021-01-01T10:00:00+00:00")];
+        assert!(loader.persist_events(&mut batch).is_err());
+        assert_eq!(
+            loader.load_batch(vec![proc_event("https://pastebin.com/2", "2021-01-01T10:00:00+00:00")]).num_failed(),
+            1
+        );
+    }
+
+    #[test]
+    fn the_dry_run_sink_counts_events_and_matches() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let handle = start_dry_run_sink(&rx);
+        let flat_match = || FlatMatch::new("default::Password".to_owned(), Vec::new(), &[b"foo".to_vec()]);
+
+        let ProcessedEvent(event, _) = proc_event("https://pastebin.com/1", "2021-01-01T10:00:00+00:00");
+        tx.send(ProcessedEvent(event, vec![flat_match(), flat_match()])).unwrap();
+        let ProcessedEvent(event, _) = proc_event("https://pastebin.com/2", "2021-01-01T10:00:00+00:00");
+        tx.send(ProcessedEvent(event, vec![flat_match()])).unwrap();
+        drop(tx);
+
+        let stats = handle.join().unwrap();
+        assert_eq!((stats.num_events(), stats.num_matches()), (2, 3));
+        assert_eq!(stats.to_string(), "Matching events: 2, rule matches: 3");
+    }
+
+    fn loader_stats(num_persisted: u32, num_failed: u32, num_retried: u32, persist_millis: u64) -> LoaderStats {
+        LoaderStats {
+            num_persisted,
+            num_failed,
+            num_retried,
+            overall_persist_time: Duration::from_millis(persist_millis)
+        }
+    }
+
+    #[test]
+    fn loader_stats_are_merged() {
+        let merged = loader_stats(3, 1, 2, 40).merge(loader_stats(5, 0, 0, 20)).merge(LoaderStats::default());
+
+        assert_eq!(merged, loader_stats(8, 1, 2, 60));
+    }
+
+    #[test]
+    fn avg_persist_time_counts_failed_events() {
+        assert_eq!(loader_stats(3, 1, 0, 40).avg_persist_time(), Duration::from_millis(10));
+        assert_eq!(LoaderStats::default().avg_persist_time(), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn loader_stats_are_displayed_on_one_line() {
+        assert_eq!(
+            loader_stats(3, 1, 2, 40).to_string(),
+            "Persisted events: 3, failed events: 1, retried events: 2, overall time spent persisting: 40ms, \
+             average time spent on each event: 10000us"
+        );
+    }
+
+    #[test]
+    fn dead_letter_events_can_be_replayed() {
+        let path = snapshot_path("dead-letters");
+        let (dead_letter_sx, dead_letter_rx) = crossbeam_channel::unbounded();
+        let handle = start_dead_letter_logger(dead_letter_rx, &path).unwrap();
+
+        dead_letter_sx.send(proc_event("https://pastebin.com/1", "2021-01-01T10:00:00+00:00")).unwrap();
+        dead_letter_sx.send(proc_event("https://pastebin.com/2", "2021-01-01T10:00:00+00:00")).unwrap();
+        drop(dead_letter_sx);
+        handle.join().unwrap();
+
+        let urls: Vec<String> = std::fs::read_to_string(&path).unwrap()
+            .lines()
+            .map(|line| Event::from_json_str(line).unwrap().url().to_owned())
+            .collect();
+        assert_eq!(urls, ["https://pastebin.com/1", "https://pastebin.com/2"]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn schema_sql_is_embedded() {
+        assert!(!DbLoader::schema_sql().trim().is_empty());
+        assert!(DbLoader::schema_sql().contains("CREATE TABLE IF NOT EXISTS events"));
+    }
+
+    #[test]
+    fn expected_schema_contains_all_tables() {
+        let mut tables: Vec<&str> = DbLoader::expected_schema().into_keys().collect();
+        tables.sort_unstable();
+
+        assert_eq!(tables, vec!["ascii_matches", "events", "index_cache", "rule_matches"]);
+    }
+
+    #[test]
+    fn expected_schema_contains_the_inserted_columns() {
+        let schema = DbLoader::expected_schema();
+
+        for column in &["source", "url", "size", "raw_content", "filename", "creator", "created_at", "discovered_at"] {
+            assert!(schema["events"].contains(column), "events.{} is missing", column);
+        }
+        assert_eq!(schema["rule_matches"], vec!["id", "event_id", "rule_matched", "tags_matched", "meta"]);
+        assert_eq!(
+            schema["ascii_matches"],
+            vec![
+                "id", "match_id", "matched_string", "threat_intel", "byte_offset", "byte_length", "raw_bytes", "context"
+            ]
+        );
+    }
+
+    #[test]
+    fn every_table_has_an_id() {
+        assert!(DbLoader::expected_schema().values().all(|columns| columns.contains(&"id")));
+    }
+
+    #[test]
+    #[ignore]
+    fn created_schema_is_healthy() {
+        assert!(loader().schema_health_check().unwrap().is_empty());
+    }
+
+    #[test]
+    #[ignore]
+    fn inserting_a_vec_inserts_every_element() {
+        let loader = loader();
+        let event_id = insert_event(&loader);
+        let mut client = loader.conn.get().unwrap();
+        let mut trans = client.transaction().unwrap();
+
+        let mut rule_matches = vec![
+            RuleMatch::new(event_id, "default::Foo".to_owned(), vec![]),
+            RuleMatch::new(event_id, "default::Bar".to_owned(), vec![])
+        ];
+        Insert::insert(&mut rule_matches, &mut trans).unwrap();
+        trans.commit().unwrap();
+
+        assert!(rule_matches.iter().all(|m| m.id().is_some()));
+    }
+
+    #[test]
+    #[ignore]
+    fn inserting_a_vec_propagates_the_first_error() {
+        let loader = loader();
+        let event_id = insert_event(&loader);
+        let mut client = loader.conn.get().unwrap();
+        let rule_match_id: i32 = client
+            .query_one("SELECT id FROM rule_matches WHERE event_id = $1", &[&event_id]).unwrap()
+            .get(0);
+        let valid = unique("valid");
+
+        let mut trans = client.transaction().unwrap();
+        // The second element references a rule match that does not exist
+        let mut ascii_matches = vec![
+            AsciiMatch::new(rule_match_id, valid.clone()),
+            AsciiMatch::new(-1, "invalid".to_owned()),
+            AsciiMatch::new(rule_match_id, unique("never-inserted"))
+        ];
+
+        assert!(Insert::insert(&mut ascii_matches, &mut trans).is_err());
+        assert!(ascii_matches[2].id().is_none());
+        drop(trans);
+
+        let row = client.query_one("SELECT COUNT(*) FROM ascii_matches WHERE matched_string = $1", &[&valid]).unwrap();
+        assert_eq!(row.get::<_, i64>(0), 0);
+    }
+
+    #[test]
+    #[ignore]
+    fn secrets_are_stripped_before_storage_when_enabled() {
+        let loader = loader().with_secret_stripping(true);
+        let url = unique("https://pastebin.com/");
+        let event = Event::new(&url, 17, "pastebin", "password: hunter2", "foo.txt", "bar", Local::now(), Local::now());
+
+        loader.persist_processed_event(ProcessedEvent(event, vec![]));
+
+        let mut client = loader.conn.get().unwrap();
+        let row = client.query_one("SELECT raw_content FROM events WHERE url = $1", &[&url]).unwrap();
+        assert_eq!(row.get::<_, String>(0), "password=[REDACTED]");
+    }
+
+    #[test]
+    #[ignore]
+    fn events_are_found_by_category() {
+        let loader = loader();
+        let category = unique("HIGH_RISK_");
+        let mut event = Event::new(&unique("https://pastebin.com/"), 3, "pastebin", "foo", "foo.txt", "bar", Local::now(), Local::now());
+        event.set_categories(vec!["COMPLIANCE".to_owned(), category.clone()]);
+        let flat_match = FlatMatch::new("default::Password".to_owned(), vec!["credentials".to_owned()], &[b"foo".to_vec()]);
+
+        loader.persist_processed_event(ProcessedEvent(event, vec![flat_match]));
+
+        let found = loader.get_events_by_category(&category, &QueryOptions::default()).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].categories(), ["COMPLIANCE".to_owned(), category]);
+        assert!(loader.get_events_by_category(&unique("NONE_"), &QueryOptions::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    #[ignore]
+    fn duplicate_strings_are_persisted_once() {
+        let loader = loader();
+        let url = unique("https://pastebin.com/");
+        let event = Event::new(&url, 9, "pastebin", "foofoofoo", "foo.txt", "bar", Local::now(), Local::now());
+        let flat_match = FlatMatch::new(
+            "default::Foo".to_owned(),
+            vec!["foo".to_owned()],
+            &[b"foo".to_vec(), b"foo".to_vec(), b"foo".to_vec()]
+        );
+
+        loader.persist_processed_event(ProcessedEvent(event, vec![flat_match]));
+
+        let mut client = loader.conn.get().unwrap();
+        let row = client.query_one(
+            "SELECT COUNT(*) FROM ascii_matches a
+             JOIN rule_matches r ON a.match_id = r.id
+             JOIN events e ON r.event_id = e.id
+             WHERE e.url = $1",
+            &[&url]
+        ).unwrap();
+
+        assert_eq!(row.get::<_, i64>(0), 1);
+    }
+
+    #[test]
+    #[ignore]
+    fn match_positions_and_contexts_are_persisted() {
+        let loader = loader();
+        let url = unique("https://pastebin.com/");
+        let content = "user: admin\npassword: hunter2";
+        let event = Event::new(&url, 29, "pastebin", content, "foo.txt", "bar", Local::now(), Local::now());
+        let mut flat_match = FlatMatch::from_yara_matches(
+            "default::Password".to_owned(),
+            vec![],
+            vec![yara::Match { base: 0, offset: 12, length: 17, data: b"password: hunter2".to_vec() }]
+        );
+        flat_match.extract_contexts(content, 6);
+
+        loader.persist_processed_event(ProcessedEvent(event, vec![flat_match]));
+
+        let mut client = loader.conn.get().unwrap();
+        let row = client.query_one(
+            "SELECT a.* FROM ascii_matches a
+             JOIN rule_matches r ON a.match_id = r.id
+             JOIN events e ON r.event_id = e.id
+             WHERE e.url = $1",
+            &[&url]
+        ).unwrap();
+        let ascii_match = AsciiMatch::from_row(&row);
+
+        assert_eq!((ascii_match.offset(), ascii_match.length()), (12, 17));
+        assert_eq!(ascii_match.slice_from_content(content), Some("password: hunter2"));
+        assert_eq!(ascii_match.context(), Some("admin\npassword: hunter2"));
+    }
+
+    #[test]
+    #[ignore]
+    fn rule_meta_fields_are_persisted() {
+        let loader = loader();
+        let url = unique("https://pastebin.com/");
+        let event = Event::new(&url, 7, "pastebin", "pw: foo", "foo.txt", "bar", Local::now(), Local::now());
+        let meta = vec![("author".to_owned(), "tester".to_owned())].into_iter().collect();
+        let flat_match = FlatMatch::from_yara_matches(
+            "default::Password".to_owned(),
+            vec![],
+            vec![yara::Match { base: 0, offset: 0, length: 7, data: b"pw: foo".to_vec() }]
+        ).with_meta(meta);
+
+        loader.persist_processed_event(ProcessedEvent(event, vec![flat_match]));
+
+        let mut client = loader.conn.get().unwrap();
+        let row = client.query_one(
+            "SELECT r.* FROM rule_matches r JOIN events e ON r.event_id = e.id WHERE e.url = $1",
+            &[&url]
+        ).unwrap();
+
+        assert_eq!(RuleMatch::from_row(&row).meta(), Some(&serde_json::json!({ "author": "tester" })));
+    }
+
+    #[test]
+    #[ignore]
+    fn binary_matches_are_persisted_when_enabled() {
+        let persisted_bytes = |loader: DbLoader| {
+            let url = unique("https://pastebin.com/");
+            let event = Event::new(&url, 6, "pastebin", "MZ", "foo.exe", "bar", Local::now(), Local::now());
+            let flat_match = FlatMatch::from_yara_matches(
+                "default::Executable".to_owned(),
+                vec![],
+                vec![yara::Match { base: 0, offset: 2, length: 4, data: vec![b'M', b'Z', 0x90, 0x00] }]
+            );
+            loader.persist_processed_event(ProcessedEvent(event, vec![flat_match]));
+
+            let mut client = loader.conn.get().unwrap();
+            client.query(
+                "SELECT a.* FROM ascii_matches a
+                 JOIN rule_matches r ON a.match_id = r.id
+                 JOIN events e ON r.event_id = e.id
+                 WHERE e.url = $1",
+                &[&url]
+            ).unwrap()
+                .iter()
+                .map(|row| {
+                    let ascii_match = AsciiMatch::from_row(row);
+                    (ascii_match.raw_bytes().map(<[u8]>::to_vec), ascii_match.offset())
+                })
+                .collect::<Vec<(Option<Vec<u8>>, usize)>>()
+        };
+
+        assert_eq!(persisted_bytes(loader().with_binary_matches(true)), vec![(Some(vec![b'M', b'Z', 0x90, 0x00]), 2)]);
+        assert!(persisted_bytes(loader()).is_empty());
+    }
+
+    #[test]
+    #[ignore]
+    fn bulk_delete_removes_only_the_given_events() {
+        let loader = loader();
+        let event_ids: Vec<i32> = (0..10).map(|_| insert_event(&loader)).collect();
+
+        let deleted = loader.bulk_delete_events(&event_ids[..5]).unwrap();
+
+        assert_eq!(deleted, 5);
+        assert_eq!(count_existing(&loader, &event_ids, ""), 5);
+        assert_eq!(count_existing(&loader, &event_ids[5..], ""), 5);
+    }
+
+    #[test]
+    #[ignore]
+    fn bulk_delete_ignores_missing_events() {
+        let loader = loader();
+        let event_id = insert_event(&loader);
+
+        assert_eq!(loader.bulk_delete_events(&[event_id, -1]).unwrap(), 1);
+        assert_eq!(loader.bulk_delete_events(&[event_id]).unwrap(), 0);
+    }
+
+    #[test]
+    #[ignore]
+    fn bulk_soft_delete_keeps_the_events() {
+        let loader = loader();
+        let event_ids: Vec<i32> = (0..10).map(|_| insert_event(&loader)).collect();
+
+        assert_eq!(loader.bulk_soft_delete_events(&event_ids[..5]).unwrap(), 5);
+        assert_eq!(loader.bulk_soft_delete_events(&event_ids[..5]).unwrap(), 0);
+        assert_eq!(count_existing(&loader, &event_ids, ""), 10);
+        assert_eq!(count_existing(&loader, &event_ids, "AND deleted_at IS NULL"), 5);
+    }
+
+    /// Adds `rule_name` to the matches of `event_id` once more, along with an ascii match
+    fn insert_rule_match(trans: &mut Transaction, event_id: i32, rule_name: &str) -> i32 {
+        let mut rule_match = RuleMatch::new(event_id, rule_name.to_owned(), vec![]);
+        rule_match.insert(trans).unwrap();
+        AsciiMatch::new(rule_match.id().unwrap(), "foo".to_owned()).insert(trans).unwrap();
+
+        rule_match.id().unwrap()
+    }
+
+    fn rule_match_ids(client: &mut Client, event_id: i32) -> Vec<i32> {
+        client.query("SELECT id FROM rule_matches WHERE event_id = $1 ORDER BY id", &[&event_id]).unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect()
+    }
+
+    #[test]
+    #[ignore]
+    fn dedup_keeps_the_earliest_match_of_each_rule() {
+        let loader = loader();
+        let event_id = insert_event(&loader);
+        let other_event_id = insert_event(&loader);
+        let mut client = loader.conn.get().unwrap();
+        let first_id = rule_match_ids(&mut client, event_id)[0];
+
+        let mut trans = client.transaction().unwrap();
+        insert_rule_match(&mut trans, event_id, "default::MyPass");
+        insert_rule_match(&mut trans, event_id, "default::MyPass");
+        let other_rule_id = insert_rule_match(&mut trans, event_id, "default::ApiKey");
+        insert_rule_match(&mut trans, other_event_id, "default::MyPass");
+        trans.commit().unwrap();
+
+        assert_eq!(loader.dedup_rule_matches_for_event(event_id).unwrap(), 2);
+        assert_eq!(rule_match_ids(&mut client, event_id), vec![first_id, other_rule_id]);
+        assert_eq!(rule_match_ids(&mut client, other_event_id).len(), 2);
+        assert_eq!(loader.dedup_rule_matches_for_event(event_id).unwrap(), 0);
+
+        let orphans: i64 = client.query_one(
+            "SELECT COUNT(*) FROM ascii_matches WHERE match_id IS NOT NULL AND match_id NOT IN (SELECT id FROM rule_matches)",
+            &[]
+        ).unwrap().get(0);
+        assert_eq!(orphans, 0);
+    }
+
+    #[test]
+    #[ignore]
+    fn unique_constraint_migration_refuses_duplicates() {
+        let loader = loader();
+        let event_id = insert_event(&loader);
+        let mut client = loader.conn.get().unwrap();
+        // Rolled back at the end, so that the other tests may still insert duplicates
+        let mut trans = client.transaction().unwrap();
+        insert_rule_match(&mut trans, event_id, "default::MyPass");
+
+        assert!(DbLoader::migrate_unique_rule_matches(&mut trans).unwrap() >= 1);
+        assert_eq!(DbLoader::migrate_unique_rule_matches(&mut trans).unwrap(), 0);
+
+        let mut duplicate = RuleMatch::new(event_id, "default::MyPass".to_owned(), vec![]);
+        assert!(duplicate.insert(&mut trans).is_err());
+    }
+
+    #[test]
+    #[ignore]
+    fn events_are_found_by_metadata() {
+        let loader = loader();
+        let language = unique("lang");
+        let json = format!(
+            r#"{{"url": "https://gist.github.com/foo", "size": 3, "source": "gist", "raw_content": "foo",
+                "filename": "foo.txt", "creator": "bar", "created_at": "2021/01/01-10:00:00",
+                "discovered_at": "2021/01/01-10:00:00", "language": "{}", "stars": 5}}"#,
+            language
+        );
+
+        let mut client = loader.conn.get().unwrap();
+        let mut trans = client.transaction().unwrap();
+        let mut event = Event::from_json_str(&json).unwrap();
+        event.insert(&mut trans).unwrap();
+        trans.commit().unwrap();
+
+        let found = loader.get_events_by_metadata_key_value("language", &language, &QueryOptions::default()).unwrap();
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id(), event.id());
+        assert_eq!(found[0].get_metadata::<u32>("stars"), Some(5));
+        assert!(loader.get_events_by_metadata_key_value("language", &unique("lang"), &QueryOptions::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    #[ignore]
+    fn events_are_paginated() {
+        let loader = loader();
+        let mut client = loader.conn.get().unwrap();
+        // Events discovered in the far future are always the first ones when ordering by `discovered_at`
+        client.execute("DELETE FROM events WHERE discovered_at >= '2999-01-01'", &[]).unwrap();
+        let mut ids = Vec::new();
+        for day in 1..=5 {
+            let discovered_at = datetime(&format!("2999-01-0{}T00:00:00+00:00", day));
+            let mut trans = client.transaction().unwrap();
+            let mut event = Event::new(&unique("https://pastebin.com/"), 3, "pastebin", "foo", "foo.txt", "bar", discovered_at, discovered_at);
+            event.insert(&mut trans).unwrap();
+            trans.commit().unwrap();
+            ids.push(event.id().unwrap());
+        }
+        ids.reverse();
+
+        let pages: Vec<(Vec<Event>, u64)> = (0..3)
+            .map(|page| loader.get_events_paginated(&QueryOptions::default().paginated(page, 2)).unwrap())
+            .collect();
+        loader.bulk_delete_events(&ids).unwrap();
+
+        let page_ids = |page: &[Event]| page.iter().map(|e| e.id().unwrap()).collect::<Vec<i32>>();
+        assert_eq!(page_ids(&pages[0].0), ids[0..2]);
+        assert_eq!(page_ids(&pages[1].0), ids[2..4]);
+        assert_eq!(pages[2].0[0].id(), Some(ids[4]));
+        assert!(pages.iter().all(|(_, total)| *total >= 5));
+    }
+
+    #[test]
+    #[ignore]
+    fn matches_are_paginated_without_overlaps() {
+        let loader = loader();
+        for _ in 0..2 {
+            insert_event(&loader);
+        }
+
+        let (first, total) = loader.get_matches_paginated(&QueryOptions::default().paginated(0, 1)).unwrap();
+        let (second, _) = loader.get_matches_paginated(&QueryOptions::default().paginated(1, 1)).unwrap();
+
+        assert!(total >= 2);
+        assert_eq!(first.len(), 1);
+        assert_eq!(second.len(), 1);
+        assert!(first[0].id() > second[0].id());
+    }
+
+    #[test]
+    #[ignore]
+    fn pages_past_the_end_are_empty() {
+        let loader = loader();
+        insert_event(&loader);
+
+        let (events, total) = loader.get_events_paginated(&QueryOptions::default().paginated(u32::MAX, 1000).ordered_by(EventOrderField::Size))
+            .unwrap();
+
+        assert!(events.is_empty());
+        assert!(total >= 1);
+    }
+
+    /// Inserts `count` events of `category`, the first `num_deleted` of which are soft-deleted, and returns their IDs
+    fn insert_soft_deleted_events(loader: &DbLoader, category: &str, count: usize, num_deleted: usize) -> Vec<i32> {
+        let mut client = loader.conn.get().unwrap();
+        let mut trans = client.transaction().unwrap();
+        let mut ids = Vec::new();
+        for _ in 0..count {
+            let mut event = Event::new(
+                "https://pastebin.com/foo", 3, "pastebin", "foo", "foo.txt", "bar", Local::now(), Local::now()
+            );
+            event.set_categories(vec![category.to_owned()]);
+            event.insert(&mut trans).unwrap();
+            ids.push(event.id().unwrap());
+        }
+        trans.commit().unwrap();
+        loader.bulk_soft_delete_events(&ids[..num_deleted]).unwrap();
+
+        ids
+    }
+
+    #[test]
+    #[ignore]
+    fn soft_deleted_events_are_hidden_by_default() {
+        let loader = loader();
+        let category = unique("DELETED_");
+        let ids = insert_soft_deleted_events(&loader, &category, 3, 1);
+
+        let found = loader.get_events_by_category(&category, &QueryOptions::default()).unwrap();
+
+        assert_eq!(found.iter().filter_map(Event::id).collect::<Vec<i32>>(), ids[1..]);
+    }
+
+    #[test]
+    #[ignore]
+    fn soft_deleted_events_are_returned_when_included() {
+        let loader = loader();
+        let category = unique("DELETED_");
+        let ids = insert_soft_deleted_events(&loader, &category, 3, 1);
+
+        let found = loader.get_events_by_category(&category, &QueryOptions::default().with_deleted()).unwrap();
+
+        assert_eq!(found.iter().filter_map(Event::id).collect::<Vec<i32>>(), ids);
+    }
+
+    #[test]
+    #[ignore]
+    fn paginated_totals_count_soft_deleted_events_only_when_included() {
+        let loader = loader();
+        let category = unique("DELETED_");
+        let ids = insert_soft_deleted_events(&loader, &category, 5, 2);
+        let (_, total) = loader.get_events_paginated(&QueryOptions::default().paginated(0, 1)).unwrap();
+        let (_, total_with_deleted) = loader.get_events_paginated(&QueryOptions::default().with_deleted().paginated(0, 1))
+            .unwrap();
+
+        let page = |options: QueryOptions| -> Vec<i32> {
+            loader.get_events_by_category(&category, &options).unwrap().iter().filter_map(Event::id).collect()
+        };
+
+        // Other tests insert and delete events concurrently, so the totals can only be bounded
+        assert!(total >= 3);
+        assert!(total_with_deleted >= 5);
+        assert_eq!(page(QueryOptions::default().paginated(0, 2)), ids[2..4]);
+        assert_eq!(page(QueryOptions::default().paginated(1, 2)), ids[4..]);
+        assert_eq!(page(QueryOptions::default().with_deleted().paginated(0, 2)), ids[0..2]);
+        assert_eq!(page(QueryOptions::default().with_deleted().paginated(2, 2)), ids[4..]);
+        assert!(page(QueryOptions::default().paginated(2, 2)).is_empty());
+    }
+
+    #[test]
+    fn default_query_options_hide_deleted_events_without_pagination() {
+        let options = QueryOptions::default();
+
+        assert_eq!(options.deleted_condition(), "AND events.deleted_at IS NULL");
+        assert_eq!(options.limit_clause(), "");
+        assert_eq!(options.order_clause("events.id"), "events.id");
+        assert_eq!(options.with_deleted().deleted_condition(), "");
+    }
+
+    #[test]
+    fn query_options_paginate_and_order() {
+        let options = QueryOptions::default().paginated(3, 20).ordered_by(EventOrderField::Size);
+
+        assert_eq!(options.limit_clause(), "LIMIT 20 OFFSET 60");
+        assert_eq!(options.order_clause("events.id"), "events.size DESC, events.id DESC");
+        assert_eq!(QueryOptions::default().paginated(u32::MAX, u32::MAX).limit_clause(),
+                   format!("LIMIT {} OFFSET {}", u32::MAX, u64::from(u32::MAX) * u64::from(u32::MAX)));
+    }
+
+    #[test]
+    fn order_fields_map_to_columns() {
+        assert_eq!(EventOrderField::DiscoveredAt.to_sql_column(), "discovered_at");
+        assert_eq!(EventOrderField::Size.to_sql_column(), "size");
+        assert_eq!(EventOrderField::Source.to_sql_column(), "source");
+    }
+
+    #[test]
+    #[ignore]
+    fn match_timeline_groups_matches_in_buckets() {
+        let loader = loader();
+        let rule_name = unique("default::Timeline");
+
+        insert_match(&loader, "pastebin", &rule_name, datetime("2021-01-01T00:10:00+00:00"));
+        insert_match(&loader, "pastebin", &rule_name, datetime("2021-01-01T01:50:00+00:00"));
+        insert_match(&loader, "pastebin", &rule_name, datetime("2021-01-01T02:00:00+00:00"));
+        insert_match(&loader, "pastebin", &rule_name, datetime("2021-01-01T05:59:59+00:00"));
+
+        let timeline = loader.get_match_timeline(&rule_name, 2, &QueryOptions::default()).unwrap();
+
+        assert_eq!(
+            timeline,
+            vec![
+                (datetime("2021-01-01T00:00:00+00:00"), 2),
+                (datetime("2021-01-01T02:00:00+00:00"), 1),
+                (datetime("2021-01-01T04:00:00+00:00"), 1)
+            ]
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn match_timeline_rejects_empty_buckets() {
+        assert!(loader().get_match_timeline("default::MyPass", 0, &QueryOptions::default()).is_err());
+    }
+
+    #[test]
+    #[ignore]
+    fn top_sources_are_ordered_by_match_count() {
+        let loader = loader();
+        let rule_name = unique("default::TopSources");
+        let busy_source = unique("busy");
+        let quiet_source = unique("quiet");
+
+        for _ in 0..3 {
+            insert_match(&loader, &busy_source, &rule_name, Local::now());
+        }
+        insert_match(&loader, &quiet_source, &rule_name, Local::now());
+
+        let top = loader.get_top_sources_by_match_count(i64::MAX, &QueryOptions::default()).unwrap();
+        let busy_pos = top.iter().position(|(s, _)| s == &busy_source).unwrap();
+        let quiet_pos = top.iter().position(|(s, _)| s == &quiet_source).unwrap();
+
+        assert!(top.windows(2).all(|w| w[0].1 >= w[1].1));
+        assert!(busy_pos < quiet_pos);
+        assert_eq!(top[busy_pos].1, 3);
+        assert_eq!(top[quiet_pos].1, 1);
+        assert_eq!(loader.get_top_sources_by_match_count(1, &QueryOptions::default()).unwrap().len(), 1);
+    }
+
+    #[test]
+    #[ignore]
+    fn check_seen_batch_returns_only_marked_entries() {
+        let loader = loader();
+        let (seen, unseen) = (unique("https://pastebin.com/seen"), unique("https://pastebin.com/unseen"));
+
+        let mut client = loader.conn.get().unwrap();
+        let mut trans = client.transaction().unwrap();
+        IndexCache::mark_seen_batch(&mut trans, &[("pastebin", &seen)]).unwrap();
+        trans.commit().unwrap();
+
+        let found = IndexCache::check_seen_batch(&mut client, &[("pastebin", &seen), ("pastebin", &unseen), ("gist", &seen)])
+            .unwrap();
+
+        assert_eq!(found, HashSet::from([("pastebin".to_owned(), seen)]));
+    }
+
+    #[test]
+    #[ignore]
+    fn exists_finds_only_marked_entries() {
+        let loader = loader();
+        let (seen, unseen) = (unique("https://pastebin.com/seen"), unique("https://pastebin.com/unseen"));
+
+        let mut client = loader.conn.get().unwrap();
+        let mut trans = client.transaction().unwrap();
+        IndexCache::mark_seen_batch(&mut trans, &[("pastebin", &seen)]).unwrap();
+        trans.commit().unwrap();
+
+        assert!(IndexCache::exists(&mut client, "pastebin", &seen).unwrap());
+        assert!(!IndexCache::exists(&mut client, "pastebin", &unseen).unwrap());
+        assert!(!IndexCache::exists(&mut client, "gist", &seen).unwrap());
+    }
+
+    #[test]
+    #[ignore]
+    fn mark_seen_batch_ignores_cached_entries() {
+        let loader = loader();
+        let url = unique("https://pastebin.com/twice");
+
+        let mut client = loader.conn.get().unwrap();
+        for _ in 0..2 {
+            let mut trans = client.transaction().unwrap();
+            IndexCache::mark_seen_batch(&mut trans, &[("pastebin", &url), ("pastebin", &url)]).unwrap();
+            trans.commit().unwrap();
+        }
+
+        let count: i64 = client.query_one("SELECT COUNT(*) FROM index_cache WHERE source_id = $1", &[&url])
+            .unwrap()
+            .get(0);
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    #[ignore]
+    fn loaded_events_are_cached_and_skipped_afterwards() {
+        let loader = loader();
+        let (url, other_url) = (unique("https://pastebin.com/batch"), unique("https://pastebin.com/other"));
+        let count_events = |loader: &DbLoader, url: &str| -> i64 {
+            let mut client = loader.conn.get().unwrap();
+            client.query_one("SELECT COUNT(*) FROM events WHERE url = $1", &[&url]).unwrap().get(0)
+        };
+
+        assert_eq!(loader.load_batch(vec![proc_event(&url, "2021-01-01T10:00:00+00:00")]).num_persisted(), 1);
+        assert_eq!(count_events(&loader, &url), 1);
+        assert!(IndexCache::exists(&mut loader.conn.get().unwrap(), "pastebin", &url).unwrap());
+
+        let stats = loader.load_batch(vec![
+            proc_event(&url, "2021-01-01T11:00:00+00:00"),
+            proc_event(&other_url, "2021-01-01T11:00:00+00:00")
+        ]);
+        assert_eq!((stats.num_persisted(), stats.num_failed()), (2, 0));
+        assert_eq!(count_events(&loader, &url), 1);
+        assert_eq!(count_events(&loader, &other_url), 1);
+    }
+
+    #[test]
+    #[ignore]
+    fn a_batch_of_events_is_persisted_in_full() {
+        let loader = loader();
+        let prefix = unique("https://pastebin.com/bulk");
+        let mut batch: Vec<ProcessedEvent> = (0..100)
+            .map(|i| {
+                let event = Event::new(
+                    &format!("{}/{}", prefix, i), 3, "pastebin", "foo", "foo.txt", "bar", Local::now(), Local::now()
+                );
+                let flat_match = FlatMatch::new("default::Foo".to_owned(), vec![], &[format!("foo{}", i).into_bytes()]);
+                ProcessedEvent(event, vec![flat_match])
+            })
+            .collect();
+
+        assert!(loader.persist_events(&mut batch).unwrap());
+
+        let pattern = format!("{}/%", prefix);
+        let mut client = loader.conn.get().unwrap();
+        let count = |client: &mut Client, query: &str| -> i64 {
+            client.query_one(query, &[&pattern]).unwrap().get(0)
+        };
+        assert_eq!(count(&mut client, "SELECT COUNT(*) FROM events WHERE url LIKE $1"), 100);
+        assert_eq!(count(
+            &mut client,
+            "SELECT COUNT(*) FROM rule_matches r JOIN events e ON e.id = r.event_id WHERE e.url LIKE $1"
+        ), 100);
+        assert_eq!(count(
+            &mut client,
+            "SELECT COUNT(*) FROM ascii_matches a JOIN rule_matches r ON r.id = a.match_id \
+             JOIN events e ON e.id = r.event_id WHERE e.url LIKE $1 AND a.matched_string = 'foo' || split_part(e.url, '/', 5)"
+        ), 100);
+    }
+
+    /// Stores an event whose content contains `word`, with a single rule match on `matched_string`
+    fn insert_searchable_event(loader: &DbLoader, word: &str, matched_string: &str) -> i32 {
+        let mut client = loader.conn.get().unwrap();
+        let mut trans = client.transaction().unwrap();
+
+        let content = format!("leaked credentials {} password", word);
+        let mut event = Event::new(
+            "https://pastebin.com/search", content.len(), "pastebin", &content, "foo.txt", "bar", Local::now(), Local::now()
+        );
+        event.insert(&mut trans).unwrap();
+        let mut rule_match = RuleMatch::new(event.id().unwrap(), "default::MyPass".to_owned(), vec![]);
+        rule_match.insert(&mut trans).unwrap();
+        AsciiMatch::new(rule_match.id().unwrap(), matched_string.to_owned()).insert(&mut trans).unwrap();
+
+        trans.commit().unwrap();
+
+        event.id().unwrap()
+    }
+
+    #[test]
+    #[ignore]
+    fn search_events_finds_content_words() {
+        let loader = loader();
+        let word = unique("needle").replace('-', "");
+        let event_id = insert_searchable_event(&loader, &word, "password");
+        insert_searchable_event(&loader, &unique("haystack").replace('-', ""), "password");
+
+        let found = loader.search_events(&word, &QueryOptions::default().paginated(0, 10)).unwrap();
+
+        assert_eq!(found.iter().map(|e| e.id().unwrap()).collect::<Vec<i32>>(), vec![event_id]);
+        assert!(loader.search_events(&unique("missing").replace('-', ""), &QueryOptions::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    #[ignore]
+    fn search_matches_returns_the_matched_strings() {
+        let loader = loader();
+        let word = unique("needle").replace('-', "");
+        let event_id = insert_searchable_event(&loader, &word, "hunter2");
+
+        let found = loader.search_matches(&word, &QueryOptions::default().paginated(0, 10)).unwrap();
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0.id(), Some(event_id));
+        let matched: Vec<&str> = found[0].1.iter().map(AsciiMatch::matched_string).collect();
+        assert_eq!(matched, vec!["hunter2"]);
+    }
+
+    #[test]
+    #[ignore]
+    fn snapshots_round_trip() {
+        let loader = loader();
+        let event_id = insert_event(&loader);
+        let path = snapshot_path("snapshot");
+        let event_path = snapshot_path("snapshot-event");
+
+        loader.export_snapshot(&path).unwrap();
+        let lines = snapshot_lines_of(&path, event_id);
+        std::fs::write(&event_path, lines.join("\n")).unwrap();
+        loader.bulk_delete_events(&[event_id]).unwrap();
+
+        assert_eq!(loader.import_snapshot(&event_path).unwrap(), (1, 1, 1));
+        let mut client = loader.conn.get().unwrap();
+        let event = Event::from_row(client.query_one("SELECT * FROM events WHERE id = $1", &[&event_id]).unwrap());
+        assert_eq!(event.raw_content(), "foo");
+        let stmt = "SELECT am.matched_string FROM ascii_matches am
+                    JOIN rule_matches rm ON rm.id = am.match_id WHERE rm.event_id = $1";
+        let matched: String = client.query_one(stmt, &[&event_id]).unwrap().get(0);
+        assert_eq!(matched, "foo");
+
+        // The sequences were advanced, so new events do not collide with the imported ones
+        assert!(insert_event(&loader) > event_id);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&event_path).unwrap();
+    }
+
+    #[test]
+    #[ignore]
+    fn failed_imports_are_rolled_back() {
+        let loader = loader();
+        let event_id = insert_event(&loader);
+        let path = snapshot_path("snapshot-conflict");
+        let event_path = snapshot_path("snapshot-conflict-event");
+
+        loader.export_snapshot(&path).unwrap();
+        let mut lines = snapshot_lines_of(&path, event_id);
+        loader.bulk_delete_events(&[event_id]).unwrap();
+        // The event is imported, but its rule match is listed twice and can't be inserted again
+        lines.push(lines[1].clone());
+        std::fs::write(&event_path, lines.join("\n")).unwrap();
+
+        assert!(loader.import_snapshot(&event_path).is_err());
+        assert_eq!(count_existing(&loader, &[event_id], ""), 0);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&event_path).unwrap();
+    }
+
+    #[test]
+    #[ignore]
+    fn snapshots_of_unknown_tables_are_rejected() {
+        let loader = loader();
+        let path = snapshot_path("snapshot-unknown");
+        std::fs::write(&path, "\n{\"table\": \"users\", \"row\": {}}").unwrap();
+
+        let err = loader.import_snapshot(&path).unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<DbLoaderError>(),
+            Some(DbLoaderError::InvalidSnapshot { line: 2, .. })
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[ignore]
+    fn events_are_filtered_by_line_count() {
+        let loader = loader();
+        let mut client = loader.conn.get().unwrap();
+        let mut trans = client.transaction().unwrap();
+
+        let mut ids = Vec::new();
+        let contents = [
+            "one line".to_owned(),
+            ["line"; 13].join("\n"),
+            ["line"; 12].join("\r\n")
+        ];
+        for content in &contents {
+            let mut event = Event::new(
+                "https://pastebin.com/lines", content.len(), "pastebin", content, "foo.txt", "bar", Local::now(), Local::now()
+            );
+            event.insert(&mut trans).unwrap();
+            ids.push(event.id().unwrap());
+        }
+        trans.commit().unwrap();
+
+        let found: Vec<i32> = loader.get_events_by_line_count_range(12, 13, &QueryOptions::default()).unwrap().iter()
+            .filter_map(Event::id)
+            .filter(|id| ids.contains(id))
+            .collect();
+
+        assert_eq!(found, vec![ids[1], ids[2]]);
+    }
+
+    /// Inserts an event with `content` that matched `rule_name` and returns its ID
+    fn insert_matched_event(loader: &DbLoader, content: &str, rule_name: &str) -> i32 {
+        let mut client = loader.conn.get().unwrap();
+        let mut trans = client.transaction().unwrap();
+
+        let mut event = Event::new(
+            "https://pastebin.com/foo", content.len(), "pastebin", content, "foo.txt", "bar", Local::now(), Local::now()
+        );
+        event.insert(&mut trans).unwrap();
+        RuleMatch::new(event.id().unwrap(), rule_name.to_owned(), vec![]).insert(&mut trans).unwrap();
+        trans.commit().unwrap();
+
+        event.id().unwrap()
+    }
+
+    #[test]
+    #[ignore]
+    fn lightweight_events_come_with_their_preview_only() {
+        let loader = loader();
+        let rule_name = unique("default::Preview");
+        let content = "x".repeat(1000);
+        let id = insert_matched_event(&loader, &content, &rule_name);
+        insert_matched_event(&loader, "other", &unique("default::Other"));
+
+        let full = loader.get_events_by_rule(&rule_name, LoadMode::Full, &QueryOptions::default()).unwrap();
+        let light = loader.get_events_by_rule(&rule_name, LoadMode::Lightweight, &QueryOptions::default()).unwrap();
+
+        assert_eq!(full.iter().map(|e| e.id().unwrap()).collect::<Vec<_>>(), vec![id]);
+        assert_eq!(light.iter().map(|e| e.id().unwrap()).collect::<Vec<_>>(), vec![id]);
+        assert_eq!(full[0].raw_content(), content);
+        assert_eq!(light[0].raw_content(), "");
+        assert_eq!(light[0].content_preview(), Some(&content[..500]));
+        assert_eq!(light[0].size(), content.len());
+    }
+
+    #[test]
+    #[ignore]
+    fn full_content_is_fetched_by_id() {
+        let loader = loader();
+        let rule_name = unique("default::FullContent");
+        let content = "y".repeat(1000);
+        insert_matched_event(&loader, &content, &rule_name);
+
+        let light = loader.get_events_by_rule(&rule_name, LoadMode::Lightweight, &QueryOptions::default()).unwrap();
+        let mut client = loader.conn.get().unwrap();
+
+        assert_eq!(light[0].full_content(&mut client).unwrap(), content);
+    }
+
+    #[test]
+    #[ignore]
+    fn events_are_found_by_their_indicators() {
+        let loader = loader();
+        let host = format!("{}.example.com", unique("indicators"));
+        let content = format!("pw: hunter2 at https://{}/login and 10.13.37.1", host);
+        let mut client = loader.conn.get().unwrap();
+        let mut trans = client.transaction().unwrap();
+
+        let mut event = Event::new(
+            "https://pastebin.com/foo", content.len(), "pastebin", &content, "foo.txt", "bar", Local::now(), Local::now()
+        );
+        event.set_indicators(crate::indicators::extract_indicators(&content));
+        event.insert(&mut trans).unwrap();
+        trans.commit().unwrap();
+        let id = event.id().unwrap();
+
+        let by_domain = loader.get_events_by_indicator(&host.to_uppercase(), &QueryOptions::default()).unwrap();
+        assert_eq!(by_domain.iter().map(|e| e.id().unwrap()).collect::<Vec<_>>(), vec![id]);
+        assert_eq!(by_domain[0].indicators(), event.indicators());
+        assert!(loader.get_events_by_indicator("10.13.37.1", &QueryOptions::default()).unwrap().iter().any(|e| e.id() == Some(id)));
+        assert!(loader.get_events_by_indicator("login", &QueryOptions::default()).unwrap().iter().all(|e| e.id() != Some(id)));
     }
 }
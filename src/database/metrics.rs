@@ -0,0 +1,117 @@
+//! Exposes the approximate size of each table as Prometheus gauges, for capacity planning.
+//! The gauges are registered in the default Prometheus registry
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::{info, error};
+use lazy_static::lazy_static;
+use prometheus::{register_int_gauge_vec, IntGaugeVec};
+use r2d2_postgres::postgres::Row;
+use anyhow::Result;
+
+use crate::database::RetryingDbConnection;
+
+lazy_static! {
+    static ref DB_TABLE_ROWS: IntGaugeVec = register_int_gauge_vec!(
+        "db_table_rows", "Approximate number of live rows per table", &["table"]
+    ).unwrap();
+    static ref DB_TABLE_DEAD_ROWS: IntGaugeVec = register_int_gauge_vec!(
+        "db_table_dead_rows", "Approximate number of dead rows (awaiting vacuum) per table", &["table"]
+    ).unwrap();
+}
+
+/// The row counts Postgres keeps for a table. These are estimates, updated by `ANALYZE` and autovacuum
+#[derive(Debug, PartialEq, Eq)]
+pub struct TableStats {
+    table: String,
+    live_rows: i64,
+    dead_rows: i64
+}
+
+impl TableStats {
+    pub fn new(table: &str, live_rows: i64, dead_rows: i64) -> Self {
+        Self { table: table.to_owned(), live_rows, dead_rows }
+    }
+
+    fn from_row(row: &Row) -> Self {
+        Self::new(row.get("relname"), row.get("n_live_tup"), row.get("n_dead_tup"))
+    }
+
+    /// Reads the stats of every table in the `public` schema
+    pub fn fetch(conn: &RetryingDbConnection) -> Result<Vec<TableStats>> {
+        let rows = conn.get()?.query(
+            "SELECT relname::text, n_live_tup, n_dead_tup FROM pg_stat_user_tables WHERE schemaname = 'public'",
+            &[]
+        )?;
+
+        Ok(rows.iter().map(TableStats::from_row).collect())
+    }
+
+    /// Sets the `db_table_rows` and `db_table_dead_rows` gauges of the table
+    pub fn record(&self) {
+        DB_TABLE_ROWS.with_label_values(&[&self.table]).set(self.live_rows);
+        DB_TABLE_DEAD_ROWS.with_label_values(&[&self.table]).set(self.dead_rows);
+    }
+}
+
+/// Spawns a thread that updates the table gauges every `interval` for as long as the process runs
+pub fn start_table_metrics(conn: RetryingDbConnection, interval: Duration) -> JoinHandle<()> {
+    info!("Polling table sizes every {}s", interval.as_secs());
+    thread::spawn(move || loop {
+        match TableStats::fetch(&conn) {
+            Ok(all_stats) => all_stats.iter().for_each(TableStats::record),
+            Err(e) => error!("Could not fetch table sizes: {}", e)
+        }
+        thread::sleep(interval);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gauges(table: &str) -> (i64, i64) {
+        (DB_TABLE_ROWS.with_label_values(&[table]).get(), DB_TABLE_DEAD_ROWS.with_label_values(&[table]).get())
+    }
+
+    #[test]
+    fn gauges_are_set_per_table() {
+        // Label values are unique to this test, since the gauges are global
+        let all_stats = [
+            TableStats::new("test_events", 120, 4),
+            TableStats::new("test_rule_matches", 300, 0),
+            TableStats::new("test_ascii_matches", 900, 17)
+        ];
+        all_stats.iter().for_each(TableStats::record);
+
+        assert_eq!(gauges("test_events"), (120, 4));
+        assert_eq!(gauges("test_rule_matches"), (300, 0));
+        assert_eq!(gauges("test_ascii_matches"), (900, 17));
+
+        TableStats::new("test_events", 80, 0).record();
+        assert_eq!(gauges("test_events"), (80, 0));
+    }
+
+    #[test]
+    fn gauges_are_exported() {
+        TableStats::new("test_exported", 1, 2).record();
+
+        let families = prometheus::gather();
+        let names: Vec<&str> = families.iter().map(|f| f.get_name()).collect();
+        assert!(names.contains(&"db_table_rows"));
+        assert!(names.contains(&"db_table_dead_rows"));
+    }
+
+    #[test]
+    #[ignore]
+    fn stats_are_fetched_for_all_tables() {
+        let conn = RetryingDbConnection::new(
+            crate::database::DbConnection::connect("postgres", "infobserve", "infobserve", "localhost", 5432).unwrap()
+        );
+
+        let tables: Vec<String> = TableStats::fetch(&conn).unwrap().into_iter().map(|s| s.table).collect();
+        for table in ["events", "rule_matches", "ascii_matches"] {
+            assert!(tables.iter().any(|t| t == table), "{} missing from {:?}", table, tables);
+        }
+    }
+}
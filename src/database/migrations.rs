@@ -0,0 +1,244 @@
+//! Versioned changes to the infobserve schema. Each migration is a numbered SQL file under `migrations/` (e.g.
+//! `0002_add_context_column.sql`), applied once and in order, and recorded in the `schema_migrations` table.
+//! To change the schema, add a file with the next number (and list it in `EMBEDDED_MIGRATIONS`) instead of editing
+//! one that may already have been applied
+use log::info;
+
+use anyhow::{Context, Result};
+
+use crate::database::Client;
+use crate::errors::DbLoaderError;
+
+/// The migrations embedded in the binary, by file name
+const EMBEDDED_MIGRATIONS: [(&str, &str); 3] = [
+    ("0001_initial.sql", include_str!("../../migrations/0001_initial.sql")),
+    ("0002_add_context_column.sql", include_str!("../../migrations/0002_add_context_column.sql")),
+    ("0003_add_rule_meta_column.sql", include_str!("../../migrations/0003_add_rule_meta_column.sql"))
+];
+/// Where the migrations are read from when built with the `runtime-schema` feature
+#[cfg(feature = "runtime-schema")]
+const MIGRATIONS_DIR: &str = "migrations";
+/// Held while the migrations are applied, so that processes starting together do not apply them twice
+const MIGRATION_LOCK_KEY: i64 = 0x696e_666f_6273;
+
+const CREATE_MIGRATIONS_TABLE: &str = "
+CREATE TABLE IF NOT EXISTS schema_migrations (
+  version INT PRIMARY KEY,
+  applied_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+";
+
+#[derive(Debug, PartialEq)]
+pub struct Migration {
+    version: i32,
+    name: String,
+    sql: String
+}
+
+impl Migration {
+    /// # Arguments
+    ///
+    /// * `file_name` - `NNNN_description.sql`, where `NNNN` is the version
+    ///
+    /// # Errors
+    ///
+    /// `errors::DbLoaderError::InvalidMigrationName` - When `file_name` does not start with a positive version
+    pub fn new(file_name: &str, sql: &str) -> Result<Self> {
+        let invalid = || DbLoaderError::InvalidMigrationName(file_name.to_owned());
+
+        let name = file_name.strip_suffix(".sql").ok_or_else(invalid)?;
+        let version = name.split('_').next()
+            .and_then(|v| v.parse::<i32>().ok())
+            .filter(|v| *v > 0)
+            .ok_or_else(invalid)?;
+
+        Ok(Self { version, name: name.to_owned(), sql: sql.to_owned() })
+    }
+}
+
+/// The migrations embedded in the binary, in order
+pub fn embedded() -> Vec<Migration> {
+    EMBEDDED_MIGRATIONS.iter()
+        .map(|(file_name, sql)| Migration::new(file_name, sql).expect("embedded migrations are named NNNN_*.sql"))
+        .collect()
+}
+
+/// The migrations to apply: the embedded ones, or with the `runtime-schema` feature, the `.sql` files of the
+/// `migrations` directory in the working directory, so that they can be changed without rebuilding
+pub fn load() -> Result<Vec<Migration>> {
+    #[cfg(not(feature = "runtime-schema"))]
+    let migrations = embedded();
+    #[cfg(feature = "runtime-schema")]
+    let migrations = read_dir(MIGRATIONS_DIR)?;
+
+    in_order(migrations)
+}
+
+#[cfg(feature = "runtime-schema")]
+fn read_dir(dir: &str) -> Result<Vec<Migration>> {
+    let mut migrations = Vec::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Could not read the migrations in {}", dir))? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("sql") {
+            continue;
+        }
+
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        migrations.push(Migration::new(&file_name, &std::fs::read_to_string(&path)?)?);
+    }
+
+    Ok(migrations)
+}
+
+/// Sorts `migrations` by version
+///
+/// # Errors
+///
+/// `errors::DbLoaderError::DuplicateMigration` - When two migrations share a version
+fn in_order(mut migrations: Vec<Migration>) -> Result<Vec<Migration>> {
+    migrations.sort_by_key(|m| m.version);
+    if let Some(pair) = migrations.windows(2).find(|pair| pair[0].version == pair[1].version) {
+        return Err(DbLoaderError::DuplicateMigration(pair[0].name.clone(), pair[1].name.clone()).into());
+    }
+
+    Ok(migrations)
+}
+
+/// The migrations of `migrations` (sorted by version) newer than `current_version`
+pub fn pending(migrations: &[Migration], current_version: i32) -> &[Migration] {
+    let applied = migrations.iter().take_while(|m| m.version <= current_version).count();
+
+    &migrations[applied..]
+}
+
+/// Applies the migrations of `migrations` (sorted by version) newer than the database's version, in a single
+/// transaction: either all of them are applied, or none
+///
+/// # Returns
+/// The versions of the migrations that were applied
+pub fn run(client: &mut Client, migrations: &[Migration]) -> Result<Vec<i32>> {
+    let mut trans = client.transaction()?;
+    trans.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_KEY])?;
+    trans.batch_execute(CREATE_MIGRATIONS_TABLE)?;
+
+    let current_version: i32 = trans.query_one("SELECT COALESCE(MAX(version), 0) FROM schema_migrations", &[])?
+        .get(0);
+
+    let mut applied = Vec::new();
+    for migration in pending(migrations, current_version) {
+        info!("Applying schema migration {}", migration.name);
+        trans.batch_execute(&migration.sql).with_context(|| format!("Schema migration {} failed", migration.name))?;
+        trans.execute("INSERT INTO schema_migrations (version) VALUES ($1)", &[&migration.version])?;
+        applied.push(migration.version);
+    }
+    trans.commit()?;
+
+    Ok(applied)
+}
+
+/// The embedded migrations as a single script, in order
+pub fn schema_sql() -> String {
+    embedded().iter()
+        .map(|m| format!("-- Migration {}\n{}", m.name, m.sql))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use crate::database::DbConnection;
+
+    fn stub(file_name: &str, sql: &str) -> Migration {
+        Migration::new(file_name, sql).unwrap()
+    }
+
+    #[test]
+    fn embedded_migrations_are_numbered_from_one() {
+        let versions: Vec<i32> = in_order(embedded()).unwrap().iter().map(|m| m.version).collect();
+
+        assert_eq!(versions, (1..=EMBEDDED_MIGRATIONS.len() as i32).collect::<Vec<i32>>());
+    }
+
+    #[test]
+    fn migration_versions_are_read_from_the_file_name() {
+        let migration = stub("0002_add_context_column.sql", "SELECT 1");
+
+        assert_eq!((migration.version, migration.name.as_str()), (2, "0002_add_context_column"));
+    }
+
+    #[test]
+    fn migrations_without_a_version_are_rejected() {
+        for file_name in ["initial.sql", "0000_initial.sql", "0001_initial.txt", "-1_initial.sql"] {
+            let err = Migration::new(file_name, "").unwrap_err();
+
+            assert!(matches!(
+                err.downcast_ref::<DbLoaderError>(),
+                Some(DbLoaderError::InvalidMigrationName(name)) if name == file_name
+            ));
+        }
+    }
+
+    #[test]
+    fn migrations_sharing_a_version_are_rejected() {
+        let migrations = vec![stub("0002_b.sql", ""), stub("0001_a.sql", ""), stub("0002_c.sql", "")];
+
+        assert!(matches!(
+            in_order(migrations).unwrap_err().downcast_ref::<DbLoaderError>(),
+            Some(DbLoaderError::DuplicateMigration(..))
+        ));
+    }
+
+    #[test]
+    fn only_newer_migrations_are_pending() {
+        let migrations = in_order(vec![stub("0003_c.sql", ""), stub("0001_a.sql", ""), stub("0002_b.sql", "")])
+            .unwrap();
+        let versions = |current| pending(&migrations, current).iter().map(|m| m.version).collect::<Vec<i32>>();
+
+        assert_eq!(versions(0), [1, 2, 3]);
+        assert_eq!(versions(2), [3]);
+        assert!(versions(3).is_empty());
+    }
+
+    #[test]
+    fn schema_sql_holds_every_migration_in_order() {
+        let sql = schema_sql();
+        let initial = sql.find("-- Migration 0001_initial").unwrap();
+        let context = sql.find("-- Migration 0002_add_context_column").unwrap();
+
+        assert!(initial < context);
+        assert!(sql.contains("CREATE TABLE IF NOT EXISTS events"));
+    }
+
+    // Needs a running postgres. Run it with `cargo test -- --ignored`
+
+    #[test]
+    #[ignore]
+    fn migrations_are_applied_once() {
+        // A single connection, so that every statement runs in the stub's schema
+        let mut client = DbConnection::lazy(5432, 1, Duration::from_secs(5)).get().unwrap();
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+        let schema = format!("migrations_test_{}", nanos);
+        client.batch_execute(&format!("CREATE SCHEMA {0}; SET search_path TO {0}", schema)).unwrap();
+
+        let mut migrations = vec![
+            stub("0001_stub.sql", "CREATE TABLE stub (id SERIAL PRIMARY KEY);"),
+            stub("0002_add_name.sql", "ALTER TABLE stub ADD COLUMN name TEXT;")
+        ];
+        assert_eq!(run(&mut client, &migrations).unwrap(), [1, 2]);
+        assert!(run(&mut client, &migrations).unwrap().is_empty());
+
+        migrations.push(stub("0003_add_size.sql", "ALTER TABLE stub ADD COLUMN size BIGINT;"));
+        assert_eq!(run(&mut client, &migrations).unwrap(), [3]);
+        client.execute("INSERT INTO stub (name, size) VALUES ('foo', 3)", &[]).unwrap();
+
+        // A failing migration leaves the schema as it was
+        migrations.push(stub("0004_broken.sql", "ALTER TABLE stub ADD COLUMN tags TEXT[]; SELECT no_such_column;"));
+        assert!(run(&mut client, &migrations).is_err());
+        let version: i32 = client.query_one("SELECT MAX(version) FROM schema_migrations", &[]).unwrap().get(0);
+        assert_eq!(version, 3);
+
+        client.batch_execute(&format!("DROP SCHEMA {} CASCADE; SET search_path TO DEFAULT", schema)).unwrap();
+    }
+}
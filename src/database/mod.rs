@@ -1,13 +1,264 @@
 mod connection;
 mod loader;
+mod metrics;
+mod migrations;
+#[cfg(feature = "tls")]
+mod tls;
 
-use r2d2_postgres::postgres::Transaction;
+use std::thread;
+use std::time::Duration;
+use log::warn;
+
+use rand::Rng;
+use r2d2_postgres::postgres::{self, Transaction};
+use r2d2_postgres::postgres::error::SqlState;
+use r2d2_postgres::postgres::types::ToSql;
 use anyhow::Result;
 
-pub use connection::{Client, DbConnection};
-pub use loader::{start_loaders, DbLoader};
+use crate::errors::DbLoaderError;
+
+pub use connection::{Client, DbConnection, RetryingDbConnection};
+pub use loader::{start_dead_letter_logger, start_dry_run_sink, start_loaders, DbLoader, LoaderStats, QueryOptions};
+pub use metrics::start_table_metrics;
+
 
+/// The most parameters postgres accepts in a single statement
+const MAX_STATEMENT_PARAMS: usize = u16::MAX as usize;
+/// The longest `retry_transaction` waits before retrying. The delay is random, so that the transactions that
+/// deadlocked are unlikely to run into each other again
+const MAX_RETRY_JITTER_MS: u64 = 100;
 
 pub trait Insert {
     fn insert(&mut self, conn: &mut Transaction) -> Result<()>;
+
+    /// Inserts every one of `items` in order, stopping at the first one that fails. Types whose rows are small
+    /// and numerous override this to insert them with a few multi-row statements instead (see `insert_rows`)
+    fn insert_all(items: &mut [Self], conn: &mut Transaction) -> Result<()> where Self: Sized {
+        for item in items.iter_mut() {
+            item.insert(conn)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Inserts every element (see `Insert::insert_all`). Since errors are propagated, the caller's transaction
+/// is never committed when an insert fails, and all inserts are rolled back
+impl<T: Insert> Insert for Vec<T> {
+    fn insert(&mut self, conn: &mut Transaction) -> Result<()> {
+        T::insert_all(self, conn)
+    }
+}
+
+/// Inserts `rows` into `table` using multi-row `INSERT ... VALUES (...), (...)` statements, as many rows at a time
+/// as the parameter limit of postgres allows. Each row holds a value for each of `columns`
+///
+/// # Returns
+/// The IDs of the inserted rows, in the order of `rows`. Rows are numbered in the order they are listed in a
+/// `VALUES` clause, so sorting the IDs each statement returns is enough to match them to their rows
+pub fn insert_rows(
+    conn: &mut Transaction,
+    table: &str,
+    columns: &[&str],
+    rows: &[Vec<&(dyn ToSql + Sync)>]
+) -> Result<Vec<i32>> {
+    let mut ids: Vec<i32> = Vec::with_capacity(rows.len());
+    if columns.is_empty() {
+        return Ok(ids);
+    }
+
+    for chunk in rows.chunks(MAX_STATEMENT_PARAMS / columns.len()) {
+        let values: Vec<String> = (0..chunk.len())
+            .map(|row| {
+                let placeholders: Vec<String> = (1..=columns.len())
+                    .map(|column| format!("${}", row * columns.len() + column))
+                    .collect();
+                format!("({})", placeholders.join(", "))
+            })
+            .collect();
+        let stmt = format!(
+            "INSERT INTO {} ({}) VALUES {} RETURNING id", table, columns.join(", "), values.join(", ")
+        );
+        let params: Vec<&(dyn ToSql + Sync)> = chunk.iter().flatten().copied().collect();
+
+        let mut chunk_ids: Vec<i32> = conn.query(stmt.as_str(), &params)?.iter().map(|row| row.get(0)).collect();
+        chunk_ids.sort_unstable();
+        ids.extend(chunk_ids);
+    }
+
+    Ok(ids)
+}
+
+/// Runs `f` in a transaction of `client` and commits it. When postgres aborts the transaction to break a deadlock
+/// (`40P01`), `f` is run again in a new transaction, up to `max_retries` times and after a short random delay.
+/// `f` must therefore not depend on what a previous (rolled back) attempt did
+///
+/// # Errors
+///
+/// `errors::DbLoaderError::TransactionRetriesExceeded` - When every attempt deadlocked. Any other error is returned
+/// as it is, without retrying
+pub fn retry_transaction<F>(client: &mut Client, max_retries: u32, mut f: F) -> Result<()>
+    where F: FnMut(&mut Transaction) -> Result<()>
+{
+    retry_while(max_retries, is_deadlock, || {
+        let mut trans = client.transaction()?;
+        f(&mut trans)?;
+        trans.commit()?;
+
+        Ok(())
+    })
+}
+
+/// Whether `e` was caused by postgres aborting a transaction to break a deadlock
+fn is_deadlock(e: &anyhow::Error) -> bool {
+    e.chain()
+        .filter_map(|cause| cause.downcast_ref::<postgres::Error>())
+        .any(|pg_error| pg_error.code() == Some(&SqlState::T_R_DEADLOCK_DETECTED))
+}
+
+/// Calls `attempt` until it succeeds, fails with an error `should_retry` rejects, or has been retried
+/// `max_retries` times
+fn retry_while<T>(
+    max_retries: u32,
+    should_retry: impl Fn(&anyhow::Error) -> bool,
+    mut attempt: impl FnMut() -> Result<T>
+) -> Result<T> {
+    let mut retries = 0;
+    loop {
+        match attempt() {
+            Err(e) if should_retry(&e) => {
+                if retries == max_retries {
+                    return Err(DbLoaderError::TransactionRetriesExceeded {
+                        attempts: retries + 1,
+                        reason: e.to_string()
+                    }.into());
+                }
+
+                retries += 1;
+                let delay = Duration::from_millis(rand::thread_rng().gen_range(0..=MAX_RETRY_JITTER_MS));
+                warn!("Transaction failed ({}). Retrying in {}ms ({}/{})", e, delay.as_millis(), retries, max_retries);
+                thread::sleep(delay);
+            },
+            result => return result
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::sync::{Arc, Barrier};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    /// Stands for a `postgres::Error` (which can't be built outside the driver) carrying `SqlState`
+    #[derive(Debug)]
+    struct FakeDbError(SqlState);
+
+    impl std::fmt::Display for FakeDbError {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "db error: {}", self.0.code())
+        }
+    }
+
+    impl std::error::Error for FakeDbError {}
+
+    fn is_fake_deadlock(e: &anyhow::Error) -> bool {
+        e.downcast_ref::<FakeDbError>().is_some_and(|fake| fake.0 == SqlState::T_R_DEADLOCK_DETECTED)
+    }
+
+    /// Fails with `code` the first `failures` times it is called
+    fn failing(code: SqlState, failures: u32, calls: &Cell<u32>) -> impl FnMut() -> Result<()> + '_ {
+        move || {
+            calls.set(calls.get() + 1);
+            if calls.get() <= failures {
+                return Err(FakeDbError(code.clone()).into());
+            }
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn deadlocked_transactions_are_retried() {
+        let calls = Cell::new(0);
+
+        assert!(retry_while(3, is_fake_deadlock, failing(SqlState::T_R_DEADLOCK_DETECTED, 2, &calls)).is_ok());
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn deadlocked_transactions_are_retried_at_most_max_retries_times() {
+        let calls = Cell::new(0);
+        let err = retry_while(2, is_fake_deadlock, failing(SqlState::T_R_DEADLOCK_DETECTED, 10, &calls)).unwrap_err();
+
+        assert_eq!(calls.get(), 3);
+        assert!(matches!(
+            err.downcast_ref::<DbLoaderError>(),
+            Some(DbLoaderError::TransactionRetriesExceeded { attempts: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn other_errors_are_not_retried() {
+        let calls = Cell::new(0);
+        let err = retry_while(3, is_fake_deadlock, failing(SqlState::UNIQUE_VIOLATION, 1, &calls)).unwrap_err();
+
+        assert_eq!(calls.get(), 1);
+        assert!(err.downcast_ref::<FakeDbError>().is_some());
+    }
+
+    #[test]
+    fn only_postgres_deadlocks_are_detected() {
+        assert!(!is_deadlock(&FakeDbError(SqlState::T_R_DEADLOCK_DETECTED).into()));
+        assert!(!is_deadlock(&anyhow::anyhow!("deadlock detected")));
+    }
+
+    // Needs a running postgres. Run it with `cargo test -- --ignored`
+
+    #[test]
+    #[ignore]
+    fn transactions_survive_a_deadlock() {
+        let conn = DbConnection::lazy(5432, 2, Duration::from_secs(5));
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+        let table = format!("deadlock_test_{}", nanos);
+        let create = format!("CREATE TABLE {0} (id INT PRIMARY KEY, n INT); INSERT INTO {0} VALUES (1, 0), (2, 0)", table);
+        conn.get().unwrap().batch_execute(&create).unwrap();
+
+        // Each transaction locks one row, waits for the other to lock the other row, then asks for it: postgres
+        // aborts one of them, which succeeds when retried on its own
+        let barrier = Arc::new(Barrier::new(2));
+        let handles: Vec<_> = [(1, 2), (2, 1)].iter().map(|&(first, second)| {
+            let (conn, barrier, table) = (conn.clone(), Arc::clone(&barrier), table.clone());
+            thread::spawn(move || {
+                let mut attempts = 0;
+                let result = retry_transaction(&mut conn.get().unwrap(), 1, |trans| {
+                    attempts += 1;
+                    trans.execute(format!("UPDATE {} SET n = n + 1 WHERE id = $1", table).as_str(), &[&first])?;
+                    if attempts == 1 {
+                        barrier.wait();
+                    }
+                    trans.execute(format!("UPDATE {} SET n = n + 1 WHERE id = $1", table).as_str(), &[&second])?;
+
+                    Ok(())
+                });
+                (result.is_ok(), attempts)
+            })
+        }).collect();
+
+        let mut attempts: Vec<u32> = handles.into_iter()
+            .map(|handle| {
+                let (ok, attempts) = handle.join().unwrap();
+                assert!(ok);
+                attempts
+            })
+            .collect();
+        attempts.sort_unstable();
+        assert_eq!(attempts, [1, 2]);
+
+        let mut client = conn.get().unwrap();
+        let total: i64 = client.query_one(format!("SELECT SUM(n) FROM {}", table).as_str(), &[]).unwrap().get(0);
+        assert_eq!(total, 4);
+        client.batch_execute(&format!("DROP TABLE {}", table)).unwrap();
+    }
 }
@@ -0,0 +1,235 @@
+//! TLS for the postgres connections, through OpenSSL. `postgres` leaves the TLS implementation to its users: this
+//! adapts OpenSSL's blocking `SslStream` to the async streams of the runtime the postgres client is built on
+
+use std::fmt;
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::ssl::{
+    ConnectConfiguration, ErrorCode, HandshakeError, MidHandshakeSslStream, ShutdownResult, SslConnector,
+    SslFiletype, SslMethod, SslStream, SslVerifyMode
+};
+use r2d2_postgres::postgres::tls::{ChannelBinding, MakeTlsConnect, TlsConnect, TlsStream};
+use tokio_02::io::{AsyncRead, AsyncWrite};
+use anyhow::{bail, Result};
+
+use crate::config::DbCfg;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Opens the TLS sessions of the connection pool (see `DbConnection::from_cfg`). Whether a session is opened at
+/// all is up to `DbCfg::ssl_mode`
+#[derive(Clone)]
+pub struct MakeTlsConnector {
+    connector: SslConnector,
+    verify_server: bool
+}
+
+impl MakeTlsConnector {
+    /// Presents the client certificate of `db_cfg` (if any) and verifies the server's certificate against its CA
+    /// bundle. Without a CA bundle the server's certificate is not verified, as with libpq's `sslmode=require`
+    ///
+    /// # Errors
+    ///
+    /// When the certificate material cannot be loaded, or only one of the client certificate and key is set
+    pub fn from_cfg(db_cfg: &DbCfg) -> Result<Self> {
+        let mut builder = SslConnector::builder(SslMethod::tls_client())?;
+        match (db_cfg.ssl_client_cert(), db_cfg.ssl_client_key()) {
+            (Some(cert), Some(key)) => {
+                builder.set_certificate_chain_file(cert)?;
+                builder.set_private_key_file(key, SslFiletype::PEM)?;
+                builder.check_private_key()?;
+            },
+            (None, None) => {},
+            _ => bail!("`database.ssl_client_cert` and `database.ssl_client_key` must be set together")
+        }
+        if let Some(ca) = db_cfg.ssl_ca_cert() {
+            builder.set_ca_file(ca)?;
+        }
+
+        Ok(Self { connector: builder.build(), verify_server: db_cfg.ssl_ca_cert().is_some() })
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + fmt::Debug + Send + 'static> MakeTlsConnect<S> for MakeTlsConnector {
+    type Stream = OpensslStream<S>;
+    type TlsConnect = TlsConnector;
+    type Error = ErrorStack;
+
+    fn make_tls_connect(&mut self, domain: &str) -> Result<TlsConnector, ErrorStack> {
+        let mut config = self.connector.configure()?;
+        // Like libpq's `verify-ca`: the certificate has to be signed by the CA, whatever host it was issued for
+        config.set_verify_hostname(false);
+        if !self.verify_server {
+            config.set_verify(SslVerifyMode::NONE);
+        }
+
+        Ok(TlsConnector { config, domain: domain.to_owned() })
+    }
+}
+
+/// Opens the TLS session of a single connection
+pub struct TlsConnector {
+    config: ConnectConfiguration,
+    domain: String
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + fmt::Debug + Send + 'static> TlsConnect<S> for TlsConnector {
+    type Stream = OpensslStream<S>;
+    type Error = BoxError;
+    type Future = Handshake<S>;
+
+    fn connect(self, stream: S) -> Handshake<S> {
+        Handshake(Some(HandshakeState::Start(self.config, self.domain, stream)))
+    }
+}
+
+enum HandshakeState<S> {
+    Start(ConnectConfiguration, String, S),
+    Midway(MidHandshakeSslStream<SyncStream<S>>)
+}
+
+/// Resolves to the stream once the TLS handshake is over
+pub struct Handshake<S>(Option<HandshakeState<S>>);
+
+impl<S: AsyncRead + AsyncWrite + Unpin + fmt::Debug> Future for Handshake<S> {
+    type Output = Result<OpensslStream<S>, BoxError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let result = match self.0.take().expect("handshake polled after completion") {
+            HandshakeState::Start(config, domain, stream) => {
+                config.connect(&domain, SyncStream { inner: stream, waker: Some(cx.waker().clone()) })
+            },
+            HandshakeState::Midway(mut stream) => {
+                stream.get_mut().waker = Some(cx.waker().clone());
+                stream.handshake()
+            }
+        };
+
+        match result {
+            Ok(mut stream) => {
+                stream.get_mut().waker = None;
+                Poll::Ready(Ok(OpensslStream(stream)))
+            },
+            Err(HandshakeError::WouldBlock(mut stream)) => {
+                stream.get_mut().waker = None;
+                self.0 = Some(HandshakeState::Midway(stream));
+                Poll::Pending
+            },
+            Err(HandshakeError::SetupFailure(e)) => Poll::Ready(Err(e.into())),
+            Err(HandshakeError::Failure(stream)) => Poll::Ready(Err(stream.into_error().into()))
+        }
+    }
+}
+
+/// Presents an async stream as a blocking one to OpenSSL: reads and writes that would wait report
+/// `io::ErrorKind::WouldBlock` instead, after arranging for the task polling them (`waker`) to be woken up
+#[derive(Debug)]
+struct SyncStream<S> {
+    inner: S,
+    waker: Option<Waker>
+}
+
+impl<S: Unpin> SyncStream<S> {
+    fn poll_io<T, F>(&mut self, f: F) -> io::Result<T>
+        where F: FnOnce(Pin<&mut S>, &mut Context<'_>) -> Poll<io::Result<T>>
+    {
+        let waker = self.waker.as_ref().expect("stream used outside of a poll");
+        match f(Pin::new(&mut self.inner), &mut Context::from_waker(waker)) {
+            Poll::Ready(result) => result,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> Read for SyncStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.poll_io(|stream, cx| stream.poll_read(cx, buf))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> Write for SyncStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.poll_io(|stream, cx| stream.poll_write(cx, buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.poll_io(|stream, cx| stream.poll_flush(cx))
+    }
+}
+
+/// A connection's stream, once its TLS session is open
+pub struct OpensslStream<S>(SslStream<SyncStream<S>>);
+
+impl<S: Unpin> OpensslStream<S> {
+    /// Runs `f` on the TLS stream, letting the underlying stream wake up the task of `cx`
+    fn with_context<T, F>(&mut self, cx: &mut Context<'_>, f: F) -> Poll<io::Result<T>>
+        where F: FnOnce(&mut SslStream<SyncStream<S>>) -> io::Result<T>
+    {
+        self.0.get_mut().waker = Some(cx.waker().clone());
+        let result = f(&mut self.0);
+        self.0.get_mut().waker = None;
+
+        match result {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Poll::Pending,
+            result => Poll::Ready(result)
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for OpensslStream<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        self.with_context(cx, |stream| stream.read(buf))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for OpensslStream<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.with_context(cx, |stream| stream.write(buf))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.with_context(cx, |stream| stream.flush())
+    }
+
+    /// Sends the TLS `close_notify` alert, then shuts the underlying stream down
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let shutdown = self.with_context(cx, |stream| match stream.shutdown() {
+            Ok(ShutdownResult::Sent) | Ok(ShutdownResult::Received) => Ok(()),
+            Err(e) if e.code() == ErrorCode::ZERO_RETURN => Ok(()),
+            Err(e) => Err(e.into_io_error().unwrap_or_else(io::Error::other))
+        });
+        if let Poll::Ready(Err(e)) = shutdown {
+            return Poll::Ready(Err(e));
+        }
+        if shutdown.is_pending() {
+            return Poll::Pending;
+        }
+
+        Pin::new(&mut self.0.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> TlsStream for OpensslStream<S> {
+    /// Lets the server prove it holds the certificate, for `SCRAM-SHA-256-PLUS` authentication (RFC 5929)
+    fn channel_binding(&self) -> ChannelBinding {
+        let digest = self.0.ssl().peer_certificate().and_then(|cert| {
+            let algorithms = cert.signature_algorithm().object().nid().signature_algorithms()?;
+            let digest = match algorithms.digest {
+                Nid::MD5 | Nid::SHA1 => MessageDigest::sha256(),
+                nid => MessageDigest::from_nid(nid)?
+            };
+            cert.digest(digest).ok()
+        });
+
+        match digest {
+            Some(digest) => ChannelBinding::tls_server_end_point(digest.to_vec()),
+            None => ChannelBinding::none()
+        }
+    }
+}
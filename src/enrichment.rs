@@ -0,0 +1,364 @@
+//! Threat intelligence lookups of the IP addresses and domain names found in matched strings (see
+//! [extract_indicators](crate::indicators::extract_indicators)). Built with the `threat-intel` feature, and enabled by
+//! setting the API key of at least one provider in the `enrichment` configuration section.
+//!
+//! Lookups happen while the loaders persist events (see [DbLoader](crate::database::DbLoader::with_threat_intel)),
+//! before the database transaction is started. Their results are stored in the `threat_intel` column of the ascii
+//! matches they were found in. Providers rate-limit their APIs, so indicators are looked up one at a time and
+//! successful lookups are cached
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use log::{info, warn};
+use lru::LruCache;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::runtime::{Builder, Runtime};
+
+use crate::config::EnrichmentCfg;
+use crate::errors::EnrichmentError;
+
+const VIRUSTOTAL_URL: &str = "https://www.virustotal.com";
+const SHODAN_URL: &str = "https://api.shodan.io";
+
+/// What a provider knows about an indicator
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThreatIntelResult {
+    /// The provider that was asked (see `Enricher::provider`)
+    pub provider: String,
+    pub indicator: String,
+    /// Whether the provider considers the indicator malicious (e.g. flagged by an engine, or known to be vulnerable)
+    pub malicious: bool,
+    /// A provider-specific summary of the lookup. `null` if the provider knows nothing about the indicator
+    pub details: Value
+}
+
+/// A threat intelligence provider
+#[async_trait]
+pub trait Enricher: Send + Sync {
+    /// The name results are tagged with (e.g. `virustotal`)
+    fn provider(&self) -> &'static str;
+
+    /// Whether `indicator` can be looked up at all (e.g. some providers only know about IP addresses)
+    fn supports(&self, _indicator: &str) -> bool {
+        true
+    }
+
+    /// Looks up the IP address or domain name `indicator`
+    ///
+    /// # Errors
+    ///
+    /// `errors::EnrichmentError::UnexpectedStatus` - When the provider rejects the request (e.g. an invalid API key)
+    /// `errors::EnrichmentError::Http` - When the provider cannot be reached, or replies with invalid JSON
+    async fn enrich(&self, indicator: &str) -> Result<ThreatIntelResult>;
+}
+
+/// Sends a `GET` request, treating `404 Not Found` as the provider knowing nothing about the indicator
+async fn get_json(provider: &'static str, request: reqwest::RequestBuilder) -> Result<Option<Value>, EnrichmentError> {
+    let response = request.send().await?;
+
+    match response.status() {
+        StatusCode::NOT_FOUND => Ok(None),
+        status if status.is_success() => Ok(Some(response.json().await?)),
+        status => Err(EnrichmentError::UnexpectedStatus { provider, status: status.as_u16() })
+    }
+}
+
+/// Looks up IP addresses and domains in VirusTotal (API v3). They are malicious if any engine flagged them
+pub struct VirusTotalEnricher {
+    api_key: String,
+    base_url: String,
+    client: reqwest::Client
+}
+
+impl VirusTotalEnricher {
+    pub fn new(api_key: &str) -> Self {
+        Self { api_key: api_key.to_owned(), base_url: VIRUSTOTAL_URL.to_owned(), client: reqwest::Client::new() }
+    }
+
+    /// Sends the requests to `base_url` instead of VirusTotal, e.g. to a mock server
+    #[allow(dead_code)]
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_owned();
+        self
+    }
+}
+
+#[async_trait]
+impl Enricher for VirusTotalEnricher {
+    fn provider(&self) -> &'static str {
+        "virustotal"
+    }
+
+    async fn enrich(&self, indicator: &str) -> Result<ThreatIntelResult> {
+        let collection = if indicator.parse::<IpAddr>().is_ok() { "ip_addresses" } else { "domains" };
+        let url = format!("{}/api/v3/{}/{}", self.base_url, collection, indicator);
+
+        let response = get_json(self.provider(), self.client.get(&url).header("x-apikey", &self.api_key)).await?;
+        let (malicious, details) = match response {
+            Some(body) => {
+                let attributes = &body["data"]["attributes"];
+                let stats = &attributes["last_analysis_stats"];
+                (
+                    stats["malicious"].as_u64().unwrap_or(0) > 0,
+                    json!({ "last_analysis_stats": stats, "reputation": attributes["reputation"] })
+                )
+            },
+            None => (false, Value::Null)
+        };
+
+        Ok(ThreatIntelResult { provider: self.provider().to_owned(), indicator: indicator.to_owned(), malicious, details })
+    }
+}
+
+/// Looks up IP addresses in Shodan. They are malicious if the host is known to be vulnerable to any CVE
+pub struct ShodanEnricher {
+    api_key: String,
+    base_url: String,
+    client: reqwest::Client
+}
+
+impl ShodanEnricher {
+    pub fn new(api_key: &str) -> Self {
+        Self { api_key: api_key.to_owned(), base_url: SHODAN_URL.to_owned(), client: reqwest::Client::new() }
+    }
+
+    /// Sends the requests to `base_url` instead of Shodan, e.g. to a mock server
+    #[allow(dead_code)]
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_owned();
+        self
+    }
+}
+
+#[async_trait]
+impl Enricher for ShodanEnricher {
+    fn provider(&self) -> &'static str {
+        "shodan"
+    }
+
+    /// Shodan indexes hosts, not domains
+    fn supports(&self, indicator: &str) -> bool {
+        indicator.parse::<IpAddr>().is_ok()
+    }
+
+    async fn enrich(&self, indicator: &str) -> Result<ThreatIntelResult> {
+        let url = format!("{}/shodan/host/{}", self.base_url, indicator);
+
+        let response = get_json(self.provider(), self.client.get(&url).query(&[("key", &self.api_key)])).await?;
+        let (malicious, details) = match response {
+            Some(host) => {
+                // A list in the summary (`minify`) view, an object keyed by CVE in the full one
+                let vulns: Vec<String> = match &host["vulns"] {
+                    Value::Array(v) => v.iter().filter_map(|cve| cve.as_str().map(String::from)).collect(),
+                    Value::Object(v) => v.keys().cloned().collect(),
+                    _ => Vec::new()
+                };
+                (
+                    !vulns.is_empty(),
+                    json!({ "ports": host["ports"], "vulns": vulns, "org": host["org"], "os": host["os"] })
+                )
+            },
+            None => (false, Value::Null)
+        };
+
+        Ok(ThreatIntelResult { provider: self.provider().to_owned(), indicator: indicator.to_owned(), malicious, details })
+    }
+}
+
+/// Looks up indicators with each of its enrichers, caching the results. Meant to be shared by the loader threads
+pub struct ThreatIntel {
+    enrichers: Vec<Box<dyn Enricher>>,
+    cache: Mutex<LruCache<(String, String), ThreatIntelResult>>,
+    runtime: Runtime
+}
+
+impl ThreatIntel {
+    /// # Errors
+    ///
+    /// `std::io::Error` - When the runtime that drives the lookups cannot be started
+    pub fn new(enrichers: Vec<Box<dyn Enricher>>, cache_size: NonZeroUsize) -> Result<Self> {
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+
+        Ok(Self { enrichers, cache: Mutex::new(LruCache::new(cache_size)), runtime })
+    }
+
+    /// Enriches with every provider that has an API key in `enrichment_cfg`
+    ///
+    /// # Returns
+    /// `None` if no provider has an API key
+    pub fn from_cfg(enrichment_cfg: &EnrichmentCfg) -> Result<Option<Self>> {
+        let mut enrichers: Vec<Box<dyn Enricher>> = Vec::new();
+        if let Some(key) = enrichment_cfg.virustotal_api_key() {
+            enrichers.push(Box::new(VirusTotalEnricher::new(key)));
+        }
+        if let Some(key) = enrichment_cfg.shodan_api_key() {
+            enrichers.push(Box::new(ShodanEnricher::new(key)));
+        }
+        if enrichers.is_empty() {
+            return Ok(None);
+        }
+
+        info!("Enriching indicators with {}", enrichers.iter().map(|e| e.provider()).collect::<Vec<_>>().join(", "));
+        Ok(Some(Self::new(enrichers, enrichment_cfg.cache_size())?))
+    }
+
+    /// Looks up each of `indicators` with every enricher that supports it. Failed lookups are logged and left out
+    pub fn lookup(&self, indicators: &[String]) -> Vec<ThreatIntelResult> {
+        let mut results = Vec::new();
+
+        for indicator in indicators {
+            for enricher in self.enrichers.iter().filter(|e| e.supports(indicator)) {
+                let key = (enricher.provider().to_owned(), indicator.to_owned());
+                if let Some(cached) = self.cache.lock().unwrap().get(&key) {
+                    results.push(cached.clone());
+                    continue;
+                }
+
+                match self.runtime.block_on(enricher.enrich(indicator)) {
+                    Ok(result) => {
+                        self.cache.lock().unwrap().put(key, result.clone());
+                        results.push(result);
+                    },
+                    Err(e) => warn!("Could not look up {} in {}: {:#}", indicator, enricher.provider(), e)
+                }
+            }
+        }
+
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Server};
+
+    fn threat_intel(enrichers: Vec<Box<dyn Enricher>>) -> ThreatIntel {
+        ThreatIntel::new(enrichers, NonZeroUsize::new(10).unwrap()).unwrap()
+    }
+
+    fn virustotal(server: &Server) -> Box<dyn Enricher> {
+        Box::new(VirusTotalEnricher::new("vt-key").with_base_url(&server.url()))
+    }
+
+    fn shodan(server: &Server) -> Box<dyn Enricher> {
+        Box::new(ShodanEnricher::new("shodan-key").with_base_url(&server.url()))
+    }
+
+    #[test]
+    fn virustotal_flags_indicators_detected_by_an_engine() {
+        let mut server = Server::new();
+        let mock = server.mock("GET", "/api/v3/domains/evil.example.com")
+            .match_header("x-apikey", "vt-key")
+            .with_body(r#"{"data": {"attributes": {"reputation": -12,
+                           "last_analysis_stats": {"malicious": 3, "suspicious": 0, "harmless": 60}}}}"#)
+            .create();
+
+        let results = threat_intel(vec![virustotal(&server)]).lookup(&["evil.example.com".to_owned()]);
+
+        mock.assert();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].provider, "virustotal");
+        assert!(results[0].malicious);
+        assert_eq!(results[0].details["reputation"], -12);
+        assert_eq!(results[0].details["last_analysis_stats"]["malicious"], 3);
+    }
+
+    #[test]
+    fn virustotal_looks_up_ips_in_their_own_collection() {
+        let mut server = Server::new();
+        let mock = server.mock("GET", "/api/v3/ip_addresses/10.0.0.1")
+            .with_body(r#"{"data": {"attributes": {"last_analysis_stats": {"malicious": 0}}}}"#)
+            .create();
+
+        let results = threat_intel(vec![virustotal(&server)]).lookup(&["10.0.0.1".to_owned()]);
+
+        mock.assert();
+        assert!(!results[0].malicious);
+    }
+
+    #[test]
+    fn shodan_reports_known_vulnerabilities() {
+        let mut server = Server::new();
+        let mock = server.mock("GET", "/shodan/host/192.0.2.7")
+            .match_query(Matcher::UrlEncoded("key".to_owned(), "shodan-key".to_owned()))
+            .with_body(r#"{"ports": [22, 443], "vulns": ["CVE-2021-44228"], "org": "Example", "os": null}"#)
+            .create();
+
+        let results = threat_intel(vec![shodan(&server)]).lookup(&["192.0.2.7".to_owned()]);
+
+        mock.assert();
+        assert!(results[0].malicious);
+        assert_eq!(results[0].details["vulns"], json!(["CVE-2021-44228"]));
+        assert_eq!(results[0].details["ports"], json!([22, 443]));
+    }
+
+    #[test]
+    fn shodan_skips_domains() {
+        let mut server = Server::new();
+        let mock = server.mock("GET", Matcher::Any).expect(0).create();
+
+        assert!(threat_intel(vec![shodan(&server)]).lookup(&["example.com".to_owned()]).is_empty());
+        mock.assert();
+    }
+
+    #[test]
+    fn unknown_indicators_are_not_malicious() {
+        let mut server = Server::new();
+        server.mock("GET", "/shodan/host/192.0.2.8").match_query(Matcher::Any).with_status(404).create();
+
+        let results = threat_intel(vec![shodan(&server)]).lookup(&["192.0.2.8".to_owned()]);
+
+        assert!(!results[0].malicious);
+        assert_eq!(results[0].details, Value::Null);
+    }
+
+    #[test]
+    fn lookups_are_cached() {
+        let mut server = Server::new();
+        let mock = server.mock("GET", "/api/v3/domains/example.com")
+            .with_body(r#"{"data": {"attributes": {"last_analysis_stats": {"malicious": 0}}}}"#)
+            .expect(1)
+            .create();
+        let threat_intel = threat_intel(vec![virustotal(&server)]);
+
+        let first = threat_intel.lookup(&["example.com".to_owned()]);
+        let second = threat_intel.lookup(&["example.com".to_owned(), "example.com".to_owned()]);
+
+        mock.assert();
+        assert_eq!(second, vec![first[0].clone(), first[0].clone()]);
+    }
+
+    #[test]
+    fn failed_lookups_are_left_out_and_not_cached() {
+        let mut server = Server::new();
+        let mock = server.mock("GET", "/api/v3/domains/example.com").with_status(401).expect(2).create();
+        let threat_intel = threat_intel(vec![virustotal(&server)]);
+
+        assert!(threat_intel.lookup(&["example.com".to_owned()]).is_empty());
+        assert!(threat_intel.lookup(&["example.com".to_owned()]).is_empty());
+        mock.assert();
+    }
+
+    #[test]
+    fn every_enricher_is_asked() {
+        let mut server = Server::new();
+        server.mock("GET", "/api/v3/ip_addresses/192.0.2.9")
+            .with_body(r#"{"data": {"attributes": {"last_analysis_stats": {"malicious": 0}}}}"#)
+            .create();
+        server.mock("GET", Matcher::Regex("^/shodan/host/192.0.2.9".to_owned()))
+            .with_body(r#"{"ports": [80]}"#)
+            .create();
+
+        let results = threat_intel(vec![virustotal(&server), shodan(&server)]).lookup(&["192.0.2.9".to_owned()]);
+
+        let providers: Vec<&str> = results.iter().map(|r| r.provider.as_str()).collect();
+        assert_eq!(providers, vec!["virustotal", "shodan"]);
+        assert!(results.iter().all(|r| !r.malicious));
+    }
+}
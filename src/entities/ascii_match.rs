@@ -1,15 +1,41 @@
 #![allow(dead_code)]
 
+use std::collections::HashSet;
+use regex::Regex;
+
 use r2d2_postgres::postgres::{Row, Transaction};
+use r2d2_postgres::postgres::types::ToSql;
 use anyhow::Result;
-use crate::database::{Client, Insert};
-use crate::entities::RuleMatch;
+use serde_json::Value;
+use crate::database::{insert_rows, Client, Insert};
+use crate::entities::{FlatMatch, RuleMatch};
+
+/// Appended to matched strings that were cut short by `AsciiMatch::truncate`
+pub const TRUNCATION_MARKER: &str = "...[truncated]";
 
 #[derive(Debug)]
 pub struct AsciiMatch {
     id: Option<i32>,
     rule_match_id: i32,
-    matched_string: String
+    matched_string: String,
+    threat_intel: Option<Value>,
+    offset: usize,
+    length: usize,
+    raw_bytes: Option<Vec<u8>>,
+    context: Option<String>
+}
+
+/// Ignores `id`, which is only assigned on insert (see `Event`'s `PartialEq`)
+impl PartialEq for AsciiMatch {
+    fn eq(&self, other: &Self) -> bool {
+        self.rule_match_id == other.rule_match_id
+            && self.matched_string == other.matched_string
+            && self.threat_intel == other.threat_intel
+            && self.offset == other.offset
+            && self.length == other.length
+            && self.raw_bytes == other.raw_bytes
+            && self.context == other.context
+    }
 }
 
 impl Insert for AsciiMatch {
@@ -18,20 +44,62 @@ impl Insert for AsciiMatch {
         INSERT INTO ascii_matches
         (
             match_id,
-            matched_string
+            matched_string,
+            threat_intel,
+            byte_offset,
+            byte_length,
+            raw_bytes,
+            context
         )
         VALUES
         (
-            $1, $2
+            $1, $2, $3, $4, $5, $6, $7
         )
         RETURNING id
         ";
 
-        let row = conn.query_one(stmt, &[&self.rule_match_id, &self.matched_string])?;
+        let row = conn.query_one(
+            stmt,
+            &[
+                &self.rule_match_id,
+                &self.matched_string,
+                &self.threat_intel,
+                &(self.offset as i64),
+                &(self.length as i64),
+                &self.raw_bytes,
+                &self.context
+            ]
+        )?;
         self.id = row.get(0);
 
         Ok(())
     }
+
+    fn insert_all(items: &mut [Self], conn: &mut Transaction) -> Result<()> {
+        let positions: Vec<(i64, i64)> = items.iter().map(|m| (m.offset as i64, m.length as i64)).collect();
+        let rows: Vec<Vec<&(dyn ToSql + Sync)>> = items.iter()
+            .zip(&positions)
+            .map(|(m, (offset, length))| vec![
+                &m.rule_match_id as &(dyn ToSql + Sync),
+                &m.matched_string,
+                &m.threat_intel,
+                offset,
+                length,
+                &m.raw_bytes,
+                &m.context
+            ])
+            .collect();
+        let columns = [
+            "match_id", "matched_string", "threat_intel", "byte_offset", "byte_length", "raw_bytes", "context"
+        ];
+        let ids = insert_rows(conn, "ascii_matches", &columns, &rows)?;
+
+        for (item, id) in items.iter_mut().zip(ids) {
+            item.id = Some(id);
+        }
+
+        Ok(())
+    }
 }
 
 impl AsciiMatch {
@@ -39,12 +107,43 @@ impl AsciiMatch {
         Self::create(None, rule_match_id, matched_string)
     }
 
+    /// A match that is not valid UTF-8 (see `FlatMatch::raw_data`). The bytes are kept in `raw_bytes`, while
+    /// `matched_string` holds a lossy conversion of them, with invalid sequences (and null bytes, which postgres
+    /// does not allow in text) replaced by U+FFFD
+    pub fn from_bytes(rule_match_id: i32, bytes: Vec<u8>) -> Self {
+        let matched_string = String::from_utf8_lossy(&bytes).replace('\0', "\u{FFFD}");
+        let mut ascii_match = Self::create(None, rule_match_id, matched_string);
+        ascii_match.raw_bytes = Some(bytes);
+
+        ascii_match
+    }
+
     pub fn from_row(row: &Row) -> Self {
-        Self::create(
+        let mut ascii_match = Self::create(
             row.get("id"),
-            row.get("rule_match_id"),
+            row.get("match_id"),
             row.get("matched_string")
-        )
+        );
+        ascii_match.threat_intel = row.get("threat_intel");
+        ascii_match.offset = row.get::<_, i64>("byte_offset") as usize;
+        ascii_match.length = row.get::<_, i64>("byte_length") as usize;
+        ascii_match.raw_bytes = row.get("raw_bytes");
+        ascii_match.context = row.get("context");
+
+        ascii_match
+    }
+
+    /// Sets where the match was found within the content of its event (see `FlatMatch::offsets`)
+    pub fn with_position(mut self, offset: usize, length: usize) -> Self {
+        self.offset = offset;
+        self.length = length;
+        self
+    }
+
+    /// Sets the text around the match (see `FlatMatch::contexts`)
+    pub fn with_context(mut self, context: Option<String>) -> Self {
+        self.context = context;
+        self
     }
 
     pub fn with_id(id: i32, rule_match_id: i32, matched_string: String) -> Self {
@@ -69,7 +168,215 @@ impl AsciiMatch {
         &self.matched_string
     }
 
+    /// The match along with the text around it, if it was extracted
+    pub fn context(&self) -> Option<&str> {
+        self.context.as_deref()
+    }
+
+    /// The matched bytes, if they are not valid UTF-8 (see `AsciiMatch::from_bytes`)
+    pub fn raw_bytes(&self) -> Option<&[u8]> {
+        self.raw_bytes.as_deref()
+    }
+
+    /// Whether the match was found in binary content, i.e. `matched_string` is only an approximation of `raw_bytes`
+    pub fn is_binary(&self) -> bool {
+        self.raw_bytes.is_some()
+    }
+
+    /// The byte offset of the match within the content of its event
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The length of the match in bytes. Not affected by `truncate`
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    /// The part of `raw_content` at the position of the match, i.e. the full matched string even if it was truncated
+    ///
+    /// # Returns
+    /// `None` if the position is out of the bounds of `raw_content` (or splits a character), e.g. because the
+    /// content was modified after it was scanned
+    pub fn slice_from_content<'a>(&self, raw_content: &'a str) -> Option<&'a str> {
+        let end = self.offset.checked_add(self.length)?;
+
+        raw_content.get(self.offset..end)
+    }
+
+    /// What threat intelligence providers know about the indicators in `matched_string` (see `enrichment`)
+    pub fn threat_intel(&self) -> Option<&Value> {
+        self.threat_intel.as_ref()
+    }
+
+    pub fn set_threat_intel(&mut self, threat_intel: Value) {
+        self.threat_intel = Some(threat_intel);
+    }
+
+    /// Keeps the first `max_length` characters of `matched_string`, followed by `TRUNCATION_MARKER`.
+    /// Does nothing if `max_length` is 0, or the string is not longer than that
+    pub fn truncate(&mut self, max_length: usize) {
+        if max_length == 0 {
+            return;
+        }
+
+        if let Some((cut, _)) = self.matched_string.char_indices().nth(max_length) {
+            self.matched_string.truncate(cut);
+            self.matched_string.push_str(TRUNCATION_MARKER);
+        }
+    }
+
+    /// Removes the matches whose `matched_string` (and `raw_bytes`) has already been seen, preserving the order of
+    /// the rest
+    ///
+    /// The Yara engine reports overlapping matches of the same string separately. Since it reports them
+    /// in ascending offset order, the match that is kept is always the one with the lowest offset
+    pub fn dedup_within_rule_match(matches: Vec<AsciiMatch>) -> Vec<AsciiMatch> {
+        let mut seen: HashSet<(String, Option<Vec<u8>>)> = HashSet::new();
+
+        matches.into_iter()
+            .filter(|m| seen.insert((m.matched_string.clone(), m.raw_bytes.clone())))
+            .collect()
+    }
+
+    /// Removes the matches whose matched strings are all known to be benign, i.e. each of them matches at least one
+    /// of `patterns`. Matches without any matched strings (e.g. of condition-only rules) are kept
+    pub fn apply_data_allowlist(matches: Vec<FlatMatch>, patterns: &[Regex]) -> Vec<FlatMatch> {
+        if patterns.is_empty() {
+            return matches;
+        }
+
+        matches.into_iter()
+            .filter(|m| {
+                m.data().is_empty() || !m.data().iter().all(|d| patterns.iter().any(|p| p.is_match(d)))
+            })
+            .collect()
+    }
+
     fn create(id: Option<i32>, rule_match_id: i32, matched_string: String) -> Self {
-        Self { id, rule_match_id, matched_string }
+        Self { id, rule_match_id, matched_string, threat_intel: None, offset: 0, length: 0, raw_bytes: None, context: None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matched_strings(matches: &[AsciiMatch]) -> Vec<&str> {
+        matches.iter().map(|m| m.matched_string()).collect()
+    }
+
+    fn flat_match(rule_name: &str, data: &[&str]) -> FlatMatch {
+        let data: Vec<Vec<u8>> = data.iter().map(|d| d.as_bytes().to_vec()).collect();
+        FlatMatch::new(rule_name.to_owned(), vec![], &data)
+    }
+
+    #[test]
+    fn long_matched_strings_are_truncated() {
+        let mut long = AsciiMatch::new(1, "password=hunter2".to_owned());
+        let mut short = AsciiMatch::new(1, "pw=1".to_owned());
+        let mut unlimited = AsciiMatch::new(1, "password=hunter2".to_owned());
+
+        long.truncate(8);
+        short.truncate(8);
+        unlimited.truncate(0);
+
+        assert_eq!(long.matched_string(), "password...[truncated]");
+        assert_eq!(short.matched_string(), "pw=1");
+        assert_eq!(unlimited.matched_string(), "password=hunter2");
+    }
+
+    #[test]
+    fn truncation_respects_character_boundaries() {
+        let mut m = AsciiMatch::new(1, "κωδικός".to_owned());
+
+        m.truncate(3);
+
+        assert_eq!(m.matched_string(), "κωδ...[truncated]");
+    }
+
+    #[test]
+    fn matches_with_only_allowlisted_data_are_removed() {
+        let patterns = [Regex::new(r"^pw: (changeme|example)$").unwrap(), Regex::new("localhost").unwrap()];
+        let matches = vec![
+            flat_match("default::Benign", &["pw: changeme", "pw: example", "http://localhost"]),
+            flat_match("default::Mixed", &["pw: changeme", "pw: hunter2"]),
+            flat_match("default::ConditionOnly", &[])
+        ];
+
+        let rule_names: Vec<String> = AsciiMatch::apply_data_allowlist(matches, &patterns).iter()
+            .map(|m| m.rule_name().to_owned())
+            .collect();
+        assert_eq!(rule_names, vec!["default::Mixed", "default::ConditionOnly"]);
+    }
+
+    #[test]
+    fn slices_the_matched_string_from_the_content() {
+        let content = "user: admin\npassword: hunter2\n";
+        let m = AsciiMatch::new(1, "password: hunter2".to_owned()).with_position(12, 17);
+
+        assert_eq!(m.slice_from_content(content), Some(m.matched_string()));
+    }
+
+    #[test]
+    fn slicing_survives_truncation() {
+        let content = "κωδικός: hunter2";
+        let mut m = AsciiMatch::new(1, content.to_owned()).with_position(0, content.len());
+
+        m.truncate(3);
+
+        assert_eq!(m.slice_from_content(content), Some(content));
+    }
+
+    #[test]
+    fn out_of_bounds_positions_are_not_sliced() {
+        let content = "κωδικός: hunter2";
+
+        assert_eq!(AsciiMatch::new(1, "x".to_owned()).with_position(10, 100).slice_from_content(content), None);
+        assert_eq!(AsciiMatch::new(1, "x".to_owned()).with_position(usize::MAX, 1).slice_from_content(content), None);
+        assert_eq!(AsciiMatch::new(1, "x".to_owned()).with_position(1, 2).slice_from_content(content), None);
+    }
+
+    #[test]
+    fn dedup_keeps_a_single_copy_of_each_string() {
+        let matches = vec![
+            AsciiMatch::new(1, "foo".to_owned()),
+            AsciiMatch::new(1, "foo".to_owned()),
+            AsciiMatch::new(1, "foo".to_owned())
+        ];
+
+        assert_eq!(matched_strings(&AsciiMatch::dedup_within_rule_match(matches)), vec!["foo"]);
+    }
+
+    #[test]
+    fn binary_matches_keep_their_bytes() {
+        let m = AsciiMatch::from_bytes(1, vec![b'M', b'Z', 0x90, 0x00]);
+
+        assert!(m.is_binary());
+        assert_eq!(m.raw_bytes(), Some(&[b'M', b'Z', 0x90, 0x00][..]));
+        assert_eq!(m.matched_string(), "MZ\u{FFFD}\u{FFFD}");
+        assert!(!AsciiMatch::new(1, "MZ".to_owned()).is_binary());
+    }
+
+    #[test]
+    fn dedup_tells_apart_binary_matches_that_look_the_same() {
+        let matches = vec![
+            AsciiMatch::from_bytes(1, vec![0xff]),
+            AsciiMatch::from_bytes(1, vec![0xfe]),
+            AsciiMatch::from_bytes(1, vec![0xff])
+        ];
+
+        let deduped = AsciiMatch::dedup_within_rule_match(matches);
+
+        assert_eq!(deduped.iter().map(|m| m.raw_bytes().unwrap()).collect::<Vec<&[u8]>>(), vec![&[0xff][..], &[0xfe][..]]);
+    }
+
+    #[test]
+    fn dedup_preserves_the_order_of_first_occurrences() {
+        let matches = ["bar", "foo", "bar", "baz", "foo"].iter()
+            .map(|s| AsciiMatch::new(1, s.to_string()))
+            .collect();
+
+        assert_eq!(matched_strings(&AsciiMatch::dedup_within_rule_match(matches)), vec!["bar", "foo", "baz"]);
     }
 }
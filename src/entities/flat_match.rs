@@ -1,15 +1,40 @@
-use std::str;
-use yara::{Rule, YrString};
-use log::error;
+use std::collections::{HashMap, HashSet};
+use yara::{Match, MetadataValue, Rule, YrString};
+use chrono::{SecondsFormat, Utc};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+/// The namespace under which the (v5) UUIDs of all STIX objects produced by infobserve are generated
+pub const STIX_NAMESPACE: Uuid = Uuid::from_u128(0x8c3a_6f5e_2b1d_4f0a_9e47_d1c2_b3a4_e5f6);
+
+/// The confidence of matches whose rule has no `confidence` meta field (see `FlatMatch::confidence`)
+pub const DEFAULT_CONFIDENCE: i64 = 100;
 
 /// `The yara::Rule` structure is complicated and largely unnecessary for our needs
 /// This struct is a flat(ter) representation of the above, that only stores the matched rule's
-/// name, tags and data (the actual matches)
+/// name, tags, meta fields and data (the actual matches), along with the byte position of each match in the scanned
+/// content. Matches that are not valid UTF-8 are kept apart, as raw bytes
 #[derive(Debug)]
 pub struct FlatMatch {
     rule_name: String,
     tags: Vec<String>,
-    data: Vec<String>
+    data: Vec<String>,
+    offsets: Vec<usize>,
+    lengths: Vec<usize>,
+    raw_data: Vec<Vec<u8>>,
+    raw_offsets: Vec<usize>,
+    raw_lengths: Vec<usize>,
+    contexts: Vec<Option<String>>,
+    confidence: Option<i64>,
+    meta: HashMap<String, String>
+}
+
+/// Matches are equal when the same rule matched the same data. The positions, contexts, confidence and meta fields
+/// only follow from those (and from the scanned content)
+impl PartialEq for FlatMatch {
+    fn eq(&self, other: &Self) -> bool {
+        self.rule_name == other.rule_name && self.tags == other.tags && self.data == other.data
+    }
 }
 
 impl FlatMatch {
@@ -32,7 +57,18 @@ impl FlatMatch {
     pub fn from_rule(rule: Rule) -> FlatMatch {
         let rule_name = format!("{}::{}", rule.namespace, rule.identifier);
         let tags: Vec<String> = rule.tags.iter().map(|&t| String::from(t)).collect();
-        let mut byte_data: Vec<Vec<u8>> = Vec::<Vec<u8>>::new();
+        let meta: HashMap<String, String> = rule.metadatas.iter()
+            .map(|m| {
+                let value = match m.value {
+                    MetadataValue::Integer(i) => i.to_string(),
+                    MetadataValue::String(s) => s.to_owned(),
+                    MetadataValue::Boolean(b) => b.to_string()
+                };
+                (m.identifier.to_owned(), value)
+            })
+            .collect();
+        let confidence = meta.get("confidence").and_then(|c| c.trim().parse().ok());
+        let mut yara_matches: Vec<Match> = Vec::new();
 
         let rule_strings: Vec<YrString> = rule.strings;
         for rule_string in rule_strings.into_iter() {
@@ -42,12 +78,26 @@ impl FlatMatch {
             }
             let rule_matches = rule_string.matches;
 
-            for single_match in rule_matches.into_iter() {
-                byte_data.push(single_match.data);
-            }
+            yara_matches.extend(rule_matches);
+        }
+
+        let flat_match = FlatMatch::from_yara_matches(rule_name, tags, yara_matches).with_meta(meta);
+        match confidence {
+            Some(c) => flat_match.with_confidence(c),
+            None => flat_match
         }
+    }
 
-        FlatMatch::new(rule_name, tags, &byte_data)
+    /// Overrides the confidence read from the rule's meta fields (see `FlatMatch::confidence`)
+    pub fn with_confidence(mut self, confidence: i64) -> Self {
+        self.confidence = Some(confidence);
+        self
+    }
+
+    /// Sets the rule's meta fields (see `FlatMatch::meta`)
+    pub fn with_meta(mut self, meta: HashMap<String, String>) -> Self {
+        self.meta = meta;
+        self
     }
 
     #[allow(dead_code)]
@@ -65,12 +115,176 @@ impl FlatMatch {
         &self.data
     }
 
+    /// The byte offset of each of `data` within the scanned content
+    pub fn offsets(&self) -> &[usize] {
+        &self.offsets
+    }
+
+    /// The length (in bytes) of each of `data` within the scanned content. Can be longer than the matched
+    /// string itself, since the Yara engine caps the data it reports for each match
+    pub fn lengths(&self) -> &[usize] {
+        &self.lengths
+    }
+
+    /// The matches that are not valid UTF-8 (e.g. found in binary content), which are left out of `data`
+    pub fn raw_data(&self) -> &[Vec<u8>] {
+        &self.raw_data
+    }
+
+    /// The byte offset of each of `raw_data` within the scanned content
+    pub fn raw_offsets(&self) -> &[usize] {
+        &self.raw_offsets
+    }
+
+    /// Same as `lengths`, for `raw_data`
+    pub fn raw_lengths(&self) -> &[usize] {
+        &self.raw_lengths
+    }
+
+    /// The text around each of `data` (see `FlatMatch::extract_contexts`). Empty unless extracted
+    pub fn contexts(&self) -> &[Option<String>] {
+        &self.contexts
+    }
+
+    /// Sets the `contexts` of `data`: each match along with up to `window` characters before and after it, as found
+    /// in `content` (the scanned content). Matches whose position is out of the bounds of `content` get no context
+    pub fn extract_contexts(&mut self, content: &str, window: usize) {
+        self.contexts = self.offsets.iter()
+            .zip(&self.lengths)
+            .map(|(&offset, &length)| context_around(content, offset, length, window))
+            .collect();
+    }
+
+    /// How much the rule's author trusts its matches, as set in its `confidence` meta field (e.g.
+    /// `meta: confidence = 60`). Rules without one are fully trusted (`DEFAULT_CONFIDENCE`)
+    pub fn confidence(&self) -> i64 {
+        self.confidence.unwrap_or(DEFAULT_CONFIDENCE)
+    }
+
+    /// The meta fields of the rule (e.g. `meta: author = "infobserve"`), with their values as strings. Integer and
+    /// boolean values are formatted as such (`60`, `true`)
+    pub fn meta(&self) -> &HashMap<String, String> {
+        &self.meta
+    }
+
+    /// The number of matched strings that are duplicates of a previous one
+    pub fn num_duplicate_data(&self) -> usize {
+        let unique: HashSet<&String> = self.data.iter().collect();
+
+        self.data.len() - unique.len()
+    }
+
+    /// Removes the matches of the rules in `allowlist`. Rule names include their namespace
+    /// (`namespace::identifier`, e.g. `default::MyPass`)
+    pub fn apply_allowlist(matches: Vec<FlatMatch>, allowlist: &HashSet<String>) -> Vec<FlatMatch> {
+        matches.into_iter()
+            .filter(|m| !allowlist.contains(&m.rule_name))
+            .collect()
+    }
+
+    /// Discards the matched strings shorter than `min_length` characters (or bytes, for `raw_data`). Matches that are left without any
+    /// matched strings are discarded altogether, while those that had none to begin with are kept
+    ///
+    /// # Returns
+    /// The remaining matches, along with the number of discarded strings
+    pub fn filter_short_data(matches: Vec<FlatMatch>, min_length: usize) -> (Vec<FlatMatch>, usize) {
+        let mut num_filtered = 0;
+        if min_length == 0 {
+            return (matches, num_filtered);
+        }
+
+        let matches = matches.into_iter()
+            .filter_map(|mut m| {
+                let had_data = !m.data.is_empty() || !m.raw_data.is_empty();
+                let keep: Vec<bool> = m.data.iter().map(|d| d.chars().count() >= min_length).collect();
+                retain_flagged(&mut m.data, &keep);
+                retain_flagged(&mut m.offsets, &keep);
+                retain_flagged(&mut m.lengths, &keep);
+                retain_flagged(&mut m.contexts, &keep);
+                // Raw data has no characters, so its length is measured in bytes
+                let keep_raw: Vec<bool> = m.raw_data.iter().map(|d| d.len() >= min_length).collect();
+                retain_flagged(&mut m.raw_data, &keep_raw);
+                retain_flagged(&mut m.raw_offsets, &keep_raw);
+                retain_flagged(&mut m.raw_lengths, &keep_raw);
+                num_filtered += keep.iter().chain(&keep_raw).filter(|k| !**k).count();
+
+                if had_data && m.data.is_empty() && m.raw_data.is_empty() {
+                    None
+                } else {
+                    Some(m)
+                }
+            })
+            .collect();
+
+        (matches, num_filtered)
+    }
+
+    /// The namespace part of `rule_name` (`namespace::identifier`)
+    #[allow(dead_code)]
+    pub fn namespace(&self) -> &str {
+        match self.rule_name.split_once("::") {
+            Some((namespace, _)) => namespace,
+            None => ""
+        }
+    }
+
+    /// The identifier part of `rule_name` (`namespace::identifier`)
+    pub fn identifier(&self) -> &str {
+        match self.rule_name.split_once("::") {
+            Some((_, identifier)) => identifier,
+            None => &self.rule_name
+        }
+    }
+
+    /// Converts the match into a STIX 2.1 `indicator` object
+    ///
+    /// The indicator's ID is a v5 UUID derived from `rule_name`, so the same rule always produces the same
+    /// indicator ID. Its pattern is a Yara rule that matches the strings found in the event. The rule's tags become
+    /// the indicator's `labels`, which are left out when there are none (STIX forbids an empty list)
+    ///
+    /// # Arguments
+    ///
+    /// * `event_url` - The url of the event in which the match was found
+    pub fn to_stix_indicator(&self, event_url: &str) -> Value {
+        let now = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+
+        let mut indicator = json!({
+            "type": "indicator",
+            "spec_version": "2.1",
+            "id": format!("indicator--{}", Uuid::new_v5(&STIX_NAMESPACE, self.rule_name.as_bytes())),
+            "created": now,
+            "modified": now,
+            "name": self.rule_name,
+            "description": format!("Yara rule {} matched {}", self.rule_name, event_url),
+            "pattern": self.yara_pattern(),
+            "pattern_type": "yara",
+            "valid_from": now
+        });
+        if !self.tags.is_empty() {
+            indicator["labels"] = json!(self.tags);
+        }
+
+        indicator
+    }
+
+    /// A Yara rule named after `identifier` that matches any of the matched strings
+    fn yara_pattern(&self) -> String {
+        let strings: String = self.data.iter()
+            .enumerate()
+            .map(|(i, d)| format!("$s{} = \"{}\" ", i, yara_escape(d)))
+            .collect();
+
+        if strings.is_empty() {
+            format!("rule {} {{ condition: false }}", self.identifier())
+        } else {
+            format!("rule {} {{ strings: {}condition: any of them }}", self.identifier(), strings)
+        }
+    }
+
     /// Constructs a new `FlatMatch` object by iterating over the first dimension of `matches`,
     /// and converting each element of the second from a byte array to a string
     ///
-    /// If a byte array does not represent a valid unicode byte sequence, it it dropped
-    /// and a warning is emitted. We should be expecting a few of those until we support binary
-    /// events as well
+    /// If a byte array does not represent a valid unicode byte sequence, it is kept as it is in `raw_data`
     ///
     /// # Arguments
     ///
@@ -86,14 +300,321 @@ impl FlatMatch {
     /// let fm = FlatMatcH::new(String::from("MyRule"), vec!["hey", "ya"], vec![vec![66, 6f, 6f], vec![62, 61, 72]])
     /// assert_eq!(fm.data, ["foo".to_string(), "bar".to_string()])
     /// ```
-    fn new(rule_name: String, tags: Vec<String>, matches: &[Vec<u8>]) -> FlatMatch {
+    ///
+    /// The positions of the matches are unknown, so they are all placed at offset 0 (see `from_yara_matches`)
+    #[allow(dead_code)]
+    pub(crate) fn new(rule_name: String, tags: Vec<String>, matches: &[Vec<u8>]) -> FlatMatch {
+        let matches = matches.iter()
+            .map(|data| Match { base: 0, offset: 0, length: data.len(), data: data.clone() })
+            .collect();
+
+        FlatMatch::from_yara_matches(rule_name, tags, matches)
+    }
+
+    /// Same as `new`, but keeps the offset and length of each of the `matches` as well
+    pub(crate) fn from_yara_matches(rule_name: String, tags: Vec<String>, matches: Vec<Match>) -> FlatMatch {
         let mut data: Vec<String> = Vec::new();
-        for single_match in matches.iter() {
-            match str::from_utf8(single_match) {
-                Ok(match_string) => data.push(match_string.to_string()),
-                Err(e) => error!("Could not convert byte array {:?} into string ({}) for Rule {}", single_match, e, rule_name)
+        let mut offsets: Vec<usize> = Vec::new();
+        let mut lengths: Vec<usize> = Vec::new();
+        let mut raw_data: Vec<Vec<u8>> = Vec::new();
+        let mut raw_offsets: Vec<usize> = Vec::new();
+        let mut raw_lengths: Vec<usize> = Vec::new();
+        for single_match in matches.into_iter() {
+            match String::from_utf8(single_match.data) {
+                Ok(match_string) => {
+                    data.push(match_string);
+                    offsets.push(single_match.offset);
+                    lengths.push(single_match.length);
+                },
+                Err(e) => {
+                    raw_data.push(e.into_bytes());
+                    raw_offsets.push(single_match.offset);
+                    raw_lengths.push(single_match.length);
+                }
             }
         }
-        FlatMatch { rule_name, tags, data }
+        FlatMatch {
+            rule_name,
+            tags,
+            data,
+            offsets,
+            lengths,
+            raw_data,
+            raw_offsets,
+            raw_lengths,
+            contexts: Vec::new(),
+            confidence: None,
+            meta: HashMap::new()
+        }
+    }
+}
+
+/// The `length` bytes of `content` at `offset`, along with up to `window` characters on either side. A position
+/// that splits a character is widened to include all of it
+fn context_around(content: &str, offset: usize, length: usize, window: usize) -> Option<String> {
+    let end = offset.checked_add(length)?;
+    if end > content.len() {
+        return None;
+    }
+
+    let start = (0..=offset).rev().find(|&i| content.is_char_boundary(i))?;
+    let end = (end..=content.len()).find(|&i| content.is_char_boundary(i))?;
+    let context_start = content[..start].char_indices().rev().take(window).last().map_or(start, |(i, _)| i);
+    let context_end = content[end..].char_indices().nth(window).map_or(content.len(), |(i, _)| end + i);
+
+    Some(content[context_start..context_end].to_owned())
+}
+
+/// Keeps the elements of `items` whose flag in `keep` is set. Elements past the end of `keep` are kept
+fn retain_flagged<T>(items: &mut Vec<T>, keep: &[bool]) {
+    let mut flags = keep.iter();
+    items.retain(|_| *flags.next().unwrap_or(&true));
+}
+
+/// `text` as the body of a Yara text string: quotes and backslashes are escaped, and every byte outside printable
+/// ASCII is written as `\xNN`
+fn yara_escape(text: &str) -> String {
+    text.bytes().map(|b| match b {
+        b'"' | b'\\' => format!("\\{}", b as char),
+        0x20..=0x7e => (b as char).to_string(),
+        _ => format!("\\x{:02x}", b)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonschema::JSONSchema;
+
+    /// The parts of the STIX 2.1 `indicator` schema that apply to the objects we produce
+    fn indicator_schema() -> Value {
+        json!({
+            "type": "object",
+            "required": ["type", "spec_version", "id", "created", "modified", "pattern", "pattern_type", "valid_from"],
+            "properties": {
+                "type": { "const": "indicator" },
+                "spec_version": { "const": "2.1" },
+                "id": {
+                    "type": "string",
+                    "pattern": "^indicator--[0-9a-f]{8}-[0-9a-f]{4}-[1-5][0-9a-f]{3}-[89ab][0-9a-f]{3}-[0-9a-f]{12}$"
+                },
+                "created": { "type": "string", "pattern": "^\\d{4}-\\d{2}-\\d{2}T\\d{2}:\\d{2}:\\d{2}(\\.\\d+)?Z$" },
+                "modified": { "type": "string", "pattern": "^\\d{4}-\\d{2}-\\d{2}T\\d{2}:\\d{2}:\\d{2}(\\.\\d+)?Z$" },
+                "name": { "type": "string" },
+                "pattern": { "type": "string" },
+                "pattern_type": { "type": "string" },
+                "valid_from": { "type": "string", "pattern": "^\\d{4}-\\d{2}-\\d{2}T\\d{2}:\\d{2}:\\d{2}(\\.\\d+)?Z$" },
+                "labels": { "type": "array", "items": { "type": "string" }, "minItems": 1 }
+            }
+        })
+    }
+
+    fn flat_match() -> FlatMatch {
+        FlatMatch::new(
+            "default::MyPass".to_owned(),
+            vec!["password".to_owned()],
+            &[b"pw: \"hello\"".to_vec()]
+        )
+    }
+
+    #[test]
+    fn splits_the_rule_name() {
+        let fm = flat_match();
+        assert_eq!(fm.namespace(), "default");
+        assert_eq!(fm.identifier(), "MyPass");
+    }
+
+    #[test]
+    fn matches_of_the_same_data_are_equal() {
+        let mut with_context = flat_match().with_confidence(50);
+        with_context.extract_contexts("pw: \"hello\"", 64);
+
+        assert_eq!(with_context, flat_match());
+        assert_ne!(flat_match(), FlatMatch::new("default::MyPass".to_owned(), vec![], &[b"pw: \"hello\"".to_vec()]));
+    }
+
+    #[test]
+    fn allowlisted_rules_are_removed() {
+        let matches = vec![
+            flat_match(),
+            FlatMatch::new("default::Noisy".to_owned(), vec![], &[b"foo".to_vec()])
+        ];
+        let allowlist: HashSet<String> = HashSet::from(["default::Noisy".to_owned()]);
+
+        let matches = FlatMatch::apply_allowlist(matches, &allowlist);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].rule_name(), "default::MyPass");
+    }
+
+    #[test]
+    fn short_data_is_filtered() {
+        let matches = vec![
+            FlatMatch::new("default::Mixed".to_owned(), vec![], &[b"pwd".to_vec(), b"hunter22".to_vec()]),
+            FlatMatch::new("default::Short".to_owned(), vec![], &[b"pw".to_vec()]),
+            FlatMatch::new("default::NoStrings".to_owned(), vec![], &[])
+        ];
+
+        let (matches, num_filtered) = FlatMatch::filter_short_data(matches, 5);
+
+        assert_eq!(num_filtered, 2);
+        assert_eq!(matches.iter().map(FlatMatch::rule_name).collect::<Vec<&str>>(), vec!["default::Mixed", "default::NoStrings"]);
+        assert_eq!(matches[0].data(), &vec!["hunter22".to_owned()]);
+    }
+
+    #[test]
+    fn positions_follow_the_data_they_belong_to() {
+        let fm = FlatMatch::from_yara_matches(
+            "default::Mixed".to_owned(),
+            vec![],
+            vec![
+                Match { base: 0, offset: 4, length: 3, data: b"pwd".to_vec() },
+                Match { base: 0, offset: 9, length: 2, data: vec![0xff, 0xfe] },
+                Match { base: 0, offset: 20, length: 8, data: b"hunter22".to_vec() }
+            ]
+        );
+        assert_eq!(fm.offsets(), &[4, 20]);
+        assert_eq!(fm.lengths(), &[3, 8]);
+
+        let (matches, _) = FlatMatch::filter_short_data(vec![fm], 5);
+        assert_eq!(matches[0].data(), &vec!["hunter22".to_owned()]);
+        assert_eq!(matches[0].offsets(), &[20]);
+        assert_eq!(matches[0].lengths(), &[8]);
+    }
+
+    #[test]
+    fn non_utf8_matches_are_kept_as_raw_data() {
+        let fm = FlatMatch::from_yara_matches(
+            "default::Binary".to_owned(),
+            vec![],
+            vec![
+                Match { base: 0, offset: 0, length: 4, data: b"MZ\x90\x00".to_vec() },
+                Match { base: 0, offset: 9, length: 3, data: vec![0xde, 0xad, 0xbe] }
+            ]
+        );
+
+        assert!(fm.data().is_empty());
+        assert_eq!(fm.raw_data(), &[b"MZ\x90\x00".to_vec(), vec![0xde, 0xad, 0xbe]]);
+        assert_eq!(fm.raw_offsets(), &[0, 9]);
+        assert_eq!(fm.raw_lengths(), &[4, 3]);
+    }
+
+    #[test]
+    fn short_raw_data_is_filtered_by_bytes() {
+        let matches = vec![
+            FlatMatch::new("default::LongBinary".to_owned(), vec![], &[vec![0xff; 6]]),
+            FlatMatch::new("default::ShortBinary".to_owned(), vec![], &[vec![0xff; 2]])
+        ];
+
+        let (matches, num_filtered) = FlatMatch::filter_short_data(matches, 5);
+
+        assert_eq!(num_filtered, 1);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].rule_name(), "default::LongBinary");
+        assert_eq!(matches[0].raw_lengths(), &[6]);
+    }
+
+    #[test]
+    fn contexts_surround_the_matches() {
+        let content = "user: admin\npassword: hunter2\nhost: db";
+        let mut fm = FlatMatch::from_yara_matches(
+            "default::Password".to_owned(),
+            vec![],
+            vec![
+                Match { base: 0, offset: 12, length: 17, data: b"password: hunter2".to_vec() },
+                Match { base: 0, offset: 0, length: 4, data: b"user".to_vec() }
+            ]
+        );
+
+        fm.extract_contexts(content, 6);
+
+        assert_eq!(fm.contexts(), &[Some("admin\npassword: hunter2\nhost:".to_owned()), Some("user: admi".to_owned())]);
+    }
+
+    #[test]
+    fn contexts_respect_character_boundaries() {
+        let content = "κωδικός: hunter2 ok";
+        let mut fm = FlatMatch::from_yara_matches(
+            "default::Password".to_owned(),
+            vec![],
+            vec![Match { base: 0, offset: 16, length: 7, data: b"hunter2".to_vec() }]
+        );
+
+        fm.extract_contexts(content, 3);
+
+        assert_eq!(fm.contexts(), &[Some("ς: hunter2 ok".to_owned())]);
+    }
+
+    #[test]
+    fn out_of_bounds_matches_have_no_context() {
+        let mut fm = FlatMatch::from_yara_matches(
+            "default::Password".to_owned(),
+            vec![],
+            vec![Match { base: 0, offset: 10, length: 7, data: b"hunter2".to_vec() }]
+        );
+
+        fm.extract_contexts("short", 64);
+
+        assert_eq!(fm.contexts(), &[None]);
+    }
+
+    #[test]
+    fn counts_duplicate_data() {
+        let fm = FlatMatch::new(
+            "default::Foo".to_owned(),
+            vec![],
+            &[b"foo".to_vec(), b"bar".to_vec(), b"foo".to_vec(), b"foo".to_vec()]
+        );
+
+        assert_eq!(fm.num_duplicate_data(), 2);
+        assert_eq!(flat_match().num_duplicate_data(), 0);
+    }
+
+    #[test]
+    fn stix_indicator_is_valid() {
+        let schema = JSONSchema::compile(&indicator_schema()).unwrap();
+        let indicator = flat_match().to_stix_indicator("https://pastebin.com/foo");
+
+        assert!(schema.is_valid(&indicator), "{}", indicator);
+        assert_eq!(indicator["pattern_type"], "yara");
+        assert_eq!(indicator["labels"], json!(["password"]));
+    }
+
+    #[test]
+    fn stix_indicator_without_tags_has_no_labels() {
+        let schema = JSONSchema::compile(&indicator_schema()).unwrap();
+        let fm = FlatMatch::new("default::MyPass".to_owned(), vec![], &[b"hello".to_vec()]);
+        let indicator = fm.to_stix_indicator("https://pastebin.com/foo");
+
+        assert!(schema.is_valid(&indicator), "{}", indicator);
+        assert!(indicator.get("labels").is_none());
+    }
+
+    #[test]
+    fn stix_indicator_id_is_deterministic() {
+        let first = flat_match().to_stix_indicator("https://pastebin.com/foo");
+        let second = flat_match().to_stix_indicator("https://pastebin.com/bar");
+
+        assert_eq!(first["id"], second["id"]);
+    }
+
+    #[test]
+    fn stix_pattern_is_a_yara_rule() {
+        let indicator = flat_match().to_stix_indicator("https://pastebin.com/foo");
+
+        assert_eq!(
+            indicator["pattern"],
+            "rule MyPass { strings: $s0 = \"pw: \\\"hello\\\"\" condition: any of them }"
+        );
+    }
+
+    #[test]
+    fn stix_pattern_escapes_strings_for_yara() {
+        let data = "C:\\pw\t\"h\u{e9}llo\"\u{1}";
+        let fm = FlatMatch::new("default::MyPass".to_owned(), vec![], &[data.as_bytes().to_vec()]);
+        let pattern = fm.to_stix_indicator("https://pastebin.com/foo")["pattern"].as_str().unwrap().to_owned();
+
+        assert!(pattern.contains(r#"$s0 = "C:\\pw\x09\"h\xc3\xa9llo\"\x01""#), "{}", pattern);
+        let rules = yara::Compiler::new().unwrap().add_rules_str(&pattern).unwrap().compile_rules().unwrap();
+        assert_eq!(rules.scan_mem(data.as_bytes(), 5).unwrap().len(), 1);
     }
 }
@@ -1,9 +1,10 @@
 #![allow(dead_code)]
 
 use std::time;
+use std::collections::HashSet;
 use r2d2_postgres::postgres::Transaction;
 use anyhow::Result;
-use crate::database::Insert;
+use crate::database::{Client, Insert};
 
 pub struct IndexCache {
     id: i32,
@@ -19,7 +20,7 @@ impl Insert for IndexCache {
         (
             source,
             source_id,
-            cached_at
+            cached_time
         )
         VALUES
         (
@@ -35,7 +36,68 @@ impl Insert for IndexCache {
     }
 }
 
+/// Splits `(source, source_id)` pairs into two parallel arrays, to be `UNNEST`ed by the queries below
+fn unzip_entries(entries: &[(&str, &str)]) -> (Vec<String>, Vec<String>) {
+    entries.iter()
+        .map(|(source, source_id)| (source.to_string(), source_id.to_string()))
+        .unzip()
+}
+
 impl IndexCache {
+    /// Whether the `(source, source_id)` pair is already in the cache
+    pub fn exists(conn: &mut Client, source: &str, source_id: &str) -> Result<bool> {
+        let stmt = "
+        SELECT EXISTS (
+            SELECT 1 FROM index_cache WHERE source = $1 AND source_id = $2
+        )
+        ";
+
+        Ok(conn.query_one(stmt, &[&source, &source_id])?.get(0))
+    }
+
+    /// Looks up a batch of `(source, source_id)` pairs with a single query
+    ///
+    /// # Returns
+    /// The pairs of `entries` that are already in the cache
+    pub fn check_seen_batch(conn: &mut Client, entries: &[(&str, &str)]) -> Result<HashSet<(String, String)>> {
+        if entries.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        let stmt = "
+        SELECT DISTINCT source, source_id
+        FROM index_cache
+        WHERE (source, source_id) IN (SELECT * FROM UNNEST($1::TEXT[], $2::TEXT[]))
+        ";
+
+        let (sources, source_ids) = unzip_entries(entries);
+        let seen = conn.query(stmt, &[&sources, &source_ids])?
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+
+        Ok(seen)
+    }
+
+    /// Adds a batch of `(source, source_id)` pairs to the cache with a single query. Pairs that are
+    /// already cached are left untouched
+    pub fn mark_seen_batch(conn: &mut Transaction, entries: &[(&str, &str)]) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let stmt = "
+        INSERT INTO index_cache (source, source_id)
+        SELECT * FROM UNNEST($1::TEXT[], $2::TEXT[])
+        ON CONFLICT (source, source_id) DO NOTHING
+        ";
+
+        let (sources, source_ids) = unzip_entries(entries);
+        conn.execute(stmt, &[&sources, &source_ids])?;
+
+        Ok(())
+    }
+
     pub fn new(id: i32, source: String, source_id: String) -> Self {
         let cached_at = time::SystemTime::now();
 
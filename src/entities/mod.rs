@@ -3,8 +3,10 @@ mod rule_match;
 mod ascii_match;
 mod index_cache;
 mod flat_match;
+#[cfg(feature = "grpc")]
+pub mod proto;
 
-pub use event::{Event, ProcessedEvent};
+pub use event::{Event, EventSchemaVersion, ProcessedEvent, DATETIME_FMT};
 pub use rule_match::RuleMatch;
 pub use ascii_match::AsciiMatch;
 pub use index_cache::IndexCache;
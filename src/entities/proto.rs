@@ -0,0 +1,4 @@
+//! The Protobuf types and the `EventFeed` gRPC service of `proto/event.proto`, generated by `tonic-build` (see
+//! `build.rs`)
+
+tonic::include_proto!("infobserve");
@@ -1,16 +1,81 @@
 #![allow(dead_code)]
 
+use std::collections::HashMap;
+use std::fmt;
+
 use r2d2_postgres::postgres::{Row, Transaction};
+use r2d2_postgres::postgres::types::ToSql;
+use serde_json::Value;
 use anyhow::Result;
-use crate::database::{Client, Insert};
+use crate::database::{insert_rows, Client, Insert};
 use crate::entities::Event;
 
+/// The version reported in the header of CEF messages
+const CEF_PRODUCT_VERSION: &str = "1.0";
+
+/// How severe a match is, as declared by the matched rule's tags (e.g. `rule MyPass : high`)
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Severity {
+    Unknown,
+    Low,
+    Medium,
+    High,
+    Critical
+}
+
+impl Severity {
+    fn from_tag(tag: &str) -> Option<Severity> {
+        match tag.to_lowercase().as_str() {
+            "low" => Some(Severity::Low),
+            "medium" => Some(Severity::Medium),
+            "high" => Some(Severity::High),
+            "critical" => Some(Severity::Critical),
+            _ => None
+        }
+    }
+
+    /// The severity on CEF's 0-10 scale
+    pub fn as_cef_int(&self) -> u8 {
+        match self {
+            Severity::Unknown => 0,
+            Severity::Low => 3,
+            Severity::Medium => 5,
+            Severity::High => 8,
+            Severity::Critical => 10
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Severity::Unknown => "UNKNOWN",
+            Severity::Low => "LOW",
+            Severity::Medium => "MEDIUM",
+            Severity::High => "HIGH",
+            Severity::Critical => "CRITICAL"
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
 #[derive(Debug)]
 pub struct RuleMatch {
     id: Option<i32>,
     event_id: i32,
     rule_matched: String,
-    tags_matched: Vec<String>
+    tags_matched: Vec<String>,
+    meta: Option<Value>
+}
+
+/// Ignores `id`, which is only assigned on insert (see `Event`'s `PartialEq`)
+impl PartialEq for RuleMatch {
+    fn eq(&self, other: &Self) -> bool {
+        self.event_id == other.event_id
+            && self.rule_matched == other.rule_matched
+            && self.tags_matched == other.tags_matched
+    }
 }
 
 impl Insert for RuleMatch {
@@ -20,25 +85,39 @@ impl Insert for RuleMatch {
         (
             event_id,
             rule_matched,
-            tags_matched
+            tags_matched,
+            meta
         )
         VALUES
         (
-            $1, $2, $3
+            $1, $2, $3, $4
         )
         RETURNING id
         ";
 
-        let row = conn.query_one(stmt, &[&self.event_id, &self.rule_matched, &self.tags_matched])?;
+        let row = conn.query_one(stmt, &[&self.event_id, &self.rule_matched, &self.tags_matched, &self.meta])?;
         self.id = row.get(0);
 
         Ok(())
     }
+
+    fn insert_all(items: &mut [Self], conn: &mut Transaction) -> Result<()> {
+        let rows: Vec<Vec<&(dyn ToSql + Sync)>> = items.iter()
+            .map(|m| vec![&m.event_id as &(dyn ToSql + Sync), &m.rule_matched, &m.tags_matched, &m.meta])
+            .collect();
+        let ids = insert_rows(conn, "rule_matches", &["event_id", "rule_matched", "tags_matched", "meta"], &rows)?;
+
+        for (item, id) in items.iter_mut().zip(ids) {
+            item.id = Some(id);
+        }
+
+        Ok(())
+    }
 }
 
 impl RuleMatch {
     pub fn new(event_id: i32, rule_matched: String, tags_matched: Vec<String>) -> Self {
-        Self::create(None, event_id, rule_matched, tags_matched)
+        Self::create(None, event_id, rule_matched, tags_matched, None)
     }
 
     pub fn from_row(row: &Row) -> Self {
@@ -46,10 +125,18 @@ impl RuleMatch {
             Some(row.get("id")),
             row.get("event_id"),
             row.get("rule_matched"),
-            row.get("tags_matched")
+            row.get("tags_matched"),
+            row.get("meta")
         )
     }
 
+    /// Stores the meta fields of the matched rule (see `FlatMatch::meta`) as a JSON object. Rules without any are
+    /// stored as `NULL`
+    pub fn with_meta(mut self, meta: &HashMap<String, String>) -> Self {
+        self.meta = if meta.is_empty() { None } else { serde_json::to_value(meta).ok() };
+        self
+    }
+
     pub fn event(&self, conn: &mut Client) -> Result<Event> {
         let row = conn.query_one("SELECT * FROM events WHERE id = $1", &[&self.event_id])?;
 
@@ -72,7 +159,125 @@ impl RuleMatch {
         &self.tags_matched
     }
 
-    fn create(id: Option<i32>, event_id: i32, rule_matched: String, tags_matched: Vec<String>) -> Self {
-        Self { id, event_id, rule_matched, tags_matched }
+    /// The meta fields of the matched rule, e.g. `{"author": "infobserve"}`
+    pub fn meta(&self) -> Option<&Value> {
+        self.meta.as_ref()
+    }
+
+    /// The namespace part of `rule_matched` (`namespace::identifier`)
+    pub fn namespace(&self) -> &str {
+        match self.rule_matched.split_once("::") {
+            Some((namespace, _)) => namespace,
+            None => ""
+        }
+    }
+
+    /// The identifier part of `rule_matched` (`namespace::identifier`), i.e. the rule's name
+    pub fn short_name(&self) -> &str {
+        match self.rule_matched.split_once("::") {
+            Some((_, identifier)) => identifier,
+            None => &self.rule_matched
+        }
+    }
+
+    /// The highest severity among the matched tags, or `Severity::Unknown` if none of them is a severity
+    pub fn severity_level(&self) -> Severity {
+        self.tags_matched.iter()
+            .filter_map(|tag| Severity::from_tag(tag))
+            .max()
+            .unwrap_or(Severity::Unknown)
+    }
+
+    /// e.g. `[HIGH] default::MyPass`
+    pub fn full_display_name(&self) -> String {
+        format!("[{}] {}", self.severity_level(), self.rule_matched)
+    }
+
+    /// Formats the match as an ArcSight CEF message, e.g.
+    /// `CEF:0|Infobserve|processor-rs|1.0|default::MyPass|[HIGH] default::MyPass|8|src=https://pastebin.com/foo`
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The url of the event the match was found in
+    pub fn as_syslog_cef(&self, url: &str) -> String {
+        format!(
+            "CEF:0|Infobserve|processor-rs|{}|{}|{}|{}|src={}",
+            CEF_PRODUCT_VERSION,
+            escape_cef_header(&self.rule_matched),
+            escape_cef_header(&self.full_display_name()),
+            self.severity_level().as_cef_int(),
+            escape_cef_extension(url)
+        )
+    }
+
+    fn create(
+        id: Option<i32>, event_id: i32, rule_matched: String, tags_matched: Vec<String>, meta: Option<Value>
+    ) -> Self {
+        Self { id, event_id, rule_matched, tags_matched, meta }
+    }
+}
+
+/// Escapes the characters that delimit CEF header fields
+fn escape_cef_header(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+/// Escapes the characters that delimit CEF extension values
+fn escape_cef_extension(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('=', "\\=")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rule_match(rule_matched: &str, tags: &[&str]) -> RuleMatch {
+        RuleMatch::new(1, rule_matched.to_owned(), tags.iter().map(|t| t.to_string()).collect())
+    }
+
+    #[test]
+    fn splits_the_rule_name() {
+        let m = rule_match("default::MyPass", &[]);
+
+        assert_eq!(m.namespace(), "default");
+        assert_eq!(m.short_name(), "MyPass");
+        assert_eq!(rule_match("MyPass", &[]).short_name(), "MyPass");
+    }
+
+    #[test]
+    fn severity_is_the_highest_severity_tag() {
+        assert_eq!(rule_match("default::MyPass", &["password", "Medium", "high"]).severity_level(), Severity::High);
+        assert_eq!(rule_match("default::MyPass", &["password"]).severity_level(), Severity::Unknown);
+    }
+
+    #[test]
+    fn full_display_name_includes_the_severity() {
+        assert_eq!(rule_match("default::MyPass", &["critical"]).full_display_name(), "[CRITICAL] default::MyPass");
+        assert_eq!(rule_match("default::MyPass", &[]).full_display_name(), "[UNKNOWN] default::MyPass");
+    }
+
+    #[test]
+    fn meta_fields_are_stored_as_a_json_object() {
+        let meta: HashMap<String, String> = vec![("author".to_owned(), "tester".to_owned())].into_iter().collect();
+
+        assert_eq!(rule_match("default::MyPass", &[]).with_meta(&meta).meta(), Some(&json!({ "author": "tester" })));
+        assert_eq!(rule_match("default::MyPass", &[]).with_meta(&HashMap::new()).meta(), None);
+    }
+
+    #[test]
+    fn formats_as_cef() {
+        assert_eq!(
+            rule_match("default::MyPass", &["high"]).as_syslog_cef("https://pastebin.com/foo"),
+            "CEF:0|Infobserve|processor-rs|1.0|default::MyPass|[HIGH] default::MyPass|8|src=https://pastebin.com/foo"
+        );
+    }
+
+    #[test]
+    fn cef_fields_are_escaped() {
+        assert_eq!(
+            rule_match("default::Pipe|Rule", &["low"]).as_syslog_cef("https://example.com/?a=b"),
+            "CEF:0|Infobserve|processor-rs|1.0|default::Pipe\\|Rule|[LOW] default::Pipe\\|Rule|3|src=https://example.com/?a\\=b"
+        );
     }
 }
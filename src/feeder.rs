@@ -1,36 +1,111 @@
-use log::{info, error};
+use log::{info, warn, error};
+use std::fmt;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-use crossbeam_channel::Sender;
-use redis::{Client, Commands, Connection};
+use crossbeam_channel::{select, Receiver, Sender, TrySendError};
+use redis::{Client, Commands, Connection, ConnectionInfo, ConnectionLike, Direction, RedisError};
+use redis::sentinel::{SentinelClient, SentinelNodeConnectionInfo, SentinelServerType};
+use r2d2::PooledConnection;
+use lru::LruCache;
+use rand::Rng;
 use anyhow::Result;
+use serde_json::Value;
+#[cfg(feature = "tracing")]
+use opentelemetry::{global, trace::{Span, Status, Tracer}, KeyValue};
+#[cfg(feature = "kafka")]
+use rdkafka::{
+    ClientConfig, ClientContext, Message as _, TopicPartitionList,
+    consumer::{BaseConsumer, Consumer, ConsumerContext, Rebalance},
+    error::KafkaResult
+};
 
-use crate::entities::Event;
+#[cfg(feature = "kafka")]
+use crate::config::KafkaCfg;
+#[cfg(feature = "grpc")]
+use crate::config::GrpcCfg;
+use crate::config::{FeederCfg, RedisCfg, SentinelCfg};
+use crate::database::DbConnection;
+use crate::entities::{Event, EventSchemaVersion, IndexCache, DATETIME_FMT};
+use crate::errors::FeederError;
+use crate::health::AliveGuard;
+use crate::utils::pluralize;
 
-/// Spawns `num_feeders` threads. Each thread listens for events through redis. Whenever an event is fetched,
-/// a message is written in the sender end of a crossbeam channel (normally, a processing thread is listening
-/// on the receiving end of that)
+#[cfg(feature = "tracing")]
+pub use opentelemetry::global::BoxedTracer;
+
+/// How long a feeder waits for a redis connection when all of the pool's connections are borrowed (see `RedisPool`)
+const POOL_CHECKOUT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The redis list events are popped from
+const EVENTS_KEY: &str = "events";
+
+/// The delay before the first reconnection attempt after a transient error (see `Backoff`)
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_millis(100);
+
+/// How long (in seconds) a redis pop waits for an event. Between pops, feeders check whether a shutdown was
+/// requested (see `signals`)
+const POP_TIMEOUT_SECS: f64 = 1.0;
+
+/// Stands in for OpenTelemetry's tracer when built without the `tracing` feature
+#[cfg(not(feature = "tracing"))]
+pub struct BoxedTracer;
+
+#[cfg(feature = "grpc")]
+mod grpc;
+
+/// Anything a feeder thread can pull events from (e.g. a `Feeder` popping messages from redis or Kafka)
+pub trait MessageSource: Send {
+    /// Fetches events and writes them in `sendr` until a quit message is received, a message arrives on (or
+    /// disconnects) `shutdown`, or a permanent error occurs
+    fn feed(&mut self, sendr: &Sender<Event>, shutdown: &Receiver<()>) -> Result<()>;
+
+    fn stats(&self) -> &FeederStats;
+}
+
+/// Spawns `num_feeders` threads. Each thread listens for events through its own source, built by calling
+/// `source_factory` once per thread. Whenever an event is fetched, a message is written in the sender end of a
+/// crossbeam channel (normally, a processing thread is listening on the receiving end of that)
 /// 
 /// # Arguments
 /// 
-/// * sendr - The write-end of a crossbeam channel. All events fetched from redis will be written there.
+/// * sendr - The write-end of a crossbeam channel. All events fetched by the sources will be written there.
 ///           If a quit message is received instead of an event, then this sender is dropped, effectively
 ///           unblocking all threads listening to it.
-/// * host - Redis host
-/// * port - Redis port
+/// * source_factory - Builds the source of each thread (see `redis_source`, `kafka_source` and `grpc_source`)
 /// * num_feeders - The amount of feeder threads to spawn
+/// * shutdown - The shutdown channel (see `signals::shutdown_channel`)
+/// * alive - Counts the feeder threads still running (see `health::Liveness::feeders`)
 /// 
 /// # Return
 /// A vector of join handles that can be used to join the threads. Threads will exit their loops only
-/// if a quit command is received from Redis.
+/// if a quit command is received from their source, or a shutdown is requested. A thread whose source could
+/// not be built (or failed permanently) returns the error, otherwise its final stats
 /// 
 /// # Example
 /// ```
-/// use feeder::start_feeders;
+/// use feeder::{redis_source, start_feeders, RedisPool};
 /// 
 /// let (proc_sendr, proc_receiver) = crossbeam_channel::unbounded();
+/// let pool = Arc::new(RedisPool::from_cfg(&RedisCfg::default()).unwrap());
+///
+/// let shutdown = signals::shutdown_channel().unwrap();
+///
+/// let liveness = Liveness::new();
 ///
-/// let handles: Vec<JoinHandle<()>> = start_feeders(&proc_sendr, "localhost", 6379, 2);
+/// let handles = start_feeders(
+///     &proc_sendr,
+///     Box::new(move || redis_source(&pool, &RedisCfg::default(), &FeederCfg::default())),
+///     2,
+///     &shutdown,
+///     liveness.feeders()
+/// );
 ///
 /// assert_eq!(handles.len(), 2);
 /// // for msg in proc_receiver {
@@ -41,18 +116,32 @@ use crate::entities::Event;
 ///     handle.join().unwrap();
 /// }
 /// ```
-pub fn start_feeders(sendr: &Sender<Event>, host: &str, port: u16, num_feeders: i32) -> Vec<JoinHandle<()>> {
+pub fn start_feeders(
+    sendr: &Sender<Event>,
+    source_factory: Box<dyn Fn() -> Result<Box<dyn MessageSource>>>,
+    num_feeders: i32,
+    shutdown: &Receiver<()>,
+    alive: &Arc<AtomicUsize>
+) -> Vec<JoinHandle<Result<FeederStats>>> {
     let mut threads = Vec::with_capacity(num_feeders as usize);
 
     for _ in 0..num_feeders {
-        let mut feeder = Feeder::connect(&host, port).expect(&format!("redis connection @redis://{}:{}", host, port));
+        let source = source_factory();
         let sendr_copy = Sender::clone(sendr);
+        let shutdown = Receiver::clone(shutdown);
+        let alive = AliveGuard::new(alive);
         threads.push(
             thread::spawn(move || {
-                if let Err(e) = feeder.listen(&sendr_copy) {
-                    error!("Feeder encountered an error!: {}", e);
-                    return;
+                let _alive = alive;
+                let mut source = source?;
+
+                let result = source.feed(&sendr_copy, &shutdown);
+                if let Err(e) = &result {
+                    error!("CRIT: Feeder stopped by an error, events are no longer fetched through it: {:#}", e);
                 }
+                info!("Feeder exiting. {}", source.stats());
+
+                result.map(|_| source.stats().clone())
             })
         );
     }
@@ -60,64 +149,2511 @@ pub fn start_feeders(sendr: &Sender<Event>, host: &str, port: u16, num_feeders:
     threads
 }
 
-struct Feeder {
-    client: Client
+/// A feeder popping events from redis, through a connection borrowed from `pool` (see `RedisPool`). It reconnects
+/// after transient errors up to `redis_cfg.max_retries` times in a row (see `Feeder::reconnect`). Events found in
+/// the index cache of `index_cache` (if given) are skipped (see `Feeder::with_index_cache`)
+pub fn redis_source(
+    pool: &Arc<RedisPool>,
+    redis_cfg: &RedisCfg,
+    feeder_cfg: &FeederCfg,
+    index_cache: Option<&DbConnection>
+) -> Result<Box<dyn MessageSource>> {
+    let feeder = Feeder::from_pool(Arc::clone(pool), feeder_cfg)
+        .with_reconnect_backoff(INITIAL_RECONNECT_DELAY, Duration::from_millis(redis_cfg.max_retry_delay_ms()))
+        .with_max_retries(redis_cfg.max_retries())
+        .with_index_cache(index_cache.cloned());
+
+    Ok(Box::new(feeder))
 }
 
-impl Feeder {
-    /// Opens a connection to a Redis server and retains a handle for it
-    fn connect(host: &str, port: u16) -> Result<Self> {
-        let client = Client::open(format!("redis://{}:{}/", host, port))?;
+/// A feeder consuming events from `kafka_cfg.topic`. All Kafka feeders join the same consumer group, so the topic's
+/// partitions are split among them
+///
+/// Kafka feeders can run alongside the redis ones (both write into the same channel), or replace them by setting
+/// `redis.enabled` to false. Each feeder stops when it consumes a `QUIT` message, just like the redis ones
+#[cfg(feature = "kafka")]
+pub fn kafka_source(
+    kafka_cfg: &KafkaCfg,
+    feeder_cfg: &FeederCfg,
+    index_cache: Option<&DbConnection>
+) -> Result<Box<dyn MessageSource>> {
+    Ok(Box::new(Feeder::from_kafka_cfg(kafka_cfg, feeder_cfg).with_index_cache(index_cache.cloned())))
+}
+
+/// A feeder serving the `EventFeed` gRPC service on `grpc_cfg.listen_addr`, which scrapers stream their events to (see
+/// `grpc::GrpcFeeder`)
+///
+/// A single feeder should listen: each one binds the same address. It has no `QUIT` message, so it stops only once a
+/// shutdown is requested
+#[cfg(feature = "grpc")]
+pub fn grpc_source(grpc_cfg: &GrpcCfg) -> Result<Box<dyn MessageSource>> {
+    Ok(Box::new(grpc::GrpcFeeder::from_cfg(grpc_cfg)))
+}
+
+/// A feeder reading newline-delimited JSON events from the file at `path`, e.g. to replay events without a live
+/// redis. It stops at the end of the file, just as if a `QUIT` message was read (see `FileQueue`)
+///
+/// A single feeder should read each file: every feeder reading the same file would send all of its events
+pub fn file_source(
+    path: &str,
+    feeder_cfg: &FeederCfg,
+    index_cache: Option<&DbConnection>
+) -> Result<Box<dyn MessageSource>> {
+    Ok(Box::new(Feeder::from_file(path, feeder_cfg).with_index_cache(index_cache.cloned())))
+}
+
+/// Counters describing the lifetime of a feeder thread
+#[derive(Debug, Default, Clone)]
+pub struct FeederStats {
+    dropped_events: u32,
+    deduped_events: u32,
+    index_cache_hits: u64,
+    transient_errors: u64,
+    permanent_errors: u64,
+    v1_events: u64,
+    v2_events: u64,
+    recovered_orphaned_events: u64,
+    grpc_events: u64,
+    rejected_grpc_events: u64
+}
 
-        Ok(Self { client })
+impl FeederStats {
+    /// The number of events that were discarded because the retry queue was full
+    #[allow(dead_code)]
+    pub fn dropped_events(&self) -> u32 {
+        self.dropped_events
     }
 
-    /// Continuously listens for events from Redis. Whenever an event is encountered, it is written
-    /// in `sendr`
-    fn listen(&mut self, sendr: &Sender<Event>) -> Result<()> {
-        let mut conn = self.client.get_connection()?;
+    /// The number of events that were skipped because their url had been seen shortly before
+    #[allow(dead_code)]
+    pub fn deduped_events(&self) -> u32 {
+        self.deduped_events
+    }
+
+    /// The number of events that were skipped because they were found in the index cache (i.e. a previous run
+    /// already stored them)
+    #[allow(dead_code)]
+    pub fn index_cache_hits(&self) -> u64 {
+        self.index_cache_hits
+    }
+
+    /// The number of errors (e.g. dropped connections) that were followed by a reconnection attempt
+    #[allow(dead_code)]
+    pub fn transient_errors(&self) -> u64 {
+        self.transient_errors
+    }
+
+    /// The number of errors that stopped the feeder
+    #[allow(dead_code)]
+    pub fn permanent_errors(&self) -> u64 {
+        self.permanent_errors
+    }
+
+    /// The number of events received in the v1 JSON schema (see `EventSchemaVersion`)
+    #[allow(dead_code)]
+    pub fn v1_events(&self) -> u64 {
+        self.v1_events
+    }
+
+    /// The number of events received in the v2 JSON schema (see `EventSchemaVersion`)
+    #[allow(dead_code)]
+    pub fn v2_events(&self) -> u64 {
+        self.v2_events
+    }
+
+    /// The number of events left unacknowledged by a previous run, which were moved back to the events list
+    /// (see `ReliableQueue`)
+    #[allow(dead_code)]
+    pub fn recovered_orphaned_events(&self) -> u64 {
+        self.recovered_orphaned_events
+    }
+
+    /// The number of events streamed over gRPC and sent to the processors (see `grpc_source`)
+    #[allow(dead_code)]
+    pub fn grpc_events(&self) -> u64 {
+        self.grpc_events
+    }
+
+    /// The number of events streamed over gRPC that could not be converted (see `Event::from_proto`), and were skipped
+    #[allow(dead_code)]
+    pub fn rejected_grpc_events(&self) -> u64 {
+        self.rejected_grpc_events
+    }
+}
+
+impl fmt::Display for FeederStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Dropped events: {}, deduplicated events: {}, index cache hits: {}, transient errors: {}, \
+             permanent errors: {}, v1 events: {}, v2 events: {}, recovered orphaned events: {}, gRPC events: {}, \
+             rejected gRPC events: {}",
+            self.dropped_events, self.deduped_events, self.index_cache_hits, self.transient_errors, self.permanent_errors,
+            self.v1_events, self.v2_events, self.recovered_orphaned_events, self.grpc_events, self.rejected_grpc_events
+        )
+    }
+}
+
+/// Exponentially increasing delays between reconnection attempts, starting from `initial` and capped at `max`
+struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration
+}
+
+impl Backoff {
+    fn new(initial: Duration, max: Duration) -> Self {
+        let initial = initial.min(max);
+        Self { initial, max, current: initial }
+    }
+
+    /// The delay before the next attempt. Each call doubles the delay of the following one
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = self.current.saturating_mul(2).min(self.max);
+        delay
+    }
+
+    fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(INITIAL_RECONNECT_DELAY, Duration::from_secs(30))
+    }
+}
+
+/// A random delay between half of `delay` and `delay`, so that feeders disconnected at the same time don't all
+/// reconnect at the same time
+fn with_jitter(delay: Duration) -> Duration {
+    rand::thread_rng().gen_range(delay / 2..=delay)
+}
+
+/// Where the feeder pops messages from (a redis connection or a Kafka consumer). Also lets failures be
+/// simulated in tests
+trait MessageQueue {
+    /// The next message, or `None` if none arrived in time
+    fn pop(&mut self) -> Result<Option<Message>, FeederError>;
+
+    /// Lets the queue know that the message carrying `payload` was handled (e.g. sent to the processors, or
+    /// discarded), so that it is not popped again. Does nothing, unless the queue keeps track of popped messages
+    fn ack(&mut self, _payload: &str) -> Result<(), FeederError> {
+        Ok(())
+    }
+}
+
+impl MessageQueue for Connection {
+    fn pop(&mut self) -> Result<Option<Message>, FeederError> {
+        let msg: Option<(String, String)> = self.blpop(EVENTS_KEY, POP_TIMEOUT_SECS)?;
+
+        Ok(msg.map(|(name, payload)| Message { name, payload }))
+    }
+}
+
+impl MessageQueue for PooledConnection<RedisConnector> {
+    fn pop(&mut self) -> Result<Option<Message>, FeederError> {
+        Connection::pop(self)
+    }
+}
+
+/// The redis list commands behind `ReliableQueue`. Also lets the lists be simulated in tests
+trait ListCommands {
+    /// Moves the head of `source` to the tail of `destination`, waiting up to `POP_TIMEOUT_SECS` for there to be
+    /// one (`BLMOVE`)
+    ///
+    /// # Returns
+    /// The moved element, or `None` if none arrived in time
+    fn move_blocking(&mut self, source: &str, destination: &str) -> Result<Option<String>, FeederError>;
+
+    /// Moves the tail of `source` to the head of `destination` (`LMOVE`)
+    ///
+    /// # Returns
+    /// The moved element, or `None` if `source` is empty
+    fn move_back(&mut self, source: &str, destination: &str) -> Result<Option<String>, FeederError>;
+
+    /// Removes the first occurrence of `value` from `key` (`LREM`)
+    fn remove(&mut self, key: &str, value: &str) -> Result<(), FeederError>;
+}
+
+impl ListCommands for Connection {
+    fn move_blocking(&mut self, source: &str, destination: &str) -> Result<Option<String>, FeederError> {
+        Ok(self.blmove(source, destination, Direction::Left, Direction::Right, POP_TIMEOUT_SECS)?)
+    }
+
+    fn move_back(&mut self, source: &str, destination: &str) -> Result<Option<String>, FeederError> {
+        Ok(self.lmove(source, destination, Direction::Right, Direction::Left)?)
+    }
+
+    fn remove(&mut self, key: &str, value: &str) -> Result<(), FeederError> {
+        let _: i64 = self.lrem(key, 1, value)?;
+        Ok(())
+    }
+}
+
+impl ListCommands for PooledConnection<RedisConnector> {
+    fn move_blocking(&mut self, source: &str, destination: &str) -> Result<Option<String>, FeederError> {
+        Connection::move_blocking(self, source, destination)
+    }
+
+    fn move_back(&mut self, source: &str, destination: &str) -> Result<Option<String>, FeederError> {
+        Connection::move_back(self, source, destination)
+    }
+
+    fn remove(&mut self, key: &str, value: &str) -> Result<(), FeederError> {
+        Connection::remove(self, key, value)
+    }
+}
+
+/// Pops events for at-least-once delivery. Each popped event is atomically moved to `processing_key`, where it
+/// stays until it is acknowledged (see `MessageQueue::ack`). Events left there by a feeder that crashed are moved
+/// back to the events list the next time the feeders start (see `Feeder::recover_orphans`)
+///
+/// Events that had to be dropped from the retry queue are never acknowledged, so they are popped again after a
+/// restart as well
+struct ReliableQueue<L> {
+    lists: L,
+    processing_key: String
+}
+
+impl<L: ListCommands> ReliableQueue<L> {
+    fn new(lists: L, processing_key: &str) -> Self {
+        Self { lists, processing_key: processing_key.to_owned() }
+    }
+}
+
+impl<L: ListCommands> MessageQueue for ReliableQueue<L> {
+    fn pop(&mut self) -> Result<Option<Message>, FeederError> {
+        let payload = self.lists.move_blocking(EVENTS_KEY, &self.processing_key)?;
+
+        Ok(payload.map(|payload| Message { name: EVENTS_KEY.to_owned(), payload }))
+    }
+
+    fn ack(&mut self, payload: &str) -> Result<(), FeederError> {
+        self.lists.remove(&self.processing_key, payload)
+    }
+}
+
+/// Logs partition assignments and revocations, so that rebalances show up next to the feeder's own logs.
+/// The partitions themselves are (un)assigned by rdkafka, which also commits the consumed offsets before
+/// a partition is revoked
+#[cfg(feature = "kafka")]
+struct RebalanceLogger;
+
+#[cfg(feature = "kafka")]
+impl ClientContext for RebalanceLogger {}
+
+#[cfg(feature = "kafka")]
+impl ConsumerContext for RebalanceLogger {
+    fn pre_rebalance(&self, rebalance: &Rebalance) {
+        match rebalance {
+            Rebalance::Revoke(partitions) => info!("Kafka partitions revoked: {}", describe_partitions(partitions)),
+            Rebalance::Assign(_) => {},
+            Rebalance::Error(e) => warn!("Kafka rebalance failed: {}", e)
+        }
+    }
+
+    fn post_rebalance(&self, rebalance: &Rebalance) {
+        if let Rebalance::Assign(partitions) = rebalance {
+            info!("Kafka partitions assigned: {}", describe_partitions(partitions));
+        }
+    }
+
+    fn commit_callback(&self, result: KafkaResult<()>, _offsets: &TopicPartitionList) {
+        if let Err(e) = result {
+            warn!("Could not commit Kafka offsets: {}", e);
+        }
+    }
+}
+
+/// `topic[partition]` for each partition of `partitions`, comma-separated
+#[cfg(feature = "kafka")]
+fn describe_partitions(partitions: &TopicPartitionList) -> String {
+    partitions.elements().iter()
+        .map(|p| format!("{}[{}]", p.topic(), p.partition()))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Consumes events from a Kafka topic (see `kafka_source`). Offsets are committed automatically
+#[cfg(feature = "kafka")]
+pub struct KafkaFeeder {
+    consumer: BaseConsumer<RebalanceLogger>,
+    poll_timeout: Duration
+}
+
+#[cfg(feature = "kafka")]
+impl KafkaFeeder {
+    /// Creates a consumer and subscribes it to `cfg.topic`. No connection is made until the first poll
+    pub fn new(cfg: &KafkaCfg) -> Result<Self, FeederError> {
+        let consumer: BaseConsumer<RebalanceLogger> = ClientConfig::new()
+            .set("bootstrap.servers", cfg.brokers().join(","))
+            .set("group.id", cfg.consumer_group())
+            .set("auto.offset.reset", cfg.auto_offset_reset())
+            .create_with_context(RebalanceLogger)?;
+        consumer.subscribe(&[cfg.topic()])?;
+
+        Ok(Self { consumer, poll_timeout: Duration::from_millis(cfg.poll_timeout_ms()) })
+    }
+}
+
+#[cfg(feature = "kafka")]
+impl MessageQueue for KafkaFeeder {
+    /// Waits up to `kafka.poll_timeout_ms` for a message. Messages without a (UTF-8) payload are skipped
+    fn pop(&mut self) -> Result<Option<Message>, FeederError> {
+        let msg = match self.consumer.poll(self.poll_timeout) {
+            Some(m) => m?,
+            None => return Ok(None)
+        };
+
+        match msg.payload_view::<str>() {
+            Some(Ok(payload)) => Ok(Some(Message { name: msg.topic().to_owned(), payload: payload.to_owned() })),
+            Some(Err(e)) => {
+                error!("Skipping Kafka message {}[{}]@{}: {}", msg.topic(), msg.partition(), msg.offset(), e);
+                Ok(None)
+            },
+            None => {
+                warn!("Skipping empty Kafka message {}[{}]@{}", msg.topic(), msg.partition(), msg.offset());
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Reads one event per line from a file. Empty lines are skipped, and the end of the file is popped as a `QUIT`
+/// message
+///
+/// The next line is only read once the previous event was dispatched, which waits for room in the channel (see
+/// `Feeder::dispatch`), so a replay is paced by the processors
+struct FileQueue {
+    path: String,
+    lines: Lines<BufReader<File>>
+}
+
+impl FileQueue {
+    fn open(path: &str) -> Result<Self, FeederError> {
+        let file = File::open(path).map_err(|source| FeederError::InputFile { path: path.to_owned(), source })?;
 
+        Ok(Self { path: path.to_owned(), lines: BufReader::new(file).lines() })
+    }
+
+    fn message(&self, payload: String) -> Message {
+        Message { name: self.path.clone(), payload }
+    }
+}
+
+impl MessageQueue for FileQueue {
+    fn pop(&mut self) -> Result<Option<Message>, FeederError> {
         loop {
-            let msg = match self.pop_msg(&mut conn) {
-                Ok(m) => m,
-                Err(e) => {
-                    error!("Could not pop event from redis queue: {}", e);
-                    continue;
+            match self.lines.next() {
+                Some(Ok(line)) if line.trim().is_empty() => continue,
+                Some(Ok(line)) => return Ok(Some(self.message(line))),
+                Some(Err(source)) => return Err(FeederError::InputFile { path: self.path.clone(), source }),
+                None => return Ok(Some(self.message("QUIT".to_owned())))
+            }
+        }
+    }
+}
+
+/// Remembers the urls of the most recently received events, so that an event pushed more than once
+/// (e.g. by different scrapers) within `ttl` is only processed the first time
+struct DedupCache {
+    seen: LruCache<String, Instant>,
+    ttl: Duration
+}
+
+impl DedupCache {
+    fn new(capacity: NonZeroUsize, ttl: Duration) -> Self {
+        Self { seen: LruCache::new(capacity), ttl }
+    }
+
+    /// Whether `url` was seen less than `ttl` before `now`. If it wasn't, it is remembered as seen at `now`
+    fn seen_recently(&mut self, url: &str, now: Instant) -> bool {
+        if let Some(seen_at) = self.seen.get(url) {
+            if now.duration_since(*seen_at) < self.ttl {
+                return true;
+            }
+        }
+
+        self.seen.put(url.to_owned(), now);
+        false
+    }
+}
+
+/// Holds on to events that could not be sent to the processors (because the channel was disconnected), so that
+/// they can be retried later. When full, the oldest event is evicted to make room for the newest one
+struct RetryQueue {
+    events: VecDeque<Event>,
+    capacity: usize
+}
+
+impl RetryQueue {
+    fn with_capacity(capacity: usize) -> Self {
+        Self { events: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Queues `event` for a later retry
+    ///
+    /// # Returns
+    /// The event that had to be dropped to make room, if the queue was full
+    fn push(&mut self, event: Event) -> Option<Event> {
+        if self.capacity == 0 {
+            return Some(event);
+        }
+
+        let dropped = if self.events.len() >= self.capacity {
+            self.events.pop_front()
+        } else {
+            None
+        };
+        self.events.push_back(event);
+
+        dropped
+    }
+
+    /// Sends queued events (oldest first) until the queue is empty or `sendr` refuses one
+    ///
+    /// # Returns
+    /// The number of events that were sent
+    fn drain_into(&mut self, sendr: &Sender<Event>) -> usize {
+        let mut sent = 0;
+
+        while let Some(event) = self.events.pop_front() {
+            match sendr.try_send(event) {
+                Ok(()) => sent += 1,
+                Err(TrySendError::Full(e)) | Err(TrySendError::Disconnected(e)) => {
+                    self.events.push_front(e);
+                    break;
                 }
-            };
-            
-            info!("New message in {}", msg.name);
+            }
+        }
 
-            let payload = msg.payload;
+        sent
+    }
 
-            if &payload == "QUIT" {
+    /// Sends queued events (oldest first), waiting for room in `sendr`, until the queue is empty or `sendr` is
+    /// disconnected
+    ///
+    /// # Returns
+    /// The number of events that were sent
+    fn flush_into(&mut self, sendr: &Sender<Event>) -> usize {
+        let mut sent = 0;
+
+        while let Some(event) = self.events.pop_front() {
+            if let Err(e) = sendr.send(event) {
+                self.events.push_front(e.into_inner());
                 break;
             }
+            sent += 1;
+        }
 
-            match Event::from_json_str(&payload) {
-                Ok(e) => {
-                    if let Err(e) = sendr.send(e) {
-                        error!("Could not send event to processor: {}", e);
-                    }
-                },
-                Err(e) => error!("Could not deserialize message from redis: msg: {}, error: {}", payload, e)
-            }
+        sent
+    }
+}
+
+/// A pool of redis connections, shared by all redis feeder threads. Connections are opened on demand, up to
+/// `redis.max_pool_size` of them, and closed once they have been idle for `redis.idle_timeout_secs`
+///
+/// Each feeder holds on to its connection while it waits for events (see `Feeder::listen`), so feeders beyond
+/// the pool's size wait (and retry, see `FeederError::is_transient`) until a connection is returned
+pub struct RedisPool {
+    pool: r2d2::Pool<RedisConnector>,
+    orphans_claimed: AtomicBool
+}
+
+impl RedisPool {
+    /// Connects to Redis using plain TCP or TLS, or to the master that the sentinels point to, depending on the
+    /// configuration
+    pub fn from_cfg(redis_cfg: &RedisCfg) -> Result<Self> {
+        if let Some(sentinel_cfg) = redis_cfg.sentinel() {
+            return Ok(Self::new(
+                sentinel_connector(sentinel_cfg, redis_cfg.password())?,
+                redis_cfg.max_pool_size(),
+                Duration::from_secs(redis_cfg.idle_timeout_secs()),
+                POOL_CHECKOUT_TIMEOUT
+            ));
         }
 
-        Ok(())
+        let client = if redis_cfg.tls() {
+            tls_client(
+                redis_cfg.host(),
+                redis_cfg.port(),
+                redis_cfg.tls_cert_path(),
+                redis_cfg.tls_key_path(),
+                redis_cfg.tls_ca_cert_path()
+            )
+        } else {
+            open_client(redis_cfg.host(), redis_cfg.port())
+        }?;
+        let client = with_password(client, redis_cfg.password())?;
+
+        Ok(Self::new(
+            client,
+            redis_cfg.max_pool_size(),
+            Duration::from_secs(redis_cfg.idle_timeout_secs()),
+            POOL_CHECKOUT_TIMEOUT
+        ))
+    }
+
+    /// No connection is opened until one is requested
+    ///
+    /// # Arguments
+    ///
+    /// * `connector` - Opens the connections, e.g. a `redis::Client`
+    /// * `max_size` - The maximum number of connections (idle or borrowed)
+    /// * `idle_timeout` - Idle connections are closed after this long. A zero timeout keeps them open
+    /// * `checkout_timeout` - How long `RedisPool::get` waits for a connection when all of them are borrowed
+    fn new<C: Into<RedisConnector>>(
+        connector: C,
+        max_size: u32,
+        idle_timeout: Duration,
+        checkout_timeout: Duration
+    ) -> Self {
+        let pool = r2d2::Pool::builder()
+            .max_size(max_size)
+            .min_idle(Some(0))
+            .idle_timeout(Some(idle_timeout).filter(|t| !t.is_zero()))
+            .connection_timeout(checkout_timeout)
+            .build_unchecked(connector.into());
+
+        Self { pool, orphans_claimed: AtomicBool::new(false) }
     }
 
-    fn pop_msg(&self, conn: &mut Connection) -> Result<Message> {
-        let msg: Vec<String> = conn.blpop("events", 0)?;
+    /// Whether the caller is the first to ask. Only the first feeder recovers the events orphaned by a previous
+    /// run (see `ReliableQueue`), so that its siblings' events are not mistaken for orphans later on
+    fn claim_orphan_recovery(&self) -> bool {
+        !self.orphans_claimed.swap(true, Ordering::SeqCst)
+    }
 
-        Ok(Message {
-            name: msg[0].to_owned(),
-            payload: msg[1].to_owned()
-        })
+    /// Borrows a connection, opening one if none is idle. The connection returns to the pool when dropped
+    ///
+    /// # Errors
+    ///
+    /// `errors::FeederError::Pool` - When no connection could be opened, or none was returned in time
+    pub fn get(&self) -> Result<PooledConnection<RedisConnector>, FeederError> {
+        Ok(self.pool.get()?)
     }
 }
 
-struct Message {
-    name: String,
-    payload: String
-}
\ No newline at end of file
+/// Opens the connections of a `RedisPool`
+pub enum RedisConnector {
+    /// To a single redis server
+    Standalone(Client),
+    /// To the current master of a set monitored by Redis Sentinel. The sentinels are asked for the master's address
+    /// whenever a connection is opened, so new connections follow a failover. The client caches its last reachable
+    /// sentinel, hence the lock
+    Sentinel(Mutex<SentinelClient>)
+}
+
+impl From<Client> for RedisConnector {
+    fn from(client: Client) -> Self {
+        RedisConnector::Standalone(client)
+    }
+}
+
+impl r2d2::ManageConnection for RedisConnector {
+    type Connection = Connection;
+    type Error = RedisError;
+
+    fn connect(&self) -> Result<Connection, RedisError> {
+        match self {
+            RedisConnector::Standalone(client) => client.get_connection(),
+            RedisConnector::Sentinel(client) => client.lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .get_connection()
+        }
+    }
+
+    fn is_valid(&self, conn: &mut Connection) -> Result<(), RedisError> {
+        if conn.check_connection() {
+            Ok(())
+        } else {
+            Err(std::io::Error::from(std::io::ErrorKind::BrokenPipe).into())
+        }
+    }
+
+    fn has_broken(&self, conn: &mut Connection) -> bool {
+        !conn.is_open()
+    }
+}
+
+/// Connects to the master that the sentinels of `sentinel_cfg` point to, authenticating with `master_password`
+/// (if any). No connection is opened until one is requested
+fn sentinel_connector(sentinel_cfg: &SentinelCfg, master_password: Option<&str>) -> Result<RedisConnector> {
+    let sentinels: Vec<ConnectionInfo> = sentinel_cfg.sentinels().iter()
+        .map(|(host, port)| with_password(open_client(host, *port)?, sentinel_cfg.password()))
+        .map(|client| client.map(|c| c.get_connection_info().clone()))
+        .collect::<Result<_>>()?;
+    let node_info = SentinelNodeConnectionInfo {
+        redis_connection_info: master_password.map(|password| redis::RedisConnectionInfo {
+            password: Some(password.to_owned()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let client = SentinelClient::build(
+        sentinels,
+        sentinel_cfg.master_name().to_owned(),
+        Some(node_info),
+        SentinelServerType::Master
+    )?;
+
+    Ok(RedisConnector::Sentinel(Mutex::new(client)))
+}
+
+/// A client for a Redis server. No connection is opened until one is requested
+fn open_client(host: &str, port: u16) -> Result<Client> {
+    Ok(Client::open(format!("redis://{}:{}/", host, port))?)
+}
+
+/// Authenticates with `password` (if any) whenever a connection is opened by `client`
+fn with_password(client: Client, password: Option<&str>) -> Result<Client> {
+    match password {
+        Some(password) => {
+            let mut info = client.get_connection_info().clone();
+            info.redis.password = Some(password.to_owned());
+            Ok(Client::open(info)?)
+        },
+        None => Ok(client)
+    }
+}
+
+/// A client for a Redis server, connecting through TLS (`rediss://`)
+///
+/// The certificate material is read (and parsed) up front, so that missing files or malformed PEMs are reported
+/// before the first connection attempt. Each connection then verifies the server against `ca_cert`, and presents
+/// the client certificate (if any) to the server
+///
+/// # Arguments
+///
+/// * `cert_path` - The client certificate (PEM). Must be given together with `key_path`
+/// * `key_path` - The client certificate's private key (PEM)
+/// * `ca_cert` - A CA bundle (PEM) used to verify the server. If `None`, the system's trust store is used
+///
+/// # Errors
+///
+/// `errors::FeederError::TlsConfigurationFailed` - When the certificate material cannot be loaded
+#[cfg(feature = "tls")]
+fn tls_client(
+    host: &str,
+    port: u16,
+    cert_path: Option<&str>,
+    key_path: Option<&str>,
+    ca_cert: Option<&str>
+) -> Result<Client> {
+    use redis::{ClientTlsConfig, TlsCertificates};
+
+    let read_pem = |path: &str| std::fs::read(path)
+        .map_err(|e| FeederError::TlsConfigurationFailed(format!("Could not read {}: {}", path, e)));
+
+    let client_tls = match (cert_path, key_path) {
+        (Some(cert), Some(key)) => Some(ClientTlsConfig { client_cert: read_pem(cert)?, client_key: read_pem(key)? }),
+        (None, None) => None,
+        _ => return Err(FeederError::TlsConfigurationFailed(
+            "`tls_cert_path` and `tls_key_path` must be set together".to_owned()
+        ).into())
+    };
+    let root_cert = ca_cert.map(read_pem).transpose()?;
+
+    Client::build_with_tls(format!("rediss://{}:{}/", host, port), TlsCertificates { client_tls, root_cert })
+        .map_err(|e| FeederError::TlsConfigurationFailed(e.to_string()).into())
+}
+
+#[cfg(not(feature = "tls"))]
+fn tls_client(
+    _host: &str,
+    _port: u16,
+    _cert_path: Option<&str>,
+    _key_path: Option<&str>,
+    _ca_cert: Option<&str>
+) -> Result<Client> {
+    Err(FeederError::TlsConfigurationFailed("processor-rs was built without the `tls` feature".to_owned()).into())
+}
+
+/// Where a feeder pops its messages from
+enum Source {
+    Redis(Arc<RedisPool>),
+    File(String),
+    #[cfg(feature = "kafka")]
+    Kafka(KafkaCfg)
+}
+
+struct Feeder {
+    source: Source,
+    retry_queue: RetryQueue,
+    dedup_cache: Option<DedupCache>,
+    /// Where events stored by a previous run are looked up (see `Feeder::with_index_cache`)
+    index_cache: Option<DbConnection>,
+    reconnect_backoff: Backoff,
+    /// Reconnection attempts in a row before giving up (see `Feeder::reconnect`). `None` never gives up
+    max_retries: Option<u32>,
+    /// Reconnection attempts since the last successful pop
+    retries: u32,
+    /// Where popped events are kept until acknowledged, if the redis reliable queue is used (see `ReliableQueue`)
+    processing_queue_key: Option<String>,
+    /// The payloads of the events in `retry_queue` (in the same order), which are acknowledged once they are sent
+    unacknowledged: VecDeque<String>,
+    /// Stops the feeder when a message arrives, or it is disconnected (see `signals`)
+    shutdown: Receiver<()>,
+    /// Whether v1 events may leave out their optional fields (see `Event::from_json_lenient`)
+    lenient_parsing: bool,
+    /// The formats the timestamps of v1 events may be in (see `Event::parse_datetime`)
+    datetime_formats: Vec<String>,
+    stats: FeederStats
+}
+
+impl Feeder {
+    /// Pops events from redis, through a connection borrowed from `pool`
+    fn from_pool(pool: Arc<RedisPool>, feeder_cfg: &FeederCfg) -> Self {
+        let feeder = Self::with_source(Source::Redis(pool))
+            .with_retry_queue_size(feeder_cfg.retry_queue_size())
+            .with_dedup_cache(feeder_cfg.dedup_cache_size(), Duration::from_secs(feeder_cfg.dedup_window_secs()))
+            .with_lenient_parsing(feeder_cfg.lenient_event_parsing())
+            .with_datetime_formats(feeder_cfg.datetime_formats());
+
+        if feeder_cfg.reliable_queue() {
+            feeder.with_reliable_queue(feeder_cfg.processing_queue_key())
+        } else {
+            feeder
+        }
+    }
+
+    /// Reads events from the file at `path` instead of redis (see `FileQueue`)
+    fn from_file(path: &str, feeder_cfg: &FeederCfg) -> Self {
+        Self::with_source(Source::File(path.to_owned()))
+            .with_retry_queue_size(feeder_cfg.retry_queue_size())
+            .with_dedup_cache(feeder_cfg.dedup_cache_size(), Duration::from_secs(feeder_cfg.dedup_window_secs()))
+            .with_lenient_parsing(feeder_cfg.lenient_event_parsing())
+            .with_datetime_formats(feeder_cfg.datetime_formats())
+    }
+
+    /// Consumes events from Kafka instead of redis (see `KafkaFeeder`)
+    #[cfg(feature = "kafka")]
+    fn from_kafka_cfg(kafka_cfg: &KafkaCfg, feeder_cfg: &FeederCfg) -> Self {
+        Self::with_source(Source::Kafka(kafka_cfg.clone()))
+            .with_retry_queue_size(feeder_cfg.retry_queue_size())
+            .with_dedup_cache(feeder_cfg.dedup_cache_size(), Duration::from_secs(feeder_cfg.dedup_window_secs()))
+            .with_lenient_parsing(feeder_cfg.lenient_event_parsing())
+            .with_datetime_formats(feeder_cfg.datetime_formats())
+    }
+
+    fn with_source(source: Source) -> Self {
+        Self {
+            source,
+            retry_queue: RetryQueue::with_capacity(0),
+            dedup_cache: None,
+            index_cache: None,
+            reconnect_backoff: Backoff::default(),
+            max_retries: None,
+            retries: 0,
+            processing_queue_key: None,
+            unacknowledged: VecDeque::new(),
+            shutdown: crossbeam_channel::never(),
+            lenient_parsing: false,
+            datetime_formats: vec![DATETIME_FMT.to_owned()],
+            stats: Default::default()
+        }
+    }
+
+    /// Pops events from redis through a `ReliableQueue`, keeping them in `processing_queue_key` until they are sent
+    /// to the processors
+    fn with_reliable_queue(mut self, processing_queue_key: &str) -> Self {
+        self.processing_queue_key = Some(processing_queue_key.to_owned());
+        self
+    }
+
+    /// Sets the maximum number of events that will be held for retrying when they can't be sent to the processors
+    fn with_retry_queue_size(mut self, size: usize) -> Self {
+        self.retry_queue = RetryQueue::with_capacity(size);
+        self
+    }
+
+    /// Skips events whose url was already received less than `ttl` ago. Remembers up to `capacity` urls,
+    /// evicting the least recently received ones. A `capacity` of 0 disables deduplication
+    fn with_dedup_cache(mut self, capacity: usize, ttl: Duration) -> Self {
+        self.dedup_cache = NonZeroUsize::new(capacity).map(|c| DedupCache::new(c, ttl));
+        self
+    }
+
+    /// Skips the events whose `(source, url)` is in the index cache of `conn`, i.e. that were already stored.
+    /// `None` disables the lookup
+    fn with_index_cache(mut self, conn: Option<DbConnection>) -> Self {
+        self.index_cache = conn;
+        self
+    }
+
+    /// Accepts v1 events that lack their optional fields, e.g. `creator` (see `Event::from_json_lenient`)
+    fn with_lenient_parsing(mut self, lenient: bool) -> Self {
+        self.lenient_parsing = lenient;
+        self
+    }
+
+    /// Parses the timestamps of v1 events with the first of `formats` that fits them (see `Event::parse_datetime`)
+    fn with_datetime_formats(mut self, formats: &[String]) -> Self {
+        self.datetime_formats = formats.to_vec();
+        self
+    }
+
+    /// Sets the delays between reconnection attempts after a transient error (see `FeederError::is_transient`)
+    fn with_reconnect_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.reconnect_backoff = Backoff::new(initial, max);
+        self
+    }
+
+    /// Gives up after `max_retries` reconnection attempts in a row. `0` never gives up
+    fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = Some(max_retries).filter(|&r| r > 0);
+        self
+    }
+
+    /// Whether `event`'s url was received shortly before (see `Feeder::with_dedup_cache`)
+    fn is_recent_duplicate(&mut self, event: &Event) -> bool {
+        let duplicate = match self.dedup_cache.as_mut() {
+            Some(cache) => cache.seen_recently(event.url(), Instant::now()),
+            None => false
+        };
+
+        if duplicate {
+            self.stats.deduped_events += 1;
+        }
+
+        duplicate
+    }
+
+    /// Whether `event` was already stored, according to the index cache (see `Feeder::with_index_cache`). When
+    /// the cache can't be queried, the event is considered new
+    fn is_indexed(&mut self, event: &Event) -> bool {
+        let conn = match &self.index_cache {
+            Some(conn) => conn,
+            None => return false
+        };
+
+        let indexed = conn.get()
+            .and_then(|mut client| IndexCache::exists(&mut client, event.source(), event.url()))
+            .unwrap_or_else(|e| {
+                error!("Failed to query the index cache: {}", e);
+                false
+            });
+
+        if indexed {
+            self.stats.index_cache_hits += 1;
+        }
+
+        indexed
+    }
+
+    /// Continuously listens for events from Redis (or Kafka). Whenever an event is encountered, it is written
+    /// in `sendr`. Events that cannot be written are retried (see `Feeder::dispatch`) before the next message is popped
+    #[cfg_attr(feature = "tracing", allow(dead_code))]
+    fn listen(&mut self, sendr: &Sender<Event>) -> Result<()> {
+        self.listen_with(sendr, Self::dispatch)
+    }
+
+    /// Same as `Feeder::listen`, but each message is received in its own `feeder.receive_message` span
+    /// (see `Feeder::dispatch_traced`)
+    #[cfg(feature = "tracing")]
+    pub fn listen_traced(&mut self, sendr: &Sender<Event>, tracer: &BoxedTracer) -> Result<()> {
+        self.listen_with(sendr, |feeder, sendr, event| feeder.dispatch_traced(sendr, event, tracer))
+    }
+
+    /// Built without the `tracing` feature, there is nothing to trace. Equivalent to `Feeder::listen`
+    #[cfg(not(feature = "tracing"))]
+    pub fn listen_traced(&mut self, sendr: &Sender<Event>, _tracer: &BoxedTracer) -> Result<()> {
+        self.listen(sendr)
+    }
+
+    /// The loop behind `Feeder::listen`. Every deserialized event is handed to `dispatch`
+    fn listen_with<F>(&mut self, sendr: &Sender<Event>, dispatch: F) -> Result<()>
+        where F: FnMut(&mut Self, &Sender<Event>, Event) -> bool
+    {
+        match &self.source {
+            Source::Redis(pool) => {
+                let pool = Arc::clone(pool);
+                match self.processing_queue_key.clone() {
+                    Some(key) => {
+                        if pool.claim_orphan_recovery() {
+                            self.recover_orphans(&mut pool.get()?, &key)?;
+                        }
+                        self.listen_on(sendr, || pool.get().map(|c| ReliableQueue::new(c, &key)), dispatch)
+                    },
+                    None => self.listen_on(sendr, || pool.get(), dispatch)
+                }
+            },
+            Source::File(path) => {
+                let path = path.clone();
+                self.listen_on(sendr, || FileQueue::open(&path), dispatch)
+            },
+            #[cfg(feature = "kafka")]
+            Source::Kafka(kafka_cfg) => {
+                let kafka_cfg = kafka_cfg.clone();
+                self.listen_on(sendr, || KafkaFeeder::new(&kafka_cfg), dispatch)
+            }
+        }
+    }
+
+    /// Pops messages from the queues returned by `connect`. Transient errors (see `FeederError::is_transient`)
+    /// are followed by a reconnection, after an exponentially increasing delay. Permanent ones stop the feeder, and
+    /// so does running out of reconnection attempts (which is returned as an error)
+    fn listen_on<Q, C, F>(&mut self, sendr: &Sender<Event>, mut connect: C, mut dispatch: F) -> Result<()>
+        where Q: MessageQueue,
+              C: FnMut() -> Result<Q, FeederError>,
+              F: FnMut(&mut Self, &Sender<Event>, Event) -> bool
+    {
+        let mut queue = connect()?;
+
+        loop {
+            select! {
+                recv(self.shutdown) -> _ => {
+                    info!("Shutdown requested, stopping feeder");
+                    break;
+                },
+                default => {}
+            }
+
+            let retried = self.retry_queue.drain_into(sendr);
+            self.acknowledge_retried(&mut queue, retried);
+
+            let msg = match queue.pop() {
+                Ok(Some(m)) => {
+                    self.reset_retries();
+                    m
+                },
+                Ok(None) => {
+                    self.reset_retries();
+                    continue;
+                },
+                Err(e) => match self.reconnect(e, &mut connect)? {
+                    Some(q) => {
+                        queue = q;
+                        continue;
+                    },
+                    None => break
+                }
+            };
+            
+            info!("New message in {}", msg.name);
+
+            let payload = msg.payload;
+
+            if &payload == "QUIT" {
+                // Nothing follows, so the events waiting to be retried are sent before the feeder stops
+                let retried = self.retry_queue.flush_into(sendr);
+                self.acknowledge_retried(&mut queue, retried);
+                if !self.retry_queue.is_empty() {
+                    warn!("Stopping with {} that could not be sent", pluralize(self.retry_queue.len(), "event"));
+                }
+                acknowledge(&mut queue, &payload);
+                break;
+            }
+
+            match self.parse_event(&payload) {
+                Ok(e) if self.is_recent_duplicate(&e) => {
+                    info!("Skipping recently received event {}", e.url());
+                    acknowledge(&mut queue, &payload);
+                },
+                Ok(e) if self.is_indexed(&e) => {
+                    info!("Skipping already stored event {}", e.url());
+                    acknowledge(&mut queue, &payload);
+                },
+                Ok(e) => {
+                    let dropped_before = self.stats.dropped_events;
+                    if dispatch(self, sendr, e) {
+                        acknowledge(&mut queue, &payload);
+                    } else {
+                        self.unacknowledged.push_back(payload);
+                        // The event that made room is lost for this run, but stays unacknowledged
+                        if self.stats.dropped_events > dropped_before {
+                            self.unacknowledged.pop_front();
+                        }
+                    }
+                },
+                Err(e) => {
+                    error!("Could not deserialize message from {}: msg: {}, error: {}", msg.name, payload, e);
+                    acknowledge(&mut queue, &payload);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Acknowledges the messages of the `retried` oldest events of the retry queue, once they were sent
+    fn acknowledge_retried<Q: MessageQueue>(&mut self, queue: &mut Q, retried: usize) {
+        let retried = retried.min(self.unacknowledged.len());
+        for payload in self.unacknowledged.drain(..retried) {
+            acknowledge(queue, &payload);
+        }
+    }
+
+    /// Moves the events left in `processing_key` by a previous run back to the head of the events list, in the
+    /// order they were originally popped (see `ReliableQueue`)
+    fn recover_orphans<L: ListCommands>(&mut self, lists: &mut L, processing_key: &str) -> Result<(), FeederError> {
+        let mut recovered = 0;
+        while lists.move_back(processing_key, EVENTS_KEY)?.is_some() {
+            recovered += 1;
+        }
+
+        if recovered > 0 {
+            warn!("Recovered {} left in {} by a previous run", pluralize(recovered, "orphaned event"), processing_key);
+        }
+        self.stats.recovered_orphaned_events += recovered as u64;
+
+        Ok(())
+    }
+
+    /// Deserializes `payload` with the parser of its schema version (see `EventSchemaVersion`). v1 events are
+    /// parsed leniently if enabled (see `Feeder::with_lenient_parsing`), with the configured datetime formats
+    ///
+    /// # Errors
+    ///
+    /// `errors::DeserializationError::UnsupportedSchemaVersion` - When the version is unknown
+    fn parse_event(&mut self, payload: &str) -> Result<Event> {
+        let json: Value = serde_json::from_str(payload)?;
+
+        let event = match EventSchemaVersion::of_json(&json)? {
+            EventSchemaVersion::V1 => {
+                let event = Event::from_json_with(&json, &self.datetime_formats, self.lenient_parsing)?;
+                self.stats.v1_events += 1;
+                event
+            },
+            EventSchemaVersion::V2 => {
+                let event = Event::from_json_v2(&json)?;
+                self.stats.v2_events += 1;
+                event
+            }
+        };
+
+        Ok(event)
+    }
+
+    /// Keeps reconnecting while `err` (and any error while reconnecting) is transient, waiting for an exponentially
+    /// increasing (jittered) delay before each attempt
+    ///
+    /// # Returns
+    /// The new queue, or `None` if a permanent error was encountered
+    ///
+    /// # Errors
+    ///
+    /// `errors::FeederError::MaxRetriesExceeded` - When `max_retries` attempts were made since the last successful
+    /// pop, and the last one failed too
+    fn reconnect<Q, C>(&mut self, mut err: FeederError, connect: &mut C) -> Result<Option<Q>, FeederError>
+        where C: FnMut() -> Result<Q, FeederError>
+    {
+        loop {
+            if !err.is_transient() {
+                self.stats.permanent_errors += 1;
+                error!("Permanent error, stopping feeder: {}", err);
+                return Ok(None);
+            }
+
+            self.stats.transient_errors += 1;
+            if let Some(max_retries) = self.max_retries.filter(|&max| self.retries >= max) {
+                return Err(FeederError::MaxRetriesExceeded { retries: max_retries, last_error: Box::new(err) });
+            }
+
+            self.retries += 1;
+            let delay = with_jitter(self.reconnect_backoff.next_delay());
+            warn!("Transient error, reconnecting in {:?} (attempt {}): {}", delay, self.retries, err);
+            thread::sleep(delay);
+
+            match connect() {
+                Ok(q) => return Ok(Some(q)),
+                Err(e) => err = e
+            }
+        }
+    }
+
+    /// Called after a successful pop: the next transient error starts a new series of reconnection attempts
+    fn reset_retries(&mut self) {
+        self.reconnect_backoff.reset();
+        self.retries = 0;
+    }
+
+    /// Sends `event` to the processors, waiting for room while a bounded channel is full (see `channel_capacity`),
+    /// so that a feeder pops no faster than the processors keep up. If the channel is disconnected (or older events
+    /// are still waiting to be retried), it is pushed into the retry queue instead
+    ///
+    /// # Returns
+    /// Whether the event was sent
+    fn dispatch(&mut self, sendr: &Sender<Event>, event: Event) -> bool {
+        let event = if self.retry_queue.is_empty() {
+            match sendr.send(event) {
+                Ok(()) => return true,
+                Err(e) => {
+                    error!("Could not send event to processor, will retry: {}", e);
+                    e.into_inner()
+                }
+            }
+        } else {
+            event
+        };
+
+        if let Some(dropped) = self.retry_queue.push(event) {
+            self.stats.dropped_events += 1;
+            warn!(
+                "Retry queue is full ({} events). Dropping event {}",
+                self.retry_queue.len(), dropped.url()
+            );
+        }
+
+        false
+    }
+
+    /// Dispatches `event` inside a `feeder.receive_message` span, which carries the event's url and source.
+    /// The event holds on to the span's trace ID, so that later stages can be correlated with it
+    ///
+    /// The span ends as soon as the event is dispatched. If the event was queued for a retry instead of being
+    /// sent, the span's status is set to an error
+    #[cfg(feature = "tracing")]
+    fn dispatch_traced(&mut self, sendr: &Sender<Event>, mut event: Event, tracer: &BoxedTracer) -> bool {
+        let mut span = tracer.start("feeder.receive_message");
+        span.set_attribute(KeyValue::new("event.url", event.url().to_owned()));
+        span.set_attribute(KeyValue::new("event.source", event.source().to_owned()));
+
+        if span.span_context().is_valid() {
+            event.set_trace_id(span.span_context().trace_id().to_string());
+        }
+
+        let sent = self.dispatch(sendr, event);
+        if !sent {
+            span.set_status(Status::error("Event was queued for retry"));
+        }
+        span.end();
+
+        sent
+    }
+
+}
+
+impl MessageSource for Feeder {
+    fn feed(&mut self, sendr: &Sender<Event>, shutdown: &Receiver<()>) -> Result<()> {
+        self.shutdown = Receiver::clone(shutdown);
+
+        #[cfg(feature = "tracing")]
+        let tracer = global::tracer("processor-rs");
+        #[cfg(not(feature = "tracing"))]
+        let tracer = BoxedTracer;
+
+        self.listen_traced(sendr, &tracer)
+    }
+
+    fn stats(&self) -> &FeederStats {
+        &self.stats
+    }
+}
+
+/// Lets `queue` know that the message carrying `payload` was handled (see `MessageQueue::ack`)
+fn acknowledge<Q: MessageQueue>(queue: &mut Q, payload: &str) {
+    if let Err(e) = queue.ack(payload) {
+        warn!("Could not acknowledge message, it may be delivered again: {}", e);
+    }
+}
+
+struct Message {
+    name: String,
+    payload: String
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+    use chrono::{Local, Utc};
+    use redis::{ErrorKind, RedisError};
+    use crate::errors::DeserializationError;
+    use crossbeam_channel::never;
+
+    fn event(url: &str) -> Event {
+        Event::new(url, 3, "pastebin", "foo", "foo.txt", "bar", Local::now(), Local::now())
+    }
+
+    /// Expects a redis server listening on localhost:6379 (see `docker-compose.yml`) once a connection is borrowed
+    fn pool(max_size: u32, checkout_timeout: Duration) -> Arc<RedisPool> {
+        let client = open_client("localhost", 6379).unwrap();
+        Arc::new(RedisPool::new(client, max_size, Duration::from_secs(300), checkout_timeout))
+    }
+
+    fn feeder(retry_queue_size: usize) -> Feeder {
+        // The pool does not connect until a connection is borrowed
+        Feeder::with_source(Source::Redis(pool(1, POOL_CHECKOUT_TIMEOUT))).with_retry_queue_size(retry_queue_size)
+    }
+
+    #[test]
+    fn events_are_sent_when_the_channel_has_room() {
+        let (sendr, recvr) = crossbeam_channel::bounded(1);
+        let mut f = feeder(10);
+
+        f.dispatch(&sendr, event("https://pastebin.com/1"));
+
+        assert_eq!(recvr.try_recv().unwrap().url(), "https://pastebin.com/1");
+        assert!(f.retry_queue.is_empty());
+    }
+
+    #[test]
+    fn dispatch_waits_for_room_while_the_channel_is_full() {
+        let (sendr, recvr) = crossbeam_channel::bounded(1);
+        let mut f = feeder(10);
+        assert!(f.dispatch(&sendr, event("https://pastebin.com/0")));
+
+        let blocked = thread::spawn(move || {
+            let sent = f.dispatch(&sendr, event("https://pastebin.com/1"));
+            (f, sent)
+        });
+        thread::sleep(Duration::from_millis(50));
+        assert!(!blocked.is_finished());
+
+        assert_eq!(recvr.recv().unwrap().url(), "https://pastebin.com/0");
+        let (f, sent) = blocked.join().unwrap();
+        assert!(sent);
+        assert_eq!(recvr.recv().unwrap().url(), "https://pastebin.com/1");
+        assert!(f.retry_queue.is_empty());
+        assert_eq!(f.stats().dropped_events(), 0);
+    }
+
+    #[test]
+    fn events_accumulate_in_the_retry_queue_while_the_channel_is_disconnected() {
+        let (sendr, recvr) = crossbeam_channel::bounded(1);
+        drop(recvr);
+        let mut f = feeder(10);
+
+        for i in 0..3 {
+            assert!(!f.dispatch(&sendr, event(&format!("https://pastebin.com/{}", i))));
+        }
+
+        assert_eq!(f.retry_queue.len(), 3);
+        assert_eq!(f.stats().dropped_events(), 0);
+    }
+
+    #[test]
+    fn retry_queue_drains_when_the_channel_has_room() {
+        let (sendr, recvr) = crossbeam_channel::bounded(1);
+        let mut retry_queue = RetryQueue::with_capacity(10);
+        for i in 0..3 {
+            retry_queue.push(event(&format!("https://pastebin.com/{}", i)));
+        }
+
+        let mut received = Vec::new();
+        while retry_queue.drain_into(&sendr) > 0 {
+            received.push(recvr.try_recv().unwrap().url().to_owned());
+        }
+
+        assert_eq!(received, vec!["https://pastebin.com/0", "https://pastebin.com/1", "https://pastebin.com/2"]);
+        assert!(retry_queue.is_empty());
+    }
+
+    #[test]
+    fn oldest_events_are_dropped_when_the_retry_queue_is_full() {
+        let (sendr, recvr) = crossbeam_channel::bounded(1);
+        drop(recvr);
+        let mut f = feeder(2);
+
+        for i in 0..5 {
+            f.dispatch(&sendr, event(&format!("https://pastebin.com/{}", i)));
+        }
+
+        assert_eq!(f.stats().dropped_events(), 3);
+        assert_eq!(f.retry_queue.events.front().unwrap().url(), "https://pastebin.com/3");
+    }
+
+    #[test]
+    fn events_are_dropped_without_a_retry_queue() {
+        let (sendr, recvr) = crossbeam_channel::bounded(0);
+        drop(recvr);
+        let mut f = feeder(0);
+
+        f.dispatch(&sendr, event("https://pastebin.com/1"));
+
+        assert_eq!(f.stats().dropped_events(), 1);
+    }
+
+    #[test]
+    fn urls_are_deduplicated_within_the_window() {
+        let mut cache = DedupCache::new(NonZeroUsize::new(10).unwrap(), Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert!(!cache.seen_recently("https://pastebin.com/1", now));
+        assert!(cache.seen_recently("https://pastebin.com/1", now + Duration::from_secs(59)));
+        assert!(!cache.seen_recently("https://pastebin.com/2", now + Duration::from_secs(59)));
+    }
+
+    #[test]
+    fn urls_are_not_deduplicated_outside_the_window() {
+        let mut cache = DedupCache::new(NonZeroUsize::new(10).unwrap(), Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert!(!cache.seen_recently("https://pastebin.com/1", now));
+        assert!(!cache.seen_recently("https://pastebin.com/1", now + Duration::from_secs(60)));
+        // The second sighting restarts the window
+        assert!(cache.seen_recently("https://pastebin.com/1", now + Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn least_recently_seen_urls_are_evicted() {
+        let mut cache = DedupCache::new(NonZeroUsize::new(1).unwrap(), Duration::from_secs(60));
+        let now = Instant::now();
+
+        cache.seen_recently("https://pastebin.com/1", now);
+        cache.seen_recently("https://pastebin.com/2", now);
+
+        assert!(!cache.seen_recently("https://pastebin.com/1", now));
+    }
+
+    #[test]
+    fn feeder_counts_deduplicated_events() {
+        let mut f = feeder(10).with_dedup_cache(10, Duration::from_secs(60));
+
+        assert!(!f.is_recent_duplicate(&event("https://pastebin.com/1")));
+        assert!(f.is_recent_duplicate(&event("https://pastebin.com/1")));
+        assert_eq!(f.stats().deduped_events(), 1);
+    }
+
+    #[test]
+    fn deduplication_is_disabled_by_default() {
+        let mut f = feeder(10).with_dedup_cache(0, Duration::from_secs(60));
+
+        assert!(!f.is_recent_duplicate(&event("https://pastebin.com/1")));
+        assert!(!f.is_recent_duplicate(&event("https://pastebin.com/1")));
+    }
+
+    type Script = Rc<RefCell<VecDeque<Result<Option<Message>, FeederError>>>>;
+
+    /// Pops scripted responses. Queues returned by `connect` share the same script
+    struct MockQueue {
+        script: Script
+    }
+
+    impl MessageQueue for MockQueue {
+        fn pop(&mut self) -> Result<Option<Message>, FeederError> {
+            self.script.borrow_mut().pop_front().expect("the feeder should stop before the script runs out")
+        }
+    }
+
+    fn message(payload: &str) -> Result<Option<Message>, FeederError> {
+        Ok(Some(Message { name: "events".to_owned(), payload: payload.to_owned() }))
+    }
+
+    fn event_json(url: &str) -> String {
+        format!(
+            r#"{{"url": "{}", "size": 3, "source": "pastebin", "raw_content": "foo", "filename": "foo.txt",
+                 "creator": "bar", "created_at": "2021/01/01-10:00:00", "discovered_at": "2021/01/01-10:05:00"}}"#,
+            url
+        )
+    }
+
+    #[test]
+    fn feeder_reconnects_after_transient_errors_and_stops_after_permanent_ones() {
+        let io_error = std::io::Error::new(std::io::ErrorKind::ConnectionReset, "connection reset by peer");
+        let script = Rc::new(RefCell::new(VecDeque::from(vec![
+            Err(RedisError::from(io_error).into()),
+            message(&event_json("https://pastebin.com/foo")),
+            Err(RedisError::from((ErrorKind::AuthenticationFailed, "WRONGPASS")).into()),
+        ])));
+        let (sendr, recvr) = crossbeam_channel::unbounded();
+        let mut feeder = feeder(0).with_reconnect_backoff(Duration::from_millis(1), Duration::from_millis(1));
+        let mut connections = 0;
+
+        let result = feeder.listen_on(
+            &sendr,
+            || {
+                connections += 1;
+                Ok(MockQueue { script: Rc::clone(&script) })
+            },
+            Feeder::dispatch
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(connections, 2);
+        assert!(script.borrow().is_empty());
+        assert_eq!(recvr.try_recv().unwrap().url(), "https://pastebin.com/foo");
+        assert_eq!(feeder.stats().transient_errors(), 1);
+        assert_eq!(feeder.stats().permanent_errors(), 1);
+    }
+
+    fn connection_reset() -> FeederError {
+        RedisError::from(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "connection reset by peer")).into()
+    }
+
+    #[test]
+    fn feeder_gives_up_after_max_retries_in_a_row() {
+        let script = Rc::new(RefCell::new(VecDeque::from(vec![Err(connection_reset())])));
+        let (sendr, _recvr) = crossbeam_channel::unbounded();
+        let mut feeder = feeder(0)
+            .with_reconnect_backoff(Duration::from_millis(1), Duration::from_millis(1))
+            .with_max_retries(3);
+        let mut connections = 0;
+
+        let result = feeder.listen_on(
+            &sendr,
+            || {
+                connections += 1;
+                match connections {
+                    1 => Ok(MockQueue { script: Rc::clone(&script) }),
+                    _ => Err(connection_reset())
+                }
+            },
+            Feeder::dispatch
+        );
+
+        let err = result.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FeederError::MaxRetriesExceeded { retries: 3, .. })));
+        assert_eq!(connections, 4);
+        assert_eq!(feeder.stats().transient_errors(), 4);
+    }
+
+    #[test]
+    fn successful_pops_reset_the_retries() {
+        let script = Rc::new(RefCell::new(VecDeque::from(vec![
+            Err(connection_reset()),
+            message(&event_json("https://pastebin.com/1")),
+            Err(connection_reset()),
+            message(&event_json("https://pastebin.com/2")),
+            Err(connection_reset()),
+            message("QUIT")
+        ])));
+        let (sendr, recvr) = crossbeam_channel::unbounded();
+        let mut feeder = feeder(0)
+            .with_reconnect_backoff(Duration::from_millis(1), Duration::from_millis(1))
+            .with_max_retries(1);
+
+        let result = feeder.listen_on(&sendr, || Ok(MockQueue { script: Rc::clone(&script) }), Feeder::dispatch);
+
+        assert!(result.is_ok());
+        assert_eq!(recvr.try_iter().count(), 2);
+        assert_eq!(feeder.stats().transient_errors(), 3);
+    }
+
+    #[test]
+    #[ignore]
+    fn events_found_in_the_index_cache_are_skipped() {
+        let conn = DbConnection::connect("postgres", "infobserve", "infobserve", "localhost", 5432).unwrap();
+        crate::database::DbLoader::with_connection(conn.clone()).run_migrations().unwrap();
+        let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos();
+        let (stored, new) = (format!("https://pastebin.com/stored-{}", nanos), format!("https://pastebin.com/new-{}", nanos));
+        let mut client = conn.get().unwrap();
+        let mut trans = client.transaction().unwrap();
+        IndexCache::mark_seen_batch(&mut trans, &[("pastebin", &stored)]).unwrap();
+        trans.commit().unwrap();
+
+        let script = Rc::new(RefCell::new(VecDeque::from(vec![
+            message(&event_json(&stored)),
+            message(&event_json(&new)),
+            message("QUIT")
+        ])));
+        let (sendr, recvr) = crossbeam_channel::unbounded();
+        let mut feeder = feeder(0).with_index_cache(Some(conn));
+
+        feeder.listen_on(&sendr, || Ok(MockQueue { script: Rc::clone(&script) }), Feeder::dispatch).unwrap();
+
+        assert_eq!(recvr.try_iter().map(|e| e.url().to_owned()).collect::<Vec<_>>(), vec![new]);
+        assert_eq!(feeder.stats().index_cache_hits(), 1);
+    }
+
+    #[test]
+    #[ignore]
+    fn events_stored_by_a_loader_are_skipped() {
+        let conn = DbConnection::connect("postgres", "infobserve", "infobserve", "localhost", 5432).unwrap();
+        let loader = crate::database::DbLoader::with_connection(conn.clone());
+        loader.run_migrations().unwrap();
+        let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos();
+        let url = format!("https://pastebin.com/loaded-{}", nanos);
+        assert_eq!(loader.load_batch(vec![crate::entities::ProcessedEvent(event(&url), vec![])]).num_persisted(), 1);
+
+        let script = Rc::new(RefCell::new(VecDeque::from(vec![message(&event_json(&url)), message("QUIT")])));
+        let (sendr, recvr) = crossbeam_channel::unbounded();
+        let mut feeder = feeder(0).with_index_cache(Some(conn));
+
+        feeder.listen_on(&sendr, || Ok(MockQueue { script: Rc::clone(&script) }), Feeder::dispatch).unwrap();
+
+        assert!(recvr.try_recv().is_err());
+        assert_eq!(feeder.stats().index_cache_hits(), 1);
+    }
+
+    #[test]
+    fn zero_max_retries_never_gives_up() {
+        let mut script: Vec<_> = (0..20).map(|_| Err(connection_reset())).collect();
+        script.push(message("QUIT"));
+        let script = Rc::new(RefCell::new(VecDeque::from(script)));
+        let (sendr, _recvr) = crossbeam_channel::unbounded();
+        let mut feeder = feeder(0)
+            .with_reconnect_backoff(Duration::from_millis(1), Duration::from_millis(1))
+            .with_max_retries(0);
+
+        let result = feeder.listen_on(&sendr, || Ok(MockQueue { script: Rc::clone(&script) }), Feeder::dispatch);
+
+        assert!(result.is_ok());
+        assert_eq!(feeder.stats().transient_errors(), 20);
+    }
+
+    /// A file holding `lines`, one per line (line breaks within them are replaced by spaces)
+    fn events_file(test: &str, lines: &[String]) -> String {
+        let path = std::env::temp_dir().join(format!("infobserve-feeder-{}-{}.jsonl", test, std::process::id()));
+        let lines: Vec<String> = lines.iter().map(|l| l.replace('\n', " ")).collect();
+        std::fs::write(&path, lines.join("\n")).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn file_feeders_send_every_event_and_stop_at_the_end() {
+        let path = events_file("all", &[
+            event_json("https://pastebin.com/1"),
+            String::new(),
+            event_json("https://pastebin.com/2")
+        ]);
+        let (sendr, recvr) = crossbeam_channel::unbounded();
+
+        let mut source = file_source(&path, &FeederCfg::default(), None).unwrap();
+
+        assert!(source.feed(&sendr, &never()).is_ok());
+        assert_eq!(urls(&recvr), vec!["https://pastebin.com/1", "https://pastebin.com/2"]);
+    }
+
+    #[test]
+    fn file_feeders_wait_for_room_instead_of_dropping_events() {
+        let lines: Vec<String> = (0..20).map(|i| event_json(&format!("https://pastebin.com/{}", i))).collect();
+        let path = events_file("slow", &lines);
+        let (sendr, recvr) = crossbeam_channel::bounded(1);
+        let consumer = thread::spawn(move || {
+            recvr.iter().inspect(|_| thread::sleep(Duration::from_millis(5))).count()
+        });
+
+        let mut source = file_source(&path, &FeederCfg::default(), None).unwrap();
+        source.feed(&sendr, &never()).unwrap();
+        drop(sendr);
+
+        assert_eq!(consumer.join().unwrap(), 20);
+        assert_eq!(source.stats().dropped_events(), 0);
+    }
+
+    #[test]
+    fn queued_events_are_sent_before_stopping_at_the_end_of_the_file() {
+        let path = events_file("eof", &[event_json("https://pastebin.com/2")]);
+        let (sendr, recvr) = crossbeam_channel::bounded(1);
+        sendr.send(event("https://pastebin.com/0")).unwrap();
+
+        // 1 waits in the retry queue and the channel is full, so 2 is queued behind it until the end of the file
+        let mut f = Feeder::from_file(&path, &FeederCfg::default());
+        f.retry_queue.push(event("https://pastebin.com/1"));
+        let consumer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            recvr.iter().map(|e| e.url().to_owned()).collect::<Vec<_>>()
+        });
+        f.feed(&sendr, &never()).unwrap();
+        drop(sendr);
+
+        assert_eq!(
+            consumer.join().unwrap(),
+            vec!["https://pastebin.com/0", "https://pastebin.com/1", "https://pastebin.com/2"]
+        );
+        assert!(f.retry_queue.is_empty());
+    }
+
+    #[test]
+    fn missing_input_files_stop_the_feeder() {
+        let (sendr, _recvr) = crossbeam_channel::unbounded();
+
+        let mut source = file_source("/nonexistent/events.jsonl", &FeederCfg::default(), None).unwrap();
+        let err = source.feed(&sendr, &never()).unwrap_err();
+
+        assert!(matches!(err.downcast_ref(), Some(FeederError::InputFile { .. })));
+    }
+
+    #[test]
+    fn events_are_parsed_according_to_their_schema_version() {
+        let v2 = r#"{"schema_version": 2, "url": "https://pastebin.com/v2", "source": "pastebin", "content": "foo",
+                     "filename": "foo.txt", "author": "bar", "created_at": "2021-01-01T10:00:00Z",
+                     "discovered_at": "2021-01-01T10:05:00Z"}"#;
+        let unknown = v2.replace(r#""schema_version": 2"#, r#""schema_version": "3""#);
+        let script = Rc::new(RefCell::new(VecDeque::from(vec![
+            message(&event_json("https://pastebin.com/v1")),
+            message(v2),
+            message(&unknown),
+            message("QUIT")
+        ])));
+        let (sendr, recvr) = crossbeam_channel::unbounded();
+        let mut feeder = feeder(0);
+
+        let result = feeder.listen_on(&sendr, || Ok(MockQueue { script: Rc::clone(&script) }), Feeder::dispatch);
+
+        assert!(result.is_ok());
+        let received: Vec<(String, EventSchemaVersion)> = recvr.try_iter()
+            .map(|e| (e.url().to_owned(), e.schema_version()))
+            .collect();
+        assert_eq!(received, vec![
+            ("https://pastebin.com/v1".to_owned(), EventSchemaVersion::V1),
+            ("https://pastebin.com/v2".to_owned(), EventSchemaVersion::V2)
+        ]);
+        assert_eq!(feeder.stats().v1_events(), 1);
+        assert_eq!(feeder.stats().v2_events(), 1);
+    }
+
+    #[test]
+    fn optional_fields_are_only_defaulted_when_lenient() {
+        let payload = r#"{"url": "https://pastebin.com/foo", "size": 3, "source": "pastebin", "raw_content": "foo",
+                          "created_at": "2021/01/01-10:00:00", "discovered_at": "2021/01/01-10:05:00"}"#;
+
+        assert!(feeder(0).parse_event(payload).is_err());
+        let event = feeder(0).with_lenient_parsing(true).parse_event(payload).unwrap();
+        assert_eq!((event.filename(), event.creator()), ("", ""));
+    }
+
+    #[test]
+    fn timestamps_are_parsed_with_the_configured_formats() {
+        let payload = r#"{"url": "https://pastebin.com/foo", "size": 3, "source": "pastebin", "raw_content": "foo",
+                          "filename": "foo.txt", "creator": "bar", "created_at": 1609495200,
+                          "discovered_at": "2021-01-01T10:05:00Z"}"#;
+        let formats = ["%Y/%m/%d-%H:%M:%S".to_owned(), "%+".to_owned(), "%s".to_owned()];
+
+        assert!(feeder(0).parse_event(payload).is_err());
+        let event = feeder(0).with_datetime_formats(&formats).parse_event(payload).unwrap();
+        assert_eq!(event.created_at().with_timezone(&Utc).to_rfc3339(), "2021-01-01T10:00:00+00:00");
+        assert_eq!(event.discovered_at().with_timezone(&Utc).to_rfc3339(), "2021-01-01T10:05:00+00:00");
+    }
+
+    #[test]
+    fn unsupported_schema_versions_are_reported() {
+        let err = feeder(0).parse_event(r#"{"schema_version": "v9"}"#).unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<DeserializationError>(),
+            Some(DeserializationError::UnsupportedSchemaVersion(v)) if v == "v9"
+        ));
+    }
+
+    #[test]
+    fn feeder_keeps_polling_when_no_message_arrives_in_time() {
+        let script = Rc::new(RefCell::new(VecDeque::from(vec![
+            Ok(None),
+            Ok(None),
+            message(&event_json("https://pastebin.com/foo")),
+            message("QUIT")
+        ])));
+        let (sendr, recvr) = crossbeam_channel::unbounded();
+        let mut feeder = feeder(0);
+
+        let result = feeder.listen_on(&sendr, || Ok(MockQueue { script: Rc::clone(&script) }), Feeder::dispatch);
+
+        assert!(result.is_ok());
+        assert_eq!(recvr.try_recv().unwrap().url(), "https://pastebin.com/foo");
+        assert_eq!(feeder.stats().transient_errors(), 0);
+    }
+
+    #[test]
+    fn feeder_stops_when_a_shutdown_is_requested() {
+        let script = Rc::new(RefCell::new(VecDeque::from(vec![
+            message(&event_json("https://pastebin.com/foo")),
+            Ok(None)
+        ])));
+        let (sendr, recvr) = crossbeam_channel::unbounded();
+        let (shutdown_sendr, shutdown_recvr) = crossbeam_channel::bounded(1);
+        let mut feeder = feeder(0);
+        feeder.shutdown = shutdown_recvr;
+
+        let result = feeder.listen_on(
+            &sendr,
+            || Ok(MockQueue { script: Rc::clone(&script) }),
+            |feeder, sendr, event| {
+                // The shutdown is requested while the first event is being dispatched
+                shutdown_sendr.send(()).unwrap();
+                Feeder::dispatch(feeder, sendr, event)
+            }
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(recvr.try_recv().unwrap().url(), "https://pastebin.com/foo");
+        assert_eq!(script.borrow().len(), 1);
+    }
+
+    #[test]
+    fn a_disconnected_shutdown_channel_stops_the_feeder() {
+        let script = Rc::new(RefCell::new(VecDeque::new()));
+        let (sendr, _recvr) = crossbeam_channel::unbounded();
+        let mut feeder = feeder(0);
+        feeder.shutdown = crossbeam_channel::bounded::<()>(1).1;
+
+        let result = feeder.listen_on(&sendr, || Ok(MockQueue { script: Rc::clone(&script) }), Feeder::dispatch);
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn failed_reconnections_are_retried_with_increasing_delays() {
+        let mut feeder = feeder(0).with_reconnect_backoff(Duration::from_millis(1), Duration::from_millis(4));
+        let mut attempts = 0;
+
+        let queue = feeder.reconnect(
+            RedisError::from((ErrorKind::TryAgain, "try again")).into(),
+            &mut || {
+                attempts += 1;
+                if attempts < 3 {
+                    Err(RedisError::from((ErrorKind::BusyLoadingError, "loading")).into())
+                } else {
+                    Ok(())
+                }
+            }
+        );
+
+        assert!(queue.unwrap().is_some());
+        assert_eq!(feeder.stats().transient_errors(), 3);
+        assert_eq!(feeder.reconnect_backoff.next_delay(), Duration::from_millis(4));
+    }
+
+    /// Feeds its events, then stops as if a quit message was received
+    struct TestMessageSource {
+        events: VecDeque<String>,
+        stats: FeederStats
+    }
+
+    impl TestMessageSource {
+        fn new(events: VecDeque<String>) -> Self {
+            Self { events, stats: FeederStats::default() }
+        }
+    }
+
+    impl MessageSource for TestMessageSource {
+        fn feed(&mut self, sendr: &Sender<Event>, _shutdown: &Receiver<()>) -> Result<()> {
+            while let Some(payload) = self.events.pop_front() {
+                sendr.send(Event::from_json_str(&payload)?)?;
+            }
+
+            Ok(())
+        }
+
+        fn stats(&self) -> &FeederStats {
+            &self.stats
+        }
+    }
+
+    fn test_source_factory(urls: &[&str]) -> Box<dyn Fn() -> Result<Box<dyn MessageSource>>> {
+        let events: VecDeque<String> = urls.iter().map(|url| event_json(url)).collect();
+
+        Box::new(move || Ok(Box::new(TestMessageSource::new(events.clone()))))
+    }
+
+    #[test]
+    fn all_events_are_delivered() {
+        let (sendr, recvr) = crossbeam_channel::unbounded();
+
+        let factory = test_source_factory(&["https://pastebin.com/1", "https://pastebin.com/2"]);
+        let handles = start_feeders(&sendr, factory, 1, &never(), &Arc::default());
+        for handle in handles {
+            assert!(handle.join().unwrap().is_ok());
+        }
+
+        let urls: Vec<String> = recvr.try_iter().map(|e| e.url().to_owned()).collect();
+        assert_eq!(urls, vec!["https://pastebin.com/1", "https://pastebin.com/2"]);
+    }
+
+    #[test]
+    fn every_feeder_thread_gets_its_own_source() {
+        let (sendr, recvr) = crossbeam_channel::unbounded();
+        let sources = Arc::new(AtomicUsize::new(0));
+        let inner = test_source_factory(&["https://pastebin.com/1", "https://pastebin.com/2", "https://pastebin.com/3"]);
+        let counted = Arc::clone(&sources);
+        let factory: Box<dyn Fn() -> Result<Box<dyn MessageSource>>> = Box::new(move || {
+            counted.fetch_add(1, AtomicOrdering::SeqCst);
+            inner()
+        });
+
+        let handles = start_feeders(&sendr, factory, 3, &never(), &Arc::default());
+        for handle in handles {
+            assert!(handle.join().unwrap().is_ok());
+        }
+
+        assert_eq!(sources.load(AtomicOrdering::SeqCst), 3);
+        assert_eq!(recvr.try_iter().count(), 9);
+    }
+
+    #[test]
+    fn source_errors_are_returned_by_the_feeder_threads() {
+        let (sendr, _recvr) = crossbeam_channel::unbounded();
+        let factory: Box<dyn Fn() -> Result<Box<dyn MessageSource>>> = Box::new(|| {
+            Err(FeederError::TlsConfigurationFailed("no certificate".to_owned()).into())
+        });
+
+        let handles = start_feeders(&sendr, factory, 2, &never(), &Arc::default());
+
+        for handle in handles {
+            let err = handle.join().unwrap().unwrap_err();
+            assert!(err.to_string().contains("no certificate"));
+        }
+    }
+
+    #[test]
+    fn invalid_events_stop_the_source() {
+        let (sendr, recvr) = crossbeam_channel::unbounded();
+        let events = VecDeque::from(vec![event_json("https://pastebin.com/1"), "not json".to_owned()]);
+        let factory: Box<dyn Fn() -> Result<Box<dyn MessageSource>>> = Box::new(move || {
+            Ok(Box::new(TestMessageSource::new(events.clone())))
+        });
+
+        let handles = start_feeders(&sendr, factory, 1, &never(), &Arc::default());
+
+        for handle in handles {
+            assert!(handle.join().unwrap().is_err());
+        }
+        assert_eq!(recvr.try_recv().unwrap().url(), "https://pastebin.com/1");
+    }
+
+    /// Redis lists, kept in memory. Copies share the same lists
+    #[derive(Clone, Default)]
+    struct MemoryLists {
+        lists: Rc<RefCell<HashMap<String, VecDeque<String>>>>
+    }
+
+    impl MemoryLists {
+        fn with_events(payloads: &[String]) -> Self {
+            let lists = Self::default();
+            lists.lists.borrow_mut().insert(EVENTS_KEY.to_owned(), payloads.iter().cloned().collect());
+            lists
+        }
+
+        fn list(&self, key: &str) -> Vec<String> {
+            self.lists.borrow().get(key).map(|l| l.iter().cloned().collect()).unwrap_or_default()
+        }
+
+        fn push(&self, key: &str, payload: &str) {
+            self.lists.borrow_mut().entry(key.to_owned()).or_default().push_back(payload.to_owned());
+        }
+    }
+
+    impl ListCommands for MemoryLists {
+        /// Never blocks. An empty `source` stops the feeder (instead of blocking it forever)
+        fn move_blocking(&mut self, source: &str, destination: &str) -> Result<Option<String>, FeederError> {
+            let mut lists = self.lists.borrow_mut();
+            let moved = lists.entry(source.to_owned()).or_default().pop_front();
+            match moved {
+                Some(m) => {
+                    lists.entry(destination.to_owned()).or_default().push_back(m.clone());
+                    Ok(Some(m))
+                },
+                None => Err(RedisError::from((ErrorKind::ResponseError, "no more events")).into())
+            }
+        }
+
+        fn move_back(&mut self, source: &str, destination: &str) -> Result<Option<String>, FeederError> {
+            let mut lists = self.lists.borrow_mut();
+            let moved = lists.entry(source.to_owned()).or_default().pop_back();
+            if let Some(m) = &moved {
+                lists.entry(destination.to_owned()).or_default().push_front(m.clone());
+            }
+
+            Ok(moved)
+        }
+
+        fn remove(&mut self, key: &str, value: &str) -> Result<(), FeederError> {
+            let mut lists = self.lists.borrow_mut();
+            let list = lists.entry(key.to_owned()).or_default();
+            if let Some(i) = list.iter().position(|v| v == value) {
+                list.remove(i);
+            }
+
+            Ok(())
+        }
+    }
+
+    const PROCESSING_KEY: &str = "events:processing";
+
+    fn listen_reliably(feeder: &mut Feeder, sendr: &Sender<Event>, lists: &MemoryLists) {
+        let result = feeder.listen_on(sendr, || Ok(ReliableQueue::new(lists.clone(), PROCESSING_KEY)), Feeder::dispatch);
+        assert!(result.is_ok());
+    }
+
+    fn urls(recvr: &crossbeam_channel::Receiver<Event>) -> Vec<String> {
+        recvr.try_iter().map(|e| e.url().to_owned()).collect()
+    }
+
+    #[test]
+    fn reliable_queue_acknowledges_handled_events() {
+        let lists = MemoryLists::with_events(&[
+            event_json("https://pastebin.com/foo"),
+            "not json".to_owned(),
+            event_json("https://pastebin.com/bar"),
+            "QUIT".to_owned()
+        ]);
+        let (sendr, recvr) = crossbeam_channel::unbounded();
+
+        listen_reliably(&mut feeder(0), &sendr, &lists);
+
+        assert_eq!(urls(&recvr), vec!["https://pastebin.com/foo", "https://pastebin.com/bar"]);
+        assert!(lists.list(EVENTS_KEY).is_empty());
+        assert!(lists.list(PROCESSING_KEY).is_empty());
+    }
+
+    #[test]
+    fn orphaned_events_are_recovered_after_a_crash() {
+        let lists = MemoryLists::with_events(&[
+            event_json("https://pastebin.com/1"),
+            event_json("https://pastebin.com/2"),
+            event_json("https://pastebin.com/3")
+        ]);
+
+        // The first run pops two events, and crashes before sending them to the processors
+        let mut crashed = ReliableQueue::new(lists.clone(), PROCESSING_KEY);
+        crashed.pop().unwrap();
+        crashed.pop().unwrap();
+        drop(crashed);
+        assert_eq!(lists.list(PROCESSING_KEY).len(), 2);
+
+        lists.push(EVENTS_KEY, "QUIT");
+        let (sendr, recvr) = crossbeam_channel::unbounded();
+        let mut feeder = feeder(0);
+        feeder.recover_orphans(&mut lists.clone(), PROCESSING_KEY).unwrap();
+        listen_reliably(&mut feeder, &sendr, &lists);
+
+        assert_eq!(urls(&recvr), vec!["https://pastebin.com/1", "https://pastebin.com/2", "https://pastebin.com/3"]);
+        assert!(lists.list(PROCESSING_KEY).is_empty());
+        assert_eq!(feeder.stats().recovered_orphaned_events(), 2);
+    }
+
+    #[test]
+    fn retried_events_are_acknowledged_once_sent() {
+        let lists = MemoryLists::with_events(&[
+            event_json("https://pastebin.com/1"),
+            event_json("https://pastebin.com/2"),
+            event_json("https://pastebin.com/3"),
+            "QUIT".to_owned()
+        ]);
+        let (disconnected, recvr) = crossbeam_channel::bounded(1);
+        drop(recvr);
+        let mut feeder = feeder(1);
+
+        // Nothing can be sent: 1 waits in the retry queue until 2 pushes it out, and so on
+        listen_reliably(&mut feeder, &disconnected, &lists);
+        assert_eq!(lists.list(PROCESSING_KEY).len(), 3);
+
+        // 3 is retried (and sent) before 4 is popped. The events that were pushed out stay unacknowledged
+        let (sendr, recvr) = crossbeam_channel::unbounded();
+        lists.push(EVENTS_KEY, &event_json("https://pastebin.com/4"));
+        lists.push(EVENTS_KEY, "QUIT");
+        listen_reliably(&mut feeder, &sendr, &lists);
+        assert_eq!(lists.list(PROCESSING_KEY), vec![event_json("https://pastebin.com/1"), event_json("https://pastebin.com/2")]);
+        assert_eq!(urls(&recvr), vec!["https://pastebin.com/3", "https://pastebin.com/4"]);
+    }
+
+    #[test]
+    fn only_the_first_feeder_recovers_orphans() {
+        let pool = pool(1, POOL_CHECKOUT_TIMEOUT);
+
+        assert!(pool.claim_orphan_recovery());
+        assert!(!pool.claim_orphan_recovery());
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(300));
+
+        assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(200));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(300));
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn backoff_never_starts_above_the_maximum() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(50));
+
+        assert_eq!(backoff.next_delay(), Duration::from_millis(50));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn jitter_keeps_at_least_half_of_the_delay() {
+        for _ in 0..100 {
+            let delay = with_jitter(Duration::from_millis(100));
+            assert!(delay >= Duration::from_millis(50) && delay <= Duration::from_millis(100));
+        }
+    }
+
+    #[test]
+    fn password_is_kept_on_the_connection_info() {
+        let client = with_password(open_client("localhost", 6379).unwrap(), Some("hunter2")).unwrap();
+
+        assert_eq!(client.get_connection_info().redis.password.as_deref(), Some("hunter2"));
+        assert_eq!(client.get_connection_info().addr.to_string(), "localhost:6379");
+    }
+
+    #[test]
+    #[cfg(not(feature = "tls"))]
+    fn tls_requires_the_tls_feature() {
+        assert!(tls_client("localhost", 6380, None, None, None).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "tls")]
+    fn tls_rejects_a_certificate_without_a_key() {
+        assert!(tls_client("localhost", 6380, Some("client.crt"), None, None).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "tls")]
+    fn tls_rejects_missing_certificate_files() {
+        assert!(tls_client("localhost", 6380, Some("missing.crt"), Some("missing.key"), None).is_err());
+    }
+
+    /// Certificates generated on the fly, for TLS servers and clients run by the tests
+    #[cfg(feature = "tls")]
+    mod certificates {
+        use openssl::asn1::Asn1Time;
+        use openssl::bn::BigNum;
+        use openssl::ec::{EcGroup, EcKey};
+        use openssl::hash::MessageDigest;
+        use openssl::nid::Nid;
+        use openssl::pkey::{PKey, Private};
+        use openssl::x509::extension::{BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectAlternativeName};
+        use openssl::x509::{X509, X509NameBuilder};
+
+        pub struct Certificate {
+            pub cert: X509,
+            pub key: PKey<Private>,
+            pub cert_path: String,
+            pub key_path: String
+        }
+
+        /// Issues a certificate for `name` (a self-signed CA one, if there is no `issuer`) and writes it, along with
+        /// its key, to PEM files
+        pub fn issue(name: &str, issuer: Option<&Certificate>) -> Certificate {
+            let key = PKey::from_ec_key(EcKey::generate(&EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap())
+                .unwrap()).unwrap();
+            let mut subject = X509NameBuilder::new().unwrap();
+            subject.append_entry_by_nid(Nid::COMMONNAME, name).unwrap();
+            let subject = subject.build();
+
+            let mut builder = X509::builder().unwrap();
+            builder.set_version(2).unwrap();
+            builder.set_serial_number(&BigNum::from_u32(rand::random()).unwrap().to_asn1_integer().unwrap()).unwrap();
+            builder.set_subject_name(&subject).unwrap();
+            builder.set_issuer_name(issuer.map_or(&subject, |i| i.cert.subject_name())).unwrap();
+            builder.set_pubkey(&key).unwrap();
+            builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+            builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+            match issuer {
+                None => {
+                    builder.append_extension(BasicConstraints::new().critical().ca().build().unwrap()).unwrap();
+                    builder.append_extension(KeyUsage::new().critical().key_cert_sign().build().unwrap()).unwrap();
+                },
+                Some(_) => {
+                    let san = SubjectAlternativeName::new().dns(name).build(&builder.x509v3_context(None, None));
+                    builder.append_extension(san.unwrap()).unwrap();
+                    builder.append_extension(KeyUsage::new().digital_signature().build().unwrap()).unwrap();
+                    builder.append_extension(ExtendedKeyUsage::new().server_auth().client_auth().build().unwrap())
+                        .unwrap();
+                }
+            }
+            builder.sign(issuer.map_or(&key, |i| &i.key), MessageDigest::sha256()).unwrap();
+            let cert = builder.build();
+
+            let path = |ext: &str| std::env::temp_dir()
+                .join(format!("infobserve-feeder-{}-{}.{}", name, std::process::id(), ext))
+                .to_string_lossy()
+                .into_owned();
+            let (cert_path, key_path) = (path("crt"), path("key"));
+            std::fs::write(&cert_path, cert.to_pem().unwrap()).unwrap();
+            std::fs::write(&key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+
+            Certificate { cert, key, cert_path, key_path }
+        }
+    }
+
+    /// A TLS server answering `+PONG` to every command, which requires its clients to present a certificate issued by
+    /// `ca`. The common name of each client's certificate (`None` if the handshake failed) is sent to the receiver
+    #[cfg(feature = "tls")]
+    fn mtls_server(
+        ca: &certificates::Certificate,
+        server: &certificates::Certificate
+    ) -> (u16, crossbeam_channel::Receiver<Option<String>>) {
+        use std::io::{Read, Write};
+        use openssl::nid::Nid;
+        use openssl::ssl::{SslAcceptor, SslMethod, SslVerifyMode};
+
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server()).unwrap();
+        acceptor.set_certificate(&server.cert).unwrap();
+        acceptor.set_private_key(&server.key).unwrap();
+        acceptor.cert_store_mut().add_cert(ca.cert.clone()).unwrap();
+        acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        let acceptor = acceptor.build();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sendr, recvr) = crossbeam_channel::unbounded();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match acceptor.accept(stream.unwrap()) {
+                    Ok(stream) => stream,
+                    Err(_) => {
+                        sendr.send(None).unwrap();
+                        continue;
+                    }
+                };
+                let common_name = stream.ssl().peer_certificate()
+                    .and_then(|cert| cert.subject_name().entries_by_nid(Nid::COMMONNAME).next()
+                        .map(|entry| String::from_utf8_lossy(entry.data().as_slice()).into_owned()));
+                sendr.send(common_name).unwrap();
+
+                // Commands may be pipelined, each one starting with the `*` of its array header
+                let mut buf = [0; 1024];
+                while let Ok(n @ 1..) = stream.read(&mut buf) {
+                    let commands = buf[..n].split(|&b| b == b'\n').filter(|line| line.starts_with(b"*")).count();
+                    stream.write_all(&b"+PONG\r\n".repeat(commands)).unwrap();
+                }
+            }
+        });
+
+        (port, recvr)
+    }
+
+    #[test]
+    #[cfg(feature = "tls")]
+    fn tls_clients_present_their_certificate() {
+        let ca = certificates::issue("infobserve-test-ca", None);
+        let server = certificates::issue("localhost", Some(&ca));
+        let client = certificates::issue("processor-rs", Some(&ca));
+        let (port, common_names) = mtls_server(&ca, &server);
+        let ping = |client: Client| client.get_connection()
+            .and_then(|mut conn| redis::cmd("PING").query::<String>(&mut conn));
+
+        let with_certificate = tls_client(
+            "localhost", port, Some(&client.cert_path), Some(&client.key_path), Some(&ca.cert_path)
+        ).unwrap();
+        assert_eq!(ping(with_certificate).unwrap(), "PONG");
+        assert_eq!(common_names.recv().unwrap().as_deref(), Some("processor-rs"));
+
+        let without_certificate = tls_client("localhost", port, None, None, Some(&ca.cert_path)).unwrap();
+        assert!(ping(without_certificate).is_err());
+        assert_eq!(common_names.recv().unwrap(), None);
+    }
+
+    #[test]
+    fn pool_follows_the_configuration() {
+        let redis_cfg = crate::config::Config::from_reader("redis:\n  max_pool_size: 3\n  idle_timeout_secs: 60".as_bytes())
+            .unwrap()
+            .redis()
+            .clone();
+
+        let pool = RedisPool::from_cfg(&redis_cfg).unwrap();
+
+        assert_eq!(pool.pool.max_size(), 3);
+        assert_eq!(pool.pool.idle_timeout(), Some(Duration::from_secs(60)));
+        assert_eq!(pool.pool.state().connections, 0);
+    }
+
+    #[test]
+    fn zero_idle_timeout_keeps_connections_open() {
+        let pool = RedisPool::new(open_client("localhost", 6379).unwrap(), 1, Duration::ZERO, POOL_CHECKOUT_TIMEOUT);
+
+        assert_eq!(pool.pool.idle_timeout(), None);
+    }
+
+    /// A stand-in for both a sentinel and the master it monitors (as `events`): `SENTINEL MASTERS` points to the
+    /// mock itself, which reports the master role. Returns its port and the commands it received, except for the
+    /// `CLIENT SETINFO` ones the redis client sends on every connection
+    fn mock_sentinel() -> (u16, Arc<std::sync::Mutex<Vec<String>>>) {
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let commands = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let received = Arc::clone(&commands);
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let received = Arc::clone(&received);
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.unwrap());
+                    let mut line = String::new();
+                    // Each command is an array of bulk strings: `*<n>`, then `$<len>` and the argument n times
+                    while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                        let num_args: usize = line.trim_end().trim_start_matches('*').parse().unwrap();
+                        let mut args = Vec::new();
+                        for _ in 0..num_args * 2 {
+                            line.clear();
+                            reader.read_line(&mut line).unwrap();
+                            args.push(line.trim_end().to_owned());
+                        }
+                        let command = args.iter().skip(1).step_by(2).cloned().collect::<Vec<_>>().join(" ");
+                        let reply = match command.as_str() {
+                            "SENTINEL MASTERS" => {
+                                let fields = ["name", "events", "ip", "127.0.0.1", "port", &port.to_string(), "flags", "master"]
+                                    .iter()
+                                    .map(|f| format!("${}\r\n{}\r\n", f.len(), f))
+                                    .collect::<String>();
+                                format!("*1\r\n*8\r\n{}", fields)
+                            },
+                            "ROLE" => "*1\r\n$6\r\nmaster\r\n".to_owned(),
+                            "PING" => "+PONG\r\n".to_owned(),
+                            _ => "+OK\r\n".to_owned()
+                        };
+                        if !command.starts_with("CLIENT SETINFO") {
+                            received.lock().unwrap().push(command);
+                        }
+                        reader.get_mut().write_all(reply.as_bytes()).unwrap();
+                        line.clear();
+                    }
+                });
+            }
+        });
+
+        (port, commands)
+    }
+
+    #[test]
+    fn sentinel_pools_connect_to_the_master() {
+        let (port, commands) = mock_sentinel();
+        let yml = format!(
+            "redis:\n  password: master-secret\n  sentinel:\n    master_name: events\n    password: sentinel-secret\n    \
+             sentinels: [127.0.0.1:{}]",
+            port
+        );
+        let redis_cfg = crate::config::Config::from_reader(yml.as_bytes()).unwrap().redis().clone();
+
+        let pool = RedisPool::from_cfg(&redis_cfg).unwrap();
+        assert!(pool.get().is_ok());
+
+        let commands = commands.lock().unwrap();
+        assert_eq!(commands[..2], ["AUTH sentinel-secret", "SENTINEL MASTERS"]);
+        assert!(commands.contains(&"ROLE".to_owned()));
+        assert!(commands.contains(&"AUTH master-secret".to_owned()));
+    }
+
+    #[test]
+    fn sentinel_pools_fail_without_a_reachable_sentinel() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let yml = format!("redis:\n  sentinel:\n    sentinels: [127.0.0.1:{}]", port);
+        let redis_cfg = crate::config::Config::from_reader(yml.as_bytes()).unwrap().redis().clone();
+
+        let pool = RedisPool::new(
+            sentinel_connector(redis_cfg.sentinel().unwrap(), None).unwrap(), 1, Duration::ZERO, Duration::from_millis(200)
+        );
+
+        assert!(matches!(pool.get(), Err(e) if e.is_transient()));
+    }
+
+    #[test]
+    #[ignore]
+    fn exhausted_pool_blocks_until_the_checkout_timeout() {
+        let pool = pool(1, Duration::from_millis(200));
+        let _borrowed = pool.get().unwrap();
+
+        let start = Instant::now();
+        let err = pool.get().err().unwrap();
+
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert!(matches!(err, FeederError::Pool(_)));
+        assert!(err.is_transient());
+    }
+
+    #[test]
+    #[ignore]
+    fn connections_are_returned_to_the_pool_on_drop() {
+        let pool = pool(1, Duration::from_millis(200));
+
+        drop(pool.get().unwrap());
+        assert_eq!(pool.pool.state().idle_connections, 1);
+
+        let _borrowed = pool.get().unwrap();
+        assert_eq!(pool.pool.state().connections, 1);
+    }
+
+    #[test]
+    #[ignore]
+    fn waiting_feeders_get_the_returned_connection() {
+        let pool = pool(1, Duration::from_secs(5));
+        let borrowed = pool.get().unwrap();
+
+        let returner = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            drop(borrowed);
+        });
+
+        assert!(pool.get().is_ok());
+        returner.join().unwrap();
+    }
+
+    #[cfg(feature = "tracing")]
+    mod tracing {
+        use super::*;
+        use std::borrow::Cow;
+        use std::sync::{Arc, Mutex};
+        use std::time::SystemTime;
+        use opentelemetry::{Context, Value};
+        use opentelemetry::trace::{SpanBuilder, SpanContext, SpanId, TraceFlags, TraceId, TraceState};
+
+        #[derive(Debug, Default, Clone)]
+        struct RecordedSpan {
+            name: String,
+            attributes: Vec<KeyValue>,
+            ended: bool,
+            failed: bool
+        }
+
+        impl RecordedSpan {
+            fn attribute(&self, key: &str) -> Option<&Value> {
+                self.attributes.iter().find(|kv| kv.key.as_str() == key).map(|kv| &kv.value)
+            }
+        }
+
+        fn trace_id(n: usize) -> TraceId {
+            TraceId::from_bytes((n as u128).to_be_bytes())
+        }
+
+        /// Records every span it starts, so that tests can inspect them
+        #[derive(Default)]
+        struct MockTracer {
+            spans: Arc<Mutex<Vec<RecordedSpan>>>
+        }
+
+        struct MockSpan {
+            index: usize,
+            context: SpanContext,
+            spans: Arc<Mutex<Vec<RecordedSpan>>>
+        }
+
+        impl Tracer for MockTracer {
+            type Span = MockSpan;
+
+            fn build_with_context(&self, builder: SpanBuilder, _parent_cx: &Context) -> MockSpan {
+                let mut spans = self.spans.lock().unwrap();
+                spans.push(RecordedSpan { name: builder.name.to_string(), ..Default::default() });
+
+                let index = spans.len() - 1;
+                let context = SpanContext::new(
+                    trace_id(index + 1),
+                    SpanId::from_bytes((index as u64 + 1).to_be_bytes()),
+                    TraceFlags::SAMPLED,
+                    false,
+                    TraceState::default()
+                );
+
+                MockSpan { index, context, spans: Arc::clone(&self.spans) }
+            }
+        }
+
+        impl Span for MockSpan {
+            fn add_event_with_timestamp<T>(&mut self, _name: T, _timestamp: SystemTime, _attributes: Vec<KeyValue>)
+                where T: Into<Cow<'static, str>> {}
+
+            fn span_context(&self) -> &SpanContext {
+                &self.context
+            }
+
+            fn is_recording(&self) -> bool {
+                true
+            }
+
+            fn set_attribute(&mut self, attribute: KeyValue) {
+                self.spans.lock().unwrap()[self.index].attributes.push(attribute);
+            }
+
+            fn set_status(&mut self, status: Status) {
+                self.spans.lock().unwrap()[self.index].failed = matches!(status, Status::Error { .. });
+            }
+
+            fn update_name<T>(&mut self, _new_name: T) where T: Into<Cow<'static, str>> {}
+
+            fn end_with_timestamp(&mut self, _timestamp: SystemTime) {
+                self.spans.lock().unwrap()[self.index].ended = true;
+            }
+        }
+
+        fn mock_tracer() -> (BoxedTracer, Arc<Mutex<Vec<RecordedSpan>>>) {
+            let tracer = MockTracer::default();
+            let spans = Arc::clone(&tracer.spans);
+
+            (BoxedTracer::new(Box::new(tracer)), spans)
+        }
+
+        #[test]
+        fn a_span_is_created_per_message() {
+            let (tracer, spans) = mock_tracer();
+            let (sendr, recvr) = crossbeam_channel::unbounded();
+            let mut f = feeder(10);
+
+            f.dispatch_traced(&sendr, event("https://pastebin.com/1"), &tracer);
+            f.dispatch_traced(&sendr, event("https://pastebin.com/2"), &tracer);
+
+            let spans = spans.lock().unwrap();
+            assert_eq!(spans.len(), 2);
+            for (i, span) in spans.iter().enumerate() {
+                assert_eq!(span.name, "feeder.receive_message");
+                assert_eq!(span.attribute("event.url"), Some(&Value::from(format!("https://pastebin.com/{}", i + 1))));
+                assert_eq!(span.attribute("event.source"), Some(&Value::from("pastebin")));
+                assert!(span.ended);
+                assert!(!span.failed);
+            }
+
+            let traced: Vec<Event> = recvr.try_iter().collect();
+            assert_eq!(traced[0].trace_id(), Some(trace_id(1).to_string().as_str()));
+            assert_eq!(traced[1].trace_id(), Some(trace_id(2).to_string().as_str()));
+        }
+
+        #[test]
+        fn spans_of_queued_events_fail() {
+            let (tracer, spans) = mock_tracer();
+            let (sendr, recvr) = crossbeam_channel::bounded(0);
+            drop(recvr);
+            let mut f = feeder(10);
+
+            assert!(!f.dispatch_traced(&sendr, event("https://pastebin.com/1"), &tracer));
+
+            let spans = spans.lock().unwrap();
+            assert!(spans[0].ended);
+            assert!(spans[0].failed);
+        }
+    }
+
+    /// Expects a TLS-enabled redis listening on localhost:6380, whose certificate is signed by the CA
+    /// in `INFOBSERVE_TEST_REDIS_CA`
+    #[test]
+    #[cfg(all(feature = "tls", feature = "integration-tests"))]
+    fn tls_connects_to_redis() {
+        let ca = std::env::var("INFOBSERVE_TEST_REDIS_CA").unwrap();
+        let client = tls_client("localhost", 6380, None, None, Some(&ca)).unwrap();
+        let mut conn = client.get_connection().unwrap();
+
+        let pong: String = redis::cmd("PING").query(&mut conn).unwrap();
+        assert_eq!(pong, "PONG");
+    }
+
+    /// Expects a Kafka broker listening on localhost:9092 (see `docker-compose.yml`)
+    #[test]
+    #[cfg(all(feature = "kafka", feature = "integration-tests"))]
+    fn kafka_events_are_consumed() {
+        use rdkafka::producer::{BaseRecord, DefaultProducerContext, Producer, ThreadedProducer};
+
+        let topic = format!("processor-rs-test-{}", uuid::Uuid::new_v4());
+        let kafka_cfg = crate::config::Config::from_reader(
+            format!("kafka:\n  enabled: true\n  topic: {}\n  poll_timeout_ms: 100", topic).as_bytes()
+        ).unwrap().kafka().clone();
+
+        let producer: ThreadedProducer<DefaultProducerContext> = ClientConfig::new()
+            .set("bootstrap.servers", kafka_cfg.brokers().join(","))
+            .create()
+            .unwrap();
+        for payload in &[event_json("https://pastebin.com/kafka"), "QUIT".to_owned()] {
+            producer.send(BaseRecord::<(), str>::to(&topic).payload(payload)).unwrap();
+        }
+        producer.flush(Duration::from_secs(10)).unwrap();
+
+        let (sendr, recvr) = crossbeam_channel::unbounded();
+        let mut feeder = Feeder::from_kafka_cfg(&kafka_cfg, &FeederCfg::default());
+        feeder.listen(&sendr).unwrap();
+
+        assert_eq!(recvr.try_recv().unwrap().url(), "https://pastebin.com/kafka");
+    }
+}
@@ -0,0 +1,229 @@
+//! Receives the events that scrapers stream over gRPC, through the `EventFeed` service of `proto/event.proto`
+use log::{info, error};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+
+use anyhow::Result;
+use crossbeam_channel::{select, Receiver, Sender};
+use tokio::sync::watch;
+use tonic::{Request, Response, Status, Streaming};
+use tonic::transport::Server;
+
+use super::{FeederStats, MessageSource};
+use crate::config::GrpcCfg;
+use crate::entities::Event;
+use crate::entities::proto::{self, PublishSummary};
+use crate::entities::proto::event_feed_server::{EventFeed, EventFeedServer};
+
+/// Serves the `EventFeed` service on `listen_addr`, writing the events of every stream into the processors' channel
+///
+/// The service runs on its own tokio runtime. The feeder stops (ending any open stream) once a shutdown is requested
+pub struct GrpcFeeder {
+    listen_addr: SocketAddr,
+    stats: FeederStats
+}
+
+impl GrpcFeeder {
+    pub fn from_cfg(grpc_cfg: &GrpcCfg) -> Self {
+        Self::new(grpc_cfg.listen_addr())
+    }
+
+    pub fn new(listen_addr: SocketAddr) -> Self {
+        Self { listen_addr, stats: FeederStats::default() }
+    }
+}
+
+impl MessageSource for GrpcFeeder {
+    /// Serves the `EventFeed` service until `shutdown` receives a message or is disconnected. Open streams are ended
+    /// (and answered) then, without waiting for the clients to close them
+    ///
+    /// # Errors
+    ///
+    /// When the runtime cannot be started, or the server fails (e.g. because `listen_addr` is taken)
+    fn feed(&mut self, sendr: &Sender<Event>, shutdown: &Receiver<()>) -> Result<()> {
+        let runtime = tokio::runtime::Builder::new_multi_thread().thread_name("grpc-feeder").enable_all().build()?;
+        let (stop, stopped) = watch::channel(false);
+
+        // The shutdown channel can only be waited on by blocking, so a thread relays it to the runtime. It also
+        // exits once `done` is dropped, i.e. when the server stopped by itself
+        let (done, finished) = crossbeam_channel::bounded::<()>(0);
+        let shutdown = Receiver::clone(shutdown);
+        let relay = thread::spawn(move || {
+            select! {
+                recv(shutdown) -> _ => {
+                    info!("Shutdown requested, stopping gRPC feeder");
+                    let _ = stop.send(true);
+                },
+                recv(finished) -> _ => {}
+            }
+        });
+
+        let counts = Arc::new(StreamCounts::default());
+        let publisher = Publisher {
+            sendr: Sender::clone(sendr),
+            stopped: stopped.clone(),
+            counts: Arc::clone(&counts)
+        };
+        let mut server_stopped = stopped;
+        info!("Listening for gRPC events on {}", self.listen_addr);
+        let served = runtime.block_on(
+            Server::builder()
+                .add_service(EventFeedServer::new(publisher))
+                .serve_with_shutdown(self.listen_addr, async move {
+                    let _ = server_stopped.wait_for(|stopped| *stopped).await;
+                })
+        );
+
+        drop(done);
+        let _ = relay.join();
+        self.stats.grpc_events += counts.accepted.load(Ordering::Relaxed);
+        self.stats.rejected_grpc_events += counts.rejected.load(Ordering::Relaxed);
+
+        Ok(served?)
+    }
+
+    fn stats(&self) -> &FeederStats {
+        &self.stats
+    }
+}
+
+/// The events of all streams, counted while the service runs
+#[derive(Default)]
+struct StreamCounts {
+    accepted: AtomicU64,
+    rejected: AtomicU64
+}
+
+/// The `EventFeed` service: every event of a stream is sent to the processors before the next one is read
+struct Publisher {
+    sendr: Sender<Event>,
+    stopped: watch::Receiver<bool>,
+    counts: Arc<StreamCounts>
+}
+
+#[tonic::async_trait]
+impl EventFeed for Publisher {
+    async fn publish(&self, request: Request<Streaming<proto::Event>>) -> Result<Response<PublishSummary>, Status> {
+        let mut stream = request.into_inner();
+        let mut stopped = self.stopped.clone();
+        let mut summary = PublishSummary::default();
+
+        while !*stopped.borrow() {
+            let proto = tokio::select! {
+                message = stream.message() => match message? {
+                    Some(proto) => proto,
+                    None => break
+                },
+                _ = stopped.changed() => break
+            };
+
+            let event = match Event::from_proto(proto) {
+                Ok(e) => e,
+                Err(e) => {
+                    error!("Skipping gRPC event: {}", e);
+                    summary.rejected += 1;
+                    self.counts.rejected.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+            };
+            info!("New gRPC event {}", event.url());
+
+            let sendr = Sender::clone(&self.sendr);
+            match tokio::task::spawn_blocking(move || sendr.send(event).is_ok()).await {
+                Ok(true) => {
+                    summary.accepted += 1;
+                    self.counts.accepted.fetch_add(1, Ordering::Relaxed);
+                },
+                _ => return Err(Status::unavailable("The processors are no longer running"))
+            }
+        }
+
+        Ok(Response::new(summary))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::proto::event_feed_client::EventFeedClient;
+    use chrono::{Local, TimeZone};
+    use std::net::TcpListener;
+    use std::time::Duration;
+    use tonic::transport::Endpoint;
+
+    fn proto_event(url: &str) -> proto::Event {
+        let t = Local.timestamp_millis_opt(1_600_000_000_000).unwrap();
+        Event::new(url, 3, "pastebin", "foo", "foo.txt", "bar", t, t).to_proto()
+    }
+
+    /// A `GrpcFeeder` feeding `sendr` from its own thread, along with the sender of its shutdown channel. The thread
+    /// returns the feeder once it stops (and panics if it failed)
+    fn start_feeder(sendr: &Sender<Event>) -> (SocketAddr, Sender<()>, thread::JoinHandle<GrpcFeeder>) {
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let (shutdown_sendr, shutdown) = crossbeam_channel::bounded(1);
+        let sendr = Sender::clone(sendr);
+
+        let handle = thread::spawn(move || {
+            let mut feeder = GrpcFeeder::new(addr);
+            feeder.feed(&sendr, &shutdown).unwrap();
+            feeder
+        });
+        (addr, shutdown_sendr, handle)
+    }
+
+    /// Connects to the feeder at `addr` (waiting for it to start listening) and streams `events` to it
+    fn publish(addr: SocketAddr, events: Vec<proto::Event>) -> PublishSummary {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async move {
+            let endpoint = Endpoint::from_shared(format!("http://{}", addr)).unwrap();
+            let mut client = loop {
+                match endpoint.connect().await {
+                    Ok(channel) => break EventFeedClient::new(channel),
+                    Err(_) => tokio::time::sleep(Duration::from_millis(10)).await
+                }
+            };
+            client.publish(tonic::codegen::tokio_stream::iter(events)).await.unwrap().into_inner()
+        })
+    }
+
+    #[test]
+    fn streamed_events_are_sent_to_the_processors() {
+        let (sendr, recvr) = crossbeam_channel::unbounded();
+        let (addr, shutdown, feeder) = start_feeder(&sendr);
+
+        let mut invalid = proto_event("https://pastebin.com/2");
+        invalid.source.clear();
+        let events = vec![proto_event("https://pastebin.com/1"), invalid, proto_event("https://pastebin.com/3")];
+        let summary = publish(addr, events);
+
+        assert_eq!((summary.accepted, summary.rejected), (2, 1));
+        let urls: Vec<String> = recvr.try_iter().map(|e| e.url().to_owned()).collect();
+        assert_eq!(urls, vec!["https://pastebin.com/1", "https://pastebin.com/3"]);
+
+        shutdown.send(()).unwrap();
+        let feeder = feeder.join().unwrap();
+        assert_eq!(feeder.stats().grpc_events(), 2);
+        assert_eq!(feeder.stats().rejected_grpc_events(), 1);
+    }
+
+    #[test]
+    fn feeders_stop_when_the_shutdown_channel_disconnects() {
+        let (sendr, _recvr) = crossbeam_channel::unbounded();
+        let (addr, shutdown, feeder) = start_feeder(&sendr);
+        assert_eq!(publish(addr, Vec::new()), PublishSummary::default());
+
+        drop(shutdown);
+        assert!(feeder.join().is_ok());
+    }
+
+    #[test]
+    fn feeders_fail_when_the_address_is_taken() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (sendr, _recvr) = crossbeam_channel::unbounded();
+
+        let result = GrpcFeeder::new(listener.local_addr().unwrap()).feed(&sendr, &crossbeam_channel::never());
+        assert!(result.is_err());
+    }
+}
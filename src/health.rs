@@ -0,0 +1,104 @@
+//! Liveness of the worker threads, served over HTTP (`GET /health`, see `metrics::start_server`) for
+//! orchestration systems (e.g. Kubernetes or Consul probes)
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde_json::{json, Value};
+
+/// How many feeder and processor threads are running
+#[derive(Debug, Clone, Default)]
+pub struct Liveness {
+    feeders: Arc<AtomicUsize>,
+    processors: Arc<AtomicUsize>
+}
+
+impl Liveness {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of running feeders, kept up to date by the `AliveGuard`s of the feeder threads
+    pub fn feeders(&self) -> &Arc<AtomicUsize> {
+        &self.feeders
+    }
+
+    /// The number of running processors, kept up to date by the `AliveGuard`s of the processor threads
+    pub fn processors(&self) -> &Arc<AtomicUsize> {
+        &self.processors
+    }
+
+    /// The status code and body of the `/health` response
+    pub fn status(&self) -> (u16, Value) {
+        health_response(self.feeders.load(Ordering::SeqCst), self.processors.load(Ordering::SeqCst))
+    }
+}
+
+/// Counts a thread as running (in one of the counters of `Liveness`) from its creation until it is dropped, which
+/// happens when the thread that owns it exits, however it exits (panics included)
+#[derive(Debug)]
+pub struct AliveGuard(Arc<AtomicUsize>);
+
+impl AliveGuard {
+    /// Create it before spawning the thread and move it in, so that the thread counts as running from the start
+    pub fn new(counter: &Arc<AtomicUsize>) -> Self {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Self(Arc::clone(counter))
+    }
+}
+
+impl Drop for AliveGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// `200 {"status":"ok"}` while at least one feeder and one processor are running, and `503` with the reason
+/// otherwise (e.g. `{"status":"degraded","reason":"no feeders"}`)
+pub fn health_response(num_feeders: usize, num_processors: usize) -> (u16, Value) {
+    match (num_feeders, num_processors) {
+        (0, _) => degraded("no feeders"),
+        (_, 0) => degraded("no processors"),
+        _ => (200, json!({ "status": "ok" }))
+    }
+}
+
+fn degraded(reason: &str) -> (u16, Value) {
+    (503, json!({ "status": "degraded", "reason": reason }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn running_workers_are_healthy() {
+        assert_eq!(health_response(2, 4), (200, json!({ "status": "ok" })));
+    }
+
+    #[test]
+    fn missing_workers_are_reported() {
+        assert_eq!(health_response(0, 4), (503, json!({ "status": "degraded", "reason": "no feeders" })));
+        assert_eq!(health_response(1, 0), (503, json!({ "status": "degraded", "reason": "no processors" })));
+        assert_eq!(health_response(0, 0).1["reason"], "no feeders");
+    }
+
+    #[test]
+    fn threads_are_counted_until_they_exit() {
+        let liveness = Liveness::new();
+        let processor = AliveGuard::new(liveness.processors());
+        assert_eq!(liveness.status().0, 503);
+
+        let feeder = AliveGuard::new(liveness.feeders());
+        assert_eq!(liveness.status().0, 200);
+
+        thread::spawn(move || {
+            let _feeder = feeder;
+            panic!("feeder crashed");
+        }).join().unwrap_err();
+        assert_eq!(liveness.status(), (503, json!({ "status": "degraded", "reason": "no feeders" })));
+
+        drop(processor);
+        assert_eq!(liveness.processors().load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,125 @@
+//! Extraction of structured indicators (IP addresses, domain names and URLs) from event content. Yara rules tell
+//! whether an event is interesting; the indicators make its events searchable by the hosts they mention
+//! (see [DbLoader::get_events_by_indicator](crate::database::DbLoader::get_events_by_indicator)).
+//! Enabled with `processing.extract_indicators`
+use std::collections::BTreeSet;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+lazy_static! {
+    static ref URL_RE: Regex = Regex::new(r#"(?i)\b(?:https?|ftp)://[^\s<>"'`]+"#).unwrap();
+    static ref IPV4_RE: Regex = Regex::new(r"\b\d{1,3}(?:\.\d{1,3}){3}\b").unwrap();
+    // Candidates only, which are then validated by `Ipv6Addr`'s parser (e.g. to tell addresses from timestamps)
+    static ref IPV6_RE: Regex = Regex::new(r"(?i)[0-9a-f]{0,4}(?::[0-9a-f]{0,4}){2,7}(?:\d{1,3}(?:\.\d{1,3}){3})?")
+        .unwrap();
+    static ref DOMAIN_RE: Regex = Regex::new(r"(?i)\b(?:[a-z0-9](?:[a-z0-9-]{0,61}[a-z0-9])?\.)+[a-z]{2,63}\b")
+        .unwrap();
+}
+
+/// Characters that end sentences or enclose URLs in text, rather than being part of the URL
+const URL_TRAILING_PUNCTUATION: &[char] = &['.', ',', ';', ':', '!', '?', ')', ']', '}'];
+
+/// Common file extensions that are also valid top-level domains syntactically (e.g. `config.yaml`), and are far
+/// more likely to be file names
+const FILE_EXTENSIONS: [&str; 24] = [
+    "txt", "log", "json", "yaml", "yml", "xml", "csv", "ini", "cfg", "conf", "exe", "dll", "bat", "ps1", "py", "js",
+    "php", "html", "htm", "jpg", "png", "gif", "zip", "gz"
+];
+
+/// The indicators found in an event's content. Each list is sorted and holds no duplicates
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndicatorSet {
+    pub ips: Vec<String>,
+    pub domains: Vec<String>,
+    pub urls: Vec<String>
+}
+
+/// Extracts the IPv4 and IPv6 addresses, domain names and URLs mentioned in `content`. Domains are lowercased, and
+/// include the hosts of the extracted URLs
+pub fn extract_indicators(content: &str) -> IndicatorSet {
+    let urls: BTreeSet<String> = URL_RE.find_iter(content)
+        .map(|m| m.as_str().trim_end_matches(URL_TRAILING_PUNCTUATION).to_owned())
+        .collect();
+
+    let mut ips: BTreeSet<String> = IPV4_RE.find_iter(content)
+        .filter_map(|m| m.as_str().parse::<Ipv4Addr>().ok())
+        .map(|ip| ip.to_string())
+        .collect();
+    ips.extend(
+        IPV6_RE.find_iter(content)
+            .filter(|m| m.as_str().chars().any(|c| c.is_ascii_hexdigit()))
+            .filter_map(|m| m.as_str().parse::<Ipv6Addr>().ok())
+            .map(|ip| ip.to_string())
+    );
+
+    let domains: BTreeSet<String> = DOMAIN_RE.find_iter(content)
+        .map(|m| m.as_str().to_lowercase())
+        .filter(|domain| domain.rsplit('.').next().is_some_and(|tld| !FILE_EXTENSIONS.contains(&tld)))
+        .collect();
+
+    IndicatorSet {
+        ips: ips.into_iter().collect(),
+        domains: domains.into_iter().collect(),
+        urls: urls.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls_are_extracted_without_trailing_punctuation() {
+        let indicators = extract_indicators(
+            "Dump at https://pastebin.com/raw/abc123. Mirror (http://mirror.example.org/x?y=1), ftp://files.example.net"
+        );
+
+        assert_eq!(
+            indicators.urls,
+            vec!["ftp://files.example.net", "http://mirror.example.org/x?y=1", "https://pastebin.com/raw/abc123"]
+        );
+    }
+
+    #[test]
+    fn ipv4_addresses_are_validated() {
+        let indicators = extract_indicators("db at 10.0.0.12, gateway 192.168.1.1; not 999.1.1.1 nor 1.2.3");
+
+        assert_eq!(indicators.ips, vec!["10.0.0.12", "192.168.1.1"]);
+    }
+
+    #[test]
+    fn ipv6_addresses_are_extracted_and_timestamps_are_not() {
+        let indicators = extract_indicators(
+            "at 12:30:45 from 2001:db8::ff00:42:8329 and fe80::1, again 2001:DB8::FF00:42:8329"
+        );
+
+        assert_eq!(indicators.ips, vec!["2001:db8::ff00:42:8329", "fe80::1"]);
+    }
+
+    #[test]
+    fn domains_include_url_hosts_but_not_file_names() {
+        let indicators = extract_indicators(
+            "login to Mail.Example.com with creds.txt, see https://pastebin.com/abc and config.yaml, ping 10.0.0.1"
+        );
+
+        assert_eq!(indicators.domains, vec!["mail.example.com", "pastebin.com"]);
+    }
+
+    #[test]
+    fn duplicates_are_removed() {
+        let indicators = extract_indicators("8.8.8.8 example.com 8.8.8.8 EXAMPLE.com");
+
+        assert_eq!(indicators.ips, vec!["8.8.8.8"]);
+        assert_eq!(indicators.domains, vec!["example.com"]);
+    }
+
+    #[test]
+    fn content_without_indicators_yields_an_empty_set() {
+        let indicators = extract_indicators("user: admin\npassword: hunter2");
+
+        assert_eq!(indicators, IndicatorSet::default());
+    }
+}
@@ -1,5 +1,9 @@
-use log::{LevelFilter, SetLoggerError};
-use log4rs::append::console::ConsoleAppender;
+use std::io::{self, Write};
+use std::sync::{Mutex, PoisonError};
+
+use chrono::{Local, SecondsFormat};
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use log4rs::append::console::{ConsoleAppender, Target};
 use log4rs::append::rolling_file::{RollingFileAppender, LogFile, policy::Policy};
 use log4rs::config::{Appender, Config, Root};
 use log4rs::Handle;
@@ -8,9 +12,19 @@ use anyhow::Result;
 const FILE_ROLL_BYTE_THRESHOLD: u64 = 2_500_000;
 const LOGFILE_PATH: &str = "logs/processor.log";
 
+/// How each log line is written
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// A JSON object per line, with the `level`, `message`, `target` and `timestamp` of the record
+    Json
+}
+
 #[allow(dead_code)]
 pub struct Logger {
-    handle: Handle
+    /// The handle of the log4rs logger. `None` in `LogFormat::Json`
+    handle: Option<Handle>
 }
 
 #[derive(Debug)]
@@ -25,9 +39,54 @@ impl Policy for SizeRotatePolicy {
     }
 }
 
-pub fn init() -> Result<Logger, SetLoggerError> {
+/// Writes each record to `out` as a JSON object, on a line of its own
+struct JsonLogger<W: Write + Send> {
+    level: LevelFilter,
+    out: Mutex<W>
+}
+
+impl<W: Write + Send> JsonLogger<W> {
+    fn new(level: LevelFilter, out: W) -> Self {
+        Self { level, out: Mutex::new(out) }
+    }
+}
+
+impl<W: Write + Send> Log for JsonLogger<W> {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = serde_json::json!({
+            "level": record.level().as_str(),
+            "message": record.args().to_string(),
+            "target": record.target(),
+            "timestamp": Local::now().to_rfc3339_opts(SecondsFormat::Millis, false)
+        });
+        let mut out = self.out.lock().unwrap_or_else(PoisonError::into_inner);
+        // There is nowhere left to report a failure to log to
+        let _ = writeln!(out, "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = self.out.lock().unwrap_or_else(PoisonError::into_inner).flush();
+    }
+}
+
+/// Logs to stderr the messages of `level` and of the levels more severe than it, in `format`
+pub fn init(level: LevelFilter, format: LogFormat) -> Result<Logger, SetLoggerError> {
+    if format == LogFormat::Json {
+        log::set_boxed_logger(Box::new(JsonLogger::new(level, io::stderr())))?;
+        log::set_max_level(level);
+
+        return Ok(Logger { handle: None });
+    }
 
-    let console = ConsoleAppender::builder().build();
+    let console = ConsoleAppender::builder().target(Target::Stderr).build();
     let pol = SizeRotatePolicy;
 
     let rollfile = RollingFileAppender::builder().build(LOGFILE_PATH, Box::new(pol)).unwrap();
@@ -40,10 +99,58 @@ pub fn init() -> Result<Logger, SetLoggerError> {
             .appender("rollfile")
             .additive(false)
             .build("app::rollfile", LevelFilter::Debug))
-        .build(Root::builder().appender("console").build(LevelFilter::Info))
+        .build(Root::builder().appender("console").build(level))
         .unwrap();
 
     let handle = log4rs::init_config(config).unwrap();
 
-    Ok(Logger { handle })
+    Ok(Logger { handle: Some(handle) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn log_lines(logger: &JsonLogger<Vec<u8>>) -> Vec<Value> {
+        let out = logger.out.lock().unwrap();
+        String::from_utf8_lossy(&out).lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+    }
+
+    fn log(logger: &JsonLogger<Vec<u8>>, level: log::Level, message: &str) {
+        logger.log(&Record::builder()
+            .level(level)
+            .target("processor_rs::feeder")
+            .args(format_args!("{}", message))
+            .build());
+    }
+
+    #[test]
+    fn records_are_written_as_json_lines() {
+        let logger = JsonLogger::new(LevelFilter::Info, Vec::new());
+        log(&logger, log::Level::Info, "Popped an event");
+        log(&logger, log::Level::Error, "Could not parse \"event\"\non two lines");
+
+        let lines = log_lines(&logger);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["message"], "Popped an event");
+        assert_eq!(lines[0]["target"], "processor_rs::feeder");
+        assert_eq!(lines[1]["level"], "ERROR");
+        assert_eq!(lines[1]["message"], "Could not parse \"event\"\non two lines");
+
+        let timestamp = lines[0]["timestamp"].as_str().unwrap();
+        assert!(chrono::DateTime::parse_from_rfc3339(timestamp).is_ok(), "{}", timestamp);
+    }
+
+    #[test]
+    fn records_less_severe_than_the_level_are_skipped() {
+        let logger = JsonLogger::new(LevelFilter::Warn, Vec::new());
+        log(&logger, log::Level::Info, "skipped");
+        log(&logger, log::Level::Debug, "skipped");
+        log(&logger, log::Level::Warn, "kept");
+
+        let messages: Vec<Value> = log_lines(&logger).iter().map(|line| line["message"].clone()).collect();
+        assert_eq!(messages, ["kept"]);
+    }
 }
@@ -0,0 +1,224 @@
+//! Operational metrics of the running process, served over HTTP (`GET /metrics`) in the Prometheus text format,
+//! along with the liveness of the workers (`GET /health`, see `health`)
+//!
+//! The metrics live in the default Prometheus registry, along with the table gauges of `database::metrics`.
+//! The processor threads update them as they go (see `record_event_processed` etc.)
+use std::net::ToSocketAddrs;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::{info, error};
+use lazy_static::lazy_static;
+use prometheus::{
+    exponential_buckets, register_histogram, register_int_counter, register_int_gauge_vec, Encoder, Histogram,
+    IntCounter, IntGaugeVec, TextEncoder
+};
+use tiny_http::{Header, Method, Request, Response, Server};
+use anyhow::{anyhow, Result};
+
+use crate::health::Liveness;
+
+lazy_static! {
+    static ref EVENTS_PROCESSED: IntCounter = register_int_counter!(
+        "events_processed_total", "Events scanned by the processors"
+    ).unwrap();
+    static ref EVENTS_MATCHED: IntCounter = register_int_counter!(
+        "events_matched_total", "Events that matched at least one rule"
+    ).unwrap();
+    static ref EVENTS_FAILED: IntCounter = register_int_counter!(
+        "events_failed_total", "Events that could not be scanned, or sent to the loaders"
+    ).unwrap();
+    // Most events take a few hundred microseconds to scan: the buckets go from 100us up to ~26s
+    static ref PROCESSING_DURATION: Histogram = register_histogram!(
+        "processing_duration_seconds", "Time spent processing each event", exponential_buckets(0.0001, 4.0, 10).unwrap()
+    ).unwrap();
+    static ref CHANNEL_QUEUE_DEPTH: IntGaugeVec = register_int_gauge_vec!(
+        "channel_queue_depth", "Events waiting in each channel", &["channel"]
+    ).unwrap();
+}
+
+/// The channels connecting the worker threads
+#[derive(Debug, Clone, Copy)]
+pub enum Channel {
+    FeederProcessor,
+    ProcessorLoader
+}
+
+impl Channel {
+    fn label(self) -> &'static str {
+        match self {
+            Channel::FeederProcessor => "feeder_processor",
+            Channel::ProcessorLoader => "processor_loader"
+        }
+    }
+}
+
+/// Counts an event a processor is done with, and the time it took
+pub fn record_event_processed(duration: Duration) {
+    EVENTS_PROCESSED.inc();
+    PROCESSING_DURATION.observe(duration.as_secs_f64());
+}
+
+pub fn record_event_matched() {
+    EVENTS_MATCHED.inc();
+}
+
+pub fn record_event_failed() {
+    EVENTS_FAILED.inc();
+}
+
+/// Sets the number of events waiting in `channel`
+pub fn set_queue_depth(channel: Channel, depth: usize) {
+    CHANNEL_QUEUE_DEPTH.with_label_values(&[channel.label()]).set(depth as i64);
+}
+
+/// Serves the metrics and the health of the workers counted by `liveness` on `port` (on all interfaces) from a
+/// thread of its own, for as long as the process runs
+///
+/// # Errors
+///
+/// When the port cannot be bound, e.g. because it is already in use
+pub fn start_server(port: u16, liveness: Liveness) -> Result<JoinHandle<()>> {
+    register();
+    let handle = serve(("0.0.0.0", port), liveness)?;
+    info!("Serving metrics and health checks on port {}", port);
+
+    Ok(handle)
+}
+
+/// Registers the metrics, which are otherwise only registered once first updated, so that every one of them is
+/// exported from the start
+fn register() {
+    lazy_static::initialize(&EVENTS_PROCESSED);
+    lazy_static::initialize(&EVENTS_MATCHED);
+    lazy_static::initialize(&EVENTS_FAILED);
+    lazy_static::initialize(&PROCESSING_DURATION);
+    set_queue_depth(Channel::FeederProcessor, 0);
+    set_queue_depth(Channel::ProcessorLoader, 0);
+}
+
+fn serve<A: ToSocketAddrs>(addr: A, liveness: Liveness) -> Result<JoinHandle<()>> {
+    let server = Server::http(addr).map_err(|e| anyhow!(e))?;
+
+    Ok(thread::spawn(move || {
+        for request in server.incoming_requests() {
+            respond(request, &liveness);
+        }
+    }))
+}
+
+fn respond(request: Request, liveness: &Liveness) {
+    let response = match (request.method(), request.url()) {
+        (Method::Get, "/metrics") => {
+            let encoder = TextEncoder::new();
+            let mut body = Vec::new();
+            if let Err(e) = encoder.encode(&prometheus::gather(), &mut body) {
+                error!("Could not encode the metrics: {}", e);
+            }
+            let content_type = Header::from_bytes("Content-Type", encoder.format_type()).unwrap();
+
+            Response::from_data(body).with_header(content_type)
+        },
+        (Method::Get, "/health") => {
+            let (status, body) = liveness.status();
+            let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+
+            Response::from_string(body.to_string()).with_status_code(status).with_header(content_type)
+        },
+        _ => Response::from_string("Not found").with_status_code(404)
+    };
+
+    if let Err(e) = request.respond(response) {
+        error!("Could not send a response: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use crate::health::AliveGuard;
+
+    /// Serves the metrics on a free port
+    fn test_server(liveness: Liveness) -> SocketAddr {
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        serve(addr, liveness).unwrap();
+        addr
+    }
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn events_are_counted() {
+        // The metrics are global, and other tests process events as well
+        let (processed, matched, failed) = (EVENTS_PROCESSED.get(), EVENTS_MATCHED.get(), EVENTS_FAILED.get());
+        let observed = PROCESSING_DURATION.get_sample_count();
+
+        record_event_processed(Duration::from_millis(2));
+        record_event_processed(Duration::from_millis(3));
+        record_event_matched();
+        record_event_failed();
+
+        assert!(EVENTS_PROCESSED.get() >= processed + 2);
+        assert!(EVENTS_MATCHED.get() > matched);
+        assert!(EVENTS_FAILED.get() > failed);
+        assert!(PROCESSING_DURATION.get_sample_count() >= observed + 2);
+    }
+
+    #[test]
+    fn queue_depths_are_exported_per_channel() {
+        register();
+
+        let families = prometheus::gather();
+        let depths = families.iter().find(|f| f.get_name() == "channel_queue_depth").unwrap();
+        let channels: Vec<&str> = depths.get_metric().iter().map(|m| m.get_label()[0].get_value()).collect();
+        assert_eq!(channels, ["feeder_processor", "processor_loader"]);
+    }
+
+    #[test]
+    fn metrics_are_served_in_the_text_format() {
+        register();
+        let response = get(test_server(Liveness::new()), "/metrics");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4"), "{}", response);
+        assert!(response.contains("# TYPE events_processed_total counter"), "{}", response);
+        assert!(response.contains("processing_duration_seconds_bucket"), "{}", response);
+        assert!(response.contains("channel_queue_depth{channel=\"feeder_processor\"}"), "{}", response);
+    }
+
+    #[test]
+    fn other_paths_are_not_found() {
+        let response = get(test_server(Liveness::new()), "/");
+
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"), "{}", response);
+    }
+
+    #[test]
+    fn health_is_served_as_json() {
+        let liveness = Liveness::new();
+        let addr = test_server(liveness.clone());
+
+        let body = |response: &str| -> serde_json::Value {
+            serde_json::from_str(response.split("\r\n\r\n").nth(1).unwrap()).unwrap()
+        };
+
+        let response = get(addr, "/health");
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"), "{}", response);
+        assert!(response.contains("Content-Type: application/json"), "{}", response);
+        assert_eq!(body(&response), serde_json::json!({ "status": "degraded", "reason": "no feeders" }));
+
+        let _feeder = AliveGuard::new(liveness.feeders());
+        let _processor = AliveGuard::new(liveness.processors());
+        let response = get(addr, "/health");
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert_eq!(body(&response), serde_json::json!({ "status": "ok" }));
+    }
+}
@@ -8,16 +8,73 @@
 //! another crossbeam channel, whose read-end is provided to the [DbLoader](crate::database::DbLoader) threads.
 #![allow(dead_code)]
 
-use std::{str, thread, sync::Arc, time, fmt};
+use std::{str, thread, sync::{Arc, Barrier, atomic::AtomicUsize}, time, fmt, fs, cmp::Ordering};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::Add;
+use std::ffi::OsString;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use log::{info, error};
 
-use yara::{Compiler, Rules, Rule, YaraError};
-use crossbeam_channel::{Sender, Receiver};
-use anyhow::Result;
+use yara::{CallbackMsg, CallbackReturn, Compiler, Rules, Rule, YaraError, YaraErrorKind};
+use lazy_static::lazy_static;
+use crossbeam_channel::{select, Sender, Receiver};
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use rand::Rng;
+use regex::Regex;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-use crate::utils::rec_get_files_by_ext;
-use crate::errors::ConfigurationError;
-use crate::entities::{Event, FlatMatch, ProcessedEvent};
+use crate::utils::{format_size, pluralize, rec_get_files_by_globs, GlobFilter};
+use crate::config::{MonitoringCfg, ProcessingCfg};
+use crate::errors::{ConfigurationError, ProcessingError};
+use crate::entities::{AsciiMatch, Event, FlatMatch, ProcessedEvent};
+use crate::indicators::extract_indicators;
+use crate::health::AliveGuard;
+use crate::metrics;
+
+/// Control messages for the processor threads, sent through their own crossbeam channel (separate from events).
+/// Each message is handled by exactly one thread
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// Process the events that are already waiting in the feed channel, then exit without waiting for new ones
+    DrainAndStop,
+    /// Process the events that are already waiting in the feed channel, then recompile the Yara rules from their
+    /// directories (e.g. after rules were added or edited). The thread then waits on the barrier, so that it can't
+    /// pick up another thread's command (see `send_recompile`). If the rules don't compile, the old ones are kept
+    RecompileRules(Arc<Barrier>)
+}
+
+/// Sends one `Command::DrainAndStop` per processor thread, so that all `num_threads` threads exit once the
+/// feed channel has been drained. Unlike dropping the feed channel's write-end, this works while feeders
+/// are still holding on to it
+pub fn send_drain_and_stop(sender: &Sender<Command>, num_threads: usize) {
+    for _ in 0..num_threads {
+        if let Err(e) = sender.send(Command::DrainAndStop) {
+            error!("Could not send drain command to processor: {}", e);
+        }
+    }
+}
+
+/// Makes all `num_threads` processor threads recompile their Yara rules, without restarting them (see
+/// `Command::RecompileRules`). Events sent after this returns are scanned with the new rules
+///
+/// Blocks until every thread has recompiled, so it must only be called while all of them are running (e.g. not
+/// after `send_drain_and_stop`)
+pub fn send_recompile(sender: &Sender<Command>, num_threads: usize) {
+    let barrier = Arc::new(Barrier::new(num_threads + 1));
+    for _ in 0..num_threads {
+        if let Err(e) = sender.send(Command::RecompileRules(Arc::clone(&barrier))) {
+            error!("Could not send recompile command to processor: {}", e);
+            return;
+        }
+    }
+
+    barrier.wait();
+}
 
 /// Spawns `num_processors` threads each of which continuously pops from the read-end of a crossbeam channel,
 /// processes the events, enriches matching ones with additional information (e.g. the matched string) and pushes them
@@ -31,9 +88,20 @@ use crate::entities::{Event, FlatMatch, ProcessedEvent};
 /// use entities::Event;
 /// 
 /// let (feed_sendr, feed_recvr) = crossbeam_channel::unbounded();
+/// let (cmd_sendr, cmd_recvr) = crossbeam_channel::unbounded();
 /// let (load_sendr, load_recvr) = crossbeam_channel::unbounded();
 ///
-/// let handles: Vec<JoinHandle<()>> = start_processors(&feed_recevr, &load_sendr, "path/to/yara/dir", 3);
+/// let handles: Vec<JoinHandle<()>> = start_processors(
+///     &feed_recevr,
+///     &cmd_recvr,
+///     &load_sendr,
+///     &["path/to/yara/dir".to_owned()],
+///     &GlobFilter::default(),
+///     &ProcessingCfg::default(),
+///     &MonitoringCfg::default(),
+///     3,
+///     Liveness::new().processors()
+/// );
 ///
 /// assert_eq!(handles.len(), 3);
 /// let e = Event::new(
@@ -64,79 +132,425 @@ use crate::entities::{Event, FlatMatch, ProcessedEvent};
 /// 
 /// * `feed_recvr` - The read-end of a crossbeam channel. While the write-end is not dropped, all threads hang
 ///                    until an event is available (only one thread processes each event)
+/// * `cmd_recvr` - The read-end of a crossbeam channel carrying `Command`s (e.g. `Command::DrainAndStop` or
+///                 `Command::RecompileRules`)
 /// * `load_sendr` - The write-end of a crossbeam channel. After processing events, it turns them into `ProcessedEvent` objects
 ///                    (the initial event (`Event`) + information on the match (`FlatMatch`)) and pushes them into the channel
-/// * `yara_dir` - The fully qualified path to the root of a yara rule directory. This directory will be recursively walked and
-///                  all Yara rule files (*.yar) will be loaded to the processor
+/// * `yara_dirs` - The fully qualified paths to the roots of yara rule directories. These directories will be recursively
+///                  walked and all Yara rule files (*.yar) will be loaded to the processor
+/// * `rule_filter` - Selects which of the rule files under `yara_dirs` are loaded (see `Config::yara_rule_filter`)
+/// * `processing_cfg` - Tunes how events are processed (e.g. whether their content is normalized before scanning)
+/// * `monitoring_cfg` - Tunes the returned stats (e.g. how many processing times are kept for their percentiles)
 /// * `num_processors` - The number of threads to spawn. Each will hang on `feed_recvr` waiting for new messages (events)
+/// * `alive` - Counts the processor threads still running (see `health::Liveness::processors`)
 /// 
 /// # Return
 /// A vector of `JoinHandle` that can be used to join the threads after the feed crossbeam channel's write-end
-/// has been dropped. The returned handles carry a [Stats](crate::processing::Stats) instance, containing statistics about
+/// has been dropped, or after `send_drain_and_stop` has been called. The returned handles carry a [Stats](crate::processing::Stats) instance, containing statistics about
 /// the number of processed events, matches, overall processing time etc.
+#[allow(clippy::too_many_arguments)]
 pub fn start_processors(
     feed_recvr: &Receiver<Event>,
+    cmd_recvr: &Receiver<Command>,
     load_sendr: &Sender<ProcessedEvent>,
-    yara_dir: &str,
-    num_processors: usize
+    yara_dirs: &[String],
+    rule_filter: &GlobFilter,
+    processing_cfg: &ProcessingCfg,
+    monitoring_cfg: &MonitoringCfg,
+    num_processors: usize,
+    alive: &Arc<AtomicUsize>
 ) -> Vec<thread::JoinHandle<Result<Stats>>> {
-    let yara_dir_arc = Arc::new(yara_dir.to_owned());
+    let yara_dirs_arc = Arc::new(yara_dirs.to_vec());
+    let rule_filter_arc = Arc::new(rule_filter.clone());
+    let processing_cfg_arc = Arc::new(processing_cfg.clone());
     let mut p_handles: Vec<thread::JoinHandle<Result<Stats>>> = Vec::new();
 
-    info!("Spawning {} processors", num_processors);
+    info!("Spawning {}", pluralize(num_processors, "processor"));
     for _ in 0..num_processors {
-        p_handles.push(process_forever(feed_recvr, load_sendr, &yara_dir_arc));
+        p_handles.push(process_forever(
+            feed_recvr,
+            cmd_recvr,
+            load_sendr,
+            &yara_dirs_arc,
+            &rule_filter_arc,
+            &processing_cfg_arc,
+            monitoring_cfg.reservoir_sample_size(),
+            alive
+        ));
     }
 
     p_handles
 }
 
-/// Given the read-end of a crossbeam channel and a Yara rule directory,
+/// Given the read-end of a crossbeam channel and Yara rule directories,
 /// spawns a new thread which continuously reads events from the channel and passes them
 /// through the processor.
 /// Events that match one or more rules are then persisted
 /// to the DB (see database::loader::DbLoader)
 /// 
 /// Returns the join handle for the newly spawned thread
-#[allow(clippy::rc_buffer)]
+#[allow(clippy::rc_buffer, clippy::too_many_arguments)]
 fn process_forever(
     feed_recvr: &Receiver<Event>,
+    cmd_recvr: &Receiver<Command>,
     load_sendr: &Sender<ProcessedEvent>,
-    yara_dir_arc: &Arc<String>
+    yara_dirs_arc: &Arc<Vec<String>>,
+    rule_filter_arc: &Arc<GlobFilter>,
+    processing_cfg_arc: &Arc<ProcessingCfg>,
+    reservoir_sample_size: Option<usize>,
+    alive: &Arc<AtomicUsize>
 ) -> thread::JoinHandle<Result<Stats>> {
     let rx = Receiver::clone(feed_recvr);
+    let mut cmd_rx = Receiver::clone(cmd_recvr);
     let sx = Sender::clone(load_sendr);
-    let yara_dir = Arc::clone(yara_dir_arc);
+    let yara_dirs = Arc::clone(yara_dirs_arc);
+    let rule_filter = Arc::clone(rule_filter_arc);
+    let processing_cfg = Arc::clone(processing_cfg_arc);
+    let alive = AliveGuard::new(alive);
 
     thread::spawn(move || {
-        let mut stats = Stats::new();
+        let _alive = alive;
+        let mut stats = Stats::new().with_reservoir_sample_size(reservoir_sample_size);
 
-        let p = Processor::from_dir(&yara_dir)?;
+        let mut p = build_processor(&yara_dirs, &rule_filter, &processing_cfg)?;
+        let allowlists = Allowlists::from_cfg(&processing_cfg)?;
 
-        for message in rx {
-            let start = time::Instant::now();
-            stats.inc_events();
-            match p.process(message.raw_content()) {
-                Ok(m) => {
-                    if !m.is_empty() {
-                        stats.inc_matches();
-                        if let Err(e) = sx.send(ProcessedEvent(message, m)) {
-                            error!("Failed to send processed event: {}", e);
-                            stats.inc_failures();
+        loop {
+            select! {
+                recv(rx) -> message => match message {
+                    Ok(message) => {
+                        process_event(&p, &processing_cfg, &allowlists, &sx, &mut stats, message);
+                        metrics::set_queue_depth(metrics::Channel::FeederProcessor, rx.len());
+                        metrics::set_queue_depth(metrics::Channel::ProcessorLoader, sx.len());
+                    },
+                    // The write-end was dropped and there are no events left
+                    Err(_) => break
+                },
+                recv(cmd_rx) -> cmd => match cmd {
+                    Ok(Command::DrainAndStop) => {
+                        while let Ok(message) = rx.try_recv() {
+                            process_event(&p, &processing_cfg, &allowlists, &sx, &mut stats, message);
                         }
-                    }
+                        break;
+                    },
+                    Ok(Command::RecompileRules(barrier)) => {
+                        // The events queued before the command are scanned with the rules they were sent under
+                        let queued = rx.len();
+                        for message in rx.try_iter().take(queued) {
+                            process_event(&p, &processing_cfg, &allowlists, &sx, &mut stats, message);
+                        }
+                        match build_processor(&yara_dirs, &rule_filter, &processing_cfg) {
+                            Ok(recompiled) => {
+                                info!("Recompiled the Yara rules");
+                                p = recompiled;
+                            },
+                            Err(e) => error!("Could not recompile Yara rules, keeping the old ones: {:#}", e)
+                        }
+                        barrier.wait();
+                    },
+                    // Nobody can send commands anymore, so stop listening for them
+                    Err(_) => cmd_rx = crossbeam_channel::never()
                 }
-                Err(e) => error!("Error encountered during processing: {}", e)
             }
-            stats.add_duration(start.elapsed());
         }
 
         Ok(stats)
     })
 }
 
+/// Compiles the rules of `yara_dirs` that `rule_filter` keeps into a processor, limited to
+/// `processing_cfg.max_scan_memory()` and `processing_cfg.yara_scan_timeout_secs()`
+fn build_processor(yara_dirs: &[String], rule_filter: &GlobFilter, processing_cfg: &ProcessingCfg) -> Result<Processor> {
+    let p = Processor::from_dir_strings(yara_dirs, rule_filter)?
+        .with_scan_timeout(processing_cfg.yara_scan_timeout_secs());
+
+    Ok(match processing_cfg.max_scan_memory() {
+        Some(limit) => p.with_memory_limit(limit),
+        None => p
+    })
+}
+
+/// The (sorted, unique) categories of the tags of all `matches`, according to `tag_category_map`
+fn categories_of(matches: &[FlatMatch], tag_category_map: &HashMap<String, String>) -> Vec<String> {
+    let categories: BTreeSet<&String> = matches.iter()
+        .flat_map(|m| m.tags())
+        .filter_map(|tag| tag_category_map.get(tag))
+        .collect();
+
+    categories.into_iter().cloned().collect()
+}
+
+/// The matches that are known to be benign (see `ProcessingCfg::rule_allowlist` and
+/// `ProcessingCfg::data_allowlist_patterns`)
+struct Allowlists {
+    rules: HashSet<String>,
+    data: Vec<Regex>
+}
+
+impl Allowlists {
+    fn from_cfg(processing_cfg: &ProcessingCfg) -> Result<Self> {
+        Ok(Self {
+            rules: processing_cfg.rule_allowlist().iter().cloned().collect(),
+            data: processing_cfg.data_allowlist_regexes()?
+        })
+    }
+
+    fn apply(&self, matches: Vec<FlatMatch>) -> Vec<FlatMatch> {
+        AsciiMatch::apply_data_allowlist(FlatMatch::apply_allowlist(matches, &self.rules), &self.data)
+    }
+}
+
+/// Scans a single event and sends it to the loaders if it matched any (non-allowlisted) rules
+fn process_event(
+    p: &Processor,
+    processing_cfg: &ProcessingCfg,
+    allowlists: &Allowlists,
+    sx: &Sender<ProcessedEvent>,
+    stats: &mut Stats,
+    mut message: Event
+) {
+    let start = time::Instant::now();
+    stats.inc_events();
+    if processing_cfg.normalize_content() {
+        message.normalize_content();
+    }
+    if let (true, Some(limit)) = (processing_cfg.truncate_oversized_events(), processing_cfg.max_scan_memory()) {
+        if message.truncate_content(limit) {
+            stats.inc_truncated();
+        }
+    }
+    let matches = p.process_with_externals(message.raw_content(), &externals_of(&message)).map(|m| {
+        let (m, num_short) = FlatMatch::filter_short_data(allowlists.apply(m), processing_cfg.min_match_length());
+        stats.add_short_matches_filtered(num_short as u32);
+        m
+    });
+    match matches {
+        Ok(m) => {
+            let mut processed = ProcessedEvent(message, m);
+            if let Some(threshold) = processing_cfg.min_confidence_threshold() {
+                processed = processed.filter_matches(|fm| fm.confidence() >= threshold);
+            }
+
+            if !processed.is_empty() {
+                let ProcessedEvent(mut message, mut m) = processed;
+                if processing_cfg.context_window() > 0 {
+                    for fm in &mut m {
+                        fm.extract_contexts(message.raw_content(), processing_cfg.context_window());
+                    }
+                }
+                stats.inc_matches();
+                metrics::record_event_matched();
+                stats.add_deduped_matches(m.iter().map(|f| f.num_duplicate_data() as u32).sum());
+                for fm in &m {
+                    stats.record_match(fm.rule_name());
+                }
+                message.set_categories(categories_of(&m, processing_cfg.tag_category_map()));
+                if processing_cfg.extract_indicators() {
+                    message.set_indicators(extract_indicators(message.raw_content()));
+                }
+                if let Err(e) = sx.send(ProcessedEvent(message, m)) {
+                    error!("Failed to send processed event: {}", e);
+                    stats.inc_failures();
+                    metrics::record_event_failed();
+                }
+            }
+        }
+        Err(ProcessingError::ContentExceedsMemoryLimit { size, limit }) => {
+            error!("Skipping event {}: {} bytes exceed the scan memory limit ({})", message.url(), size, format_size(limit));
+            stats.inc_memory_limit_exceeded();
+            metrics::record_event_failed();
+        }
+        Err(ProcessingError::Yara(YaraError { kind: YaraErrorKind::ScanTimeout })) => {
+            error!("Skipping event {}: scanning it exceeded the scan timeout ({}s)", message.url(), p.scan_timeout);
+            stats.inc_timeouts();
+            metrics::record_event_failed();
+        }
+        Err(e) => {
+            error!("Error encountered during processing: {}", e);
+            metrics::record_event_failed();
+        }
+    }
+    let elapsed = start.elapsed();
+    stats.add_duration(elapsed);
+    metrics::record_event_processed(elapsed);
+}
+
+/// Scans `content` `iterations` times with the rules under `yara_dirs` that `rule_filter` keeps
+/// (see `Processor::benchmark`)
+pub fn benchmark_rules(
+    yara_dirs: &[String],
+    rule_filter: &GlobFilter,
+    content: &str,
+    iterations: u32
+) -> Result<BenchmarkResult> {
+    let p = Processor::from_dir_strings(yara_dirs, rule_filter)?;
+
+    Ok(p.benchmark(content, iterations)?)
+}
+
+/// Compiles the rules under `rule_dir` and returns the names of the (non-private) rules they define
+///
+/// # Errors
+///
+/// `errors::ConfigurationError::NoYaraRulesError` - When `rule_dir` does not exist or holds no `.yar` files
+pub fn validate_rules(rule_dir: &str) -> Result<Vec<String>> {
+    validate_rule_dirs(&[rule_dir.to_owned()], &GlobFilter::default())
+}
+
+/// Same as `validate_rules`, but compiles the rules under all `yara_dirs` that `rule_filter` keeps, i.e. the rules
+/// the processors would load
+///
+/// # Errors
+///
+/// `errors::ConfigurationError::NoYaraRulesError` - When one of `yara_dirs` does not exist, or `rule_filter` keeps
+/// no `.yar` files
+pub fn validate_rule_dirs(yara_dirs: &[String], rule_filter: &GlobFilter) -> Result<Vec<String>> {
+    if let Some(missing) = yara_dirs.iter().find(|dir| !Path::new(dir).is_dir()) {
+        return Err(ConfigurationError::NoYaraRulesError(missing.to_owned()).into());
+    }
+    let p = Processor::from_dir_strings(yara_dirs, rule_filter)?;
+
+    Ok(p.rule_names()?)
+}
+
+/// Every module Yara can be built with. Whether the optional ones (`hash`, `magic`, `cuckoo`, `dotnet`, `dex` and
+/// `macho`) are compiled in depends on the features the `yara` crate was built with
+const KNOWN_MODULES: [&str; 12] = [
+    "pe", "elf", "math", "time", "string", "console", "hash", "magic", "cuckoo", "dotnet", "dex", "macho"
+];
+
+lazy_static! {
+    static ref AVAILABLE_MODULES: Vec<&'static str> = KNOWN_MODULES.iter()
+        .copied()
+        .filter(|module| module_compiles(module))
+        .collect();
+}
+
+/// Yara has no API for listing its modules, so a module is considered compiled in if importing it compiles
+fn module_compiles(module: &str) -> bool {
+    match Compiler::new() {
+        Ok(compiler) => compiler.add_rules_str(&format!("import \"{}\"", module)).is_ok(),
+        Err(_) => false
+    }
+}
+
+/// The Yara modules that rules can import
+pub fn available_modules() -> &'static [&'static str] {
+    &AVAILABLE_MODULES
+}
+
+/// Checks that every one of `modules` (see `processing.enabled_modules`) is compiled in
+///
+/// # Errors
+///
+/// `errors::ConfigurationError::ModuleNotAvailable` - For the first of `modules` that is not compiled in
+pub fn check_modules(modules: &[String]) -> Result<(), ConfigurationError> {
+    match modules.iter().find(|m| !available_modules().contains(&m.as_str())) {
+        Some(missing) => Err(ConfigurationError::ModuleNotAvailable(missing.to_owned())),
+        None => Ok(())
+    }
+}
+
+/// Reads one of an event's string fields (e.g. `Event::source`)
+type EventField = fn(&Event) -> &str;
+
+/// The (string) external variables that rules may reference, e.g. `condition: external_source == "pastebin"`, along
+/// with the `Event` field each is set from when scanning the event (see `externals_of`). They are empty strings when
+/// scanning content without an event
+const EXTERNAL_VARIABLES: [(&str, EventField); 3] = [
+    ("external_source", Event::source),
+    ("external_url", Event::url),
+    ("external_creator", Event::creator)
+];
+
+/// The values of `EXTERNAL_VARIABLES` for `event`
+fn externals_of(event: &Event) -> Vec<(&'static str, &str)> {
+    EXTERNAL_VARIABLES.iter().map(|(identifier, field)| (*identifier, field(event))).collect()
+}
+
+/// A rule compiled into a `Processor`
+#[derive(Clone)]
+enum RuleSource {
+    /// A rule file, compiled by path so that its relative `include`s resolve and compile errors name it
+    File(PathBuf),
+    /// Yara source added with `Processor::with_rules` or `Processor::add_rules_bytes`
+    Str(String)
+}
+
+/// Compiles `rules` into a single engine, declaring `EXTERNAL_VARIABLES`
+fn compile(rules: &[RuleSource]) -> Result<Rules> {
+    let mut compiler = Compiler::new()?;
+    for (identifier, _) in &EXTERNAL_VARIABLES {
+        compiler.define_variable(identifier, "")?;
+    }
+
+    for rule in rules {
+        compiler = match rule {
+            RuleSource::File(filename) => compiler.add_rules_file(filename),
+            RuleSource::Str(source) => compiler.add_rules_str(source)
+        }.map_err(module_error)?;
+    }
+
+    Ok(compiler.compile_rules()?)
+}
+
+/// Compilation fails with an "unknown module" error when a rule imports a module that is not compiled in. That
+/// error is turned into `errors::ConfigurationError::ModuleNotAvailable`, any other one is returned as is
+fn module_error(err: yara::Error) -> anyhow::Error {
+    if let yara::Error::Compile(errors) = &err {
+        let missing = errors.iter().find_map(|e| {
+            e.message.strip_prefix("unknown module \"").and_then(|m| m.strip_suffix('"'))
+        });
+        if let Some(module) = missing {
+            return ConfigurationError::ModuleNotAvailable(module.to_owned()).into();
+        }
+    }
+
+    err.into()
+}
+
+/// How long scanning a piece of content took, over a number of iterations
+#[derive(Debug, Default)]
+pub struct BenchmarkResult {
+    pub min: time::Duration,
+    pub max: time::Duration,
+    pub avg: time::Duration,
+    pub p95: time::Duration,
+    pub total_matches: u64,
+    /// How long scanning all iterations as a single batch took, one piece of content after the other
+    /// (`Processor::process_batch`) and in parallel (`Processor::process_batch_parallel`)
+    pub batch: time::Duration,
+    pub parallel_batch: time::Duration
+}
+
+impl fmt::Display for BenchmarkResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "min: {}ns, max: {}ns, avg: {}ns, p95: {}ns, total matches: {}, batch: {}ns, parallel batch: {}ns",
+            self.min.as_nanos(),
+            self.max.as_nanos(),
+            self.avg.as_nanos(),
+            self.p95.as_nanos(),
+            self.total_matches,
+            self.batch.as_nanos(),
+            self.parallel_batch.as_nanos()
+        )
+    }
+}
+
+/// The scan timeout of processors that are not given one (see `Processor::with_scan_timeout`)
+const DEFAULT_SCAN_TIMEOUT_SECS: i32 = 10;
+
 struct Processor {
-    engine: Rules
+    engine: Rules,
+    /// Every rule compiled into `engine`, so that it can be recompiled with more rules
+    rules: Vec<RuleSource>,
+    /// The Yara source of each of `rules` (see `Processor::rule_sources`)
+    rule_sources: Vec<String>,
+    memory_limit: Option<usize>,
+    /// How many seconds Yara may spend scanning a single piece of content (`0` means no limit)
+    scan_timeout: i32
 }
 
 impl Processor {
@@ -156,31 +570,74 @@ impl Processor {
     /// # Errors
     ///
     /// `errors::ConfigurationError::NoYaraRulesError` - When no `.yar` files can be found under `rule_root`
+    /// `std::io::Error` - When `rule_root` cannot be read
     fn from_dir(rule_root: &str) -> Result<Processor> {
-        let rule_files = rec_get_files_by_ext(rule_root, "yar");
+        Processor::from_dirs(&[rule_root], &GlobFilter::default())
+    }
+
+    /// Same as `Processor::from_dir`, but loads the rules under all `rule_roots`. A file reachable from more than
+    /// one root (e.g. when one root is nested in another) is only loaded once. Only the files that `filter` keeps
+    /// are loaded, so `errors::ConfigurationError::NoYaraRulesError` is returned when it keeps none of them
+    ///
+    /// # Errors
+    ///
+    /// `errors::ConfigurationError::DuplicateRuleFile` - When two different files under different roots have the
+    /// same name
+    fn from_dirs(rule_roots: &[&str], filter: &GlobFilter) -> Result<Processor> {
+        let mut rule_files: Vec<PathBuf> = Vec::new();
+        let mut seen_paths: HashSet<PathBuf> = HashSet::new();
+        let mut seen_names: HashMap<OsString, usize> = HashMap::new();
+
+        for (root_idx, rule_root) in rule_roots.iter().enumerate() {
+            let files = rec_get_files_by_globs(rule_root, "yar", filter)
+                .with_context(|| format!("Could not read yara rule directory {}", rule_root))?;
+
+            for file in files {
+                if !seen_paths.insert(file.canonicalize().unwrap_or_else(|_| file.clone())) {
+                    continue;
+                }
+
+                if let Some(name) = file.file_name() {
+                    match seen_names.insert(name.to_owned(), root_idx) {
+                        Some(other_idx) if other_idx != root_idx => {
+                            return Err(ConfigurationError::DuplicateRuleFile(name.to_string_lossy().into_owned()).into());
+                        },
+                        _ => {}
+                    }
+                }
+                rule_files.push(file);
+            }
+        }
 
+        if rule_files.is_empty() {
+            let rule_roots = rule_roots.join(", ");
+            error!("No .yar files found under {}", rule_roots);
+            return Err(ConfigurationError::NoYaraRulesError(rule_roots).into());
+        }
+
+        info!("Yara modules compiled in: {}", available_modules().join(", "));
         Processor::with_rule_files(rule_files)
     }
 
+    fn from_dir_strings(rule_roots: &[String], filter: &GlobFilter) -> Result<Processor> {
+        Processor::from_dirs(&rule_roots.iter().map(String::as_str).collect::<Vec<&str>>(), filter)
+    }
+
     /// Constructs a Processor object whose rules have been loaded by
     /// the contents of the provided files
     /// Largely works the same as `Processor::from_dir`, but each file must
-    /// be passed explicitly
-    fn with_rule_files(filenames: Vec<String>) -> Result<Processor> {
-        if filenames.is_empty() {
-            error!("No .yar files found");
-            return Err(ConfigurationError::NoYaraRulesError.into());
-        }
-
-        let mut compiler = Compiler::new()?;
-
-        for filename in filenames.into_iter() {
-            compiler = compiler.add_rules_file(&filename)?;
-        }
+    /// be passed explicitly. `include`s are resolved relative to the including file
+    fn with_rule_files<P: AsRef<Path>>(filenames: Vec<P>) -> Result<Processor> {
+        let rule_sources = filenames.iter()
+            .map(|f| {
+                fs::read(f)
+                    .map(|source| String::from_utf8_lossy(&source).into_owned())
+                    .with_context(|| format!("Could not read yara rule file {}", f.as_ref().display()))
+            })
+            .collect::<Result<Vec<String>>>()?;
+        let rules = filenames.iter().map(|f| RuleSource::File(f.as_ref().to_owned())).collect();
 
-        let engine = compiler.compile_rules()?;
-
-        Ok(Processor { engine })
+        Processor::compiled(rules, rule_sources)
     }
 
     /// Constructs a Processor object from a string representing a Yara rule
@@ -201,14 +658,54 @@ impl Processor {
     ///
     /// * `rules` - A vector of Yara rule strings
     fn with_rules(rules: Vec<String>) -> Result<Processor> {
-        let mut compiler = Compiler::new()?;
+        Processor::compiled(rules.iter().cloned().map(RuleSource::Str).collect(), rules)
+    }
 
-        for rule in rules.into_iter() {
-            compiler = compiler.add_rules_str(&rule)?;
-        }
+    /// Compiles `rules`, whose Yara source is `rule_sources`, into a new Processor
+    fn compiled(rules: Vec<RuleSource>, rule_sources: Vec<String>) -> Result<Processor> {
+        let engine = compile(&rules)?;
+
+        Ok(Processor { engine, rules, rule_sources, memory_limit: None, scan_timeout: DEFAULT_SCAN_TIMEOUT_SECS })
+    }
+
+    /// Adds the rules in `rule_bytes` (UTF-8 Yara source, e.g. as delivered over the network) to the ones already
+    /// loaded. All rules are recompiled, and the new engine only replaces the current one if compilation succeeds
+    ///
+    /// # Errors
+    ///
+    /// `errors::ProcessingError::InvalidRuleEncoding` - When `rule_bytes` is not valid UTF-8
+    /// `yara::Error` - When the rules do not compile (e.g. a rule with the same name is already loaded)
+    #[allow(dead_code)]
+    fn add_rules_bytes(&mut self, rule_bytes: &[u8]) -> Result<()> {
+        let rule = str::from_utf8(rule_bytes).map_err(ProcessingError::from)?.to_owned();
+
+        let mut rules = self.rules.clone();
+        rules.push(RuleSource::Str(rule.clone()));
+        self.engine = compile(&rules)?;
+        self.rules = rules;
+        self.rule_sources.push(rule);
+
+        Ok(())
+    }
+
+    /// The source of every loaded rule, in the order it was added. Rule files are read lossily, and their
+    /// `include`s are not expanded
+    #[allow(dead_code)]
+    fn rule_sources(&self) -> &[String] {
+        &self.rule_sources
+    }
 
-        let engine = compiler.compile_rules()?;
-        Ok(Processor { engine })
+    /// Refuses to scan content larger than `limit` bytes (see `Processor::process`), as a safety valve
+    /// against Yara allocating excessive memory while matching strings on huge events
+    fn with_memory_limit(mut self, limit: usize) -> Self {
+        self.memory_limit = Some(limit);
+        self
+    }
+
+    /// Gives up scanning content after `secs` seconds (see `Processor::process`). `0` means no limit
+    fn with_scan_timeout(mut self, secs: u64) -> Self {
+        self.scan_timeout = secs.min(i32::MAX as u64) as i32;
+        self
     }
 
     /// Given a string, tries to match the compiled Yara rules against it
@@ -229,34 +726,246 @@ impl Processor {
     ///     m.data(); // ["HelloWorld"]
     /// }
     /// ```
-    fn process(&self, filestr: &str) -> Result<Vec<FlatMatch>, YaraError> {
-        let rules: Vec<Rule> = self.engine.scan_mem(filestr.as_bytes(), 10)?;
+    ///
+    /// # Errors
+    ///
+    /// `errors::ProcessingError::ContentExceedsMemoryLimit` - When a memory limit has been set
+    /// (`Processor::with_memory_limit`) and `filestr` is larger than it. The content is not scanned
+    /// `errors::ProcessingError::Yara` - e.g. with `yara::YaraErrorKind::ScanTimeout` when scanning takes longer
+    /// than the scan timeout (`Processor::with_scan_timeout`)
+    fn process(&self, filestr: &str) -> Result<Vec<FlatMatch>, ProcessingError> {
+        self.process_with_externals(filestr, &[])
+    }
+
+    /// Same as `Processor::process`, but sets the given `(identifier, value)` external variables (see
+    /// `EXTERNAL_VARIABLES`) for this scan only. The ones that are not given keep their empty default
+    fn process_with_externals(
+        &self,
+        filestr: &str,
+        externals: &[(&str, &str)]
+    ) -> Result<Vec<FlatMatch>, ProcessingError> {
+        if let Some(limit) = self.memory_limit {
+            if filestr.len() > limit {
+                return Err(ProcessingError::ContentExceedsMemoryLimit { size: filestr.len(), limit });
+            }
+        }
+
+        let mut scanner = self.engine.scanner()?;
+        scanner.set_timeout(self.scan_timeout);
+        for (identifier, value) in externals {
+            scanner.define_variable(identifier, *value)?;
+        }
+
+        let rules: Vec<Rule> = scanner.scan_mem(filestr.as_bytes())?;
         Ok(FlatMatch::from_rules(rules))
     }
+
+    /// The names of the compiled rules. Yara has no API for listing them, so they are collected by scanning an
+    /// empty buffer, for which every rule is reported as either matching or not matching
+    fn rule_names(&self) -> Result<Vec<String>, ProcessingError> {
+        let mut names = Vec::new();
+        self.engine.scan_mem_callback(b"", self.scan_timeout, |msg| {
+            if let CallbackMsg::RuleMatching(rule) | CallbackMsg::RuleNotMatching(rule) = msg {
+                names.push(rule.identifier.to_owned());
+            }
+            CallbackReturn::Continue
+        })?;
+
+        Ok(names)
+    }
+
+    /// Scans `content` `iterations` times, measuring how long each scan takes
+    /// Every scan's matches are counted in `total_matches`. The `iterations` scans are then timed again as a batch,
+    /// serially and in parallel
+    fn benchmark(&self, content: &str, iterations: u32) -> Result<BenchmarkResult, ProcessingError> {
+        let mut durations: Vec<time::Duration> = Vec::with_capacity(iterations as usize);
+        let mut total_matches: u64 = 0;
+
+        for _ in 0..iterations {
+            let start = time::Instant::now();
+            total_matches += self.process(content)?.len() as u64;
+            durations.push(start.elapsed());
+        }
+
+        if durations.is_empty() {
+            return Ok(BenchmarkResult::default());
+        }
+
+        durations.sort();
+        let p95_index = ((durations.len() as f64 * 0.95).ceil() as usize).saturating_sub(1);
+
+        let contents = vec![content; iterations as usize];
+        let start = time::Instant::now();
+        self.process_batch(&contents)?;
+        let batch = start.elapsed();
+        let start = time::Instant::now();
+        self.process_batch_parallel(&contents)?;
+        let parallel_batch = start.elapsed();
+
+        Ok(BenchmarkResult {
+            min: durations[0],
+            max: durations[durations.len() - 1],
+            avg: durations.iter().sum::<time::Duration>() / durations.len() as u32,
+            p95: durations[p95_index],
+            total_matches,
+            batch,
+            parallel_batch
+        })
+    }
+
+    /// Runs the compiled Yara rules against each of the given strings, one after the other
+    /// Returns the matches of each string in the same order as `contents`. If scanning any of the
+    /// strings fails, the error is returned and the remaining strings are not scanned
+    ///
+    /// # Arguments
+    ///
+    /// * `contents` - The strings against which the Yara matcher will run
+    pub fn process_batch(&self, contents: &[&str]) -> Result<Vec<Vec<FlatMatch>>, ProcessingError> {
+        let mut batch_matches = Vec::with_capacity(contents.len());
+
+        for content in contents {
+            batch_matches.push(self.process(content)?);
+        }
+
+        Ok(batch_matches)
+    }
+
+    /// Same as `process_batch`, but the strings are scanned in parallel using rayon's global thread pool
+    /// The order of the returned matches still follows the order of `contents`
+    pub fn process_batch_parallel(&self, contents: &[&str]) -> Result<Vec<Vec<FlatMatch>>, ProcessingError> {
+        contents.par_iter()
+            .map(|content| self.process(content))
+            .collect()
+    }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Stats {
     /// An instance of this class is returned by each Processing thread when they are joined
     /// It measures the overall & average time spent processing, the number of processed events, the number of matches,
     /// etc.
+    #[serde(rename = "overall_proc_time_ns", with = "duration_nanos")]
     overall_proc_time: time::Duration,
     num_events: u32,
     num_matches: u32,
-    num_failures: u32
+    num_failures: u32,
+    num_memory_limit_exceeded: u32,
+    #[serde(default)]
+    num_truncated: u32,
+    #[serde(default)]
+    num_timeouts: u32,
+    num_deduped_matches: u32,
+    #[serde(default)]
+    num_short_matches_filtered: u32,
+    rule_hit_counts: HashMap<String, u64>,
+    #[serde(default, rename = "min_proc_time_ns", with = "duration_nanos")]
+    min_proc_time: time::Duration,
+    #[serde(default, rename = "max_proc_time_ns", with = "duration_nanos")]
+    max_proc_time: time::Duration,
+    /// The processing time of each event (in nanoseconds), or of a random sample of them if
+    /// `reservoir_sample_size` is set
+    #[serde(skip)]
+    latency_samples: Vec<u64>,
+    #[serde(skip)]
+    reservoir_sample_size: Option<usize>,
+    /// How many processing times were added, including the ones left out of `latency_samples`
+    #[serde(skip)]
+    num_latencies: u64
 }
 
+/// How many of the most matched rules are shown when displaying `Stats`
+const DISPLAYED_TOP_RULES: usize = 5;
+
 impl Stats {
     fn new() -> Self {
         Self {
             overall_proc_time: time::Duration::from_secs(0),
             num_events: 0,
             num_matches: 0,
-            num_failures: 0
+            num_failures: 0,
+            num_memory_limit_exceeded: 0,
+            num_truncated: 0,
+            num_timeouts: 0,
+            num_deduped_matches: 0,
+            num_short_matches_filtered: 0,
+            rule_hit_counts: HashMap::new(),
+            min_proc_time: time::Duration::from_secs(0),
+            max_proc_time: time::Duration::from_secs(0),
+            latency_samples: Vec::new(),
+            reservoir_sample_size: None,
+            num_latencies: 0
+        }
+    }
+
+    /// Keeps at most `size` processing times for the latency percentiles, picked at random among all events
+    /// (reservoir sampling). `None` keeps all of them. The minimum and maximum are always exact
+    fn with_reservoir_sample_size(mut self, size: Option<usize>) -> Self {
+        self.reservoir_sample_size = size;
+        self
+    }
+
+    /// Combines the stats of several processor threads into overall stats, e.g. summing their events,
+    /// matches and per-rule hit counts
+    pub fn merge_all(all_stats: &[Stats]) -> Stats {
+        let mut merged = Stats::new();
+
+        for stats in all_stats {
+            merged.absorb(stats);
+        }
+
+        merged
+    }
+
+    /// Combines these stats with those of another processor thread (see `Stats::merge_all`). The average
+    /// processing time of the result is that of the events of both threads. Also available as `a + b`
+    pub fn merge(mut self, other: Stats) -> Stats {
+        self.absorb(&other);
+        self
+    }
+
+    fn absorb(&mut self, other: &Stats) {
+        self.overall_proc_time += other.overall_proc_time;
+        self.num_events += other.num_events;
+        self.num_matches += other.num_matches;
+        self.num_failures += other.num_failures;
+        self.num_memory_limit_exceeded += other.num_memory_limit_exceeded;
+        self.num_truncated += other.num_truncated;
+        self.num_timeouts += other.num_timeouts;
+        self.num_deduped_matches += other.num_deduped_matches;
+        self.num_short_matches_filtered += other.num_short_matches_filtered;
+        for (rule_name, hits) in &other.rule_hit_counts {
+            *self.rule_hit_counts.entry(rule_name.to_owned()).or_insert(0) += hits;
+        }
+        if other.num_latencies > 0 {
+            if self.num_latencies == 0 || other.min_proc_time < self.min_proc_time {
+                self.min_proc_time = other.min_proc_time;
+            }
+            self.max_proc_time = self.max_proc_time.max(other.max_proc_time);
         }
+        self.num_latencies += other.num_latencies;
+        // The samples of both threads are kept, so the percentiles of sampled threads are approximate
+        self.latency_samples.extend_from_slice(&other.latency_samples);
     }
 
     fn add_duration(&mut self, elapsed: time::Duration) {
         self.overall_proc_time += elapsed;
+        if self.num_latencies == 0 || elapsed < self.min_proc_time {
+            self.min_proc_time = elapsed;
+        }
+        self.max_proc_time = self.max_proc_time.max(elapsed);
+        self.num_latencies += 1;
+
+        let sample = elapsed.as_nanos() as u64;
+        match self.reservoir_sample_size {
+            // Algorithm R: the n-th processing time replaces a random sample with a probability of `size / n`
+            Some(size) if self.latency_samples.len() >= size => {
+                let i = rand::thread_rng().gen_range(0..self.num_latencies) as usize;
+                if i < size {
+                    self.latency_samples[i] = sample;
+                }
+            },
+            _ => self.latency_samples.push(sample)
+        }
     }
 
     fn inc_events(&mut self) {
@@ -271,6 +980,31 @@ impl Stats {
         self.num_failures += 1;
     }
 
+    fn inc_memory_limit_exceeded(&mut self) {
+        self.num_memory_limit_exceeded += 1;
+    }
+
+    fn inc_truncated(&mut self) {
+        self.num_truncated += 1;
+    }
+
+    fn inc_timeouts(&mut self) {
+        self.num_timeouts += 1;
+    }
+
+    fn add_deduped_matches(&mut self, num_deduped: u32) {
+        self.num_deduped_matches += num_deduped;
+    }
+
+    fn add_short_matches_filtered(&mut self, num_filtered: u32) {
+        self.num_short_matches_filtered += num_filtered;
+    }
+
+    /// Counts a match of `rule_name`
+    fn record_match(&mut self, rule_name: &str) {
+        *self.rule_hit_counts.entry(rule_name.to_owned()).or_insert(0) += 1;
+    }
+
     pub fn overall_proc_time(&self) -> time::Duration {
         self.overall_proc_time
     }
@@ -283,6 +1017,30 @@ impl Stats {
         self.overall_proc_time / self.num_events
     }
 
+    /// The time spent processing the fastest event. Zero if no events were processed
+    pub fn min_proc_time(&self) -> time::Duration {
+        self.min_proc_time
+    }
+
+    /// The time spent processing the slowest event. Zero if no events were processed
+    pub fn max_proc_time(&self) -> time::Duration {
+        self.max_proc_time
+    }
+
+    /// The time within which `p` percent of the (sampled) events were processed, e.g. `percentile(95.0)`, using
+    /// the nearest-rank method. `p` is clamped between 0 and 100. Zero if no events were processed
+    pub fn percentile(&self, p: f64) -> time::Duration {
+        if self.latency_samples.is_empty() {
+            return time::Duration::from_secs(0);
+        }
+
+        let mut samples = self.latency_samples.clone();
+        samples.sort_unstable();
+        let rank = (p.clamp(0.0, 100.0) / 100.0 * samples.len() as f64).ceil() as usize;
+
+        time::Duration::from_nanos(samples[rank.saturating_sub(1)])
+    }
+
     pub fn num_events(&self) -> u32 {
         self.num_events
     }
@@ -294,53 +1052,288 @@ impl Stats {
     pub fn num_failures(&self) -> u32 {
         self.num_failures
     }
-}
 
-impl fmt::Display for Stats {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            r#"
-              Overall time spent processing: {}ns
-              Average time spend processing each event: {}ns
-              Events processed: {}
-              Matches: {}
-              Also encountered {} failures
-            "#,
-            self.overall_proc_time().as_nanos(),
-            self.avg_proc_time().as_nanos(),
-            self.num_events(),
-            self.num_matches(),
-            self.num_failures()
-        )
+    pub fn num_memory_limit_exceeded(&self) -> u32 {
+        self.num_memory_limit_exceeded
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// The number of events cut down to the scan memory limit (see `processing.truncate_oversized_events`)
+    pub fn num_truncated(&self) -> u32 {
+        self.num_truncated
+    }
 
-    fn password_rule() -> String {
-        String::from(r#"
-        rule MyPass
-        {
-            meta:
-                name = "My Pass"
+    /// The number of events that Yara gave up scanning (see `processing.yara_scan_timeout_secs`)
+    pub fn num_timeouts(&self) -> u32 {
+        self.num_timeouts
+    }
 
-            strings:
-                $a = /pw:.+/
+    /// The number of duplicate matched strings that will not be persisted
+    pub fn num_deduped_matches(&self) -> u32 {
+        self.num_deduped_matches
+    }
 
-            condition:
-                $a
-        }
-        "#)
+    /// The number of matched strings that were discarded for being shorter than `processing.min_match_length`
+    pub fn num_short_matches_filtered(&self) -> u32 {
+        self.num_short_matches_filtered
     }
 
-    fn processor() -> Processor {
-        Processor::with_rule_str(&password_rule()).unwrap()
+    /// How many times each rule matched
+    pub fn rule_hit_counts(&self) -> &HashMap<String, u64> {
+        &self.rule_hit_counts
     }
 
-    #[test]
+    /// The `n` rules that matched the most times, most matched first. Ties are broken by rule name
+    pub fn top_rules(&self, n: usize) -> Vec<(&str, u64)> {
+        let mut rules: Vec<(&str, u64)> = self.rule_hit_counts.iter()
+            .map(|(rule_name, hits)| (rule_name.as_str(), *hits))
+            .collect();
+        rules.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        rules.truncate(n);
+
+        rules
+    }
+
+    /// Renders the per-rule hit counts in the InfluxDB line protocol, one line per rule (sorted by rule name)
+    /// e.g. `processor_rule_hits,rule=default::MyPass count=3i`
+    pub fn to_influx_line_per_rule(&self) -> Vec<String> {
+        let mut rules: Vec<(&String, &u64)> = self.rule_hit_counts.iter().collect();
+        rules.sort();
+
+        rules.into_iter()
+            .map(|(rule_name, hits)| format!("processor_rule_hits,rule={} count={}i", escape_influx_tag(rule_name), hits))
+            .collect()
+    }
+
+    /// Whether this thread spent less time on average processing each event than `other`
+    pub fn is_faster_than(&self, other: &Stats) -> bool {
+        self < other
+    }
+
+    /// Returns the position of this thread in `all_stats` once ranked slowest first
+    /// i.e. the number of threads in `all_stats` that were slower than this one
+    pub fn rank(&self, all_stats: &[Stats]) -> usize {
+        all_stats.iter().filter(|other| *other > self).count()
+    }
+
+    /// Appends these stats to `path` (created if missing) as a single JSON line, along with the current time
+    /// and a random run ID (see `HistoricalStats`)
+    pub fn serialize_to_file(&self, path: &str) -> Result<()> {
+        let mut record = serde_json::to_value(self)?;
+        record["timestamp"] = serde_json::to_value(Local::now())?;
+        record["run_id"] = serde_json::to_value(Uuid::new_v4())?;
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Could not open stats file {}", path))?;
+        writeln!(file, "{}", record).with_context(|| format!("Could not write to stats file {}", path))?;
+
+        Ok(())
+    }
+
+    /// Reads the stats written into `path` by `Stats::serialize_to_file`, oldest first. Blank lines are skipped
+    pub fn load_history(path: &str) -> Result<Vec<HistoricalStats>> {
+        let contents = fs::read_to_string(path).with_context(|| format!("Could not read stats file {}", path))?;
+
+        contents.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line).with_context(|| format!("Invalid stats on line {} of {}", i + 1, path))
+            })
+            .collect()
+    }
+
+    /// How these stats changed since `baseline` (e.g. the previous run)
+    pub fn compare_with_baseline(&self, baseline: &Stats) -> StatsDiff {
+        let baseline_avg = baseline.avg_proc_time().as_nanos() as f64;
+        let avg_time_delta_pct = if baseline_avg == 0.0 {
+            0.0
+        } else {
+            (self.avg_proc_time().as_nanos() as f64 - baseline_avg) / baseline_avg * 100.0
+        };
+
+        StatsDiff {
+            events_delta: self.num_events as i64 - baseline.num_events as i64,
+            matches_delta: self.num_matches as i64 - baseline.num_matches as i64,
+            avg_time_delta_pct
+        }
+    }
+}
+
+/// (De)serializes a `Duration` as a number of nanoseconds
+mod duration_nanos {
+    use std::time::Duration;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_nanos() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_nanos)
+    }
+}
+
+/// The stats of a past run, as read by `Stats::load_history`
+#[derive(Deserialize)]
+pub struct HistoricalStats {
+    timestamp: DateTime<Local>,
+    run_id: Uuid,
+    #[serde(flatten)]
+    stats: Stats
+}
+
+impl HistoricalStats {
+    /// When the stats were written
+    pub fn timestamp(&self) -> &DateTime<Local> {
+        &self.timestamp
+    }
+
+    pub fn run_id(&self) -> &Uuid {
+        &self.run_id
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+}
+
+/// The difference between two `Stats` (see `Stats::compare_with_baseline`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StatsDiff {
+    events_delta: i64,
+    matches_delta: i64,
+    avg_time_delta_pct: f64
+}
+
+impl StatsDiff {
+    pub fn events_delta(&self) -> i64 {
+        self.events_delta
+    }
+
+    pub fn matches_delta(&self) -> i64 {
+        self.matches_delta
+    }
+
+    /// How much the average processing time changed, as a percentage of the baseline's. `0` if the
+    /// baseline processed no events
+    pub fn avg_time_delta_pct(&self) -> f64 {
+        self.avg_time_delta_pct
+    }
+}
+
+impl fmt::Display for StatsDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Events: {:+}, matches: {:+}, average processing time: {:+.1}%",
+            self.events_delta, self.matches_delta, self.avg_time_delta_pct
+        )
+    }
+}
+
+impl Add for Stats {
+    type Output = Stats;
+
+    fn add(self, other: Stats) -> Stats {
+        self.merge(other)
+    }
+}
+
+/// Stats are ordered by their average processing time, so that the slowest thread compares as the greatest
+impl Ord for Stats {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.avg_proc_time().cmp(&other.avg_proc_time())
+    }
+}
+
+impl PartialOrd for Stats {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Stats {
+    fn eq(&self, other: &Self) -> bool {
+        self.avg_proc_time() == other.avg_proc_time()
+    }
+}
+
+impl Eq for Stats {}
+
+/// Escapes the characters that have a special meaning in InfluxDB line protocol tag values
+fn escape_influx_tag(value: &str) -> String {
+    value.replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            r#"
+              Overall time spent processing: {}ns
+              Average time spend processing each event: {}ns
+              Fastest/slowest event: {}ns/{}ns
+              95th percentile of the time spent processing each event: {}ns
+              Events processed: {}
+              Matches: {}
+              Also encountered {} failures
+              Events over the scan memory limit: {}
+              Events truncated to the scan memory limit: {}
+              Events that timed out: {}
+              Duplicate matched strings: {}
+              Matched strings shorter than the minimum length: {}
+              Top rules: {}
+            "#,
+            self.overall_proc_time().as_nanos(),
+            self.avg_proc_time().as_nanos(),
+            self.min_proc_time().as_nanos(),
+            self.max_proc_time().as_nanos(),
+            self.percentile(95.0).as_nanos(),
+            self.num_events(),
+            self.num_matches(),
+            self.num_failures(),
+            self.num_memory_limit_exceeded(),
+            self.num_truncated(),
+            self.num_timeouts(),
+            self.num_deduped_matches(),
+            self.num_short_matches_filtered(),
+            self.top_rules(DISPLAYED_TOP_RULES).iter()
+                .map(|(rule_name, hits)| format!("{} ({})", rule_name, hits))
+                .collect::<Vec<String>>()
+                .join(", ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn password_rule() -> String {
+        String::from(r#"
+        rule MyPass
+        {
+            meta:
+                name = "My Pass"
+
+            strings:
+                $a = /pw:.+/
+
+            condition:
+                $a
+        }
+        "#)
+    }
+
+    fn processor() -> Processor {
+        Processor::with_rule_str(&password_rule()).unwrap()
+    }
+
+    #[test]
     fn processor_does_not_blow_up() {
         processor();
     }
@@ -351,6 +1344,499 @@ mod tests {
         Processor::with_rule_str("Bad Rule").unwrap();
     }
 
+    #[test]
+    fn benchmark_stats_are_ordered() {
+        let p = processor();
+
+        for iterations in [1, 2, 7, 20] {
+            let result = p.benchmark("pw: \"hello\"", iterations).unwrap();
+
+            assert!(result.min <= result.avg);
+            assert!(result.avg <= result.max);
+            assert!(result.min <= result.p95 && result.p95 <= result.max);
+            assert_eq!(result.total_matches, iterations as u64);
+            assert!(result.batch > time::Duration::from_secs(0));
+            assert!(result.parallel_batch > time::Duration::from_secs(0));
+        }
+    }
+
+    #[test]
+    fn benchmark_without_iterations_is_empty() {
+        let result = processor().benchmark("foo", 0).unwrap();
+
+        assert_eq!(result.max, time::Duration::from_secs(0));
+        assert_eq!(result.batch, time::Duration::from_secs(0));
+        assert_eq!(result.total_matches, 0);
+    }
+
+    fn spawn_processor(
+        feed_recvr: &Receiver<Event>,
+        cmd_recvr: &Receiver<Command>
+    ) -> (thread::JoinHandle<Result<Stats>>, Receiver<ProcessedEvent>) {
+        let (load_sendr, load_recvr) = crossbeam_channel::unbounded();
+        let handle = process_forever(
+            feed_recvr,
+            cmd_recvr,
+            &load_sendr,
+            &Arc::new(vec!["yara-rules".to_owned()]),
+            &Arc::new(GlobFilter::default()),
+            &Arc::new(ProcessingCfg::default()),
+            None,
+            &Arc::default()
+        );
+
+        (handle, load_recvr)
+    }
+
+    fn event(content: &str) -> Event {
+        Event::new("https://pastebin.com/foo", content.len(), "pastebin", content, "foo.txt", "bar",
+                   chrono::Local::now(), chrono::Local::now())
+    }
+
+    #[test]
+    fn drain_and_stop_processes_the_queued_events() {
+        let (feed_sendr, feed_recvr) = crossbeam_channel::unbounded();
+        let (cmd_sendr, cmd_recvr) = crossbeam_channel::unbounded();
+        for _ in 0..7 {
+            feed_sendr.send(event("foo")).unwrap();
+        }
+
+        let (handle, _load_recvr) = spawn_processor(&feed_recvr, &cmd_recvr);
+        send_drain_and_stop(&cmd_sendr, 1);
+
+        // The feed channel is still open, yet the thread exits
+        let stats = handle.join().unwrap().unwrap();
+        assert_eq!(stats.num_events(), 7);
+        assert!(feed_recvr.is_empty());
+        drop(feed_sendr);
+    }
+
+    #[test]
+    fn drain_and_stop_stops_every_thread() {
+        let (_feed_sendr, feed_recvr) = crossbeam_channel::unbounded();
+        let (cmd_sendr, cmd_recvr) = crossbeam_channel::unbounded();
+        let handles: Vec<_> = (0..3).map(|_| spawn_processor(&feed_recvr, &cmd_recvr).0).collect();
+
+        send_drain_and_stop(&cmd_sendr, 3);
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap().unwrap().num_events(), 0);
+        }
+    }
+
+    /// Spawns a processor thread scanning with the rules of `rule_dirs`
+    fn spawn_processor_with_rules(
+        feed_recvr: &Receiver<Event>,
+        cmd_recvr: &Receiver<Command>,
+        load_sendr: &Sender<ProcessedEvent>,
+        rule_dirs: &[String]
+    ) -> thread::JoinHandle<Result<Stats>> {
+        process_forever(
+            feed_recvr,
+            cmd_recvr,
+            load_sendr,
+            &Arc::new(rule_dirs.to_vec()),
+            &Arc::new(GlobFilter::default()),
+            &Arc::new(ProcessingCfg::default()),
+            None,
+            &Arc::default()
+        )
+    }
+
+    fn matched_rules(processed: &ProcessedEvent) -> Vec<&str> {
+        processed.1.iter().map(|m| m.rule_name()).collect()
+    }
+
+    #[test]
+    fn events_after_a_recompile_are_scanned_with_the_new_rules() {
+        let dirs = rule_dirs("recompile-mid-stream", &[("rules.yar", "Foo")]);
+        let (feed_sendr, feed_recvr) = crossbeam_channel::unbounded();
+        let (cmd_sendr, cmd_recvr) = crossbeam_channel::unbounded();
+        let (load_sendr, load_recvr) = crossbeam_channel::unbounded();
+        let handle = spawn_processor_with_rules(&feed_recvr, &cmd_recvr, &load_sendr, &dirs);
+
+        // Wait for the first event, so that the thread is known to have compiled the original rules
+        feed_sendr.send(event("Foo Bar")).unwrap();
+        assert_eq!(matched_rules(&load_recvr.recv().unwrap()), ["default::Foo"]);
+
+        for _ in 0..3 {
+            feed_sendr.send(event("Foo Bar")).unwrap();
+        }
+        fs::write(
+            Path::new(&dirs[0]).join("rules.yar"), r#"rule Bar { strings: $a = "Bar" condition: $a }"#
+        ).unwrap();
+        send_recompile(&cmd_sendr, 1);
+        for _ in 0..3 {
+            feed_sendr.send(event("Foo Bar")).unwrap();
+        }
+        drop(feed_sendr);
+
+        assert_eq!(handle.join().unwrap().unwrap().num_events(), 7);
+        let processed: Vec<ProcessedEvent> = load_recvr.try_iter().collect();
+        let rules: Vec<&str> = processed.iter().flat_map(matched_rules).collect();
+        assert_eq!(rules, ["default::Foo", "default::Foo", "default::Foo", "default::Bar", "default::Bar", "default::Bar"]);
+    }
+
+    #[test]
+    fn every_thread_recompiles_its_rules() {
+        let dirs = rule_dirs("recompile-every-thread", &[("rules.yar", "Foo")]);
+        let (feed_sendr, feed_recvr) = crossbeam_channel::unbounded();
+        let (cmd_sendr, cmd_recvr) = crossbeam_channel::unbounded();
+        let (load_sendr, load_recvr) = crossbeam_channel::unbounded();
+        let handles: Vec<_> = (0..3)
+            .map(|_| spawn_processor_with_rules(&feed_recvr, &cmd_recvr, &load_sendr, &dirs))
+            .collect();
+
+        fs::write(
+            Path::new(&dirs[0]).join("rules.yar"), r#"rule Bar { strings: $a = "Bar" condition: $a }"#
+        ).unwrap();
+        send_recompile(&cmd_sendr, 3);
+        for _ in 0..9 {
+            feed_sendr.send(event("Foo Bar")).unwrap();
+        }
+        drop(feed_sendr);
+
+        let num_events: u32 = handles.into_iter().map(|h| h.join().unwrap().unwrap().num_events()).sum();
+        assert_eq!(num_events, 9);
+        assert!(load_recvr.try_iter().all(|p| matched_rules(&p) == ["default::Bar"]));
+    }
+
+    #[test]
+    fn invalid_rules_are_not_recompiled() {
+        let dirs = rule_dirs("recompile-invalid", &[("rules.yar", "Foo")]);
+        let (feed_sendr, feed_recvr) = crossbeam_channel::unbounded();
+        let (cmd_sendr, cmd_recvr) = crossbeam_channel::unbounded();
+        let (load_sendr, load_recvr) = crossbeam_channel::unbounded();
+        let handle = spawn_processor_with_rules(&feed_recvr, &cmd_recvr, &load_sendr, &dirs);
+
+        feed_sendr.send(event("Foo")).unwrap();
+        load_recvr.recv().unwrap();
+        fs::write(Path::new(&dirs[0]).join("rules.yar"), "rule Broken {").unwrap();
+        send_recompile(&cmd_sendr, 1);
+        feed_sendr.send(event("Foo")).unwrap();
+        drop(feed_sendr);
+
+        handle.join().unwrap().unwrap();
+        assert_eq!(matched_rules(&load_recvr.try_recv().unwrap()), ["default::Foo"]);
+    }
+
+    #[test]
+    fn processors_outlive_the_command_channel() {
+        let (feed_sendr, feed_recvr) = crossbeam_channel::unbounded();
+        let (cmd_sendr, cmd_recvr) = crossbeam_channel::unbounded();
+        let (handle, _load_recvr) = spawn_processor(&feed_recvr, &cmd_recvr);
+        drop(cmd_sendr);
+
+        feed_sendr.send(event("foo")).unwrap();
+        feed_sendr.send(event("bar")).unwrap();
+        drop(feed_sendr);
+
+        assert_eq!(handle.join().unwrap().unwrap().num_events(), 2);
+    }
+
+    #[test]
+    fn matched_tags_are_mapped_to_categories() {
+        let p = Processor::with_rule_str(r#"
+            rule Password : credentials { strings: $a = "pw:" condition: $a }
+            rule Email : pii credentials { strings: $a = "@example.com" condition: $a }
+            rule Hash : crypto { strings: $a = "sha256:" condition: $a }
+        "#).unwrap();
+        let tag_category_map = HashMap::from([
+            ("credentials".to_owned(), "HIGH_RISK".to_owned()),
+            ("pii".to_owned(), "COMPLIANCE".to_owned())
+        ]);
+
+        let matches = p.process("pw: foo, user@example.com, sha256:abc").unwrap();
+        assert_eq!(categories_of(&matches, &tag_category_map), vec!["COMPLIANCE", "HIGH_RISK"]);
+        assert!(categories_of(&p.process("sha256:abc").unwrap(), &tag_category_map).is_empty());
+    }
+
+    #[test]
+    fn matched_strings_shorter_than_the_minimum_are_not_stored() {
+        let p = Processor::with_rule_str(r#"
+            rule Password { strings: $short = "pwd" $long = "hunter22" condition: any of them }
+        "#).unwrap();
+        let processing_cfg = Config::from_reader("processing:\n  min_match_length: 5".as_bytes())
+            .unwrap()
+            .processing()
+            .clone();
+        let allowlists = Allowlists::from_cfg(&processing_cfg).unwrap();
+        let (load_sendr, load_recvr) = crossbeam_channel::unbounded();
+        let mut stats = Stats::new();
+
+        process_event(&p, &processing_cfg, &allowlists, &load_sendr, &mut stats, event("pwd: hunter22"));
+
+        let ProcessedEvent(_, matches) = load_recvr.try_recv().unwrap();
+        assert_eq!(matches[0].data(), &vec!["hunter22".to_owned()]);
+        assert_eq!(stats.num_short_matches_filtered(), 1);
+    }
+
+    #[test]
+    fn matches_carry_their_context() {
+        let processing_cfg = Config::from_reader("processing:\n  context_window: 6".as_bytes())
+            .unwrap()
+            .processing()
+            .clone();
+        let allowlists = Allowlists::from_cfg(&processing_cfg).unwrap();
+        let (load_sendr, load_recvr) = crossbeam_channel::unbounded();
+        let mut stats = Stats::new();
+
+        process_event(&processor(), &processing_cfg, &allowlists, &load_sendr, &mut stats, event("user admin\npw: 1234\n"));
+        process_event(&processor(), &ProcessingCfg::default(), &allowlists, &load_sendr, &mut stats, event("pw: 1234"));
+
+        assert_eq!(load_recvr.try_recv().unwrap().matches()[0].contexts(), &[Some("admin\npw: 1234\n".to_owned())]);
+        let default_contexts = load_recvr.try_recv().unwrap().matches()[0].contexts().to_vec();
+        assert_eq!(default_contexts, vec![Some("pw: 1234".to_owned())]);
+    }
+
+    #[test]
+    fn matches_below_the_confidence_threshold_are_not_stored() {
+        let p = Processor::with_rule_str(r#"
+            rule Guess { meta: confidence = 40 strings: $a = "pw" condition: $a }
+            rule Sure { meta: confidence = "90" strings: $a = "hunter2" condition: $a }
+            rule Unrated { strings: $a = "admin" condition: $a }
+        "#).unwrap();
+        let processing_cfg = Config::from_reader("processing:\n  min_confidence_threshold: 50".as_bytes())
+            .unwrap()
+            .processing()
+            .clone();
+        let allowlists = Allowlists::from_cfg(&processing_cfg).unwrap();
+        let (load_sendr, load_recvr) = crossbeam_channel::unbounded();
+        let mut stats = Stats::new();
+
+        process_event(&p, &processing_cfg, &allowlists, &load_sendr, &mut stats, event("admin pw: hunter2"));
+        process_event(&p, &processing_cfg, &allowlists, &load_sendr, &mut stats, event("pw: 1234"));
+
+        let processed = load_recvr.try_recv().unwrap();
+        let rule_names: Vec<&str> = processed.matches().iter().map(FlatMatch::rule_name).collect();
+        assert_eq!(rule_names, vec!["default::Sure", "default::Unrated"]);
+        assert_eq!(processed.matches()[0].confidence(), 90);
+        assert!(load_recvr.try_recv().is_err());
+        assert_eq!(stats.num_matches(), 1);
+    }
+
+    #[test]
+    fn matches_carry_the_meta_fields_of_their_rule() {
+        let p = Processor::with_rule_str(r#"
+            rule Tested { meta: author = "tester" reviewed = true strings: $a = "pw" condition: $a }
+        "#).unwrap();
+        let (load_sendr, load_recvr) = crossbeam_channel::unbounded();
+        let processing_cfg = ProcessingCfg::default();
+        let allowlists = Allowlists::from_cfg(&processing_cfg).unwrap();
+
+        process_event(&p, &processing_cfg, &allowlists, &load_sendr, &mut Stats::new(), event("pw: hunter2"));
+
+        let processed = load_recvr.try_recv().unwrap();
+        let meta = processed.matches()[0].meta();
+        assert_eq!(meta["author"], "tester");
+        assert_eq!(meta["reviewed"], "true");
+        assert_eq!(meta.len(), 2);
+    }
+
+    #[test]
+    fn processed_events_carry_their_categories() {
+        let (feed_sendr, feed_recvr) = crossbeam_channel::unbounded();
+        let (_cmd_sendr, cmd_recvr) = crossbeam_channel::unbounded();
+        let (load_sendr, load_recvr) = crossbeam_channel::unbounded();
+        let processing_cfg = Config::from_reader("processing:\n  tag_category_map:\n    credentials: HIGH_RISK".as_bytes())
+            .unwrap()
+            .processing()
+            .clone();
+        let rule_dir = std::env::temp_dir().join(format!("infobserve-categories-{}", std::process::id()));
+        std::fs::create_dir_all(&rule_dir).unwrap();
+        std::fs::write(
+            rule_dir.join("password.yar"),
+            r#"rule Password : credentials { strings: $a = "password:" condition: $a }"#
+        ).unwrap();
+
+        let handle = process_forever(
+            &feed_recvr,
+            &cmd_recvr,
+            &load_sendr,
+            &Arc::new(vec![rule_dir.to_string_lossy().into_owned()]),
+            &Arc::new(GlobFilter::default()),
+            &Arc::new(processing_cfg),
+            None,
+            &Arc::default()
+        );
+        feed_sendr.send(event("password: hunter2")).unwrap();
+        drop(feed_sendr);
+        handle.join().unwrap().unwrap();
+
+        let processed = load_recvr.try_recv().unwrap();
+        assert!(!processed.1.is_empty());
+        assert_eq!(processed.categories(), ["HIGH_RISK"]);
+    }
+
+    #[test]
+    fn indicators_are_extracted_when_enabled() {
+        let processing_cfg = Config::from_reader("processing:\n  extract_indicators: true".as_bytes())
+            .unwrap()
+            .processing()
+            .clone();
+        let allowlists = Allowlists::from_cfg(&processing_cfg).unwrap();
+        let (load_sendr, load_recvr) = crossbeam_channel::unbounded();
+        let mut stats = Stats::new();
+        let content = "pw: hunter2 for https://admin.example.com/login from 10.0.0.1";
+
+        process_event(&processor(), &processing_cfg, &allowlists, &load_sendr, &mut stats, event(content));
+        process_event(&processor(), &ProcessingCfg::default(), &allowlists, &load_sendr, &mut stats, event(content));
+
+        let indicators = load_recvr.try_recv().unwrap().indicators().cloned().unwrap();
+        assert_eq!(indicators.ips, vec!["10.0.0.1"]);
+        assert_eq!(indicators.domains, vec!["admin.example.com"]);
+        assert_eq!(indicators.urls, vec!["https://admin.example.com/login"]);
+        assert!(load_recvr.try_recv().unwrap().indicators().is_none());
+    }
+
+    #[test]
+    fn rules_can_import_the_hash_module() {
+        let p = Processor::with_rule_str(r#"
+            import "hash"
+            rule Md5 { condition: hash.md5(0, filesize) == "acbd18db4cc2f85cedef654fccc4a4d8" }
+        "#).unwrap();
+
+        assert_eq!(p.process("foo").unwrap()[0].rule_name(), "default::Md5");
+        assert!(check_modules(&["pe".to_owned(), "hash".to_owned()]).is_ok());
+    }
+
+    #[test]
+    #[cfg(not(feature = "yara-cuckoo"))]
+    fn rules_importing_missing_modules_are_rejected() {
+        let err = Processor::with_rule_str(r#"import "cuckoo" rule Sandboxed { condition: true }"#).err().unwrap();
+
+        assert!(matches!(
+            err.downcast_ref::<ConfigurationError>(),
+            Some(ConfigurationError::ModuleNotAvailable(m)) if m == "cuckoo"
+        ));
+    }
+
+    #[test]
+    #[cfg(not(feature = "yara-magic"))]
+    fn configured_modules_must_be_compiled_in() {
+        let err = check_modules(&["hash".to_owned(), "magic".to_owned()]).unwrap_err();
+
+        assert!(matches!(err, ConfigurationError::ModuleNotAvailable(m) if m == "magic"));
+    }
+
+    #[test]
+    #[cfg(feature = "yara-magic")]
+    fn magic_module_is_compiled_in() {
+        assert!(check_modules(&["magic".to_owned()]).is_ok());
+    }
+
+    #[test]
+    fn processor_loads_rules_from_dir() {
+        assert!(Processor::from_dir("yara-rules").is_ok());
+    }
+
+    /// Creates a fresh rule directory per entry of `rules` (as in `(file name, rule name)`)
+    fn rule_dirs(test: &str, rules: &[(&str, &str)]) -> Vec<String> {
+        rules.iter().enumerate().map(|(i, (filename, rule_name))| {
+            let dir = std::env::temp_dir().join(format!("infobserve-{}-{}-{}", test, std::process::id(), i));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(
+                dir.join(filename),
+                format!("rule {} {{ strings: $a = \"{}\" condition: $a }}", rule_name, rule_name)
+            ).unwrap();
+
+            dir.to_string_lossy().into_owned()
+        }).collect()
+    }
+
+    #[test]
+    fn processor_loads_rules_from_multiple_dirs() {
+        let dirs = rule_dirs("multi", &[("internal.yar", "Internal"), ("community.yar", "Community")]);
+        let p = Processor::from_dir_strings(&dirs, &GlobFilter::default()).unwrap();
+
+        let matches = p.process("Internal and Community").unwrap();
+        let mut rule_names: Vec<String> = matches.iter().map(|m| m.rule_name().to_owned()).collect();
+        rule_names.sort();
+        assert_eq!(rule_names, ["default::Community", "default::Internal"]);
+    }
+
+    #[test]
+    fn processor_loads_overlapping_dirs_once() {
+        let dirs = rule_dirs("overlap", &[("internal.yar", "Internal")]);
+        // Compiling the same rule twice would fail with a duplicate identifier
+        let p = Processor::from_dirs(&[&dirs[0], &format!("{}/", dirs[0])], &GlobFilter::default()).unwrap();
+
+        assert_eq!(p.process("Internal").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn processor_refuses_rule_files_with_the_same_name() {
+        let dirs = rule_dirs("dup", &[("secrets.yar", "A"), ("secrets.yar", "B")]);
+        let err = Processor::from_dir_strings(&dirs, &GlobFilter::default()).err().unwrap();
+
+        assert!(matches!(
+            err.downcast_ref::<ConfigurationError>(),
+            Some(ConfigurationError::DuplicateRuleFile(name)) if name == "secrets.yar"
+        ));
+    }
+
+    #[test]
+    fn processor_refuses_dirs_without_rules() {
+        let err = Processor::from_dir("src").err().unwrap();
+        assert!(matches!(err.downcast_ref::<ConfigurationError>(), Some(ConfigurationError::NoYaraRulesError(_))));
+    }
+
+    #[test]
+    fn processor_accepts_dirs_without_rules_next_to_ones_with_rules() {
+        let dirs = rule_dirs("partly-empty", &[("internal.yar", "Internal")]);
+        let p = Processor::from_dirs(&["src", &dirs[0]], &GlobFilter::default()).unwrap();
+
+        assert_eq!(p.process("Internal").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn processor_skips_excluded_rule_files() {
+        let dirs = rule_dirs("excluded", &[("internal.yar", "Internal"), ("stub.yar", "Stub")]);
+        let filter = GlobFilter::new(vec![], vec![glob::Pattern::new("stub.yar").unwrap()]);
+        let p = Processor::from_dir_strings(&dirs, &filter).unwrap();
+
+        assert_eq!(p.rule_names().unwrap(), ["Internal"]);
+    }
+
+    #[test]
+    fn invalid_rule_files_fail_the_validation() {
+        let dir = rule_dirs("validation", &[("valid.yar", "Valid")]).remove(0);
+        std::fs::write(Path::new(&dir).join("invalid.yar"), "rule Invalid { condition: $undefined }").unwrap();
+
+        assert!(Processor::from_dir(&dir).is_err());
+        assert!(validate_rules(&dir).is_err());
+
+        let filter = GlobFilter::new(vec![], vec![glob::Pattern::new("invalid.yar").unwrap()]);
+        assert_eq!(validate_rule_dirs(&[dir], &filter).unwrap(), ["Valid"]);
+    }
+
+    #[test]
+    fn missing_rule_dirs_fail_the_validation() {
+        let mut dirs = rule_dirs("validation-missing", &[("valid.yar", "Valid")]);
+        dirs.push("/nonexistent/".to_owned());
+
+        assert!(matches!(
+            validate_rule_dirs(&dirs, &GlobFilter::default()).unwrap_err().downcast_ref::<ConfigurationError>(),
+            Some(ConfigurationError::NoYaraRulesError(dir)) if dir == "/nonexistent/"
+        ));
+    }
+
+    #[test]
+    fn processor_refuses_filters_that_exclude_every_rule() {
+        let dirs = rule_dirs("all-excluded", &[("internal.yar", "Internal")]);
+        let filter = GlobFilter::new(vec![glob::Pattern::new("passwords/*.yar").unwrap()], vec![]);
+        let err = Processor::from_dir_strings(&dirs, &filter).err().unwrap();
+
+        assert!(matches!(err.downcast_ref::<ConfigurationError>(), Some(ConfigurationError::NoYaraRulesError(_))));
+    }
+
+    #[test]
+    fn processor_fails_fast_for_missing_rule_dir() {
+        let err = Processor::from_dir("non-existent-dir").err().unwrap();
+        assert!(err.to_string().contains("non-existent-dir"));
+    }
+
     #[test]
     fn process_does_not_blow_up() {
         let p = processor();
@@ -361,10 +1847,492 @@ mod tests {
     fn process_returns_correct_data() {
         let p = processor();
         let matches = p.process(&"pw: helloworld").unwrap();
+        assert_eq!(matches, vec![FlatMatch::new("default::MyPass".to_owned(), vec![], &[b"pw: helloworld".to_vec()])]);
+    }
+
+    #[test]
+    fn process_batch_returns_matches_in_order() {
+        let p = processor();
+        let matches = p.process_batch(&["foo", "pw: helloworld", "bar"]).unwrap();
+        assert_eq!(matches.len(), 3);
+        assert!(matches[0].is_empty());
+        assert_eq!(matches[1], vec![FlatMatch::new("default::MyPass".to_owned(), vec![], &[b"pw: helloworld".to_vec()])]);
+        assert!(matches[2].is_empty());
+    }
+
+    #[test]
+    fn process_batch_handles_empty_batch() {
+        let p = processor();
+        assert!(p.process_batch(&[]).unwrap().is_empty());
+        assert!(p.process_batch_parallel(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn batch_processing_agrees_with_serial_processing() {
+        let p = processor();
+        let contents: Vec<String> = (0..100)
+            .map(|i| if i % 3 == 0 { format!("pw: secret{}", i) } else { format!("nothing to see in #{}", i) })
+            .collect();
+        let contents: Vec<&str> = contents.iter().map(String::as_str).collect();
+
+        let serial: Vec<Vec<FlatMatch>> = contents.iter().map(|c| p.process(c).unwrap()).collect();
+        let batch = p.process_batch(&contents).unwrap();
+        let parallel = p.process_batch_parallel(&contents).unwrap();
+
+        let data = |matches: &Vec<Vec<FlatMatch>>| -> Vec<Vec<Vec<String>>> {
+            matches.iter().map(|m| m.iter().map(|fm| fm.data().clone()).collect()).collect()
+        };
+        assert_eq!(data(&batch), data(&serial));
+        assert_eq!(data(&parallel), data(&serial));
+        assert_eq!(serial.iter().filter(|m| !m.is_empty()).count(), 34);
+    }
+
+    #[test]
+    fn rule_matches_windows_formatted_content_only_after_normalization() {
+        let p = Processor::with_rule_str(r#"
+        rule UnixPass
+        {
+            strings:
+                $a = /password: [a-z]+\n/
+
+            condition:
+                $a
+        }
+        "#).unwrap();
+        let mut e = Event::new(
+            "https://pastebin.com/foo", 28, "pastebin", "user: foo\r\npassword:\t\tbar  \r\n", "foo.txt", "bar",
+            chrono::Local::now(), chrono::Local::now()
+        );
+
+        assert!(p.process(e.raw_content()).unwrap().is_empty());
+
+        e.normalize_content();
+        let matches = p.process(e.raw_content()).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(*matches[0].data()[0], String::from("password: bar\n"));
+    }
+
+    #[test]
+    fn matches_keep_their_offset_within_the_content() {
+        let content = "user: admin\npw: hunter2";
+
+        let matches = processor().process(content).unwrap();
+
+        assert_eq!(matches[0].offsets(), &[12]);
+        assert_eq!(matches[0].lengths(), &[11]);
+        assert_eq!(&content[12..], matches[0].data()[0]);
+    }
+
+    #[test]
+    fn binary_matches_keep_their_raw_bytes() {
+        // Matches the colon along with the first byte of the two-byte 'κ', which on its own is not valid UTF-8
+        let p = Processor::with_rule_str("rule Binary { strings: $b = { 3A CE } condition: $b }").unwrap();
+
+        let matches = p.process("pw:κ").unwrap();
+
         assert_eq!(matches.len(), 1);
-        assert_eq!(matches[0].rule_name(), String::from("default::MyPass"));
-        assert_eq!(matches[0].tags().len(), 0);
-        assert_eq!(*matches[0].data()[0], String::from("pw: helloworld"));
+        assert!(matches[0].data().is_empty());
+        assert_eq!(matches[0].raw_data(), &[vec![0x3A, 0xCE]]);
+        assert_eq!(matches[0].raw_offsets(), &[2]);
+    }
+
+    #[test]
+    fn process_scans_content_under_the_memory_limit() {
+        let p = processor().with_memory_limit(1024 * 1024);
+        let content = format!("pw: {}", "a".repeat(1024));
+
+        assert_eq!(p.process(&content).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn process_scans_content_at_the_memory_limit() {
+        let p = processor().with_memory_limit(1024 * 1024);
+        let content = format!("pw: {}", "a".repeat(1024 * 1024 - 4));
+
+        assert_eq!(p.process(&content).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn process_refuses_content_over_the_memory_limit() {
+        let p = processor().with_memory_limit(1024 * 1024);
+        let content = format!("pw: {}", "a".repeat(1024 * 1024 - 3));
+
+        match p.process(&content) {
+            Err(ProcessingError::ContentExceedsMemoryLimit { size, limit }) => {
+                assert_eq!(size, 1024 * 1024 + 1);
+                assert_eq!(limit, 1024 * 1024);
+            },
+            other => panic!("Expected the memory limit to be exceeded, got {:?}", other)
+        }
+    }
+
+    fn pastebin_rule() -> String {
+        String::from(r#"
+        rule FromPastebin
+        {
+            condition:
+                external_source == "pastebin"
+        }
+        "#)
+    }
+
+    #[test]
+    fn rules_can_reference_external_variables() {
+        let p = Processor::with_rule_str(&pastebin_rule()).unwrap();
+
+        assert_eq!(p.process_with_externals("foo", &[("external_source", "pastebin")]).unwrap().len(), 1);
+        assert!(p.process_with_externals("foo", &[("external_source", "github")]).unwrap().is_empty());
+        assert!(p.process("foo").unwrap().is_empty());
+    }
+
+    #[test]
+    fn external_variables_are_set_from_the_event() {
+        let p = Processor::with_rule_str(&pastebin_rule()).unwrap();
+        let (load_sendr, load_recvr) = crossbeam_channel::unbounded();
+        let processing_cfg = ProcessingCfg::default();
+        let allowlists = Allowlists::from_cfg(&processing_cfg).unwrap();
+        let mut stats = Stats::new();
+        let gist = Event::new("https://gist.github.com/foo", 3, "gist", "foo", "foo.txt", "bar",
+                              chrono::Local::now(), chrono::Local::now());
+
+        process_event(&p, &processing_cfg, &allowlists, &load_sendr, &mut stats, event("foo"));
+        process_event(&p, &processing_cfg, &allowlists, &load_sendr, &mut stats, gist);
+
+        assert_eq!(stats.num_matches(), 1);
+        assert_eq!(load_recvr.try_recv().unwrap().0.source(), "pastebin");
+        assert!(load_recvr.try_recv().is_err());
+    }
+
+    #[test]
+    fn externals_cover_every_declared_variable() {
+        let e = event("foo");
+        let externals = externals_of(&e);
+
+        assert_eq!(externals, [
+            ("external_source", "pastebin"),
+            ("external_url", "https://pastebin.com/foo"),
+            ("external_creator", "bar")
+        ]);
+    }
+
+    /// A rule whose condition takes far longer than a second to evaluate on any content
+    fn slow_rule() -> String {
+        String::from(r#"
+        rule Slow
+        {
+            condition:
+                for all i in (0..1000000000) : (for all j in (0..1000000000) : (uint8(i % filesize) != 256 + j))
+        }
+        "#)
+    }
+
+    #[test]
+    fn process_gives_up_after_the_scan_timeout() {
+        let p = Processor::with_rule_str(&slow_rule()).unwrap().with_scan_timeout(1);
+
+        match p.process("foo") {
+            Err(ProcessingError::Yara(YaraError { kind: YaraErrorKind::ScanTimeout })) => {},
+            other => panic!("Expected the scan to time out, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn stats_count_timeouts_apart_from_failures() {
+        let p = Processor::with_rule_str(&slow_rule()).unwrap().with_scan_timeout(1);
+        let (load_sendr, _load_recvr) = crossbeam_channel::unbounded();
+        let processing_cfg = ProcessingCfg::default();
+        let allowlists = Allowlists::from_cfg(&processing_cfg).unwrap();
+        let mut stats = Stats::new();
+
+        process_event(&p, &processing_cfg, &allowlists, &load_sendr, &mut stats, event("foo"));
+
+        assert_eq!(stats.num_timeouts(), 1);
+        assert_eq!(stats.num_failures(), 0);
+        assert_eq!(stats.num_events(), 1);
+    }
+
+    #[test]
+    fn stats_count_memory_limit_violations() {
+        let mut s = Stats::new();
+        s.inc_memory_limit_exceeded();
+        assert_eq!(s.num_memory_limit_exceeded(), 1);
+    }
+
+    /// Processes `content` with a 16 byte scan memory limit
+    fn process_oversized(content: &str, truncate: bool) -> (Stats, Vec<ProcessedEvent>) {
+        let p = processor().with_memory_limit(16);
+        let yml = format!("processing:\n    max_scan_memory_mb: 16B\n    truncate_oversized_events: {}", truncate);
+        let processing_cfg = Config::from_yaml_string(&yml).unwrap().processing().clone();
+        let allowlists = Allowlists::from_cfg(&processing_cfg).unwrap();
+        let (load_sendr, load_recvr) = crossbeam_channel::unbounded();
+        let mut stats = Stats::new();
+
+        process_event(&p, &processing_cfg, &allowlists, &load_sendr, &mut stats, event(content));
+        drop(load_sendr);
+
+        (stats, load_recvr.iter().collect())
+    }
+
+    #[test]
+    fn oversized_events_are_skipped() {
+        let (stats, processed) = process_oversized("pw: hunter2 and then some more", false);
+
+        assert_eq!(stats.num_memory_limit_exceeded(), 1);
+        assert_eq!(stats.num_truncated(), 0);
+        assert!(processed.is_empty());
+    }
+
+    #[test]
+    fn oversized_events_are_truncated_when_configured() {
+        let (stats, processed) = process_oversized("pw: hunter2 and then some more", true);
+
+        assert_eq!(stats.num_truncated(), 1);
+        assert_eq!(stats.num_memory_limit_exceeded(), 0);
+        assert_eq!(processed.len(), 1);
+        assert_eq!(processed[0].0.raw_content(), "pw: hunter2 and ");
+        assert!(processed[0].1[0].rule_name().ends_with("MyPass"));
+    }
+
+    #[test]
+    fn events_within_the_limit_are_not_truncated() {
+        let (stats, processed) = process_oversized("pw: hunter2", true);
+
+        assert_eq!(stats.num_truncated(), 0);
+        assert_eq!(processed[0].0.raw_content(), "pw: hunter2");
+    }
+
+    fn user_rule() -> String {
+        String::from(r#"
+        rule MyUser
+        {
+            strings:
+                $a = /user:.+/
+
+            condition:
+                $a
+        }
+        "#)
+    }
+
+    fn rule_names_matching(p: &Processor, content: &str) -> Vec<String> {
+        p.process(content).unwrap().iter().map(|m| m.rule_name().to_owned()).collect()
+    }
+
+    #[test]
+    fn rules_are_added_without_losing_the_loaded_ones() {
+        let mut p = processor();
+        let content = "user: admin\npw: hunter2\ntoken: abc";
+
+        p.add_rules_bytes(user_rule().as_bytes()).unwrap();
+        assert_eq!(rule_names_matching(&p, content), vec!["default::MyPass", "default::MyUser"]);
+
+        p.add_rules_bytes(br#"rule MyToken { strings: $a = /token:.+/ condition: $a }"#).unwrap();
+        assert_eq!(rule_names_matching(&p, content), vec!["default::MyPass", "default::MyUser", "default::MyToken"]);
+        assert_eq!(p.rule_sources().len(), 3);
+        assert_eq!(p.rule_sources()[1], user_rule());
+    }
+
+    #[test]
+    fn rules_that_do_not_compile_are_not_added() {
+        let mut p = processor();
+
+        assert!(p.add_rules_bytes(password_rule().as_bytes()).is_err());
+        assert!(p.add_rules_bytes(b"Bad Rule").is_err());
+        assert_eq!(p.rule_sources(), [password_rule()]);
+        assert_eq!(rule_names_matching(&p, "pw: hunter2"), vec!["default::MyPass"]);
+    }
+
+    #[test]
+    fn rules_must_be_utf8() {
+        let err = processor().add_rules_bytes(&[0x72, 0x75, 0xff, 0xfe]).unwrap_err();
+
+        assert!(matches!(err.downcast_ref::<ProcessingError>(), Some(ProcessingError::InvalidRuleEncoding(_))));
+    }
+
+    #[test]
+    fn rule_files_are_kept_as_sources() {
+        let dirs = rule_dirs("rule_files_are_kept_as_sources", &[("leaked.yar", "Leaked")]);
+        let mut p = Processor::from_dir_strings(&dirs, &GlobFilter::default()).unwrap();
+
+        p.add_rules_bytes(user_rule().as_bytes()).unwrap();
+
+        assert_eq!(p.rule_sources(), [r#"rule Leaked { strings: $a = "Leaked" condition: $a }"#.to_owned(), user_rule()]);
+        assert_eq!(rule_names_matching(&p, "Leaked user: admin"), vec!["default::Leaked", "default::MyUser"]);
+    }
+
+    #[test]
+    fn rule_files_include_files_relative_to_them() {
+        let dirs = rule_dirs("rule_files_include_files_relative_to_them", &[("leaked.inc", "Leaked")]);
+        let dir = Path::new(&dirs[0]);
+        std::fs::write(dir.join("main.yar"), "include \"leaked.inc\"").unwrap();
+        let mut p = Processor::with_rule_files(vec![dir.join("main.yar")]).unwrap();
+
+        p.add_rules_bytes(user_rule().as_bytes()).unwrap();
+
+        assert_eq!(rule_names_matching(&p, "Leaked user: admin"), vec!["default::Leaked", "default::MyUser"]);
+    }
+
+    #[test]
+    fn stats_count_hits_per_rule() {
+        let p = Processor::with_rules(vec![password_rule(), user_rule()]).unwrap();
+        let mut s = Stats::new();
+
+        for content in ["pw: foo", "user: bar\npw: baz", "pw: qux", "nothing"] {
+            for fm in p.process(content).unwrap() {
+                s.record_match(fm.rule_name());
+            }
+        }
+
+        assert_eq!(s.rule_hit_counts().len(), 2);
+        assert_eq!(s.rule_hit_counts()["default::MyPass"], 3);
+        assert_eq!(s.rule_hit_counts()["default::MyUser"], 1);
+        assert_eq!(s.top_rules(1), vec![("default::MyPass", 3)]);
+    }
+
+    #[test]
+    fn top_rules_are_ordered_by_hits_then_name() {
+        let mut s = Stats::new();
+        for rule_name in ["b", "a", "c", "c"] {
+            s.record_match(rule_name);
+        }
+
+        assert_eq!(s.top_rules(5), vec![("c", 2), ("a", 1), ("b", 1)]);
+        assert!(s.top_rules(0).is_empty());
+    }
+
+    #[test]
+    fn merging_stats_sums_rule_hits() {
+        let mut a = stats_with(100, 1);
+        a.record_match("foo");
+        a.record_match("bar");
+        let mut b = stats_with(300, 2);
+        b.record_match("foo");
+
+        let merged = Stats::merge_all(&[a, b]);
+
+        assert_eq!(merged.num_events(), 3);
+        assert_eq!(merged.overall_proc_time().as_millis(), 400);
+        assert_eq!(merged.rule_hit_counts()["foo"], 2);
+        assert_eq!(merged.rule_hit_counts()["bar"], 1);
+    }
+
+    #[test]
+    fn merging_with_zeroed_stats_changes_nothing() {
+        let mut a = stats_with(100, 2);
+        a.inc_matches();
+        a.inc_failures();
+
+        let merged = a.merge(Stats::new());
+
+        assert_eq!(merged.num_events(), 2);
+        assert_eq!(merged.num_matches(), 1);
+        assert_eq!(merged.num_failures(), 1);
+        assert_eq!(merged.overall_proc_time().as_millis(), 100);
+        assert_eq!(merged.avg_proc_time().as_millis(), 50);
+    }
+
+    #[test]
+    fn merging_two_threads_recalculates_the_average() {
+        let mut a = stats_with(100, 1);
+        a.inc_matches();
+        let mut b = stats_with(500, 3);
+        b.inc_matches();
+        b.inc_matches();
+        b.inc_failures();
+
+        let merged = a + b;
+
+        assert_eq!(merged.num_events(), 4);
+        assert_eq!(merged.num_matches(), 3);
+        assert_eq!(merged.num_failures(), 1);
+        assert_eq!(merged.overall_proc_time().as_millis(), 600);
+        assert_eq!(merged.avg_proc_time().as_millis(), 150);
+        assert!(format!("{}", merged).contains("Events processed: 4"));
+    }
+
+    #[test]
+    fn merge_sums_the_hits_of_overlapping_rules() {
+        let mut a = Stats::new();
+        a.record_match("default::MyPass");
+        a.record_match("default::MyUser");
+        let mut b = Stats::new();
+        b.record_match("default::MyPass");
+        b.record_match("default::MyPass");
+
+        let merged = a.merge(b);
+
+        assert_eq!(merged.rule_hit_counts().len(), 2);
+        assert_eq!(merged.rule_hit_counts()["default::MyPass"], 3);
+        assert_eq!(merged.rule_hit_counts()["default::MyUser"], 1);
+        assert_eq!(merged.top_rules(1), vec![("default::MyPass", 3)]);
+    }
+
+    /// Stats of events that took 1ms, 2ms, ..., `n`ms, added out of order
+    fn stats_of_millis(n: u64, reservoir_sample_size: Option<usize>) -> Stats {
+        let mut s = Stats::new().with_reservoir_sample_size(reservoir_sample_size);
+        for ms in (1..=n).rev() {
+            s.add_duration(time::Duration::from_millis(ms));
+        }
+
+        s
+    }
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let s = stats_of_millis(20, None);
+
+        assert_eq!(s.percentile(50.0), time::Duration::from_millis(10));
+        assert_eq!(s.percentile(95.0), time::Duration::from_millis(19));
+        assert_eq!(s.percentile(96.0), time::Duration::from_millis(20));
+        assert_eq!(s.percentile(100.0), time::Duration::from_millis(20));
+        assert_eq!(s.percentile(0.0), time::Duration::from_millis(1));
+        assert_eq!(s.percentile(250.0), time::Duration::from_millis(20));
+    }
+
+    #[test]
+    fn percentiles_of_no_events_are_zero() {
+        assert_eq!(Stats::new().percentile(95.0), time::Duration::from_secs(0));
+        assert_eq!(Stats::new().max_proc_time(), time::Duration::from_secs(0));
+    }
+
+    #[test]
+    fn min_and_max_are_exact_when_sampling() {
+        let s = stats_of_millis(1000, Some(10));
+
+        assert_eq!(s.latency_samples.len(), 10);
+        assert_eq!(s.min_proc_time(), time::Duration::from_millis(1));
+        assert_eq!(s.max_proc_time(), time::Duration::from_millis(1000));
+        assert!(s.percentile(95.0) <= time::Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn merge_keeps_the_fastest_and_slowest_events() {
+        let merged = stats_of_millis(3, None).merge(stats_of_millis(5, None)).merge(Stats::new());
+
+        assert_eq!(merged.min_proc_time(), time::Duration::from_millis(1));
+        assert_eq!(merged.max_proc_time(), time::Duration::from_millis(5));
+        assert_eq!(merged.percentile(50.0), time::Duration::from_millis(2));
+    }
+
+    #[test]
+    fn influx_lines_are_rendered_per_rule() {
+        let mut s = Stats::new();
+        s.record_match("default::MyPass");
+        s.record_match("default::My Pass,v2");
+        s.record_match("default::MyPass");
+
+        assert_eq!(s.to_influx_line_per_rule(), vec![
+            "processor_rule_hits,rule=default::My\\ Pass\\,v2 count=1i",
+            "processor_rule_hits,rule=default::MyPass count=2i"
+        ]);
+    }
+
+    #[test]
+    fn stats_count_deduped_matches() {
+        let mut s = Stats::new();
+        s.add_deduped_matches(2);
+        s.add_deduped_matches(1);
+        assert_eq!(s.num_deduped_matches(), 3);
     }
 
     #[test]
@@ -405,6 +2373,100 @@ mod tests {
         assert_eq!(s.overall_proc_time, time::Duration::from_millis(1010));
     }
 
+    fn stats_with(overall_ms: u64, num_events: u32) -> Stats {
+        let mut s = Stats::new();
+        s.add_duration(time::Duration::from_millis(overall_ms));
+        for _ in 0..num_events {
+            s.inc_events();
+        }
+
+        s
+    }
+
+    fn stats_file(test: &str) -> String {
+        let path = std::env::temp_dir().join(format!("infobserve-stats-{}-{}.jsonl", test, std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        path.to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn stats_are_appended_to_the_history() {
+        let path = stats_file("append");
+        let mut first = stats_with(1000, 2);
+        first.inc_matches();
+        first.record_match("default::MyPass");
+
+        first.serialize_to_file(&path).unwrap();
+        stats_with(3000, 3).serialize_to_file(&path).unwrap();
+        let history = Stats::load_history(&path).unwrap();
+
+        assert_eq!(history.len(), 2);
+        assert_ne!(history[0].run_id(), history[1].run_id());
+        assert!(history[0].timestamp() <= history[1].timestamp());
+        assert_eq!(history[0].stats().num_events(), 2);
+        assert_eq!(history[0].stats().num_matches(), 1);
+        assert_eq!(history[0].stats().rule_hit_counts()["default::MyPass"], 1);
+        assert_eq!(history[1].stats().overall_proc_time(), time::Duration::from_millis(3000));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn invalid_history_lines_are_reported() {
+        let path = stats_file("invalid");
+        stats_with(1000, 2).serialize_to_file(&path).unwrap();
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(b"\nnot json\n").unwrap();
+
+        let err = Stats::load_history(&path).err().unwrap();
+
+        assert!(format!("{}", err).starts_with("Invalid stats on line 3"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn stats_are_compared_with_the_baseline() {
+        let baseline = stats_with(1000, 10);
+        let mut current = stats_with(1800, 12);
+        current.inc_matches();
+
+        let diff = current.compare_with_baseline(&baseline);
+
+        assert_eq!(diff.events_delta(), 2);
+        assert_eq!(diff.matches_delta(), 1);
+        assert!((diff.avg_time_delta_pct() - 50.0).abs() < 1e-9);
+        assert_eq!(format!("{}", diff), "Events: +2, matches: +1, average processing time: +50.0%");
+        assert_eq!(current.compare_with_baseline(&Stats::new()).avg_time_delta_pct(), 0.0);
+    }
+
+    #[test]
+    fn slower_stats_compare_greater() {
+        let slow = stats_with(1000, 2);
+        let fast = stats_with(1000, 10);
+
+        assert!(slow > fast);
+        assert!(fast.is_faster_than(&slow));
+        assert!(!slow.is_faster_than(&fast));
+        assert!(stats_with(500, 1) == slow);
+    }
+
+    #[test]
+    fn sorting_stats_puts_the_slowest_first() {
+        let mut all = [stats_with(100, 1), stats_with(300, 1), stats_with(200, 1)];
+        all.sort_by(|a, b| b.cmp(a));
+
+        let avgs: Vec<u128> = all.iter().map(|s| s.avg_proc_time().as_millis()).collect();
+        assert_eq!(avgs, vec![300, 200, 100]);
+    }
+
+    #[test]
+    fn stats_rank_counts_slower_threads() {
+        let all = [stats_with(100, 1), stats_with(300, 1), stats_with(200, 1)];
+
+        assert_eq!(all[1].rank(&all), 0);
+        assert_eq!(all[2].rank(&all), 1);
+        assert_eq!(all[0].rank(&all), 2);
+    }
+
     #[test]
     fn stats_calculates_avg_correctly() {
         let mut s = Stats::new();
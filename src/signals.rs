@@ -0,0 +1,75 @@
+//! Turns `SIGTERM` and `SIGINT` (e.g. `Ctrl-C`) into a graceful shutdown
+//!
+//! The worker threads are connected by two kinds of channels:
+//! * The **event channels** (feeder → processor → loader) carry the actual work. Each stage stops once the
+//!   senders of its input channel are dropped and it has drained that channel, so dropping the feed sender
+//!   makes every later stage finish the events in flight and exit, in order
+//! * The **shutdown channel** carries a single message, sent when the first signal arrives. Its sender is
+//!   dropped right after, disconnecting the channel, so that every thread watching it wakes up (a message is
+//!   only received once). The feeders watch it between pops and stop, which starts the cascade above
+//!
+//! A second signal exits right away, without draining the events in flight
+use log::{info, warn};
+use std::process;
+use std::thread;
+
+use anyhow::Result;
+use crossbeam_channel::{Receiver, Sender};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+/// Installs the `SIGTERM` and `SIGINT` handlers
+///
+/// # Returns
+/// The receiving end of the shutdown channel, which receives a message (and is then disconnected) when the
+/// first signal arrives
+pub fn shutdown_channel() -> Result<Receiver<()>> {
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+    let (sendr, recvr) = crossbeam_channel::bounded(1);
+
+    thread::Builder::new()
+        .name("signals".to_owned())
+        .spawn(move || watch(signals.forever(), sendr))?;
+
+    Ok(recvr)
+}
+
+/// Requests a shutdown through `sendr` on the first of `signals`, and exits the process on the second one
+fn watch<I: Iterator<Item = i32>>(mut signals: I, sendr: Sender<()>) {
+    if let Some(signal) = signals.next() {
+        info!("Received {}, shutting down gracefully. Send it again to exit immediately", signal_name(signal));
+        // Nobody may be listening any more (e.g. while the threads are already being joined)
+        let _ = sendr.try_send(());
+        drop(sendr);
+    }
+
+    if let Some(signal) = signals.next() {
+        warn!("Received {} again, exiting without draining the events in flight", signal_name(signal));
+        process::exit(128 + signal);
+    }
+}
+
+fn signal_name(signal: i32) -> &'static str {
+    match signal {
+        SIGTERM => "SIGTERM",
+        SIGINT => "SIGINT",
+        _ => "signal"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam_channel::RecvError;
+
+    #[test]
+    fn the_first_signal_is_sent_and_disconnects_the_channel() {
+        let (sendr, recvr) = crossbeam_channel::bounded(1);
+        let other = Receiver::clone(&recvr);
+
+        watch(vec![SIGTERM].into_iter(), sendr);
+
+        assert_eq!(recvr.recv(), Ok(()));
+        assert_eq!(other.recv(), Err(RecvError));
+    }
+}
@@ -1,11 +1,20 @@
 //! Contains varius utility/helper functions
 
-use std::cmp;
+use std::{cmp, io};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
+use glob::{MatchOptions, Pattern};
+use lazy_static::lazy_static;
+use anyhow::{Context, Result};
+use log::warn;
 use walkdir::WalkDir;
 
+use crate::errors::ConfigurationError;
+
 /// Recursively finds and returns the relative path
 /// to all files that satisfy the `ext` extension filter
+/// Paths that are not valid UTF-8 are skipped with a warning (see `rec_get_files_by_path`)
 ///
 /// # Arguments
 ///
@@ -20,21 +29,85 @@ use walkdir::WalkDir;
 /// let rule_files: Vec<&str> = rec_get_files_by_ext("yara-rules", "yar");
 /// assert_eq!(rule_files, vec!["yara-rules/generic_password.yar"])
 /// ```
+#[allow(dead_code)]
 pub fn rec_get_files_by_ext(dir: &str, ext: &str) -> Vec<String> {
-    let mut discovered_files: Vec<String> = Vec::new();
-
-    for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
-        let entry_path = entry.path();
-        if let Some(file_ext) = entry_path.extension() {
-            if file_ext == ext {
-                if let Some(filepath) = entry_path.to_str() {
-                    discovered_files.push(String::from(filepath));
-                }
+    rec_get_files_by_path(dir, ext).into_iter()
+        .filter_map(|path| match path.into_os_string().into_string() {
+            Ok(p) => Some(p),
+            Err(p) => {
+                warn!("Skipping file with non UTF-8 path: {}", p.to_string_lossy());
+                None
             }
+        })
+        .collect()
+}
+
+/// Same as `rec_get_files_by_ext`, but returns the paths as they are, without dropping the ones
+/// that are not valid UTF-8
+/// Entries that cannot be read are skipped
+pub fn rec_get_files_by_path(dir: &str, ext: &str) -> Vec<PathBuf> {
+    WalkDir::new(dir).into_iter()
+        .filter_map(|e| e.ok())
+        .map(|e| e.into_path())
+        .filter(|path| path.extension().is_some_and(|file_ext| file_ext == ext))
+        .collect()
+}
+
+/// Same as `rec_get_files_by_path`, but fails if `dir` (or anything under it) cannot be read,
+/// instead of skipping it
+///
+/// # Errors
+///
+/// `std::io::Error` - e.g. when `dir` does not exist or its permissions do not allow reading it
+pub fn rec_get_files_by_ext_strict(dir: &str, ext: &str) -> Result<Vec<PathBuf>, io::Error> {
+    let mut discovered_files: Vec<PathBuf> = Vec::new();
+
+    for entry in WalkDir::new(dir) {
+        let entry_path = entry?.into_path();
+        if entry_path.extension().is_some_and(|file_ext| file_ext == ext) {
+            discovered_files.push(entry_path);
         }
     }
 
-    discovered_files
+    Ok(discovered_files)
+}
+
+/// Include and exclude glob patterns (e.g. `**/passwords/*.yar`) that select some of the files found under a
+/// directory (see `rec_get_files_by_globs`). `*` and `?` do not match path separators, while `**` matches any
+/// number of directories
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GlobFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>
+}
+
+impl GlobFilter {
+    /// An empty `include` keeps every file that is not excluded
+    pub fn new(include: Vec<Pattern>, exclude: Vec<Pattern>) -> Self {
+        Self { include, exclude }
+    }
+
+    /// Whether `path`, found under `root`, is included and not excluded. Patterns may match either the path
+    /// as found or its part under `root`
+    pub fn is_match(&self, path: &Path, root: &Path) -> bool {
+        let options = MatchOptions { require_literal_separator: true, ..MatchOptions::new() };
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        let any_match = |patterns: &[Pattern]| patterns.iter()
+            .any(|p| p.matches_path_with(path, options) || p.matches_path_with(relative, options));
+
+        (self.include.is_empty() || any_match(&self.include)) && !any_match(&self.exclude)
+    }
+}
+
+/// Same as `rec_get_files_by_ext_strict`, but only returns the files that `filter` keeps
+///
+/// # Errors
+///
+/// `std::io::Error` - e.g. when `dir` does not exist or its permissions do not allow reading it
+pub fn rec_get_files_by_globs(dir: &str, ext: &str, filter: &GlobFilter) -> Result<Vec<PathBuf>, io::Error> {
+    let root = Path::new(dir);
+
+    Ok(rec_get_files_by_ext_strict(dir, ext)?.into_iter().filter(|path| filter.is_match(path, root)).collect())
 }
 
 /// Clamps the given value over the given minimum value
@@ -55,6 +128,143 @@ pub fn clamp_min<T: cmp::Ord>(val: T, min: T) -> T {
     }
 }
 
+/// Parses a list of IDs, one per line. Blank lines and surrounding whitespace are ignored
+///
+/// # Example
+/// ```
+/// use utils::parse_id_list;
+///
+/// assert_eq!(parse_id_list("1\n 2\n\n3").unwrap(), vec![1, 2, 3]);
+/// ```
+pub fn parse_id_list(contents: &str) -> Result<Vec<i32>> {
+    contents.lines()
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(i, line)| line.parse::<i32>().with_context(|| format!("Invalid ID on line {}: {}", i + 1, line)))
+        .collect()
+}
+
+/// Size units understood by `parse_size_str`, from the largest to the smallest, along with their size in bytes
+const SIZE_UNITS: [(&str, usize); 5] = [
+    ("TB", 1 << 40),
+    ("GB", 1 << 30),
+    ("MB", 1 << 20),
+    ("KB", 1 << 10),
+    ("B", 1)
+];
+
+/// Parses a human-readable size (e.g. `512MB`) into bytes. Units are powers of 1024 and case-insensitive,
+/// and a number without a unit is a number of bytes
+///
+/// # Example
+/// ```
+/// use utils::parse_size_str;
+///
+/// assert_eq!(parse_size_str("2kb").unwrap(), 2048);
+/// assert_eq!(parse_size_str("100").unwrap(), 100);
+/// ```
+///
+/// # Errors
+///
+/// `ConfigurationError::InvalidSizeString` - when the number is not a non-negative integer, the unit is not
+/// one of B, KB, MB, GB and TB, or the size does not fit in a `usize`
+pub fn parse_size_str(s: &str) -> Result<usize, ConfigurationError> {
+    let invalid = || ConfigurationError::InvalidSizeString(s.to_owned());
+    let trimmed = s.trim();
+    let unit_start = trimmed.find(|c: char| !c.is_ascii_digit()).unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(unit_start);
+    let unit = unit.trim_start().to_ascii_uppercase();
+
+    let multiplier = if unit.is_empty() {
+        1
+    } else {
+        SIZE_UNITS.iter().find(|(name, _)| *name == unit).map(|(_, bytes)| *bytes).ok_or_else(invalid)?
+    };
+
+    number.parse::<usize>().ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(invalid)
+}
+
+/// Formats a number of bytes using the largest unit that divides it evenly, so that the result can be
+/// read back by `parse_size_str`
+///
+/// # Example
+/// ```
+/// use utils::format_size;
+///
+/// assert_eq!(format_size(2048), "2KB");
+/// assert_eq!(format_size(1536), "1536B");
+/// ```
+pub fn format_size(bytes: usize) -> String {
+    let (name, unit_bytes) = SIZE_UNITS.iter()
+        .find(|(_, unit_bytes)| bytes != 0 && bytes.is_multiple_of(*unit_bytes))
+        .unwrap_or(&("B", 1));
+
+    format!("{}{}", bytes / unit_bytes, name)
+}
+
+/// Formats a count along with the singular or plural form of a word (e.g. `3 matches`).
+/// Words are pluralized by appending an `s`, unless an irregular plural has been registered for them
+///
+/// # Examples
+///
+/// ```
+/// use utils::Pluralizer;
+///
+/// let p = Pluralizer::new().irregular("index", "indices");
+/// assert_eq!(p.pluralize(1, "index"), "1 index");
+/// assert_eq!(p.pluralize(2, "index"), "2 indices");
+/// assert_eq!(p.pluralize(0, "event"), "0 events");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Pluralizer {
+    irregulars: HashMap<String, String>
+}
+
+impl Pluralizer {
+    /// A pluralizer without any irregular plurals
+    pub fn new() -> Self {
+        Self { irregulars: HashMap::new() }
+    }
+
+    /// Registers `plural` as the plural form of `singular`
+    pub fn irregular(mut self, singular: &str, plural: &str) -> Self {
+        self.irregulars.insert(singular.to_owned(), plural.to_owned());
+        self
+    }
+
+    pub fn pluralize(&self, n: usize, word: &str) -> String {
+        if n == 1 {
+            return format!("{} {}", n, word);
+        }
+
+        match self.irregulars.get(word) {
+            Some(plural) => format!("{} {}", n, plural),
+            None => format!("{} {}s", n, word)
+        }
+    }
+}
+
+/// Registers the irregular plurals of the words used throughout the processor
+impl Default for Pluralizer {
+    fn default() -> Self {
+        Self::new()
+            .irregular("match", "matches")
+            .irregular("entry", "entries")
+    }
+}
+
+/// Same as `Pluralizer::pluralize`, using the default irregular plurals
+pub fn pluralize(n: usize, word: &str) -> String {
+    PLURALIZER.pluralize(n, word)
+}
+
+lazy_static! {
+    static ref PLURALIZER: Pluralizer = Pluralizer::default();
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -71,6 +281,130 @@ mod tests {
         assert!(!actual.iter().any(|e| e == "src/utils.rs"));
     }
 
+    #[test]
+    fn it_returns_this_file_as_a_path() {
+        let actual = rec_get_files_by_path("src", "rs");
+        assert!(actual.contains(&PathBuf::from("src/utils.rs")));
+        assert!(rec_get_files_by_path("src", "txt").is_empty());
+    }
+
+    #[test]
+    fn it_returns_this_file_strictly() {
+        let actual = rec_get_files_by_ext_strict("src", "rs").unwrap();
+        assert!(actual.contains(&PathBuf::from("src/utils.rs")));
+    }
+
+    #[test]
+    fn strict_variant_fails_for_missing_directories() {
+        assert!(rec_get_files_by_ext_strict("non-existent-dir", "rs").is_err());
+        assert!(rec_get_files_by_path("non-existent-dir", "rs").is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn only_the_path_variants_return_non_utf8_paths() {
+        use std::ffi::OsStr;
+        use std::fs;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = std::env::temp_dir().join(format!("infobserve-utils-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(OsStr::from_bytes(b"bad\xff.yar")), "").unwrap();
+        fs::write(dir.join("good.yar"), "").unwrap();
+        let dir_str = dir.to_str().unwrap();
+
+        let lossless = rec_get_files_by_path(dir_str, "yar");
+        let strict = rec_get_files_by_ext_strict(dir_str, "yar").unwrap();
+        let lossy = rec_get_files_by_ext(dir_str, "yar");
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(lossless.len(), 2);
+        assert_eq!(strict.len(), 2);
+        assert_eq!(lossy, vec![format!("{}/good.yar", dir_str)]);
+    }
+
+    /// Creates `files` (relative paths) under a fresh temporary directory and returns its path
+    fn rule_tree(test: &str, files: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("infobserve-utils-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for file in files {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+
+        dir
+    }
+
+    fn patterns(globs: &[&str]) -> Vec<Pattern> {
+        globs.iter().map(|g| Pattern::new(g).unwrap()).collect()
+    }
+
+    /// The names of the files under `dir` that `filter` keeps, sorted
+    fn filtered_names(dir: &Path, filter: &GlobFilter) -> Vec<String> {
+        let mut names: Vec<String> = rec_get_files_by_globs(dir.to_str().unwrap(), "yar", filter).unwrap().iter()
+            .map(|p| p.strip_prefix(dir).unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+
+        names
+    }
+
+    #[test]
+    fn an_empty_glob_filter_keeps_every_file() {
+        let dir = rule_tree("glob-empty", &["a.yar", "passwords/b.yar", "notes.txt"]);
+
+        assert_eq!(filtered_names(&dir, &GlobFilter::default()), ["a.yar", "passwords/b.yar"]);
+    }
+
+    #[test]
+    fn only_included_files_are_kept() {
+        let dir = rule_tree("glob-include", &["a.yar", "passwords/b.yar", "pii/deep/c.yar"]);
+        let filter = GlobFilter::new(patterns(&["**/passwords/*.yar", "pii/**/*.yar"]), vec![]);
+
+        assert_eq!(filtered_names(&dir, &filter), ["passwords/b.yar", "pii/deep/c.yar"]);
+    }
+
+    #[test]
+    fn excluded_files_are_not_returned() {
+        let dir = rule_tree("glob-exclude", &["a.yar", "template.yar", "passwords/template.yar", "passwords/b.yar"]);
+        let filter = GlobFilter::new(vec![], patterns(&["**/template.yar"]));
+
+        assert_eq!(filtered_names(&dir, &filter), ["a.yar", "passwords/b.yar"]);
+    }
+
+    #[test]
+    fn exclusion_is_applied_after_inclusion() {
+        let dir = rule_tree("glob-both", &["passwords/a.yar", "passwords/stub.yar", "pii/b.yar"]);
+        let filter = GlobFilter::new(patterns(&["passwords/*.yar"]), patterns(&["*/stub.yar"]));
+
+        assert_eq!(filtered_names(&dir, &filter), ["passwords/a.yar"]);
+    }
+
+    #[test]
+    fn single_stars_do_not_cross_directories() {
+        let dir = rule_tree("glob-separator", &["a.yar", "passwords/b.yar"]);
+        let filter = GlobFilter::new(patterns(&["*.yar"]), vec![]);
+
+        assert_eq!(filtered_names(&dir, &filter), ["a.yar"]);
+    }
+
+    #[test]
+    fn parses_one_id_per_line() {
+        assert_eq!(parse_id_list("1\n2\n3\n").unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn id_list_ignores_blank_lines_and_whitespace() {
+        assert_eq!(parse_id_list("\n  1 \r\n\n\t2\n").unwrap(), vec![1, 2]);
+    }
+
+    #[test]
+    fn id_list_rejects_non_numeric_ids() {
+        let err = parse_id_list("1\nfoo\n").unwrap_err();
+        assert!(err.to_string().contains("line 2"));
+    }
+
     #[test]
     fn clamps_when_below_min() {
         assert_eq!(2, clamp_min(2, 0));
@@ -80,4 +414,80 @@ mod tests {
     fn does_not_clamp_when_below_min() {
         assert_eq!(0, clamp_min(-2, 0));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn regular_words_get_an_s() {
+        assert_eq!(pluralize(0, "event"), "0 events");
+        assert_eq!(pluralize(2, "processor"), "2 processors");
+    }
+
+    #[test]
+    fn single_items_are_not_pluralized() {
+        assert_eq!(pluralize(1, "event"), "1 event");
+        assert_eq!(pluralize(1, "match"), "1 match");
+        assert_eq!(pluralize(1, "entry"), "1 entry");
+    }
+
+    #[test]
+    fn default_irregulars_are_registered() {
+        assert_eq!(pluralize(3, "match"), "3 matches");
+        assert_eq!(pluralize(0, "entry"), "0 entries");
+    }
+
+    #[test]
+    fn custom_irregulars_can_be_registered() {
+        let p = Pluralizer::new().irregular("processor", "processor threads").irregular("match", "matches");
+
+        assert_eq!(p.pluralize(3, "match"), "3 matches");
+        assert_eq!(p.pluralize(4, "processor"), "4 processor threads");
+        assert_eq!(Pluralizer::new().pluralize(3, "match"), "3 matchs");
+    }
+
+    #[test]
+    fn sizes_without_a_unit_are_bytes() {
+        assert_eq!(parse_size_str("0").unwrap(), 0);
+        assert_eq!(parse_size_str("100").unwrap(), 100);
+        assert_eq!(parse_size_str("100B").unwrap(), 100);
+    }
+
+    #[test]
+    fn size_units_are_powers_of_1024() {
+        assert_eq!(parse_size_str("2KB").unwrap(), 2048);
+        assert_eq!(parse_size_str("3MB").unwrap(), 3 * 1024 * 1024);
+        assert_eq!(parse_size_str("1GB").unwrap(), 1 << 30);
+        assert_eq!(parse_size_str("1TB").unwrap(), 1 << 40);
+    }
+
+    #[test]
+    fn size_units_are_case_insensitive_and_may_be_spaced() {
+        assert_eq!(parse_size_str("512mb").unwrap(), 512 * 1024 * 1024);
+        assert_eq!(parse_size_str(" 4 Kb ").unwrap(), 4096);
+    }
+
+    #[test]
+    fn invalid_sizes_are_rejected() {
+        for s in ["", "MB", "-1MB", "1.5GB", "10 megabytes", "1PB", "99999999999999999999TB"] {
+            match parse_size_str(s) {
+                Err(ConfigurationError::InvalidSizeString(value)) => assert_eq!(value, s),
+                other => panic!("Expected {:?} to be rejected, got {:?}", s, other)
+            }
+        }
+    }
+
+    #[test]
+    fn sizes_are_formatted_with_the_largest_even_unit() {
+        assert_eq!(format_size(0), "0B");
+        assert_eq!(format_size(1023), "1023B");
+        assert_eq!(format_size(1024), "1KB");
+        assert_eq!(format_size(1536), "1536B");
+        assert_eq!(format_size(5 * 1024 * 1024), "5MB");
+        assert_eq!(format_size(1 << 40), "1TB");
+    }
+
+    #[test]
+    fn formatted_sizes_can_be_parsed_back() {
+        for bytes in [0, 7, 2048, 3 << 20, 1 << 30] {
+            assert_eq!(parse_size_str(&format_size(bytes)).unwrap(), bytes);
+        }
+    }
+}
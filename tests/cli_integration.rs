@@ -0,0 +1,404 @@
+//! Tests of the compiled `processor-rs` binary
+//!
+//! Every invocation runs in its own temporary working directory, since the binary writes its log file
+//! under `logs/` relative to it
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+
+fn fixture(path: &str) -> String {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(path).to_string_lossy().into_owned()
+}
+
+fn work_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("infobserve-cli-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn processor(test: &str) -> Command {
+    let mut cmd = Command::cargo_bin("processor-rs").unwrap();
+    cmd.current_dir(work_dir(test));
+    cmd
+}
+
+#[test]
+fn help_describes_the_processor() {
+    processor("help")
+        .arg("--help")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Infobserve Processor"))
+        .stdout(predicate::str::contains("validate-rules"));
+}
+
+#[test]
+fn unknown_flags_are_rejected() {
+    processor("unknown-flag")
+        .arg("--no-such-flag")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("--no-such-flag"));
+}
+
+#[test]
+fn dump_schema_prints_the_schema() {
+    processor("dump-schema")
+        .arg("--dump-schema")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("CREATE TABLE IF NOT EXISTS events"));
+}
+
+#[test]
+#[ignore = "requires that no database is listening on the default address (localhost:5432)"]
+fn empty_configuration_fails_without_a_database() {
+    let dir = work_dir("empty-config");
+    let config = dir.join("config.yaml");
+    fs::write(&config, "").unwrap();
+
+    processor("empty-config")
+        .arg("--config")
+        .arg(&config)
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("Could not connect to database"));
+}
+
+#[test]
+fn unreachable_database_is_reported() {
+    let dir = work_dir("unreachable-db");
+    let config = dir.join("config.yaml");
+    fs::write(&config, "database:\n  host: 127.0.0.1\n  port: 1\n").unwrap();
+
+    processor("unreachable-db")
+        .arg("--config")
+        .arg(&config)
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("Could not connect to database"));
+}
+
+#[test]
+fn toml_configuration_is_read() {
+    let dir = work_dir("toml-config");
+    let config = dir.join("config.toml");
+    fs::write(&config, "[database]\nhost = \"127.0.0.1\"\nport = 1\n").unwrap();
+
+    processor("toml-config")
+        .arg("--config")
+        .arg(&config)
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("Could not connect to database"))
+        .stderr(predicate::str::contains("127.0.0.1:1"));
+}
+
+#[test]
+fn environment_variables_override_the_configuration_file() {
+    let dir = work_dir("env-override");
+    let config = dir.join("config.yaml");
+    fs::write(&config, "database:\n  host: db.invalid\n  port: 5432\n").unwrap();
+
+    processor("env-override")
+        .arg("--config")
+        .arg(&config)
+        .env("INFOBSERVE_DB_HOST", "127.0.0.1")
+        .env("INFOBSERVE_DB_PORT", "1")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("Could not connect to database"))
+        .stderr(predicate::str::contains("127.0.0.1:1"));
+}
+
+#[test]
+fn invalid_configuration_is_reported() {
+    let dir = work_dir("invalid-config");
+    let config = dir.join("config.yaml");
+    fs::write(&config, "workers: lots\n").unwrap();
+
+    processor("invalid-config")
+        .arg("--config")
+        .arg(&config)
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("Could not load configuration file"));
+}
+
+#[test]
+fn unknown_configuration_formats_are_reported() {
+    processor("unknown-config-format")
+        .args(["--config", "config.json"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("Unrecognized configuration file format: config.json"));
+}
+
+#[test]
+fn validate_rules_lists_the_rule_names() {
+    processor("validate-rules")
+        .args(["validate-rules", "--rules-dir", &fixture("rules/")])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("MyPass"))
+        .stdout(predicate::str::contains("Compiled 1 rule"));
+}
+
+#[test]
+fn validate_rules_fails_for_missing_directories() {
+    processor("validate-rules-missing")
+        .args(["validate-rules", "--rules-dir", "/nonexistent/"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("NoYaraRulesError"));
+}
+
+#[test]
+fn validate_rules_fails_for_directories_without_rules() {
+    let dir = work_dir("validate-rules-empty").join("rules");
+    fs::create_dir_all(&dir).unwrap();
+
+    processor("validate-rules-empty")
+        .arg("validate-rules")
+        .arg("--rules-dir")
+        .arg(&dir)
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("NoYaraRulesError"));
+}
+
+#[test]
+fn validate_rules_fails_for_invalid_rules() {
+    let dir = work_dir("validate-rules-invalid").join("rules");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("broken.yar"), "rule Broken { condition: $undefined }").unwrap();
+
+    processor("validate-rules-invalid")
+        .arg("validate-rules")
+        .arg("--rules-dir")
+        .arg(&dir)
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("Invalid yara rules"));
+}
+
+#[test]
+fn validate_rules_flag_compiles_the_configured_rules() {
+    let dir = work_dir("validate-rules-flag");
+    let rules = dir.join("rules");
+    fs::create_dir_all(&rules).unwrap();
+    fs::write(rules.join("valid.yar"), "rule Valid { strings: $a = \"valid\" condition: $a }").unwrap();
+    fs::write(rules.join("broken.yar"), "rule Broken { condition: $undefined }").unwrap();
+    let config = dir.join("config.yaml");
+    // Nothing listens on port 1: validating the rules must not connect to the database
+    fs::write(
+        &config,
+        format!("yara_rule_dir: {}\ndatabase:\n  host: 127.0.0.1\n  port: 1\n", rules.display())
+    ).unwrap();
+
+    processor("validate-rules-flag")
+        .arg("--config")
+        .arg(&config)
+        .arg("--validate-rules")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("Invalid yara rules"));
+
+    fs::write(
+        &config,
+        format!("yara_rule_dir: {}\nyara_exclude_patterns: [broken.yar]\n", rules.display())
+    ).unwrap();
+    processor("validate-rules-flag")
+        .arg("--config")
+        .arg(&config)
+        .arg("--validate-rules")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Compiled 1 rule"));
+}
+
+#[test]
+fn validate_rules_requires_a_directory() {
+    processor("validate-rules-no-dir")
+        .arg("validate-rules")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("--rules-dir"));
+}
+
+#[test]
+#[ignore = "requires a database on the default address (localhost:5432)"]
+fn input_file_events_are_processed_end_to_end() {
+    let dir = work_dir("input-file");
+    let config = dir.join("config.yaml");
+    fs::write(
+        &config,
+        format!("yara_rule_dir: {}\nfeeder:\n  index_cache_lookup: false\n", fixture("rules/"))
+    ).unwrap();
+
+    processor("input-file")
+        .arg("--config")
+        .arg(&config)
+        .args(["--input-file", &fixture("events.jsonl")])
+        .timeout(std::time::Duration::from_secs(60))
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Events processed: 3"))
+        .stderr(predicate::str::contains("Matches: 2"))
+        .stderr(predicate::str::contains("All loaders: Persisted events: 2"));
+}
+
+#[test]
+fn dry_runs_print_the_events_without_a_database() {
+    let dir = work_dir("dry-run");
+    let config = dir.join("config.yaml");
+    // Nothing listens on port 1: a dry run must not try to connect
+    fs::write(
+        &config,
+        format!("yara_rule_dir: {}\ndatabase:\n  host: 127.0.0.1\n  port: 1\n", fixture("rules/"))
+    ).unwrap();
+
+    processor("dry-run")
+        .arg("--config")
+        .arg(&config)
+        .args(["--dry-run", "--input-file", &fixture("events.jsonl")])
+        .timeout(std::time::Duration::from_secs(60))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("ALERT: ["))
+        .stderr(predicate::str::contains("Events processed: 3"))
+        .stderr(predicate::str::contains("Dry run: Matching events: 2"))
+        .stderr(predicate::str::contains("Could not connect to database").not());
+}
+
+#[test]
+fn log_level_hides_less_severe_messages() {
+    let dir = work_dir("log-level");
+    let config = dir.join("config.yaml");
+    fs::write(&config, format!("yara_rule_dir: {}\n", fixture("rules/"))).unwrap();
+
+    processor("log-level")
+        .arg("--config")
+        .arg(&config)
+        .args(["--log-level", "warn", "--dry-run", "--input-file", &fixture("events.jsonl")])
+        .timeout(std::time::Duration::from_secs(60))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("ALERT: ["))
+        .stderr(predicate::str::contains("Events processed").not());
+}
+
+#[test]
+fn json_log_lines_are_json_objects() {
+    let dir = work_dir("log-format");
+    let config = dir.join("config.yaml");
+    fs::write(&config, format!("yara_rule_dir: {}\n", fixture("rules/"))).unwrap();
+
+    let output = processor("log-format")
+        .arg("--config")
+        .arg(&config)
+        .args(["--log-format", "json", "--dry-run", "--input-file", &fixture("events.jsonl")])
+        .timeout(std::time::Duration::from_secs(60))
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let lines: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stderr).lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|_| panic!("not a JSON log line: {}", line)))
+        .collect();
+    assert!(lines.iter().any(|line| {
+        line["level"] == "INFO" && line["message"].as_str().unwrap_or_default().contains("Events processed: 3")
+    }));
+}
+
+/// Sends `GET path` to localhost:`port`, waiting up to 30s for something to listen there
+fn http_get(port: u16, path: &str) -> std::io::Result<String> {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::{Duration, Instant};
+
+    let started = Instant::now();
+    let mut stream = loop {
+        match TcpStream::connect(("127.0.0.1", port)) {
+            Ok(stream) => break stream,
+            Err(_) if started.elapsed() < Duration::from_secs(30) => std::thread::sleep(Duration::from_millis(100)),
+            Err(e) => return Err(e)
+        }
+    };
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path)?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    Ok(response)
+}
+
+#[test]
+fn metrics_and_health_are_served_while_running() {
+    use std::net::TcpListener;
+
+    let dir = work_dir("metrics");
+    // Nothing listens on port 1, so the feeder keeps retrying, and the process keeps running
+    fs::write(
+        dir.join("config.yaml"),
+        format!("yara_rule_dir: {}\nredis:\n  host: 127.0.0.1\n  port: 1\n", fixture("rules/"))
+    ).unwrap();
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("processor-rs"))
+        .current_dir(&dir)
+        .arg("--dry-run")
+        .env("INFOBSERVE_MONITORING_METRICS_PORT", port.to_string())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let (metrics, health) = (http_get(port, "/metrics"), http_get(port, "/health"));
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let metrics = metrics.unwrap();
+    assert!(metrics.starts_with("HTTP/1.1 200 OK"), "{}", metrics);
+    assert!(metrics.contains("Content-Type: text/plain; version=0.0.4"), "{}", metrics);
+    assert!(metrics.contains("events_processed_total 0"), "{}", metrics);
+
+    let health = health.unwrap();
+    assert!(health.starts_with("HTTP/1.1 200 OK"), "{}", health);
+    assert!(health.ends_with(r#"{"status":"ok"}"#), "{}", health);
+}
+
+#[test]
+fn unknown_log_levels_are_rejected() {
+    processor("log-level-unknown")
+        .args(["-l", "verbose"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("expected one of error, warn, info, debug or trace"));
+}
+
+#[test]
+fn dry_runs_cannot_delete_events() {
+    processor("dry-run-delete")
+        .args(["--dry-run", "--delete-events-file", "ids.txt"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("--delete-events-file"));
+}
+
+#[test]
+fn missing_input_files_are_reported() {
+    let dir = work_dir("input-file-missing");
+    let config = dir.join("config.yaml");
+    fs::write(&config, format!("yara_rule_dir: {}\n", fixture("rules/"))).unwrap();
+
+    processor("input-file-missing")
+        .arg("--config")
+        .arg(&config)
+        .args(["-f", "/nonexistent/events.jsonl"])
+        .timeout(std::time::Duration::from_secs(60))
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("Could not open input file /nonexistent/events.jsonl"));
+}